@@ -0,0 +1,405 @@
+/* Atacante de teste
+
+    Escuta os quadros que o controlador transmite e, a cada 'periodo', injeta num
+    veículo escutado há pouco um SetAcel:
+        forjado     montado do zero, autenticado com uma chave chutada
+        adulterado  último quadro capturado, virado SetAcel com outra aceleração e
+                    sequência adiantada, com o MAC original
+        repetido    quadro capturado há mais de IDADE_REPETICAO, enviado de novo como está
+    ou, no lugar do SetAcel, uma chave:
+        chave       Chave forjada com a chave chutada, seguida de um SetAcel autenticado
+                    com ela, como se o controlador tivesse trocado a chave do veículo
+        credencial  a chave chutada entregue como um controlador com o segredo só de
+                    zeros entregaria, cifrada e autenticada com a credencial que sairia
+                    dele, seguida de um SetAcel autenticado com ela
+    Sem a autenticação, qualquer um deles mudaria a aceleração do veículo, e a sequência
+    adiantada ainda o faria ignorar o controlador de verdade por um bom tempo.
+*/
+
+use std::collections::VecDeque;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::autenticacao::{chave_do_veiculo, cifra_chave, Chave};
+use crate::comunicacao::{Comunicacao, MensagemDoControlador, Pacote};
+use crate::protocolo::{autentica, Quadro};
+use crate::registro::{registra, Categoria, Nivel};
+
+const GUARDA: f64 = 3000.0; // tempo que um quadro capturado fica guardado, ms
+const RECENTE: f64 = 500.0; // só ataca veículos escutados há menos que isso, ms
+const IDADE_REPETICAO: f64 = 1000.0; // idade mínima de um quadro para repeti-lo, ms
+const SALTO_SEQUENCIA: u64 = 1000; // quanto o atacante adianta a sequência
+const SEGREDO_ZERO: Chave = [0; 32]; // segredo que qualquer um chutaria, recusado no cenário
+
+// Quantas mensagens o atacante injetou de cada tipo
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Injecoes {
+    pub forjadas: i32,
+    pub adulteradas: i32,
+    pub repetidas: i32,
+    pub chaves: i32,      // cada uma seguida de um SetAcel
+    pub credenciais: i32, // chaves entregues com a credencial do segredo só de zeros
+}
+
+// Quadro escutado no canal do controlador
+struct Capturado {
+    instante: f64, // ms
+    placa: String, // destino
+    bytes: Vec<u8>,
+}
+
+pub struct Atacante {
+    periodo: f64, // entre ataques, ms
+    espera: f64,  // tempo desde o último ataque, ms
+    rng: StdRng,
+    chave: Chave,                    // chute, não é a chave de nenhum veículo
+    capturados: VecDeque<Capturado>, // do mais velho para o mais novo
+    pub injecoes: Injecoes,
+}
+
+impl Atacante {
+    // Cria um atacante que ataca a cada 'periodo' ms, com sorteios a partir de 'semente'
+    pub fn new(periodo: f64, semente: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(semente);
+        let chave = rng.gen();
+        Self {
+            periodo,
+            espera: 0.0,
+            rng,
+            chave,
+            capturados: VecDeque::new(),
+            injecoes: Injecoes::default(),
+        }
+    }
+
+    // Escuta o canal e, se está na hora, injeta uma mensagem
+    pub fn tick(&mut self, tickms: f64, comunicacao: &mut Comunicacao) {
+        let agora = comunicacao.agora();
+        for (placa, bytes) in comunicacao.retira_escutados() {
+            self.capturados.push_back(Capturado {
+                instante: agora,
+                placa,
+                bytes,
+            });
+        }
+        while self
+            .capturados
+            .front()
+            .is_some_and(|c| agora - c.instante > GUARDA)
+        {
+            self.capturados.pop_front();
+        }
+
+        self.espera += tickms;
+        if self.espera < self.periodo {
+            return;
+        }
+        self.espera = 0.0;
+
+        // Vítima sorteada entre os quadros recentes
+        let recentes = self
+            .capturados
+            .iter()
+            .filter(|c| agora - c.instante <= RECENTE)
+            .count();
+        if recentes == 0 {
+            return;
+        }
+        let i = self.capturados.len() - 1 - self.rng.gen_range(0..recentes);
+        let placa = self.capturados[i].placa.clone();
+        let acel = self.rng.gen_range(-8.0..3.0);
+
+        let (tipo, quadros) = match self.rng.gen_range(0..5) {
+            0 => {
+                let sequencia = self.ultima_sequencia(&placa) + SALTO_SEQUENCIA;
+                let set_acel = MensagemDoControlador::SetAcel {
+                    placa: placa.clone(),
+                    acel,
+                };
+                self.injecoes.forjadas += 1;
+                ("forjada", vec![self.forja(agora, sequencia, set_acel)])
+            }
+            1 => {
+                let Ok((Quadro::DoControlador(mut pacote, mac), _)) =
+                    Quadro::decodifica(&self.capturados[i].bytes)
+                else {
+                    return;
+                };
+                pacote.sequencia += SALTO_SEQUENCIA;
+                pacote.mensagem = MensagemDoControlador::SetAcel {
+                    placa: placa.clone(),
+                    acel,
+                };
+                self.injecoes.adulteradas += 1;
+                (
+                    "adulterada",
+                    vec![Quadro::DoControlador(pacote, mac).codifica()],
+                )
+            }
+            2 => {
+                let Some(antigo) = self
+                    .capturados
+                    .iter()
+                    .find(|c| c.placa == placa && agora - c.instante >= IDADE_REPETICAO)
+                else {
+                    return;
+                };
+                self.injecoes.repetidas += 1;
+                ("repetida", vec![antigo.bytes.clone()])
+            }
+            3 => {
+                let sequencia = self.ultima_sequencia(&placa) + SALTO_SEQUENCIA;
+                self.injecoes.chaves += 1;
+                ("chave", self.forja_chave(&placa, agora, sequencia, acel))
+            }
+            _ => {
+                let sequencia = self.ultima_sequencia(&placa) + SALTO_SEQUENCIA;
+                self.injecoes.credenciais += 1;
+                (
+                    "credencial",
+                    self.entrega_com_segredo_zero(&placa, agora, sequencia, acel),
+                )
+            }
+        };
+        registra!(
+            Categoria::Comunicacao,
+            Nivel::Depuracao,
+            placa = &placa,
+            "atacante injeta mensagem {} para @{}",
+            tipo,
+            placa
+        );
+        for bytes in quadros {
+            comunicacao.injeta_para_veiculo(&placa, bytes);
+        }
+    }
+
+    // Quadro do controlador montado do zero, autenticado com a chave chutada
+    fn forja(&self, agora: f64, sequencia: u64, mensagem: MensagemDoControlador) -> Vec<u8> {
+        let pacote = Pacote {
+            enviada_em: agora,
+            sequencia,
+            mensagem,
+        };
+        let mac = autentica(&pacote, &self.chave);
+        Quadro::DoControlador(pacote, mac).codifica()
+    }
+
+    // Chave forjada para 'placa', a chave chutada em claro, e logo depois um SetAcel
+    // com 'acel' autenticado com ela
+    fn forja_chave(&self, placa: &str, agora: f64, sequencia: u64, acel: f64) -> Vec<Vec<u8>> {
+        let chave = MensagemDoControlador::Chave {
+            placa: placa.to_string(),
+            chave: self.chave,
+        };
+        let set_acel = MensagemDoControlador::SetAcel {
+            placa: placa.to_string(),
+            acel,
+        };
+        vec![
+            self.forja(agora, sequencia, chave),
+            self.forja(agora, sequencia + 1, set_acel),
+        ]
+    }
+
+    // A chave chutada para 'placa' cifrada e autenticada com a credencial que o segredo
+    // só de zeros daria ao veículo, e logo depois um SetAcel com 'acel' autenticado com ela
+    fn entrega_com_segredo_zero(
+        &self,
+        placa: &str,
+        agora: f64,
+        sequencia: u64,
+        acel: f64,
+    ) -> Vec<Vec<u8>> {
+        let credencial = chave_do_veiculo(&SEGREDO_ZERO, placa);
+        let pacote = Pacote {
+            enviada_em: agora,
+            sequencia,
+            mensagem: MensagemDoControlador::Chave {
+                placa: placa.to_string(),
+                chave: cifra_chave(&self.chave, &credencial, agora),
+            },
+        };
+        let mac = autentica(&pacote, &credencial);
+        let set_acel = MensagemDoControlador::SetAcel {
+            placa: placa.to_string(),
+            acel,
+        };
+        vec![
+            Quadro::DoControlador(pacote, mac).codifica(),
+            self.forja(agora, sequencia + 1, set_acel),
+        ]
+    }
+
+    // Maior sequência escutada do controlador para um veículo
+    fn ultima_sequencia(&self, placa: &str) -> u64 {
+        self.capturados
+            .iter()
+            .filter(|c| c.placa == placa)
+            .filter_map(|c| match Quadro::decodifica(&c.bytes) {
+                Ok((Quadro::DoControlador(pacote, _), _)) => Some(pacote.sequencia),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comunicacao::{ParametrosComunicacao, Transporte};
+    use crate::registro::{self, ConfiguracaoLog};
+    use crate::simulacao::TICKMS;
+    use crate::transito::{Carro, ParametrosVeiculo, Via};
+
+    const SEGREDO: Chave = [7; 32];
+    const PLACA: &str = "CCC0001";
+    const SESSAO: Chave = [9; 32]; // chave que o controlador de verdade entrega
+
+    // Carro recém-chegado, com a credencial do SEGREDO e ainda sem a chave, e um
+    // canal sem imperfeições
+    fn carro_novo() -> (Carro, Comunicacao) {
+        carro_com_segredo(&SEGREDO)
+    }
+
+    // Carro recém-chegado, com a credencial de 'segredo'
+    fn carro_com_segredo(segredo: &Chave) -> (Carro, Comunicacao) {
+        registro::configura(ConfiguracaoLog::interpreta("nenhum").unwrap());
+        let carro = Carro::new(
+            PLACA.to_string(),
+            chave_do_veiculo(segredo, PLACA),
+            Via::ViaH,
+            10.0,
+            0.0,
+            &ParametrosVeiculo::default(),
+            150.0,
+            4.0,
+        );
+        let comunicacao =
+            Comunicacao::new(ParametrosComunicacao::default(), 1, Transporte::Memoria);
+        (carro, comunicacao)
+    }
+
+    // Avança um tick, entregando ao carro o que estiver no canal
+    fn passa(carro: &mut Carro, comunicacao: &mut Comunicacao) {
+        comunicacao.define_tempo(comunicacao.agora() + TICKMS);
+        carro.tick(TICKMS, None, comunicacao);
+    }
+
+    // Entrega a SESSAO como o controlador de verdade, com a credencial do carro
+    fn entrega_chave(comunicacao: &mut Comunicacao) {
+        let credencial = chave_do_veiculo(&SEGREDO, PLACA);
+        let msg = MensagemDoControlador::Chave {
+            placa: PLACA.to_string(),
+            chave: cifra_chave(&SESSAO, &credencial, comunicacao.agora()),
+        };
+        comunicacao.send_por_controlador(PLACA.to_string(), msg, &credencial);
+    }
+
+    fn envia_acel(comunicacao: &mut Comunicacao, acel: f64) {
+        let msg = MensagemDoControlador::SetAcel {
+            placa: PLACA.to_string(),
+            acel,
+        };
+        comunicacao.send_por_controlador(PLACA.to_string(), msg, &SESSAO);
+    }
+
+    // Um quadro qualquer do controlador abre o canal para o carro, que o descarta por
+    // ainda não ter a chave
+    fn abre_canal(comunicacao: &mut Comunicacao) {
+        let msg = MensagemDoControlador::PedeSituacao {
+            placa: PLACA.to_string(),
+        };
+        comunicacao.send_por_controlador(PLACA.to_string(), msg, &[0; 32]);
+    }
+
+    // Injeta a chave forjada e o SetAcel autenticado com ela
+    fn ataca(comunicacao: &mut Comunicacao) {
+        let atacante = Atacante::new(100.0, 2);
+        let agora = comunicacao.agora();
+        for bytes in atacante.forja_chave(PLACA, agora, SALTO_SEQUENCIA, -8.0) {
+            comunicacao.injeta_para_veiculo(PLACA, bytes);
+        }
+    }
+
+    #[test]
+    fn carro_novo_rejeita_chave_forjada() {
+        let (mut carro, mut comunicacao) = carro_novo();
+        abre_canal(&mut comunicacao);
+        ataca(&mut comunicacao);
+        passa(&mut carro, &mut comunicacao);
+        // A Chave não bate com a credencial, e sem chave o SetAcel nem é conferido
+        assert_eq!(carro.forjadas, 1);
+        assert_eq!(carro.ultima_sequencia, 0);
+        assert_eq!(carro.chave, None);
+        assert!(!carro.conhecido);
+        assert_eq!(carro.acel_pedida, 0.0);
+
+        // A chave de verdade continua valendo, e os comandos autenticados com ela
+        entrega_chave(&mut comunicacao);
+        envia_acel(&mut comunicacao, -2.0);
+        passa(&mut carro, &mut comunicacao);
+        assert_eq!(carro.chave, Some(SESSAO));
+        assert_eq!(carro.acel_pedida, -2.0);
+        assert_eq!(carro.forjadas, 1);
+    }
+
+    // Entrega a chave chutada com a credencial do segredo só de zeros
+    fn ataca_com_segredo_zero(comunicacao: &mut Comunicacao) -> Chave {
+        let atacante = Atacante::new(100.0, 2);
+        let agora = comunicacao.agora();
+        for bytes in atacante.entrega_com_segredo_zero(PLACA, agora, SALTO_SEQUENCIA, -8.0) {
+            comunicacao.injeta_para_veiculo(PLACA, bytes);
+        }
+        atacante.chave
+    }
+
+    #[test]
+    fn carro_rejeita_credencial_do_segredo_zero() {
+        let (mut carro, mut comunicacao) = carro_novo();
+        abre_canal(&mut comunicacao);
+        ataca_com_segredo_zero(&mut comunicacao);
+        passa(&mut carro, &mut comunicacao);
+        assert_eq!(carro.forjadas, 1);
+        assert_eq!(carro.chave, None);
+        assert!(!carro.conhecido);
+        assert_eq!(carro.acel_pedida, 0.0);
+    }
+
+    // O mesmo ataque contra um carro do segredo só de zeros: é por isso que o cenário
+    // o recusa
+    #[test]
+    fn segredo_zero_entrega_a_chave_do_atacante() {
+        let (mut carro, mut comunicacao) = carro_com_segredo(&SEGREDO_ZERO);
+        abre_canal(&mut comunicacao);
+        let chave = ataca_com_segredo_zero(&mut comunicacao);
+        passa(&mut carro, &mut comunicacao);
+        assert_eq!(carro.forjadas, 0);
+        assert_eq!(carro.chave, Some(chave));
+        assert_eq!(carro.acel_pedida, -8.0);
+    }
+
+    #[test]
+    fn carro_em_falha_segura_rejeita_chave_forjada() {
+        let (mut carro, mut comunicacao) = carro_novo();
+        entrega_chave(&mut comunicacao);
+        passa(&mut carro, &mut comunicacao);
+        assert_eq!(carro.chave, Some(SESSAO));
+
+        // Como depois de tempo_falha_segura sem o controlador
+        carro.em_falha_segura = true;
+        carro.conhecido = false;
+        let acel_pedida = carro.acel_pedida;
+        ataca(&mut comunicacao);
+        passa(&mut carro, &mut comunicacao);
+        // A Chave não bate com a credencial, e o SetAcel não bate com a chave que o
+        // carro já tinha
+        assert_eq!(carro.forjadas, 2);
+        assert_eq!(carro.ultima_sequencia, 1);
+        assert_eq!(carro.chave, Some(SESSAO));
+        assert!(carro.em_falha_segura);
+        assert_eq!(carro.acel_pedida, acel_pedida);
+    }
+}
@@ -0,0 +1,183 @@
+/* Autenticação das mensagens do controlador
+
+    HMAC-SHA-256 (FIPS 180-4 e RFC 2104), escrito aqui porque o projeto não tem
+    dependências de criptografia.
+
+    Cada cruzamento sorteia a sua chave quando o controlador começa: na simulação, do
+    gerador da simulação, e a mesma semente repete as chaves; no servidor, do sistema
+    operacional, porque a semente do cenário é pública. Na Chegada o
+    controlador entrega ao veículo uma chave só dele, o HMAC da placa com a chave do
+    cruzamento: um veículo que conhece a própria chave não consegue forjar comandos
+    para os outros, e o controlador não precisa guardar uma chave por veículo.
+
+    A entrega não pode ser confiada à própria rede: cada veículo recebe fora dela,
+    ao ser criado, uma credencial, o HMAC da placa com o segredo do cruzamento
+    (seção [autenticacao] do cenário, o mesmo na simulação e no servidor). A
+    mensagem Chave vem autenticada com a credencial e traz a chave cifrada com ela,
+    então quem escuta não a lê e quem não tem o segredo não entrega chave nenhuma.
+    Um segredo conhecido de todos, como só zeros, não protege nada e é recusado; sem
+    segredo no cenário, a simulação sorteia um do sistema operacional a cada execução,
+    e o servidor, que precisa do mesmo segredo das simulações, não começa.
+*/
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+pub type Chave = [u8; 32];
+pub type Mac = [u8; 32];
+
+const BLOCO: usize = 64; // bytes por bloco do SHA-256
+
+// Constantes das rodadas do SHA-256
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+// Estado inicial do SHA-256
+const H0: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+// Processa um bloco de 64 bytes
+fn comprime(estado: &mut [u32; 8], bloco: &[u8]) {
+    let mut w = [0u32; 64];
+    for (i, palavra) in bloco.chunks_exact(4).enumerate() {
+        w[i] = u32::from_be_bytes(palavra.try_into().unwrap());
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *estado;
+    for i in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let escolha = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(escolha)
+            .wrapping_add(K[i])
+            .wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maioria = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(maioria);
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+    for (x, novo) in estado.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *x = x.wrapping_add(novo);
+    }
+}
+
+// Resumo SHA-256 das partes concatenadas
+fn sha256(partes: &[&[u8]]) -> [u8; 32] {
+    let mut estado = H0;
+    let mut pendente: Vec<u8> = Vec::with_capacity(2 * BLOCO);
+    let mut total: u64 = 0;
+    for parte in partes {
+        total += parte.len() as u64;
+        pendente.extend_from_slice(parte);
+        let completos = pendente.len() / BLOCO * BLOCO;
+        for bloco in pendente[..completos].chunks_exact(BLOCO) {
+            comprime(&mut estado, bloco);
+        }
+        pendente.drain(..completos);
+    }
+
+    // Enchimento: um bit 1, zeros e o tamanho em bits no fim do último bloco
+    pendente.push(0x80);
+    while pendente.len() % BLOCO != BLOCO - 8 {
+        pendente.push(0);
+    }
+    pendente.extend_from_slice(&(total * 8).to_be_bytes());
+    for bloco in pendente.chunks_exact(BLOCO) {
+        comprime(&mut estado, bloco);
+    }
+
+    let mut resumo = [0u8; 32];
+    for (bytes, x) in resumo.chunks_exact_mut(4).zip(estado) {
+        bytes.copy_from_slice(&x.to_be_bytes());
+    }
+    resumo
+}
+
+// HMAC-SHA-256 de 'dados' com 'chave'
+pub fn hmac(chave: &[u8], dados: &[u8]) -> Mac {
+    let mut bloco = [0u8; BLOCO];
+    if chave.len() > BLOCO {
+        bloco[..32].copy_from_slice(&sha256(&[chave]));
+    } else {
+        bloco[..chave.len()].copy_from_slice(chave);
+    }
+    let interno = bloco.map(|x| x ^ 0x36);
+    let externo = bloco.map(|x| x ^ 0x5c);
+    let resumo = sha256(&[&interno, dados]);
+    sha256(&[&externo, &resumo])
+}
+
+// Compara dois MACs sem sair mais cedo, para o tempo não revelar quantos bytes batem
+pub fn iguais(a: &Mac, b: &Mac) -> bool {
+    a.iter().zip(b).fold(0u8, |dif, (x, y)| dif | (x ^ y)) == 0
+}
+
+// Chave nova, sorteada de 'rng'
+pub fn nova_chave(rng: &mut StdRng) -> Chave {
+    rng.gen()
+}
+
+// Segredo sorteado do sistema operacional, para quando o cenário não tem um
+pub fn sorteia_segredo() -> Chave {
+    nova_chave(&mut StdRng::from_entropy())
+}
+
+// Chave de um veículo, derivada da chave do cruzamento
+pub fn chave_do_veiculo(chave_do_cruzamento: &Chave, placa: &str) -> Chave {
+    hmac(chave_do_cruzamento, placa.as_bytes())
+}
+
+// Cifra ou decifra a chave de um veículo levada pela mensagem Chave enviada em
+// 'enviada_em': XOR com o HMAC do instante pela credencial do veículo. Cada instante
+// tem o seu fluxo, e a mesma credencial só cifra duas chaves diferentes se dois
+// controladores a entregarem no mesmo instante
+pub fn cifra_chave(chave: &Chave, credencial: &Chave, enviada_em: f64) -> Chave {
+    let fluxo = hmac(credencial, &enviada_em.to_le_bytes());
+    let mut cifrada = *chave;
+    for (x, f) in cifrada.iter_mut().zip(fluxo) {
+        *x ^= f;
+    }
+    cifrada
+}
+
+// Bytes em hexadecimal, para registros e JSON
+pub fn hexadecimal(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+// Chave escrita em hexadecimal, como no cenário
+pub fn chave_de_hexadecimal(texto: &str) -> Option<Chave> {
+    let mut chave = [0u8; 32];
+    if texto.len() != 2 * chave.len() || !texto.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+    for (x, par) in chave.iter_mut().zip(texto.as_bytes().chunks_exact(2)) {
+        *x = u8::from_str_radix(std::str::from_utf8(par).ok()?, 16).ok()?;
+    }
+    Some(chave)
+}
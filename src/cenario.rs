@@ -0,0 +1,659 @@
+/* Arquivo de cenário
+
+    Arquivo TOML, com seções e linhas 'chave = valor', '#' inicia comentário.
+    Qualquer chave omitida fica com o valor padrão. Unidades:
+    distâncias em metros, velocidades em km/h, acelerações em m/s², tempos em segundos.
+
+    [geometria]
+    viah_margem = 15.0
+    viav_margem = 15.0
+    viah_largura = 4.0
+    viav_largura = 4.0
+    viah_perimetro = 150.0
+    viav_perimetro = 150.0
+
+    [veiculo]
+    comprimento = 4.0
+    velocidade_cruzeiro = 80.0
+    velocidade_maxima = 400.0
+    aceleracao_maxima = 3.0
+    aceleracao_minima = -10.0
+    controle_a_bordo = "cacc"   # cacc | acc | nenhum, limita a aceleração atrás do da frente
+    periodo_v2v = 0.1           # entre difusões do estado para os vizinhos, 0 não difunde
+    distancia_minima = 2.0      # até a traseira do da frente, depois de parar
+    tempo_falha_segura = 1.0    # sem mensagens do controlador até parar antes do cruzamento, 0 nunca
+    informe = "pedido"          # pedido | espontaneo, quando informa a situação ao controlador
+    periodo_informe = 0.1       # espontaneo, máximo entre informes
+    limiar_aceleracao = 0.5     # espontaneo, mudança de aceleração que faz informar antes
+    limiar_velocidade = 3.6     # espontaneo, mudança de velocidade que faz informar antes
+
+    [chegadas]                  # vale para as duas vias
+    processo = "uniforme"       # uniforme | poisson | perfil | trace
+    min_entre_chegadas = 3.0    # uniforme
+    max_entre_chegadas = 6.0    # uniforme
+    taxa = 1200.0               # poisson, veículos por hora
+    intervalo_minimo = 2.0      # poisson e perfil
+    perfil = [[0, 300], [300, 1200], [600, 300]]   # perfil, pares [segundos, veículos/h]
+    arquivo = "chegadas.csv"    # trace, linhas 'tempo' ou 'tempo,via' (H ou V)
+
+    [chegadas.h]                # só a via H, sobrepõe [chegadas]
+    [chegadas.v]                # só a via V, sobrepõe [chegadas]
+
+    [comunicacao]               # vale para todos os canais
+    latencia = 0.0              # atraso fixo de cada mensagem
+    jitter = 0.0                # atraso adicional sorteado entre zero e este valor
+    perda = 0.0                 # probabilidade de uma mensagem se perder
+    duplicacao = 0.0            # probabilidade de uma mensagem ser entregue duas vezes
+    reordenacao = 0.0           # probabilidade de uma mensagem poder ultrapassar as de outros remetentes
+    capacidade = 0.0            # bytes por segundo que o canal transmite, zero é ilimitada
+    mensagens_por_segundo = 0.0 # mensagens por segundo que o canal transmite, zero é ilimitado
+    slot = 0.0                  # transmissões começam no início de um slot e ocupam slots inteiros
+    fila_maxima = 0.2           # espera e transmissão máximas no canal limitado, além disso descarta
+
+    [comunicacao.veiculo]       # só veículos -> controlador, sobrepõe [comunicacao]
+    [comunicacao.controlador]   # só controlador -> veículos, sobrepõe [comunicacao]
+    [comunicacao.v2v]           # só veículo -> vizinhos, sobrepõe [comunicacao]
+
+    [controle]
+    controlador = "semaforo"		# semaforo | faz_nada | reservas | atuado | mpc | fifo | pelotoes
+    tempo_entre_controles = 0.05
+
+    [semaforo]
+    tempo_verde = 13.0
+    tempo_amarelo = 5.0         # também do atuado
+    plano = "fixo"              # fixo | webster, webster recalcula os verdes pelo fluxo
+    fluxo_saturacao = 1800.0    # webster, veículos por hora de verde numa via com fila
+    tempo_perdido = 5.0         # webster, por fase
+    periodo_plano = 60.0        # webster, entre cálculos do plano
+    janela_fluxo = 120.0        # webster, chegadas contadas para estimar o fluxo
+    ciclo_minimo = 30.0         # webster
+    ciclo_maximo = 120.0        # webster
+    verde_minimo = 5.0          # webster
+
+    [atuado]
+    verde_minimo = 8.0
+    verde_maximo = 30.0
+    passagem = 2.0              # sem detecção na via verde até encerrar o verde
+    detectores = [100, 5]       # distâncias dos laços antes do cruzamento, nas duas vias
+
+    [fifo]
+    intervalo = 1.0             # distância em tempo mínima até o anterior na ordem de passagem
+
+    [pelotoes]
+    intervalo = 0.5             # distância em tempo mínima até o da frente no mesmo pelotão
+    agrupamento = 1.5           # diferença de chegada ao cruzamento para entrar no pelotão da frente
+    tamanho_maximo = 8          # veículos por pelotão
+
+    [simulacao]
+    semente = 42
+
+    [autenticacao]
+    segredo = "3f...a1"         # 64 dígitos hexadecimais, credenciais dos veículos saem dele,
+                                # não só zeros; sem ele a simulação sorteia um e o server não
+                                # começa
+*/
+
+use std::fs;
+
+use crate::autenticacao::{chave_de_hexadecimal, Chave};
+use crate::chegadas::{ParametrosChegada, TipoProcesso};
+use crate::comunicacao::{ParametrosCanal, ParametrosComunicacao};
+use crate::controlador::{
+    ParametrosAtuado, ParametrosControle, ParametrosFifo, ParametrosPelotoes, ParametrosSemaforo,
+    TipoControlador, TipoPlano,
+};
+use crate::transito::{ControleABordo, Geometria, ModoInforme, ParametrosVeiculo, Via};
+
+const KMH: f64 = 1000.0 / 3600.0; // km/h para m/s
+
+// Valor de uma chave do arquivo de cenário
+enum Valor {
+    Numero(f64),
+    Inteiro(i64),
+    Texto(String),
+    Lista(Vec<Valor>),
+}
+
+impl Valor {
+    // Converte um valor lido pelo TOML, só números, textos e listas deles
+    fn de_toml(valor: &toml::Value) -> Result<Self, String> {
+        match valor {
+            toml::Value::Float(x) if x.is_finite() => Ok(Valor::Numero(*x)),
+            toml::Value::Integer(i) => Ok(Valor::Inteiro(*i)),
+            toml::Value::String(t) => Ok(Valor::Texto(t.clone())),
+            toml::Value::Array(lista) => lista
+                .iter()
+                .map(Valor::de_toml)
+                .collect::<Result<_, _>>()
+                .map(Valor::Lista),
+            _ => Err("valor deve ser número finito, texto ou lista".to_string()),
+        }
+    }
+
+    fn numero(&self, chave: &str) -> Result<f64, String> {
+        match self {
+            Valor::Numero(x) => Ok(*x),
+            Valor::Inteiro(i) => Ok(*i as f64),
+            _ => Err(format!("'{}' deve ser um número", chave)),
+        }
+    }
+
+    // Número multiplicado por 'fator' para as unidades do programa, sem estourar
+    fn convertido(&self, chave: &str, fator: f64) -> Result<f64, String> {
+        let x = fator * self.numero(chave)?;
+        if x.is_finite() {
+            Ok(x)
+        } else {
+            Err(format!("'{}' grande demais", chave))
+        }
+    }
+
+    fn inteiro(&self, chave: &str) -> Result<u64, String> {
+        match self {
+            Valor::Inteiro(i) if *i >= 0 => Ok(*i as u64),
+            _ => Err(format!("'{}' deve ser um inteiro não negativo", chave)),
+        }
+    }
+
+    fn texto(&self, chave: &str) -> Result<&str, String> {
+        match self {
+            Valor::Texto(t) => Ok(t),
+            _ => Err(format!("'{}' deve ser um texto entre aspas", chave)),
+        }
+    }
+
+    fn lista(&self, chave: &str) -> Result<&[Valor], String> {
+        match self {
+            Valor::Lista(lista) => Ok(lista),
+            _ => Err(format!("'{}' deve ser uma lista entre colchetes", chave)),
+        }
+    }
+
+    fn numeros(&self, chave: &str) -> Result<Vec<f64>, String> {
+        self.lista(chave)?
+            .iter()
+            .map(|v| v.numero(chave))
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("'{}' deve ser uma lista de números", chave))
+    }
+}
+
+// Atribui uma chave das seções de chegadas
+fn atribui_chegada(
+    chegadas: &mut ParametrosChegada,
+    chave: &str,
+    valor: &Valor,
+) -> Result<(), String> {
+    match chave {
+        "processo" => {
+            let nome = valor.texto(chave)?;
+            chegadas.processo = TipoProcesso::de_nome(nome)
+                .ok_or(format!("processo de chegada desconhecido: {}", nome))?;
+        }
+        "min_entre_chegadas" => chegadas.tec_min = valor.convertido(chave, 1000.0)?,
+        "max_entre_chegadas" => chegadas.tec_max = valor.convertido(chave, 1000.0)?,
+        "taxa" => chegadas.taxa = valor.numero(chave)?,
+        "intervalo_minimo" => chegadas.intervalo_minimo = valor.convertido(chave, 1000.0)?,
+        "perfil" => {
+            chegadas.perfil = valor
+                .lista(chave)?
+                .iter()
+                .map(|par| match par.numeros(chave)?.as_slice() {
+                    [t, taxa] => Ok((1000.0 * t, *taxa)),
+                    _ => Err("perfil deve ter pares [segundos, veículos/h]".to_string()),
+                })
+                .collect::<Result<_, String>>()?
+        }
+        "arquivo" => chegadas.arquivo = Some(valor.texto(chave)?.to_string()),
+        _ => return Err(format!("chave desconhecida '{}'", chave)),
+    }
+    Ok(())
+}
+
+// Atribui uma chave das seções de comunicação
+fn atribui_canal(canal: &mut ParametrosCanal, chave: &str, valor: &Valor) -> Result<(), String> {
+    match chave {
+        "latencia" => canal.latencia = valor.convertido(chave, 1000.0)?,
+        "jitter" => canal.jitter = valor.convertido(chave, 1000.0)?,
+        "perda" => canal.perda = valor.numero(chave)?,
+        "duplicacao" => canal.duplicacao = valor.numero(chave)?,
+        "reordenacao" => canal.reordenacao = valor.numero(chave)?,
+        "capacidade" => canal.capacidade = valor.numero(chave)?,
+        "mensagens_por_segundo" => canal.mensagens_por_segundo = valor.numero(chave)?,
+        "slot" => canal.slot = valor.convertido(chave, 1000.0)?,
+        "fila_maxima" => canal.fila_maxima = valor.convertido(chave, 1000.0)?,
+        _ => return Err(format!("chave desconhecida '{}'", chave)),
+    }
+    Ok(())
+}
+
+// Tudo que pode ser configurado em uma simulação
+#[derive(Debug, Clone, Default)]
+pub struct Cenario {
+    pub geometria: Geometria,
+    pub veiculo: ParametrosVeiculo,
+    pub chegadas_h: ParametrosChegada,
+    pub chegadas_v: ParametrosChegada,
+    pub comunicacao: ParametrosComunicacao,
+    pub controle: ParametrosControle,
+    pub semaforo: ParametrosSemaforo,
+    pub atuado: ParametrosAtuado,
+    pub fifo: ParametrosFifo,
+    pub pelotoes: ParametrosPelotoes,
+    pub semente: Option<u64>,   // semente informada no cenário, se houver
+    pub segredo: Option<Chave>, // do cruzamento, combinado com os veículos fora da rede
+}
+
+impl Cenario {
+    // Lê e valida um arquivo de cenário
+    pub fn carrega(caminho: &str) -> Result<Self, String> {
+        let texto = fs::read_to_string(caminho)
+            .map_err(|e| format!("Não foi possível ler o cenário {}: {}", caminho, e))?;
+        Cenario::interpreta(&texto).map_err(|e| format!("Cenário {}: {}", caminho, e))
+    }
+
+    // Interpreta e valida o conteúdo de um arquivo de cenário
+    pub fn interpreta(texto: &str) -> Result<Self, String> {
+        let tabela = texto.parse::<toml::Table>().map_err(|e| e.to_string())?;
+        let mut cenario = Cenario::default();
+        cenario.atribui_tabela("", &tabela)?;
+
+        cenario.chegadas_h.carrega_trace(Via::ViaH)?;
+        cenario.chegadas_v.carrega_trace(Via::ViaV)?;
+
+        cenario.valida()?;
+        Ok(cenario)
+    }
+
+    // Atribui as chaves de uma seção e depois as das subseções, que sobrepõem a seção
+    fn atribui_tabela(&mut self, secao: &str, tabela: &toml::Table) -> Result<(), String> {
+        for (chave, valor) in tabela.iter().filter(|(_, v)| !v.is_table()) {
+            Valor::de_toml(valor)
+                .and_then(|v| self.atribui(secao, chave, v))
+                .map_err(|e| match secao {
+                    "" => e,
+                    _ => format!("[{}] {}", secao, e),
+                })?;
+        }
+        for (nome, valor) in tabela {
+            if let toml::Value::Table(subtabela) = valor {
+                let subsecao = match secao {
+                    "" => nome.clone(),
+                    _ => format!("{}.{}", secao, nome),
+                };
+                self.atribui_tabela(&subsecao, subtabela)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Atribui o valor de uma chave
+    fn atribui(&mut self, secao: &str, chave: &str, valor: Valor) -> Result<(), String> {
+        match (secao, chave) {
+            ("geometria", "viah_margem") => self.geometria.viah_margem = valor.numero(chave)?,
+            ("geometria", "viav_margem") => self.geometria.viav_margem = valor.numero(chave)?,
+            ("geometria", "viah_largura") => self.geometria.viah_largura = valor.numero(chave)?,
+            ("geometria", "viav_largura") => self.geometria.viav_largura = valor.numero(chave)?,
+            ("geometria", "viah_perimetro") => {
+                self.geometria.viah_perimetro = valor.numero(chave)?
+            }
+            ("geometria", "viav_perimetro") => {
+                self.geometria.viav_perimetro = valor.numero(chave)?
+            }
+
+            ("veiculo", "comprimento") => self.veiculo.comprimento = valor.numero(chave)?,
+            ("veiculo", "velocidade_cruzeiro") => {
+                self.veiculo.velocidade_cruzeiro = valor.convertido(chave, KMH)?
+            }
+            ("veiculo", "velocidade_maxima") => {
+                self.veiculo.velocidade_maxima = valor.convertido(chave, KMH)?
+            }
+            ("veiculo", "aceleracao_maxima") => {
+                self.veiculo.aceleracao_maxima = valor.numero(chave)?
+            }
+            ("veiculo", "aceleracao_minima") => {
+                self.veiculo.aceleracao_minima = valor.numero(chave)?
+            }
+            ("veiculo", "controle_a_bordo") => {
+                let nome = valor.texto(chave)?;
+                self.veiculo.controle_a_bordo = ControleABordo::de_nome(nome)
+                    .ok_or(format!("controle a bordo desconhecido: {}", nome))?;
+            }
+            ("veiculo", "periodo_v2v") => {
+                self.veiculo.periodo_v2v = valor.convertido(chave, 1000.0)?
+            }
+            ("veiculo", "distancia_minima") => {
+                self.veiculo.distancia_minima = valor.numero(chave)?
+            }
+            ("veiculo", "tempo_falha_segura") => {
+                self.veiculo.tempo_falha_segura = valor.convertido(chave, 1000.0)?
+            }
+            ("veiculo", "informe") => {
+                let nome = valor.texto(chave)?;
+                self.veiculo.informe = ModoInforme::de_nome(nome)
+                    .ok_or(format!("modo de informe desconhecido: {}", nome))?;
+            }
+            ("veiculo", "periodo_informe") => {
+                self.veiculo.periodo_informe = valor.convertido(chave, 1000.0)?
+            }
+            ("veiculo", "limiar_aceleracao") => {
+                self.veiculo.limiar_aceleracao = valor.numero(chave)?
+            }
+            ("veiculo", "limiar_velocidade") => {
+                self.veiculo.limiar_velocidade = valor.convertido(chave, KMH)?
+            }
+
+            ("chegadas", _) => {
+                atribui_chegada(&mut self.chegadas_h, chave, &valor)?;
+                atribui_chegada(&mut self.chegadas_v, chave, &valor)?;
+            }
+            ("chegadas.h", _) => atribui_chegada(&mut self.chegadas_h, chave, &valor)?,
+            ("chegadas.v", _) => atribui_chegada(&mut self.chegadas_v, chave, &valor)?,
+
+            ("comunicacao", _) => {
+                for canal in self.comunicacao.canais() {
+                    atribui_canal(canal, chave, &valor)?;
+                }
+            }
+            ("comunicacao.veiculo", _) => {
+                atribui_canal(&mut self.comunicacao.de_veiculo, chave, &valor)?
+            }
+            ("comunicacao.controlador", _) => {
+                atribui_canal(&mut self.comunicacao.do_controlador, chave, &valor)?
+            }
+            ("comunicacao.v2v", _) => {
+                atribui_canal(&mut self.comunicacao.entre_veiculos, chave, &valor)?
+            }
+
+            ("controle", "controlador") => {
+                let nome = valor.texto(chave)?;
+                self.controle.tipo = TipoControlador::de_nome(nome)
+                    .ok_or(format!("controlador desconhecido: {}", nome))?;
+            }
+            ("controle", "tempo_entre_controles") => {
+                self.controle.tempo_entre_controles = valor.convertido(chave, 1000.0)?
+            }
+
+            ("semaforo", "tempo_verde") => {
+                self.semaforo.tempo_verde = valor.convertido(chave, 1000.0)?
+            }
+            ("semaforo", "tempo_amarelo") => {
+                self.semaforo.tempo_amarelo = valor.convertido(chave, 1000.0)?
+            }
+            ("semaforo", "plano") => {
+                let nome = valor.texto(chave)?;
+                self.semaforo.plano = TipoPlano::de_nome(nome)
+                    .ok_or(format!("plano do semáforo desconhecido: {}", nome))?;
+            }
+            ("semaforo", "fluxo_saturacao") => {
+                self.semaforo.fluxo_saturacao = valor.numero(chave)?
+            }
+            ("semaforo", "tempo_perdido") => {
+                self.semaforo.tempo_perdido = valor.convertido(chave, 1000.0)?
+            }
+            ("semaforo", "periodo_plano") => {
+                self.semaforo.periodo_plano = valor.convertido(chave, 1000.0)?
+            }
+            ("semaforo", "janela_fluxo") => {
+                self.semaforo.janela_fluxo = valor.convertido(chave, 1000.0)?
+            }
+            ("semaforo", "ciclo_minimo") => {
+                self.semaforo.ciclo_minimo = valor.convertido(chave, 1000.0)?
+            }
+            ("semaforo", "ciclo_maximo") => {
+                self.semaforo.ciclo_maximo = valor.convertido(chave, 1000.0)?
+            }
+            ("semaforo", "verde_minimo") => {
+                self.semaforo.verde_minimo = valor.convertido(chave, 1000.0)?
+            }
+
+            ("atuado", "verde_minimo") => {
+                self.atuado.verde_minimo = valor.convertido(chave, 1000.0)?
+            }
+            ("atuado", "verde_maximo") => {
+                self.atuado.verde_maximo = valor.convertido(chave, 1000.0)?
+            }
+            ("atuado", "passagem") => self.atuado.passagem = valor.convertido(chave, 1000.0)?,
+            ("atuado", "detectores") => self.atuado.detectores = valor.numeros(chave)?,
+
+            ("fifo", "intervalo") => self.fifo.intervalo = valor.convertido(chave, 1000.0)?,
+
+            ("pelotoes", "intervalo") => {
+                self.pelotoes.intervalo = valor.convertido(chave, 1000.0)?
+            }
+            ("pelotoes", "agrupamento") => {
+                self.pelotoes.agrupamento = valor.convertido(chave, 1000.0)?
+            }
+            ("pelotoes", "tamanho_maximo") => {
+                self.pelotoes.tamanho_maximo = valor.inteiro(chave)? as usize
+            }
+
+            ("simulacao", "semente") => self.semente = Some(valor.inteiro(chave)?),
+
+            ("autenticacao", "segredo") => {
+                let segredo = chave_de_hexadecimal(valor.texto(chave)?)
+                    .ok_or(format!("'{}' deve ter 64 dígitos hexadecimais", chave))?;
+                if segredo == [0; 32] {
+                    return Err(format!("'{}' não pode ser só zeros", chave));
+                }
+                self.segredo = Some(segredo);
+            }
+
+            ("", _) => return Err(format!("chave '{}' fora de uma seção", chave)),
+            _ => return Err(format!("chave desconhecida '{}'", chave)),
+        }
+        Ok(())
+    }
+
+    // Descrição curta do controlador, das chegadas e da comunicação, para mensagens
+    pub fn descricao(&self) -> String {
+        let mut descricao = format!(
+            "controlador {}, chegadas H {}, chegadas V {}",
+            self.controle.tipo.nome(),
+            self.chegadas_h.descricao(),
+            self.chegadas_v.descricao()
+        );
+        if self.veiculo.controle_a_bordo != ControleABordo::Cacc {
+            descricao.push_str(&format!(
+                ", controle a bordo {}",
+                self.veiculo.controle_a_bordo.nome()
+            ));
+        }
+        if self.controle.tipo == TipoControlador::Semaforo && self.semaforo.plano != TipoPlano::Fixo
+        {
+            descricao.push_str(&format!(", plano {}", self.semaforo.plano.nome()));
+        }
+        if self.veiculo.informe != ModoInforme::Pedido {
+            descricao.push_str(&format!(
+                ", informe {} a cada {} ms",
+                self.veiculo.informe.nome(),
+                self.veiculo.periodo_informe
+            ));
+        }
+        let c = &self.comunicacao;
+        if !c.de_veiculo.ideal() || !c.do_controlador.ideal() || !c.entre_veiculos.ideal() {
+            descricao.push_str(&format!(
+                ", comunicação veículo -> controlador {}, controlador -> veículo {}, entre veículos {}",
+                c.de_veiculo.descricao(),
+                c.do_controlador.descricao(),
+                c.entre_veiculos.descricao()
+            ));
+        }
+        descricao
+    }
+
+    // Confere se os valores fazem sentido
+    pub fn valida(&self) -> Result<(), String> {
+        let g = &self.geometria;
+        if g.viah_margem < 0.0 || g.viav_margem < 0.0 {
+            return Err("margens não podem ser negativas".to_string());
+        }
+        if g.viah_largura <= 0.0 || g.viav_largura <= 0.0 {
+            return Err("larguras das vias devem ser positivas".to_string());
+        }
+        if g.viah_perimetro < 20.0 || g.viav_perimetro < 20.0 {
+            return Err("perímetros devem ter no mínimo 20 metros".to_string());
+        }
+
+        let v = &self.veiculo;
+        if v.comprimento <= 0.0 {
+            return Err("comprimento do veículo deve ser positivo".to_string());
+        }
+        if v.velocidade_cruzeiro <= 0.0 {
+            return Err("velocidade de cruzeiro deve ser positiva".to_string());
+        }
+        if v.velocidade_maxima < v.velocidade_cruzeiro {
+            return Err("velocidade máxima não pode ser menor que a de cruzeiro".to_string());
+        }
+        if v.aceleracao_maxima <= 0.0 {
+            return Err("aceleração máxima deve ser positiva".to_string());
+        }
+        if v.aceleracao_minima >= 0.0 {
+            return Err("aceleração mínima deve ser negativa".to_string());
+        }
+        if v.periodo_v2v < 0.0 {
+            return Err("período das difusões entre veículos não pode ser negativo".to_string());
+        }
+        if v.distancia_minima < 0.0 {
+            return Err("distância mínima não pode ser negativa".to_string());
+        }
+        if v.tempo_falha_segura < 0.0 {
+            return Err("tempo até a falha segura não pode ser negativo".to_string());
+        }
+        if v.periodo_informe <= 0.0 {
+            return Err("período dos informes espontâneos deve ser positivo".to_string());
+        }
+        if v.limiar_aceleracao < 0.0 || v.limiar_velocidade < 0.0 {
+            return Err("limiares dos informes espontâneos não podem ser negativos".to_string());
+        }
+
+        self.chegadas_h.valida("chegadas da via H")?;
+        self.chegadas_v.valida("chegadas da via V")?;
+        self.comunicacao.valida()?;
+
+        if self.controle.tempo_entre_controles <= 0.0 {
+            return Err("tempo entre controles deve ser positivo".to_string());
+        }
+        // Com menos que isto, o veículo desiste do controlador que está funcionando
+        let sem_controlador = self.controle.tempo_entre_controles + self.comunicacao.ida_e_volta();
+        if v.tempo_falha_segura > 0.0 && v.tempo_falha_segura <= sem_controlador {
+            return Err(format!(
+                "tempo até a falha segura deve passar de {} s, um ciclo de controle mais a ida e volta da comunicação",
+                sem_controlador / 1000.0
+            ));
+        }
+
+        let s = &self.semaforo;
+        if s.tempo_verde <= 0.0 || s.tempo_amarelo <= 0.0 {
+            return Err("tempos de verde e amarelo devem ser positivos".to_string());
+        }
+        if s.fluxo_saturacao <= 0.0 || s.periodo_plano <= 0.0 || s.janela_fluxo <= 0.0 {
+            return Err(
+                "fluxo de saturação, período do plano e janela do fluxo devem ser positivos"
+                    .to_string(),
+            );
+        }
+        if s.tempo_perdido < 0.0 || s.verde_minimo <= 0.0 {
+            return Err(
+                "tempo perdido não pode ser negativo e verde mínimo deve ser positivo".to_string(),
+            );
+        }
+        // O ciclo mínimo precisa caber os dois verdes mínimos e os dois amarelos
+        if s.plano == TipoPlano::Webster
+            && s.ciclo_minimo < 2.0 * (s.verde_minimo + s.tempo_amarelo)
+            || s.ciclo_maximo < s.ciclo_minimo
+        {
+            return Err(format!(
+                "ciclo do semáforo deve ir de pelo menos {} s, dois verdes mínimos e dois amarelos, até o ciclo máximo",
+                2.0 * (s.verde_minimo + s.tempo_amarelo) / 1000.0
+            ));
+        }
+        let a = &self.atuado;
+        if a.verde_minimo <= 0.0 || a.passagem <= 0.0 {
+            return Err("verde mínimo e passagem do atuado devem ser positivos".to_string());
+        }
+        if a.verde_maximo < a.verde_minimo {
+            return Err("verde máximo do atuado não pode ser menor que o mínimo".to_string());
+        }
+        let perimetro = g.viah_perimetro.min(g.viav_perimetro);
+        if a.detectores.is_empty() || a.detectores.iter().any(|d| *d <= 0.0 || *d >= perimetro) {
+            return Err(format!(
+                "detectores do atuado devem ficar entre o cruzamento e {} m antes dele",
+                perimetro
+            ));
+        }
+        if self.fifo.intervalo < 0.0 {
+            return Err("intervalo do fifo não pode ser negativo".to_string());
+        }
+        let p = &self.pelotoes;
+        if p.intervalo < 0.0 || p.agrupamento < 0.0 {
+            return Err("intervalo e agrupamento dos pelotões não podem ser negativos".to_string());
+        }
+        if p.tamanho_maximo == 0 {
+            return Err("pelotões devem ter pelo menos um veículo".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segredo_so_de_zeros_recusado() {
+        let zeros = format!("[autenticacao]\nsegredo = \"{}\"\n", "0".repeat(64));
+        let erro = Cenario::interpreta(&zeros).unwrap_err();
+        assert!(erro.contains("só zeros"), "{}", erro);
+
+        let segredo = format!("[autenticacao]\nsegredo = \"{}\"\n", "3a".repeat(32));
+        let cenario = Cenario::interpreta(&segredo).unwrap();
+        assert_eq!(cenario.segredo, Some([0x3a; 32]));
+        assert_eq!(Cenario::default().segredo, None);
+    }
+
+    #[test]
+    fn cenario_padrao_interpretado() {
+        let cenario = Cenario::interpreta(include_str!("../cenarios/padrao.toml")).unwrap();
+        assert_eq!(cenario.descricao(), Cenario::default().descricao());
+    }
+
+    #[test]
+    fn chaves_e_secoes_desconhecidas_recusadas() {
+        let erro = Cenario::interpreta("[veiculo]\ncomprimeto = 4.0\n").unwrap_err();
+        assert!(erro.contains("comprimeto"), "{}", erro);
+        let erro = Cenario::interpreta("[veiculos]\ncomprimento = 4.0\n").unwrap_err();
+        assert!(erro.contains("veiculos"), "{}", erro);
+        let erro = Cenario::interpreta("[comunicacao.radio]\nlatencia = 0.1\n").unwrap_err();
+        assert!(erro.contains("comunicacao.radio"), "{}", erro);
+    }
+
+    #[test]
+    fn tipos_errados_recusados() {
+        for texto in [
+            "[veiculo]\ncomprimento = \"4\"\n",
+            "[pelotoes]\ntamanho_maximo = 2.5\n",
+            "[controle]\ntipo = 3\n",
+            "[atuado]\ndetectores = 30.0\n",
+            "[geometria]\nviah_largura = true\n",
+        ] {
+            assert!(Cenario::interpreta(texto).is_err(), "{}", texto);
+        }
+    }
+
+    // Números finitos no arquivo que estouram ao passar de segundos para ms
+    #[test]
+    fn valores_que_estouram_recusados() {
+        for texto in [
+            "[semaforo]\ntempo_verde = 1e308\n",
+            "[pelotoes]\nagrupamento = 1e308\n",
+            "[veiculo]\ntempo_falha_segura = 1e308\n",
+            "[controle]\ntempo_entre_controles = 1e308\n",
+            "[chegadas]\nmax_entre_chegadas = 1e308\n",
+        ] {
+            let erro = Cenario::interpreta(texto).unwrap_err();
+            assert!(erro.contains("grande demais"), "{}: {}", texto, erro);
+        }
+    }
+}
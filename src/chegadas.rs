@@ -0,0 +1,415 @@
+/* Processos de chegada de veículos
+
+    Cada via tem o seu próprio processo, independente do outro:
+        uniforme    intervalo sorteado uniformemente entre mínimo e máximo
+        poisson     intervalos exponenciais, com taxa média em veículos por hora
+        perfil      como poisson, mas a taxa varia no tempo (ex: hora do rush)
+        trace       instantes de chegada lidos de um arquivo CSV
+*/
+
+use std::fs;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use crate::transito::Via;
+
+const MS_POR_HORA: f64 = 3_600_000.0;
+
+// Usado para definir o tipo de processo de chegada
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TipoProcesso {
+    Uniforme,
+    Poisson,
+    Perfil,
+    Trace,
+}
+
+impl TipoProcesso {
+    // Interpreta o nome do processo
+    pub fn de_nome(nome: &str) -> Option<Self> {
+        match nome {
+            "uniforme" => Some(TipoProcesso::Uniforme),
+            "poisson" => Some(TipoProcesso::Poisson),
+            "perfil" => Some(TipoProcesso::Perfil),
+            "trace" => Some(TipoProcesso::Trace),
+            _ => None,
+        }
+    }
+}
+
+// Parâmetros do processo de chegada de uma via
+#[derive(Debug, Clone)]
+pub struct ParametrosChegada {
+    pub processo: TipoProcesso,
+    pub tec_min: f64,            // uniforme, ms
+    pub tec_max: f64,            // uniforme, ms
+    pub taxa: f64,               // poisson, veículos por hora
+    pub intervalo_minimo: f64,   // poisson e perfil, ms
+    pub perfil: Vec<(f64, f64)>, // perfil, pares (instante em ms, veículos por hora)
+    pub arquivo: Option<String>, // trace, arquivo CSV com 'tempo[,via]' por linha
+    pub trace: Vec<f64>,         // trace, instantes de chegada lidos do arquivo, ms
+}
+
+impl Default for ParametrosChegada {
+    fn default() -> Self {
+        Self {
+            processo: TipoProcesso::Uniforme,
+            tec_min: 3000.0,
+            tec_max: 6000.0,
+            taxa: 1200.0,
+            intervalo_minimo: 2000.0,
+            perfil: Vec::new(),
+            arquivo: None,
+            trace: Vec::new(),
+        }
+    }
+}
+
+impl ParametrosChegada {
+    // Lê os instantes de chegada do arquivo de trace, apenas os desta via
+    // Cada linha tem 'tempo' em segundos e, opcionalmente, ',H' ou ',V'
+    pub fn carrega_trace(&mut self, via: Via) -> Result<(), String> {
+        let caminho = match &self.arquivo {
+            None => return Ok(()),
+            Some(c) => c,
+        };
+        let texto = fs::read_to_string(caminho)
+            .map_err(|e| format!("Não foi possível ler o trace {}: {}", caminho, e))?;
+
+        self.trace.clear();
+        for (i, linha) in texto.lines().enumerate() {
+            let linha = linha.trim();
+            if linha.is_empty() || linha.starts_with('#') {
+                continue;
+            }
+
+            let mut colunas = linha.split(',').map(|c| c.trim());
+            let tempo = colunas.next().unwrap_or("");
+            // Instantes em segundos, finitos e não negativos também em ms
+            let tempo = match tempo.parse::<f64>() {
+                Ok(t) if t >= 0.0 && (1000.0 * t).is_finite() => t,
+                Ok(_) => {
+                    return Err(format!(
+                        "trace {} linha {}: tempo inválido {}",
+                        caminho,
+                        i + 1,
+                        tempo
+                    ))
+                }
+                // Admite uma linha de cabeçalho
+                Err(_) if i == 0 => continue,
+                Err(_) => {
+                    return Err(format!(
+                        "trace {} linha {}: tempo inválido {}",
+                        caminho,
+                        i + 1,
+                        tempo
+                    ))
+                }
+            };
+
+            let via_linha = match colunas.next() {
+                None => None,
+                Some("H") | Some("h") => Some(Via::ViaH),
+                Some("V") | Some("v") => Some(Via::ViaV),
+                Some(outra) => {
+                    return Err(format!(
+                        "trace {} linha {}: via inválida {}",
+                        caminho,
+                        i + 1,
+                        outra
+                    ))
+                }
+            };
+
+            if via_linha.is_none() || via_linha == Some(via) {
+                self.trace.push(1000.0 * tempo);
+            }
+        }
+
+        self.trace.sort_unstable_by(|a, b| a.total_cmp(b));
+        Ok(())
+    }
+
+    // Descrição curta do processo, para mensagens
+    pub fn descricao(&self) -> String {
+        match self.processo {
+            TipoProcesso::Uniforme => {
+                format!("uniforme entre {} e {} ms", self.tec_min, self.tec_max)
+            }
+            TipoProcesso::Poisson => format!("poisson {} veículos/h", self.taxa),
+            TipoProcesso::Perfil => format!("perfil com {} pontos", self.perfil.len()),
+            TipoProcesso::Trace => format!(
+                "trace {} com {} chegadas",
+                self.arquivo.as_deref().unwrap_or(""),
+                self.trace.len()
+            ),
+        }
+    }
+
+    // Confere se os valores fazem sentido, 'nome' identifica a via nas mensagens
+    pub fn valida(&self, nome: &str) -> Result<(), String> {
+        let valores = [self.tec_min, self.tec_max, self.taxa, self.intervalo_minimo];
+        let perfil = self.perfil.iter().flat_map(|&(t, taxa)| [t, taxa]);
+        if !valores.into_iter().chain(perfil).all(f64::is_finite) {
+            return Err(format!("{}: valores das chegadas devem ser finitos", nome));
+        }
+        match self.processo {
+            TipoProcesso::Uniforme => {
+                if self.tec_min < 2000.0 || self.tec_max < 2000.0 {
+                    return Err(format!(
+                        "{}: tempo entre chegadas deve ser no mínimo 2 segundos",
+                        nome
+                    ));
+                }
+                if self.tec_min > self.tec_max {
+                    return Err(format!(
+                        "{}: tempo entre chegadas mínimo não pode ser maior que o máximo",
+                        nome
+                    ));
+                }
+            }
+            TipoProcesso::Poisson => {
+                if self.taxa <= 0.0 || !(MS_POR_HORA / self.taxa).is_finite() {
+                    return Err(format!("{}: taxa de chegada deve ser positiva", nome));
+                }
+                if self.intervalo_minimo < 0.0 || self.intervalo_minimo >= MS_POR_HORA / self.taxa {
+                    return Err(format!(
+                        "{}: intervalo mínimo deve ser menor que o intervalo médio",
+                        nome
+                    ));
+                }
+            }
+            TipoProcesso::Perfil => {
+                if self.perfil.is_empty() {
+                    return Err(format!("{}: processo 'perfil' exige a chave perfil", nome));
+                }
+                for i in 0..self.perfil.len() {
+                    if self.perfil[i].1 < 0.0 {
+                        return Err(format!("{}: taxas do perfil não podem ser negativas", nome));
+                    }
+                    if i > 0 && self.perfil[i].0 <= self.perfil[i - 1].0 {
+                        return Err(format!(
+                            "{}: instantes do perfil devem ser crescentes",
+                            nome
+                        ));
+                    }
+                }
+                if self.intervalo_minimo < 0.0 {
+                    return Err(format!("{}: intervalo mínimo não pode ser negativo", nome));
+                }
+            }
+            TipoProcesso::Trace => {
+                if self.arquivo.is_none() {
+                    return Err(format!("{}: processo 'trace' exige a chave arquivo", nome));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Descreve funções exigidas de um processo de chegada
+pub trait ProcessoChegada {
+    // Instante da próxima chegada depois de 'agora', em ms, ou None se não há mais chegadas
+    fn proxima_chegada(&mut self, agora: f64, rng: &mut StdRng) -> Option<f64>;
+}
+
+// Intervalo sorteado uniformemente, a primeira chegada é imediata
+pub struct Uniforme {
+    tec_min: f64,
+    tec_max: f64,
+    primeira: bool,
+}
+
+impl ProcessoChegada for Uniforme {
+    fn proxima_chegada(&mut self, agora: f64, rng: &mut StdRng) -> Option<f64> {
+        if self.primeira {
+            self.primeira = false;
+            return Some(agora);
+        }
+        Some(agora + rng.gen_range(self.tec_min..=self.tec_max))
+    }
+}
+
+// Intervalos exponenciais deslocados pelo intervalo mínimo, média 1/taxa
+pub struct Poisson {
+    media_exponencial: f64, // ms
+    intervalo_minimo: f64,  // ms
+}
+
+// Sorteia um intervalo exponencial com a média dada
+fn exponencial(media: f64, rng: &mut StdRng) -> f64 {
+    let u: f64 = rng.gen_range(f64::EPSILON..1.0);
+    -media * u.ln()
+}
+
+impl ProcessoChegada for Poisson {
+    fn proxima_chegada(&mut self, agora: f64, rng: &mut StdRng) -> Option<f64> {
+        Some(agora + self.intervalo_minimo + exponencial(self.media_exponencial, rng))
+    }
+}
+
+// Poisson não homogêneo, taxa interpolada linearmente entre os pontos do perfil
+pub struct Perfil {
+    perfil: Vec<(f64, f64)>, // (instante em ms, veículos por hora)
+    taxa_max: f64,           // veículos por hora
+    intervalo_minimo: f64,   // ms
+}
+
+impl Perfil {
+    // Taxa no instante 't', mantida constante antes do primeiro e depois do último ponto
+    fn taxa(&self, t: f64) -> f64 {
+        let primeiro = self.perfil[0];
+        let ultimo = self.perfil[self.perfil.len() - 1];
+        if t <= primeiro.0 {
+            return primeiro.1;
+        }
+        if t >= ultimo.0 {
+            return ultimo.1;
+        }
+        for par in self.perfil.windows(2) {
+            let (t0, r0) = par[0];
+            let (t1, r1) = par[1];
+            if t <= t1 {
+                return r0 + (r1 - r0) * (t - t0) / (t1 - t0);
+            }
+        }
+        ultimo.1
+    }
+}
+
+impl ProcessoChegada for Perfil {
+    fn proxima_chegada(&mut self, agora: f64, rng: &mut StdRng) -> Option<f64> {
+        if self.taxa_max <= 0.0 {
+            return None;
+        }
+
+        // Método da rejeição (thinning): candidatos com a taxa máxima,
+        // aceitos com probabilidade taxa(t) / taxa_max
+        let mut t = agora + self.intervalo_minimo;
+        loop {
+            t += exponencial(MS_POR_HORA / self.taxa_max, rng);
+            if rng.gen::<f64>() * self.taxa_max <= self.taxa(t) {
+                return Some(t);
+            }
+            // Taxa zero daqui para frente, não chega mais ninguém
+            if t >= self.perfil[self.perfil.len() - 1].0 && self.taxa(t) <= 0.0 {
+                return None;
+            }
+        }
+    }
+}
+
+// Instantes de chegada lidos de um arquivo
+pub struct Trace {
+    instantes: Vec<f64>, // ms, em ordem
+    proximo: usize,
+}
+
+impl ProcessoChegada for Trace {
+    fn proxima_chegada(&mut self, agora: f64, _rng: &mut StdRng) -> Option<f64> {
+        // Chegadas que ficaram para trás acontecem agora
+        let t = *self.instantes.get(self.proximo)?;
+        self.proximo += 1;
+        Some(t.max(agora))
+    }
+}
+
+// Processo de chegada de uma via, do tipo definido no cenário
+pub enum MeuProcesso {
+    Uniforme(Uniforme),
+    Poisson(Poisson),
+    Perfil(Perfil),
+    Trace(Trace),
+}
+
+impl MeuProcesso {
+    // Cria o processo descrito pelos parâmetros
+    pub fn new(parametros: &ParametrosChegada) -> Self {
+        match parametros.processo {
+            TipoProcesso::Uniforme => MeuProcesso::Uniforme(Uniforme {
+                tec_min: parametros.tec_min,
+                tec_max: parametros.tec_max,
+                primeira: true,
+            }),
+            TipoProcesso::Poisson => MeuProcesso::Poisson(Poisson {
+                media_exponencial: MS_POR_HORA / parametros.taxa - parametros.intervalo_minimo,
+                intervalo_minimo: parametros.intervalo_minimo,
+            }),
+            TipoProcesso::Perfil => MeuProcesso::Perfil(Perfil {
+                perfil: parametros.perfil.clone(),
+                taxa_max: parametros.perfil.iter().fold(0.0, |m, p| p.1.max(m)),
+                intervalo_minimo: parametros.intervalo_minimo,
+            }),
+            TipoProcesso::Trace => MeuProcesso::Trace(Trace {
+                instantes: parametros.trace.clone(),
+                proximo: 0,
+            }),
+        }
+    }
+
+    // Instante da próxima chegada depois de 'agora', em ms
+    pub fn proxima_chegada(&mut self, agora: f64, rng: &mut StdRng) -> Option<f64> {
+        match self {
+            MeuProcesso::Uniforme(p) => p.proxima_chegada(agora, rng),
+            MeuProcesso::Poisson(p) => p.proxima_chegada(agora, rng),
+            MeuProcesso::Perfil(p) => p.proxima_chegada(agora, rng),
+            MeuProcesso::Trace(p) => p.proxima_chegada(agora, rng),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valores_nao_finitos_recusados() {
+        for (tec_min, tec_max) in [(f64::NAN, 6000.0), (3000.0, f64::INFINITY)] {
+            let parametros = ParametrosChegada {
+                tec_min,
+                tec_max,
+                ..Default::default()
+            };
+            assert!(parametros.valida("H").is_err());
+        }
+        let parametros = ParametrosChegada {
+            processo: TipoProcesso::Perfil,
+            perfil: vec![(0.0, 600.0), (f64::NAN, 1200.0)],
+            ..Default::default()
+        };
+        assert!(parametros.valida("H").is_err());
+        assert!(ParametrosChegada::default().valida("H").is_ok());
+    }
+
+    #[test]
+    fn trace_recusa_tempo_invalido_com_a_linha() {
+        let caminho = std::env::temp_dir().join("cruzamento_trace_invalido.csv");
+        for (tempo, valido) in [
+            ("nan", false),
+            ("-1", false),
+            ("1e308", false),
+            ("2.5", true),
+        ] {
+            fs::write(&caminho, format!("tempo,via\n1,H\n{},H\n", tempo)).unwrap();
+            let mut parametros = ParametrosChegada {
+                processo: TipoProcesso::Trace,
+                arquivo: Some(caminho.to_string_lossy().into_owned()),
+                ..Default::default()
+            };
+            match parametros.carrega_trace(Via::ViaH) {
+                Ok(()) => {
+                    assert!(valido, "{}", tempo);
+                    assert_eq!(parametros.trace, vec![1000.0, 2500.0]);
+                }
+                Err(erro) => {
+                    assert!(!valido, "{}", tempo);
+                    assert!(erro.contains("linha 3"), "{}", erro);
+                }
+            }
+        }
+        fs::remove_file(&caminho).unwrap();
+    }
+}
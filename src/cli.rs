@@ -0,0 +1,648 @@
+/* Linha de comando
+
+    cruzamento_automatico <subcomando> [opções]
+
+    Subcomandos:
+        run                 simula com janela gráfica
+        headless            simula sem janela, o mais rápido possível, e mostra o resumo
+        list-controllers    lista os controladores disponíveis
+        replay              reproduz na janela gráfica uma simulação gravada
+        trace               mostra as mensagens de um rastro gravado com --rastro
+        batch               simula sem janela uma grade de parâmetros, várias sementes por ponto
+        server              executa só o controlador, para simulações em outros processos
+*/
+
+use crate::cenario::Cenario;
+use crate::chegadas::TipoProcesso;
+use crate::controlador::{TipoControlador, TipoPlano};
+use crate::lote::{Grade, ParametrosLote};
+use crate::rastro::{Formato, ParametrosRastro};
+use crate::rede::ENDERECO_PADRAO;
+use crate::registro::{ConfiguracaoLog, VARIAVEL_AMBIENTE};
+use crate::servidor::ParametrosServidor;
+use crate::simulacao::{sorteia_semente, LimitesSimulacao};
+use crate::transito::{ControleABordo, ModoInforme};
+
+pub const AJUDA: &str = "\
+Uso: cruzamento_automatico <subcomando> [opções]
+
+Subcomandos:
+    run                 simula com janela gráfica
+    headless            simula sem janela, o mais rápido possível, e mostra o resumo
+    list-controllers    lista os controladores disponíveis
+    replay              reproduz na janela gráfica uma simulação gravada
+    trace               mostra as mensagens de um rastro gravado com --rastro
+    batch               simula sem janela uma grade de parâmetros, várias sementes por ponto
+    server              executa só o controlador, para simulações em outros processos
+
+Opções de simulação (run e headless):
+    --cenario <arquivo>         lê o cenário do arquivo, as demais opções têm precedência
+    --controlador <nome>        controlador usado, veja list-controllers
+    --min-chegadas <s>          tempo mínimo entre chegadas, em segundos (chegadas uniformes,
+                                padrão 3)
+    --max-chegadas <s>          tempo máximo entre chegadas, em segundos (chegadas uniformes,
+                                padrão 6)
+    --semente <n>               semente dos números aleatórios
+    --latencia <s>              atraso fixo das mensagens, em todos os canais
+    --jitter <s>                atraso adicional sorteado entre zero e este valor
+    --perda <p>                 probabilidade de uma mensagem se perder, entre 0 e 1
+    --capacidade <bytes/s>      capacidade de cada canal, o que passar espera ou é descartado
+    --a-bordo <nome>            controle a bordo atrás do veículo da frente: cacc (padrão),
+                                acc (só o sensor) ou nenhum (só o controlador)
+    --informe <nome>            quando os veículos informam a situação: pedido (padrão, o
+                                controlador pergunta) ou espontaneo (periódico e nas mudanças)
+    --plano <nome>              tempos de verde do semáforo: fixo (padrão, os do cenário) ou
+                                webster (recalculados pelo fluxo de cada via)
+    --log <config>              o que registrar, ex: aviso,controlador=depuracao (padrão info)
+                                níveis: nenhum, erro, aviso, info, depuracao, detalhe
+                                categorias: comunicacao, controlador, veiculos, transito, simulacao
+                                sem --log, usa a variável de ambiente CRUZAMENTO_LOG
+    --log-placa <placa>         registra apenas mensagens deste veículo, além de erros e avisos
+    --gravar <arquivo>          grava estados, mensagens e decisões de cada tick, para o replay
+    --rastro <arquivo>          grava cada envio e entrega de mensagem, para o trace
+    --servidor <endereço>       usa o controlador de um server, ex: 127.0.0.1:7878
+    --falha-controlador <s:s>   para o controlador entre estes instantes e o reinicia sem
+                                memória, ex: 60:75 (não vale com --servidor)
+    --atacante <s>              injeta nos veículos, a este intervalo, comandos forjados,
+                                adulterados ou repetidos, para testar a autenticação
+
+Opções de run:
+    --janela <pixels>           tamanho da janela, entre 200 e 1000 (padrão 600)
+
+Opções de headless:
+    --duracao <s>               tempo simulado máximo, em segundos (padrão 600)
+    --max-veiculos <n>          para quando este número de veículos sair do cruzamento
+    --saida <arquivo>           grava o resumo em CSV neste arquivo
+
+Opções de replay:
+    --gravacao <arquivo>        arquivo gravado com --gravar
+    --janela <pixels>           tamanho da janela, entre 200 e 1000 (padrão 600)
+    teclas: espaço pausa, '.' e ',' avançam e voltam um tick, ']' e '[' avançam e
+            voltam 1 s, '}' e '{' 10 s, '+' e '-' mudam a velocidade, 'x' termina
+
+Opções de trace:
+    --rastro <arquivo>          arquivo gravado com --rastro
+    --placa <placas>            só mensagens destes veículos, ex: AAA0001,BBB0002
+    --tipo <tipos>              só mensagens destes tipos, ex: chegada,setacel
+    --inicio <s>, --fim <s>     só mensagens entre estes instantes simulados
+    --formato <nome>            linha (padrão, uma mensagem por linha com o conteúdo)
+                                ou sequencia (diagrama de sequência)
+
+Opções de batch (listas separadas por vírgula, cada combinação é um ponto da grade):
+    --cenario <arquivo>         cenário base, valores não listados vêm dele
+    --controlador <nomes>       ex: semaforo,reservas
+    --min-chegadas <s>          ex: 2,3
+    --max-chegadas <s>          ex: 3,6
+    --tempo-verde <s>           tempos de verde do semáforo
+    --tempo-amarelo <s>         tempos de amarelo do semáforo
+    --plano <nomes>             planos do semáforo, ex: fixo,webster
+    --largura <m>               larguras das duas vias
+    --perimetro <m>             perímetros das duas vias
+    --latencia <s>              latências da comunicação, em todos os canais
+    --perda <p>                 probabilidades de perda de mensagens, em todos os canais
+    --capacidade <bytes/s>      capacidades da comunicação, em todos os canais (0 é ilimitada)
+    --a-bordo <nomes>           controles a bordo, ex: nenhum,acc,cacc
+    --informe <nomes>           modos de informe da situação, ex: pedido,espontaneo
+    --sementes <n>              simulações por ponto (padrão 10)
+    --semente <n>               primeira semente, as demais são consecutivas (padrão 1)
+    --duracao <s>, --max-veiculos <n>   como em headless
+    --threads <n>               simulações em paralelo (padrão: número de núcleos)
+    --saida <arquivo>           grava o CSV neste arquivo e mostra médias por ponto,
+                                sem esta opção o CSV vai para a saída padrão
+    --log <config>              como em run e headless (padrão nenhum)
+
+Opções de server (e as de cenário de run e headless, que devem ser as mesmas da simulação):
+    --endereco <endereço>       onde esperar as simulações (padrão 127.0.0.1:7878)
+    --atende <n>                termina depois de atender n simulações (padrão: nunca)
+
+    -h, --help                  mostra esta ajuda";
+
+const TAM_JANELA: f64 = 600.0; // pixels
+const DURACAO: f64 = 600.0; // segundos
+const SEMENTES_LOTE: u64 = 10; // simulações por ponto da grade
+
+// Opções comuns a todos os subcomandos que simulam
+pub struct OpcoesSimulacao {
+    pub cenario: Cenario,
+    pub semente: u64,
+    pub log: ConfiguracaoLog,
+    pub gravacao: Option<String>, // arquivo onde gravar a simulação
+    pub rastro: Option<String>,   // arquivo onde gravar as mensagens
+    pub servidor: Option<String>, // endereço do controlador, se estiver em outro processo
+    pub falha_controlador: Option<(f64, f64)>, // início e fim da queda do controlador, ms
+    pub atacante: Option<f64>,    // intervalo entre mensagens falsas injetadas, ms
+}
+
+// O que foi pedido na linha de comando, criado uma única vez
+#[allow(clippy::large_enum_variant)]
+pub enum Comando {
+    Executa {
+        simulacao: OpcoesSimulacao,
+        tam_janela: f64,
+    },
+    SemJanela {
+        simulacao: OpcoesSimulacao,
+        limites: LimitesSimulacao,
+        saida: Option<String>,
+    },
+    Lote {
+        lote: ParametrosLote,
+        log: ConfiguracaoLog,
+    },
+    Reproduz {
+        gravacao: String,
+        tam_janela: f64,
+    },
+    Rastro(ParametrosRastro),
+    Servidor {
+        servidor: ParametrosServidor,
+        log: ConfiguracaoLog,
+    },
+    ListaControladores,
+    Ajuda,
+}
+
+// Converte o valor de uma opção
+fn valor<T: std::str::FromStr>(opcao: &str, texto: &str) -> Result<T, String> {
+    texto
+        .trim()
+        .parse::<T>()
+        .map_err(|_| format!("valor inválido para {}: {}", opcao, texto))
+}
+
+// Converte o valor numérico de uma opção, que deve ser finito e não negativo
+fn numero(opcao: &str, texto: &str) -> Result<f64, String> {
+    match valor::<f64>(opcao, texto)? {
+        x if x.is_finite() && x >= 0.0 => Ok(x),
+        _ => Err(format!(
+            "valor inválido para {}: {}, deve ser um número finito e não negativo",
+            opcao, texto
+        )),
+    }
+}
+
+// Converte uma lista de valores numéricos separados por vírgula
+fn numeros(opcao: &str, texto: &str) -> Result<Vec<f64>, String> {
+    texto.split(',').map(|t| numero(opcao, t)).collect()
+}
+
+// Percorre as opções de um subcomando, em pares '--opcao valor'
+struct Opcoes {
+    pares: Vec<(String, String)>,
+}
+
+impl Opcoes {
+    // Separa os argumentos em pares, recusando argumentos soltos
+    fn new(args: &[String]) -> Result<Self, String> {
+        let mut pares = Vec::new();
+        let mut iter = args.iter();
+        while let Some(opcao) = iter.next() {
+            if !opcao.starts_with("--") {
+                return Err(format!("argumento inesperado: {}", opcao));
+            }
+            match iter.next() {
+                Some(v) => pares.push((opcao.clone(), v.clone())),
+                None => return Err(format!("falta o valor de {}", opcao)),
+            }
+        }
+        Ok(Self { pares })
+    }
+
+    // Retira uma opção, se presente
+    fn retira(&mut self, nome: &str) -> Option<String> {
+        let i = self.pares.iter().position(|(opcao, _)| opcao == nome)?;
+        Some(self.pares.remove(i).1)
+    }
+
+    // Confere que todas as opções foram usadas
+    fn fim(self) -> Result<(), String> {
+        match self.pares.first() {
+            None => Ok(()),
+            Some((opcao, _)) => Err(format!("opção desconhecida: {}", opcao)),
+        }
+    }
+}
+
+// Configuração do log, da opção --log, da variável de ambiente ou o padrão dado
+fn configuracao_log(opcoes: &mut Opcoes, padrao: &str) -> Result<ConfiguracaoLog, String> {
+    match opcoes.retira("--log") {
+        Some(texto) => ConfiguracaoLog::interpreta(&texto),
+        None => match std::env::var(VARIAVEL_AMBIENTE) {
+            Ok(texto) => ConfiguracaoLog::interpreta(&texto)
+                .map_err(|e| format!("{}: {}", VARIAVEL_AMBIENTE, e)),
+            Err(_) => ConfiguracaoLog::interpreta(padrao),
+        },
+    }
+}
+
+// Limites de uma simulação sem janela
+fn limites_simulacao(opcoes: &mut Opcoes) -> Result<LimitesSimulacao, String> {
+    let duracao = match opcoes.retira("--duracao") {
+        Some(texto) => numero("--duracao", &texto)?,
+        None => DURACAO,
+    };
+    if !duracao.is_finite() || duracao <= 0.0 {
+        return Err("duração da simulação deve ser um número positivo".to_string());
+    }
+    let max_veiculos = match opcoes.retira("--max-veiculos") {
+        Some(texto) => match valor::<i32>("--max-veiculos", &texto)? {
+            n if n >= 1 => Some(n),
+            _ => return Err("--max-veiculos deve ser pelo menos 1".to_string()),
+        },
+        None => None,
+    };
+    Ok(LimitesSimulacao {
+        duracao: 1000.0 * duracao,
+        max_veiculos,
+    })
+}
+
+// Monta o lote a partir das opções de batch
+fn opcoes_lote(opcoes: &mut Opcoes) -> Result<ParametrosLote, String> {
+    let cenario = match opcoes.retira("--cenario") {
+        Some(caminho) => Cenario::carrega(&caminho)?,
+        None => Cenario::default(),
+    };
+
+    // Valores em segundos na linha de comando, em ms na grade
+    let mut ms = |opcao: &str| -> Result<Vec<f64>, String> {
+        match opcoes.retira(opcao) {
+            Some(texto) => Ok(numeros(opcao, &texto)?.iter().map(|s| 1000.0 * s).collect()),
+            None => Ok(Vec::new()),
+        }
+    };
+    let tec_min = ms("--min-chegadas")?;
+    let tec_max = ms("--max-chegadas")?;
+    let tempo_verde = ms("--tempo-verde")?;
+    let tempo_amarelo = ms("--tempo-amarelo")?;
+    let latencia = ms("--latencia")?;
+
+    let mut grade = Grade {
+        tec_min,
+        tec_max,
+        tempo_verde,
+        tempo_amarelo,
+        latencia,
+        ..Default::default()
+    };
+    if let Some(texto) = opcoes.retira("--controlador") {
+        for nome in texto.split(',') {
+            grade.controladores.push(
+                TipoControlador::de_nome(nome.trim())
+                    .ok_or(format!("controlador desconhecido: {}", nome))?,
+            );
+        }
+    }
+    if let Some(texto) = opcoes.retira("--a-bordo") {
+        for nome in texto.split(',') {
+            grade.a_bordo.push(
+                ControleABordo::de_nome(nome.trim())
+                    .ok_or(format!("controle a bordo desconhecido: {}", nome))?,
+            );
+        }
+    }
+    if let Some(texto) = opcoes.retira("--informe") {
+        for nome in texto.split(',') {
+            grade.informe.push(
+                ModoInforme::de_nome(nome.trim())
+                    .ok_or(format!("modo de informe desconhecido: {}", nome))?,
+            );
+        }
+    }
+    if let Some(texto) = opcoes.retira("--plano") {
+        for nome in texto.split(',') {
+            grade.plano.push(
+                TipoPlano::de_nome(nome.trim())
+                    .ok_or(format!("plano do semáforo desconhecido: {}", nome))?,
+            );
+        }
+    }
+    if let Some(texto) = opcoes.retira("--largura") {
+        grade.largura = numeros("--largura", &texto)?;
+    }
+    if let Some(texto) = opcoes.retira("--perimetro") {
+        grade.perimetro = numeros("--perimetro", &texto)?;
+    }
+    if let Some(texto) = opcoes.retira("--perda") {
+        grade.perda = numeros("--perda", &texto)?;
+    }
+    if let Some(texto) = opcoes.retira("--capacidade") {
+        grade.capacidade = numeros("--capacidade", &texto)?;
+    }
+
+    let sementes = match opcoes.retira("--sementes") {
+        Some(texto) => valor::<u64>("--sementes", &texto)?,
+        None => SEMENTES_LOTE,
+    };
+    if sementes == 0 {
+        return Err("--sementes deve ser no mínimo 1".to_string());
+    }
+    let semente_inicial = match opcoes.retira("--semente") {
+        Some(texto) => valor::<u64>("--semente", &texto)?,
+        None => 1,
+    };
+
+    let threads = match opcoes.retira("--threads") {
+        Some(texto) => valor::<usize>("--threads", &texto)?,
+        None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    if threads == 0 {
+        return Err("--threads deve ser no mínimo 1".to_string());
+    }
+
+    Ok(ParametrosLote {
+        cenario,
+        grade,
+        sementes,
+        semente_inicial,
+        limites: limites_simulacao(opcoes)?,
+        threads,
+        saida: opcoes.retira("--saida"),
+    })
+}
+
+// Monta cenário e semente a partir das opções de simulação
+fn opcoes_simulacao(opcoes: &mut Opcoes) -> Result<OpcoesSimulacao, String> {
+    let mut cenario = match opcoes.retira("--cenario") {
+        Some(caminho) => Cenario::carrega(&caminho)?,
+        None => Cenario::default(),
+    };
+
+    if let Some(nome) = opcoes.retira("--controlador") {
+        cenario.controle.tipo =
+            TipoControlador::de_nome(&nome).ok_or(format!("controlador desconhecido: {}", nome))?;
+    }
+    // Tempos entre chegadas valem para as duas vias, com processo uniforme
+    if let Some(texto) = opcoes.retira("--min-chegadas") {
+        let tec_min = 1000.0 * numero("--min-chegadas", &texto)?;
+        for chegadas in [&mut cenario.chegadas_h, &mut cenario.chegadas_v] {
+            chegadas.processo = TipoProcesso::Uniforme;
+            chegadas.tec_min = tec_min;
+        }
+    }
+    if let Some(texto) = opcoes.retira("--max-chegadas") {
+        let tec_max = 1000.0 * numero("--max-chegadas", &texto)?;
+        for chegadas in [&mut cenario.chegadas_h, &mut cenario.chegadas_v] {
+            chegadas.processo = TipoProcesso::Uniforme;
+            chegadas.tec_max = tec_max;
+        }
+    }
+    if let Some(nome) = opcoes.retira("--a-bordo") {
+        cenario.veiculo.controle_a_bordo = ControleABordo::de_nome(&nome)
+            .ok_or(format!("controle a bordo desconhecido: {}", nome))?;
+    }
+    if let Some(nome) = opcoes.retira("--informe") {
+        cenario.veiculo.informe =
+            ModoInforme::de_nome(&nome).ok_or(format!("modo de informe desconhecido: {}", nome))?;
+    }
+    if let Some(nome) = opcoes.retira("--plano") {
+        cenario.semaforo.plano =
+            TipoPlano::de_nome(&nome).ok_or(format!("plano do semáforo desconhecido: {}", nome))?;
+    }
+    // Imperfeições da comunicação valem para todos os canais
+    if let Some(texto) = opcoes.retira("--latencia") {
+        let latencia = 1000.0 * numero("--latencia", &texto)?;
+        for canal in cenario.comunicacao.canais() {
+            canal.latencia = latencia;
+        }
+    }
+    if let Some(texto) = opcoes.retira("--jitter") {
+        let jitter = 1000.0 * numero("--jitter", &texto)?;
+        for canal in cenario.comunicacao.canais() {
+            canal.jitter = jitter;
+        }
+    }
+    if let Some(texto) = opcoes.retira("--perda") {
+        let perda = numero("--perda", &texto)?;
+        for canal in cenario.comunicacao.canais() {
+            canal.perda = perda;
+        }
+    }
+    if let Some(texto) = opcoes.retira("--capacidade") {
+        let capacidade = numero("--capacidade", &texto)?;
+        for canal in cenario.comunicacao.canais() {
+            canal.capacidade = capacidade;
+        }
+    }
+    cenario.valida()?;
+
+    let semente = match opcoes.retira("--semente") {
+        Some(texto) => valor::<u64>("--semente", &texto)?,
+        None => cenario.semente.unwrap_or_else(sorteia_semente),
+    };
+    cenario.semente = Some(semente);
+
+    let mut log = configuracao_log(opcoes, "info")?;
+    log.filtra_placa(opcoes.retira("--log-placa"));
+
+    let servidor = opcoes.retira("--servidor");
+    let falha_controlador = match opcoes.retira("--falha-controlador") {
+        None => None,
+        Some(_) if servidor.is_some() => {
+            return Err("--falha-controlador só vale com o controlador próprio".to_string())
+        }
+        Some(texto) => {
+            let (inicio, fim) = texto.split_once(':').ok_or(format!(
+                "--falha-controlador espera 'inicio:fim': {}",
+                texto
+            ))?;
+            let inicio = 1000.0 * numero("--falha-controlador", inicio)?;
+            let fim = 1000.0 * numero("--falha-controlador", fim)?;
+            if inicio < 0.0 || fim <= inicio {
+                return Err("--falha-controlador deve terminar depois de começar".to_string());
+            }
+            Some((inicio, fim))
+        }
+    };
+
+    let atacante = match opcoes.retira("--atacante") {
+        None => None,
+        Some(texto) => {
+            let periodo = 1000.0 * numero("--atacante", &texto)?;
+            if periodo <= 0.0 {
+                return Err("--atacante espera um intervalo positivo".to_string());
+            }
+            Some(periodo)
+        }
+    };
+
+    Ok(OpcoesSimulacao {
+        cenario,
+        semente,
+        log,
+        gravacao: opcoes.retira("--gravar"),
+        rastro: opcoes.retira("--rastro"),
+        servidor,
+        falha_controlador,
+        atacante,
+    })
+}
+
+// Tamanho da janela gráfica
+fn tamanho_janela(opcoes: &mut Opcoes) -> Result<f64, String> {
+    let tam_janela = match opcoes.retira("--janela") {
+        Some(texto) => numero("--janela", &texto)?,
+        None => TAM_JANELA,
+    };
+    if !(200.0..=1000.0).contains(&tam_janela) {
+        return Err("tamanho da janela deve estar entre 200 e 1000".to_string());
+    }
+    Ok(tam_janela)
+}
+
+// Opções do trace
+fn opcoes_rastro(opcoes: &mut Opcoes) -> Result<ParametrosRastro, String> {
+    let caminho = opcoes
+        .retira("--rastro")
+        .ok_or("trace exige --rastro <arquivo>".to_string())?;
+    let separa = |texto: Option<String>| -> Vec<String> {
+        match texto {
+            None => Vec::new(),
+            Some(texto) => texto.split(',').map(|t| t.trim().to_string()).collect(),
+        }
+    };
+    let placas = separa(opcoes.retira("--placa"));
+    let tipos = separa(opcoes.retira("--tipo"));
+    let inicio = match opcoes.retira("--inicio") {
+        Some(texto) => 1000.0 * numero("--inicio", &texto)?,
+        None => 0.0,
+    };
+    let fim = match opcoes.retira("--fim") {
+        Some(texto) => 1000.0 * numero("--fim", &texto)?,
+        None => f64::INFINITY,
+    };
+    if fim < inicio {
+        return Err("--fim deve ser depois de --inicio".to_string());
+    }
+    let formato = match opcoes.retira("--formato") {
+        Some(nome) => Formato::de_nome(&nome).ok_or(format!("formato desconhecido: {}", nome))?,
+        None => Formato::LinhaDoTempo,
+    };
+    Ok(ParametrosRastro {
+        caminho,
+        placas,
+        tipos,
+        inicio,
+        fim,
+        formato,
+    })
+}
+
+// Interpreta a linha de comando, sem o nome do programa
+pub fn interpreta(args: &[String]) -> Result<Comando, String> {
+    let (subcomando, resto) = match args.split_first() {
+        None => return Ok(Comando::Ajuda),
+        Some((s, r)) => (s.as_str(), r),
+    };
+
+    if subcomando == "-h"
+        || subcomando == "--help"
+        || resto.iter().any(|a| a == "-h" || a == "--help")
+    {
+        return Ok(Comando::Ajuda);
+    }
+
+    let mut opcoes = Opcoes::new(resto)?;
+
+    let comando = match subcomando {
+        "run" => {
+            let simulacao = opcoes_simulacao(&mut opcoes)?;
+            Comando::Executa {
+                simulacao,
+                tam_janela: tamanho_janela(&mut opcoes)?,
+            }
+        }
+
+        "headless" => {
+            let simulacao = opcoes_simulacao(&mut opcoes)?;
+            Comando::SemJanela {
+                simulacao,
+                limites: limites_simulacao(&mut opcoes)?,
+                saida: opcoes.retira("--saida"),
+            }
+        }
+
+        "replay" => Comando::Reproduz {
+            gravacao: opcoes
+                .retira("--gravacao")
+                .ok_or("replay exige --gravacao <arquivo>".to_string())?,
+            tam_janela: tamanho_janela(&mut opcoes)?,
+        },
+
+        "trace" => Comando::Rastro(opcoes_rastro(&mut opcoes)?),
+
+        "batch" => Comando::Lote {
+            lote: opcoes_lote(&mut opcoes)?,
+            log: configuracao_log(&mut opcoes, "nenhum")?,
+        },
+
+        "server" => {
+            let simulacao = opcoes_simulacao(&mut opcoes)?;
+            if simulacao.gravacao.is_some()
+                || simulacao.rastro.is_some()
+                || simulacao.servidor.is_some()
+                || simulacao.falha_controlador.is_some()
+                || simulacao.atacante.is_some()
+            {
+                return Err(
+                    "server não aceita --gravar, --rastro, --servidor, --falha-controlador \
+                     nem --atacante"
+                        .to_string(),
+                );
+            }
+            let atendimentos = match opcoes.retira("--atende") {
+                Some(texto) => Some(valor::<u32>("--atende", &texto)?),
+                None => None,
+            };
+            Comando::Servidor {
+                servidor: ParametrosServidor {
+                    cenario: simulacao.cenario,
+                    endereco: opcoes
+                        .retira("--endereco")
+                        .unwrap_or(ENDERECO_PADRAO.to_string()),
+                    atendimentos,
+                },
+                log: simulacao.log,
+            }
+        }
+
+        "list-controllers" => Comando::ListaControladores,
+
+        outro => return Err(format!("subcomando desconhecido: {}", outro)),
+    };
+
+    opcoes.fim()?;
+    Ok(comando)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headless(opcoes: &str) -> Result<Comando, String> {
+        let args: Vec<String> = format!("headless --log nenhum {}", opcoes)
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+        interpreta(&args)
+    }
+
+    #[test]
+    fn opcoes_numericas_recusam_valores_invalidos() {
+        for opcoes in [
+            "--min-chegadas nan",
+            "--max-chegadas inf",
+            "--falha-controlador nan:5",
+            "--falha-controlador 1:inf",
+            "--latencia inf",
+            "--jitter nan",
+            "--capacidade -1",
+            "--perda nan",
+            "--duracao nan",
+            "--atacante inf",
+        ] {
+            assert!(headless(opcoes).is_err(), "{}", opcoes);
+        }
+        assert!(headless("--min-chegadas 3 --latencia 0.1 --falha-controlador 60:75").is_ok());
+    }
+}
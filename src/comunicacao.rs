@@ -20,11 +20,209 @@
 
 */
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::autenticacao::{Chave, Mac};
+use crate::protocolo::{autentica, Quadro};
+use crate::rastro::{Rastreador, Sentido};
+use crate::rede::Conexao;
+use crate::registro::{registra, Categoria, Nivel};
 use crate::transito::Via;
 
+const FILA_MAXIMA: f64 = 200.0; // espera máxima padrão no meio de capacidade limitada, ms
+
+// Imperfeições de um sentido da comunicação, todas zeradas significa canal ideal
+// Capacidade, mensagens por segundo e slot zerados significam meio sem limite
+#[derive(Debug, Copy, Clone)]
+pub struct ParametrosCanal {
+    pub latencia: f64,              // atraso fixo de cada mensagem, ms
+    pub jitter: f64,                // atraso adicional sorteado entre zero e este valor, ms
+    pub perda: f64,                 // probabilidade de uma mensagem se perder
+    pub duplicacao: f64,            // probabilidade de uma mensagem ser entregue duas vezes
+    pub reordenacao: f64, // probabilidade de uma mensagem poder ultrapassar as de outros remetentes
+    pub capacidade: f64,  // bytes por segundo que o meio transmite
+    pub mensagens_por_segundo: f64, // mensagens por segundo que o meio transmite
+    pub slot: f64,        // transmissões começam no início de um slot e ocupam slots inteiros, ms
+    pub fila_maxima: f64, // atraso máximo de espera e transmissão no meio, além dele descarta, ms
+}
+
+impl Default for ParametrosCanal {
+    fn default() -> Self {
+        Self {
+            latencia: 0.0,
+            jitter: 0.0,
+            perda: 0.0,
+            duplicacao: 0.0,
+            reordenacao: 0.0,
+            capacidade: 0.0,
+            mensagens_por_segundo: 0.0,
+            slot: 0.0,
+            fila_maxima: FILA_MAXIMA,
+        }
+    }
+}
+
+impl ParametrosCanal {
+    // Atraso máximo de uma mensagem que não se perdeu, ms
+    pub fn atraso_maximo(&self) -> f64 {
+        if self.limitado() {
+            self.latencia + self.jitter + self.fila_maxima
+        } else {
+            self.latencia + self.jitter
+        }
+    }
+
+    // O meio transmite uma mensagem de cada vez, numa velocidade limitada
+    pub fn limitado(&self) -> bool {
+        self.capacidade > 0.0 || self.mensagens_por_segundo > 0.0 || self.slot > 0.0
+    }
+
+    // Tempo que o meio fica ocupado transmitindo um quadro de 'bytes', ms
+    fn ocupacao(&self, bytes: usize) -> f64 {
+        let mut ocupacao: f64 = 0.0;
+        if self.capacidade > 0.0 {
+            ocupacao = ocupacao.max(1000.0 * bytes as f64 / self.capacidade);
+        }
+        if self.mensagens_por_segundo > 0.0 {
+            ocupacao = ocupacao.max(1000.0 / self.mensagens_por_segundo);
+        }
+        if self.slot > 0.0 {
+            ocupacao = (ocupacao / self.slot).ceil().max(1.0) * self.slot;
+        }
+        ocupacao
+    }
+
+    // Sem nenhuma imperfeição
+    pub fn ideal(&self) -> bool {
+        self.latencia == 0.0
+            && self.jitter == 0.0
+            && self.perda == 0.0
+            && self.duplicacao == 0.0
+            && self.reordenacao == 0.0
+            && !self.limitado()
+    }
+
+    // Descrição curta, para mensagens
+    pub fn descricao(&self) -> String {
+        let mut descricao = format!(
+            "latência {} ms + até {} ms, perda {}, duplicação {}, reordenação {}",
+            self.latencia, self.jitter, self.perda, self.duplicacao, self.reordenacao
+        );
+        if self.capacidade > 0.0 {
+            descricao.push_str(&format!(", capacidade {} bytes/s", self.capacidade));
+        }
+        if self.mensagens_por_segundo > 0.0 {
+            descricao.push_str(&format!(", {} mensagens/s", self.mensagens_por_segundo));
+        }
+        if self.slot > 0.0 {
+            descricao.push_str(&format!(", slot {} ms", self.slot));
+        }
+        if self.limitado() {
+            descricao.push_str(&format!(", fila até {} ms", self.fila_maxima));
+        }
+        descricao
+    }
+
+    // Confere se os valores fazem sentido
+    pub fn valida(&self, nome: &str) -> Result<(), String> {
+        let tempos_e_taxas = [
+            self.latencia,
+            self.jitter,
+            self.capacidade,
+            self.mensagens_por_segundo,
+            self.slot,
+            self.fila_maxima,
+        ];
+        if !tempos_e_taxas.iter().all(|x| x.is_finite()) {
+            return Err(format!(
+                "{}: latência, jitter, capacidade, mensagens por segundo, slot e fila máxima \
+                 devem ser finitos",
+                nome
+            ));
+        }
+        if self.latencia < 0.0 || self.jitter < 0.0 {
+            return Err(format!(
+                "{}: latência e jitter não podem ser negativos",
+                nome
+            ));
+        }
+        if self.capacidade < 0.0 || self.mensagens_por_segundo < 0.0 || self.slot < 0.0 {
+            return Err(format!(
+                "{}: capacidade, mensagens por segundo e slot não podem ser negativos",
+                nome
+            ));
+        }
+        if self.limitado() && self.fila_maxima <= 0.0 {
+            return Err(format!(
+                "{}: com capacidade limitada, a fila máxima deve ser positiva",
+                nome
+            ));
+        }
+        for (chave, p) in [
+            ("perda", self.perda),
+            ("duplicação", self.duplicacao),
+            ("reordenação", self.reordenacao),
+        ] {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(format!("{}: {} deve estar entre 0 e 1", nome, chave));
+            }
+        }
+        if self.perda == 1.0 {
+            return Err(format!("{}: com perda 1 nenhuma mensagem chega", nome));
+        }
+        Ok(())
+    }
+}
+
+// Imperfeições de cada canal da comunicação
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ParametrosComunicacao {
+    pub de_veiculo: ParametrosCanal,     // veículos -> controlador
+    pub do_controlador: ParametrosCanal, // controlador -> veículos
+    pub entre_veiculos: ParametrosCanal, // veículo -> veículos vizinhos (V2V)
+}
+
+impl ParametrosComunicacao {
+    // Todos os canais, para alterar todos da mesma forma
+    pub fn canais(&mut self) -> [&mut ParametrosCanal; 3] {
+        [
+            &mut self.de_veiculo,
+            &mut self.do_controlador,
+            &mut self.entre_veiculos,
+        ]
+    }
+
+    // Tempo máximo entre uma pergunta do controlador e a resposta do veículo, ms
+    pub fn ida_e_volta(&self) -> f64 {
+        self.de_veiculo.atraso_maximo() + self.do_controlador.atraso_maximo()
+    }
+
+    // Número de ciclos seguidos em que o pedido ou a resposta podem se perder,
+    // com probabilidade abaixo de 'chance'
+    pub fn perdas_seguidas(&self, chance: f64) -> i32 {
+        let sucesso = (1.0 - self.de_veiculo.perda) * (1.0 - self.do_controlador.perda);
+        if sucesso < 1.0 {
+            (chance.ln() / (1.0 - sucesso).ln()).ceil() as i32
+        } else {
+            0
+        }
+    }
+
+    // Confere se os valores fazem sentido
+    pub fn valida(&self) -> Result<(), String> {
+        self.de_veiculo
+            .valida("comunicação veículo -> controlador")?;
+        self.do_controlador
+            .valida("comunicação controlador -> veículo")?;
+        self.entre_veiculos.valida("comunicação entre veículos")
+    }
+}
+
 // Tipos de mensagens enviadas por veículos para o controlador
+#[derive(Debug, Clone)]
 pub enum MensagemDeVeiculo {
     Chegada {
         placa: String,
@@ -33,6 +231,7 @@ pub enum MensagemDeVeiculo {
         acel_min: f64,
         vel_max: f64,
         comprimento: f64,
+        periodo_informe: f64, // entre informes espontâneos da situação, ms, zero só quando pedida
     }, // Informa que chegou
     SituacaoAtual {
         placa: String,
@@ -43,61 +242,939 @@ pub enum MensagemDeVeiculo {
 }
 
 // Tipos de mensagens enviadas pelo controlador para veículos
+#[derive(Debug, Clone)]
 pub enum MensagemDoControlador {
     SetAcel { placa: String, acel: f64 }, // Determina a nova aceleração
     PedeSituacao { placa: String },       // Pede a situação
+    Chave { placa: String, chave: Chave }, // Entrega a chave do veículo, resposta à chegada
+}
+
+// Tipos de mensagens difundidas por um veículo para os vizinhos
+#[derive(Debug, Clone)]
+pub enum MensagemEntreVeiculos {
+    Estado {
+        placa: String,
+        via: Via,
+        pos_atual: f64,
+        vel_atual: f64,
+        acel_atual: f64,
+        acel_min: f64,
+        comprimento: f64,
+    }, // Informa o seu estado e o quanto consegue frear
+}
+
+impl MensagemDeVeiculo {
+    // Placa do veículo que enviou a mensagem
+    pub fn placa(&self) -> &str {
+        match self {
+            MensagemDeVeiculo::Chegada { placa, .. } => placa,
+            MensagemDeVeiculo::SituacaoAtual { placa, .. } => placa,
+        }
+    }
+}
+
+impl MensagemDoControlador {
+    // Placa do veículo destino da mensagem
+    pub fn placa(&self) -> &str {
+        match self {
+            MensagemDoControlador::SetAcel { placa, .. } => placa,
+            MensagemDoControlador::PedeSituacao { placa } => placa,
+            MensagemDoControlador::Chave { placa, .. } => placa,
+        }
+    }
+}
+
+impl MensagemEntreVeiculos {
+    // Placa do veículo que difundiu a mensagem
+    pub fn placa(&self) -> &str {
+        match self {
+            MensagemEntreVeiculos::Estado { placa, .. } => placa,
+        }
+    }
+}
+
+// Mensagem como circula na comunicação, com o instante do envio e a sua posição
+// entre as mensagens do mesmo remetente
+#[derive(Debug, Clone)]
+pub struct Pacote<T> {
+    pub enviada_em: f64, // tempo simulado do envio, ms
+    pub sequencia: u64,  // 1, 2, 3, ... para cada remetente
+    pub mensagem: T,
+}
+
+// Cópia de uma mensagem enviada, para quem observa a comunicação
+#[derive(Debug, Clone)]
+pub enum MensagemEnviada {
+    DeVeiculo(MensagemDeVeiculo),
+    DoControlador(MensagemDoControlador),
+}
+
+// Mensagem a caminho do destino
+struct EmTransito<T> {
+    entrega: f64, // instante em que chega ao destino, ms
+    msg: T,
+}
+
+// Mensagens a caminho de um mesmo destino, em ordem de entrega
+// As de um mesmo remetente sempre chegam na ordem em que foram enviadas: uma mensagem
+// que o canal atrasou segura as seguintes, como faz quem numera os pacotes
+struct Fila<T> {
+    mensagens: VecDeque<EmTransito<T>>,
+    ultima_entrega: f64, // entrega mais tardia já enfileirada, ms
+    ultima_do_remetente: HashMap<String, f64>, // idem, para cada remetente
+}
+
+impl<T> Fila<T> {
+    fn new() -> Self {
+        Self {
+            mensagens: VecDeque::new(),
+            ultima_entrega: 0.0,
+            ultima_do_remetente: HashMap::new(),
+        }
+    }
+
+    // Enfileira uma mensagem, que só ultrapassa as anteriores de outros remetentes
+    // se 'pode_ultrapassar', e nunca as do seu remetente
+    fn insere(&mut self, remetente: &str, entrega: f64, pode_ultrapassar: bool, msg: T) {
+        let do_remetente = self
+            .ultima_do_remetente
+            .entry(remetente.to_string())
+            .or_insert(0.0);
+        let entrega = if pode_ultrapassar {
+            entrega.max(*do_remetente)
+        } else {
+            entrega.max(self.ultima_entrega)
+        };
+        *do_remetente = entrega;
+        self.ultima_entrega = self.ultima_entrega.max(entrega);
+        let i = self.mensagens.partition_point(|m| m.entrega <= entrega);
+        self.mensagens.insert(i, EmTransito { entrega, msg });
+    }
+
+    // Retira a próxima mensagem, se já chegou
+    fn retira(&mut self, agora: f64) -> Option<T> {
+        match self.mensagens.front() {
+            Some(m) if m.entrega <= agora => self.mensagens.pop_front().map(|m| m.msg),
+            _ => None,
+        }
+    }
+}
+
+// Sorteia o que acontece com uma mensagem enviada agora: nenhuma, uma ou duas
+// entregas, cada uma com o seu instante e se pode ultrapassar as anteriores
+fn sorteia_entregas(canal: &ParametrosCanal, agora: f64, rng: &mut StdRng) -> Vec<(f64, bool)> {
+    let mut entregas = Vec::new();
+    if canal.perda > 0.0 && rng.gen_bool(canal.perda) {
+        return entregas;
+    }
+    let copias = if canal.duplicacao > 0.0 && rng.gen_bool(canal.duplicacao) {
+        2
+    } else {
+        1
+    };
+    for _ in 0..copias {
+        let mut entrega = agora + canal.latencia;
+        if canal.jitter > 0.0 {
+            entrega += rng.gen_range(0.0..canal.jitter);
+        }
+        let pode_ultrapassar = canal.reordenacao > 0.0 && rng.gen_bool(canal.reordenacao);
+        entregas.push((entrega, pode_ultrapassar));
+    }
+    entregas
+}
+
+// O que passou por um canal numa simulação
+#[derive(Debug, Copy, Clone, Default)]
+pub struct EstatisticasCanal {
+    pub enviadas: u64,     // mensagens que entraram no canal
+    pub bytes: u64,        // tamanho somado dos quadros enviados
+    pub enfileiradas: u64, // esperaram o meio ficar livre
+    pub descartadas: u64,  // não couberam na fila do meio
+    pub espera_total: f64, // espera somada das enfileiradas, ms
+}
+
+impl EstatisticasCanal {
+    // Mostra uma linha do resumo, com as taxas em 'tempo_simulado' ms
+    fn mostra(&self, nome: &str, tempo_simulado: f64) {
+        let segundos = (tempo_simulado / 1000.0).max(0.001);
+        let mut linha = format!(
+            "   mensagens {}: {} enviadas ({:.1}/s, {:.0} bytes/s)",
+            nome,
+            self.enviadas,
+            self.enviadas as f64 / segundos,
+            self.bytes as f64 / segundos
+        );
+        if self.enfileiradas > 0 || self.descartadas > 0 {
+            linha.push_str(&format!(
+                ", {} enfileiradas (espera média {:.1} ms), {} descartadas por falta de capacidade",
+                self.enfileiradas,
+                self.espera_total / (self.enfileiradas as f64).max(1.0),
+                self.descartadas
+            ));
+        }
+        println!("{}", linha);
+    }
+}
+
+// Idade da situação de cada veículo que o controlador tem, amostrada a cada tick
+#[derive(Debug, Copy, Clone, Default)]
+pub struct IdadeSituacao {
+    pub soma: f64, // ms
+    pub amostras: u64,
+    pub maxima: f64, // ms
+}
+
+impl IdadeSituacao {
+    // Idade média, ms
+    pub fn media(&self) -> f64 {
+        self.soma / (self.amostras as f64).max(1.0)
+    }
+}
+
+// Estatísticas de cada canal
+#[derive(Debug, Copy, Clone, Default)]
+pub struct EstatisticasComunicacao {
+    pub de_veiculo: EstatisticasCanal,
+    pub do_controlador: EstatisticasCanal,
+    pub entre_veiculos: EstatisticasCanal,
+    pub idade_situacao: IdadeSituacao,
+}
+
+impl EstatisticasComunicacao {
+    // Todos os canais
+    pub fn canais(&self) -> [&EstatisticasCanal; 3] {
+        [&self.de_veiculo, &self.do_controlador, &self.entre_veiculos]
+    }
+
+    // Mostra o resumo na tela, com as taxas em 'tempo_simulado' ms
+    pub fn mostra(&self, tempo_simulado: f64) {
+        self.de_veiculo
+            .mostra("veículo -> controlador", tempo_simulado);
+        self.do_controlador
+            .mostra("controlador -> veículo", tempo_simulado);
+        self.entre_veiculos.mostra("entre veículos", tempo_simulado);
+        if self.idade_situacao.amostras > 0 {
+            println!(
+                "   situação no controlador: idade média {:.1} ms, máxima {:.1} ms",
+                self.idade_situacao.media(),
+                self.idade_situacao.maxima
+            );
+        }
+    }
+}
+
+// Meio por onde passam as mensagens de um canal, ocupado por uma transmissão de cada vez
+// quando a capacidade é limitada
+#[derive(Default)]
+struct Meio {
+    livre_em: f64, // fim da última transmissão reservada, ms
+    estatisticas: EstatisticasCanal,
+}
+
+impl Meio {
+    // Reserva o meio para um quadro de 'bytes' enviado 'agora', depois dos já reservados
+    // Devolve o atraso até o fim da transmissão, ou None se passaria da fila máxima
+    fn ocupa(&mut self, canal: &ParametrosCanal, agora: f64, bytes: usize) -> Option<f64> {
+        self.estatisticas.enviadas += 1;
+        self.estatisticas.bytes += bytes as u64;
+        if !canal.limitado() {
+            return Some(0.0);
+        }
+        let mut inicio = agora.max(self.livre_em);
+        if canal.slot > 0.0 {
+            inicio = (inicio / canal.slot).ceil() * canal.slot;
+        }
+        let fim = inicio + canal.ocupacao(bytes);
+        if fim - agora > canal.fila_maxima {
+            self.estatisticas.descartadas += 1;
+            return None;
+        }
+        if inicio > agora {
+            self.estatisticas.enfileiradas += 1;
+            self.estatisticas.espera_total += inicio - agora;
+        }
+        self.livre_em = fim;
+        Some(fim - agora)
+    }
+}
+
+// Passa um quadro de 'bytes' pelo meio e sorteia as entregas, None se foi descartado
+// Os sorteios acontecem mesmo no descarte, para a capacidade não mudar os das outras mensagens
+fn transmite(
+    canal: &ParametrosCanal,
+    meio: &mut Meio,
+    agora: f64,
+    bytes: usize,
+    rng: &mut StdRng,
+) -> Option<Vec<(f64, bool)>> {
+    let transmissao = meio.ocupa(canal, agora, bytes);
+    let entregas = sorteia_entregas(canal, agora + transmissao.unwrap_or(0.0), rng);
+    transmissao.map(|_| entregas)
+}
+
+// Remetente das mensagens do controlador, para a ordem de entrega
+const CONTROLADOR: &str = "controlador";
+const ATACANTE: &str = "atacante";
+
+// Onde estão veículos e controlador
+// As imperfeições da comunicação são sorteadas sempre do lado da simulação, assim
+// a mesma semente dá o mesmo resultado com o controlador em outro processo
+pub enum Transporte {
+    // Todos no mesmo processo
+    Memoria,
+    // Lado da simulação, com o controlador em outro processo
+    ParaControlador(Conexao),
+    // Lado do controlador, com a simulação em outro processo
+    ParaVeiculos {
+        conexao: Conexao,
+        recebidos: VecDeque<Pacote<MensagemDeVeiculo>>, // chegaram no tick atual
+    },
 }
 
 // Sistema de comunicação entre veículos e controlador
+// As mensagens viajam como quadros do protocolo, como viajariam numa rede
 pub struct Comunicacao {
-    mensagens_de_veiculo: Vec<MensagemDeVeiculo>,
-    mensagens_do_controlador: HashMap<String, VecDeque<MensagemDoControlador>>,
+    mensagens_de_veiculo: Fila<Vec<u8>>,
+    mensagens_do_controlador: HashMap<String, Fila<Vec<u8>>>,
+    mensagens_entre_veiculos: BTreeMap<String, Fila<Vec<u8>>>, // em ordem, para os sorteios
+    sequencias_de_veiculo: HashMap<String, u64>, // última sequência usada por cada veículo
+    sequencia_do_controlador: u64,               // última sequência usada pelo controlador
+    meio_de_veiculo: Meio,
+    meio_do_controlador: Meio,
+    meio_entre_veiculos: Meio,
+    parametros: ParametrosComunicacao,
+    rng: StdRng, // sorteios das imperfeições, separados dos sorteios do trânsito
+    agora: f64,  // tempo simulado, ms
+    copias: Option<Vec<MensagemEnviada>>, // mensagens enviadas, se alguém está observando
+    escutados: Option<Vec<(String, Vec<u8>)>>, // quadros do controlador, se alguém escuta o canal
+    rastro: Option<Rastreador>, // grava cada envio e entrega, se pedido
+    situacoes: BTreeMap<String, Option<f64>>, // envio da última situação entregue ao controlador,
+    // de cada veículo, ms; None depois que o veículo saiu
+    idade_situacao: IdadeSituacao,
+    transporte: Transporte,
 }
 
 /*
     Controlador pode mandar várias mensagens para o mesmo carro
-    Comunicação precisa de vet para enfileirar as mensagens para um mesmo carro
-    Preciso de um hashmap de vets de MensagemDoControlador
+    Comunicação precisa de uma fila para as mensagens de cada carro
+    Preciso de um hashmap de filas de MensagemDoControlador
+    Cada mensagem só é entregue quando o tempo simulado chega no instante de entrega
+    Cada mensagem vai num Pacote com o instante do envio e a sequência do remetente,
+    quem recebe descarta cópias repetidas e mensagens mais velhas do que já conhece
+    Mensagens entre veículos são difundidas: cada veículo presente recebe a sua cópia,
+    com perdas e atrasos sorteados para cada um
 */
 
 impl Comunicacao {
-    // Cria um novo sistema de comunicação
-    pub fn new() -> Self {
+    // Cria um novo sistema de comunicação, com as imperfeições sorteadas a partir de 'semente'
+    // e veículos e controlador onde 'transporte' diz
+    pub fn new(parametros: ParametrosComunicacao, semente: u64, transporte: Transporte) -> Self {
         Self {
-            mensagens_de_veiculo: Vec::new(),
+            mensagens_de_veiculo: Fila::new(),
             mensagens_do_controlador: HashMap::new(),
+            mensagens_entre_veiculos: BTreeMap::new(),
+            sequencias_de_veiculo: HashMap::new(),
+            sequencia_do_controlador: 0,
+            meio_de_veiculo: Meio::default(),
+            meio_do_controlador: Meio::default(),
+            meio_entre_veiculos: Meio::default(),
+            parametros,
+            rng: StdRng::seed_from_u64(semente),
+            agora: 0.0,
+            copias: None,
+            escutados: None,
+            rastro: None,
+            situacoes: BTreeMap::new(),
+            idade_situacao: IdadeSituacao::default(),
+            transporte,
+        }
+    }
+
+    // Avança o tempo simulado, mensagens com entrega até 'agora' ficam disponíveis
+    pub fn define_tempo(&mut self, agora: f64) {
+        self.agora = agora;
+    }
+
+    // Tempo simulado, ms
+    pub fn agora(&self) -> f64 {
+        self.agora
+    }
+
+    // Imperfeições de cada canal
+    pub fn parametros(&self) -> &ParametrosComunicacao {
+        &self.parametros
+    }
+
+    // O que passou por cada canal até agora
+    pub fn estatisticas(&self) -> EstatisticasComunicacao {
+        EstatisticasComunicacao {
+            de_veiculo: self.meio_de_veiculo.estatisticas,
+            do_controlador: self.meio_do_controlador.estatisticas,
+            entre_veiculos: self.meio_entre_veiculos.estatisticas,
+            idade_situacao: self.idade_situacao,
+        }
+    }
+
+    // Soma a idade da situação que o controlador tem de cada veículo presente, a cada tick
+    pub fn amostra_situacoes(&mut self) {
+        for enviada_em in self.situacoes.values().flatten() {
+            let idade = self.agora - enviada_em;
+            self.idade_situacao.soma += idade;
+            self.idade_situacao.amostras += 1;
+            self.idade_situacao.maxima = self.idade_situacao.maxima.max(idade);
         }
     }
 
+    // Passa a guardar uma cópia de cada mensagem enviada
+    pub fn copia_mensagens(&mut self) {
+        self.copias = Some(Vec::new());
+    }
+
+    // Retira as cópias das mensagens enviadas desde a última chamada
+    pub fn retira_copias(&mut self) -> Vec<MensagemEnviada> {
+        match &mut self.copias {
+            None => Vec::new(),
+            Some(copias) => std::mem::take(copias),
+        }
+    }
+
+    // Passa a gravar no 'rastreador' cada mensagem que entra no canal e cada entrega
+    pub fn rastreia(&mut self, rastreador: Rastreador) {
+        self.rastro = Some(rastreador);
+    }
+
+    // Termina o rastro, se houver, informando erros de escrita
+    pub fn encerra_rastro(&mut self) -> Result<(), String> {
+        match self.rastro.take() {
+            None => Ok(()),
+            Some(mut rastreador) => rastreador.encerra(),
+        }
+    }
+
+    fn rastreia_envio(&mut self, sentido: Sentido, placa: &str, copias: usize, quadro: &[u8]) {
+        if let Some(rastro) = &mut self.rastro {
+            rastro.envio(self.agora, sentido, placa, copias, quadro);
+        }
+    }
+
+    fn rastreia_entrega_ao_controlador(&mut self, quadro: &[u8]) {
+        if let Some(rastro) = &mut self.rastro {
+            rastro.entrega(self.agora, Sentido::DeVeiculo, &remetente(quadro), quadro);
+        }
+    }
+
+    // Passa a guardar os quadros que o controlador transmite, como faria quem escuta o rádio
+    pub fn escuta_controlador(&mut self) {
+        self.escutados = Some(Vec::new());
+    }
+
+    // Retira os quadros escutados desde a última chamada, com a placa do destino
+    pub fn retira_escutados(&mut self) -> Vec<(String, Vec<u8>)> {
+        match &mut self.escutados {
+            None => Vec::new(),
+            Some(escutados) => std::mem::take(escutados),
+        }
+    }
+
+    // Transmite um quadro qualquer para um veículo presente, pelo canal do controlador
+    // mas como outro remetente: é assim que um atacante injeta mensagens
+    pub fn injeta_para_veiculo(&mut self, placa: &str, bytes: Vec<u8>) {
+        let fila = match self.mensagens_do_controlador.get_mut(placa) {
+            None => return,
+            Some(fila) => fila,
+        };
+        let entregas = transmite(
+            &self.parametros.do_controlador,
+            &mut self.meio_do_controlador,
+            self.agora,
+            bytes.len(),
+            &mut self.rng,
+        )
+        .unwrap_or_default();
+        for (entrega, pode_ultrapassar) in &entregas {
+            fila.insere(ATACANTE, *entrega, *pode_ultrapassar, bytes.clone());
+        }
+        self.rastreia_envio(Sentido::DoAtacante, placa, entregas.len(), &bytes);
+    }
+
+    // Próxima sequência de um veículo, contando tudo que ele envia
+    fn proxima_sequencia(&mut self, placa: &str) -> u64 {
+        let sequencia = self
+            .sequencias_de_veiculo
+            .entry(placa.to_string())
+            .or_insert(0);
+        *sequencia += 1;
+        *sequencia
+    }
+
     // Permite um veículo enviar mensagens
     pub fn send_por_veiculo(&mut self, msg: MensagemDeVeiculo) {
-        self.mensagens_de_veiculo.push(msg);
+        if let Some(copias) = &mut self.copias {
+            copias.push(MensagemEnviada::DeVeiculo(msg.clone()));
+        }
+        let remetente = msg.placa().to_string();
+        let quadro = Quadro::DeVeiculo(Pacote {
+            enviada_em: self.agora,
+            sequencia: self.proxima_sequencia(&remetente),
+            mensagem: msg,
+        });
+        registra!(
+            Categoria::Comunicacao,
+            Nivel::Detalhe,
+            placa = &remetente,
+            "veiculo -> controlador {}",
+            quadro.json()
+        );
+
+        let bytes = quadro.codifica();
+        let entregas = match transmite(
+            &self.parametros.de_veiculo,
+            &mut self.meio_de_veiculo,
+            self.agora,
+            bytes.len(),
+            &mut self.rng,
+        ) {
+            None => {
+                registra!(
+                    Categoria::Comunicacao,
+                    Nivel::Detalhe,
+                    placa = &remetente,
+                    "descartada, canal sem capacidade {}",
+                    quadro.json()
+                );
+                Vec::new()
+            }
+            Some(entregas) => {
+                if entregas.is_empty() {
+                    registra!(
+                        Categoria::Comunicacao,
+                        Nivel::Detalhe,
+                        placa = &remetente,
+                        "perdida {}",
+                        quadro.json()
+                    );
+                }
+                entregas
+            }
+        };
+        self.rastreia_envio(Sentido::DeVeiculo, &remetente, entregas.len(), &bytes);
+        for (entrega, pode_ultrapassar) in entregas {
+            self.mensagens_de_veiculo
+                .insere(&remetente, entrega, pode_ultrapassar, bytes.clone());
+        }
     }
 
-    // Permite o controlador enviar mensagens
-    #[allow(clippy::unwrap_or_default)]
-    pub fn send_por_controlador(&mut self, placa: String, msg: MensagemDoControlador) {
-        let lista = self
+    // Permite um veículo difundir mensagens para todos os outros veículos presentes
+    pub fn difunde_por_veiculo(&mut self, msg: MensagemEntreVeiculos) {
+        let remetente = msg.placa().to_string();
+        let quadro = Quadro::EntreVeiculos(Pacote {
+            enviada_em: self.agora,
+            sequencia: self.proxima_sequencia(&remetente),
+            mensagem: msg,
+        });
+        registra!(
+            Categoria::Comunicacao,
+            Nivel::Detalhe,
+            placa = &remetente,
+            "veiculo -> vizinhos {}",
+            quadro.json()
+        );
+
+        // Uma difusão ocupa o meio uma vez só, para todos os vizinhos
+        let bytes = quadro.codifica();
+        let canal = &self.parametros.entre_veiculos;
+        let transmissao = self
+            .meio_entre_veiculos
+            .ocupa(canal, self.agora, bytes.len());
+        if transmissao.is_none() {
+            registra!(
+                Categoria::Comunicacao,
+                Nivel::Detalhe,
+                placa = &remetente,
+                "descartada, canal sem capacidade {}",
+                quadro.json()
+            );
+        }
+        let mut copias = 0;
+        for (placa, fila) in self.mensagens_entre_veiculos.iter_mut() {
+            if *placa == remetente {
+                continue;
+            }
+            let inicio = self.agora + transmissao.unwrap_or(0.0);
+            let mut entregas = sorteia_entregas(canal, inicio, &mut self.rng);
+            if transmissao.is_none() {
+                entregas.clear();
+            }
+            copias += entregas.len();
+            for (entrega, pode_ultrapassar) in entregas {
+                fila.insere(&remetente, entrega, pode_ultrapassar, bytes.clone());
+            }
+        }
+        self.rastreia_envio(Sentido::EntreVeiculos, &remetente, copias, &bytes);
+    }
+
+    // Permite o controlador enviar mensagens, autenticadas com a 'chave' do veículo
+    pub fn send_por_controlador(
+        &mut self,
+        placa: String,
+        msg: MensagemDoControlador,
+        chave: &Chave,
+    ) {
+        self.sequencia_do_controlador += 1;
+        let pacote = Pacote {
+            enviada_em: self.agora,
+            sequencia: self.sequencia_do_controlador,
+            mensagem: msg,
+        };
+        let mac = autentica(&pacote, chave);
+        match &mut self.transporte {
+            // As imperfeições ficam para o lado da simulação
+            Transporte::ParaVeiculos { conexao, .. } => {
+                let quadro = Quadro::DoControlador(pacote, mac);
+                registra!(
+                    Categoria::Comunicacao,
+                    Nivel::Detalhe,
+                    placa = &placa,
+                    "controlador -> @{} {}",
+                    placa,
+                    quadro.json()
+                );
+                conexao.envia(&quadro);
+            }
+            _ => self.transmite_do_controlador(pacote, mac),
+        }
+    }
+
+    // Passa uma mensagem do controlador pelo canal até a fila do veículo
+    fn transmite_do_controlador(&mut self, pacote: Pacote<MensagemDoControlador>, mac: Mac) {
+        if let Some(copias) = &mut self.copias {
+            copias.push(MensagemEnviada::DoControlador(pacote.mensagem.clone()));
+        }
+        let placa = pacote.mensagem.placa().to_string();
+        let quadro = Quadro::DoControlador(pacote, mac);
+        registra!(
+            Categoria::Comunicacao,
+            Nivel::Detalhe,
+            placa = &placa,
+            "controlador -> @{} {}",
+            placa,
+            quadro.json()
+        );
+
+        let bytes = quadro.codifica();
+        let entregas = match transmite(
+            &self.parametros.do_controlador,
+            &mut self.meio_do_controlador,
+            self.agora,
+            bytes.len(),
+            &mut self.rng,
+        ) {
+            None => {
+                registra!(
+                    Categoria::Comunicacao,
+                    Nivel::Detalhe,
+                    placa = &placa,
+                    "descartada, canal sem capacidade {}",
+                    quadro.json()
+                );
+                Vec::new()
+            }
+            Some(entregas) => {
+                if entregas.is_empty() {
+                    registra!(
+                        Categoria::Comunicacao,
+                        Nivel::Detalhe,
+                        placa = &placa,
+                        "perdida {}",
+                        quadro.json()
+                    );
+                }
+                entregas
+            }
+        };
+        self.rastreia_envio(Sentido::DoControlador, &placa, entregas.len(), &bytes);
+        if let Some(escutados) = &mut self.escutados {
+            escutados.push((placa.clone(), bytes.clone()));
+        }
+        let fila = self
             .mensagens_do_controlador
             .entry(placa)
-            .or_insert(VecDeque::new());
-        lista.push_back(msg);
+            .or_insert_with(Fila::new);
+        for (entrega, pode_ultrapassar) in entregas {
+            fila.insere(CONTROLADOR, entrega, pode_ultrapassar, bytes.clone());
+        }
     }
 
-    // Permite um veículo receber uma mensagem vinda do controlador
-    pub fn receive_por_veiculo(&mut self, placa: &String) -> Option<MensagemDoControlador> {
-        match self.mensagens_do_controlador.get_mut(placa) {
-            None => None,
-            Some(x) => x.pop_front(),
+    // Permite um veículo receber uma mensagem vinda do controlador, com o MAC que veio
+    // junto; conferir o MAC é com o veículo, que tem a chave
+    pub fn receive_por_veiculo(
+        &mut self,
+        placa: &String,
+    ) -> Option<(Pacote<MensagemDoControlador>, Mac)> {
+        let fila = self.mensagens_do_controlador.get_mut(placa)?;
+        while let Some(bytes) = fila.retira(self.agora) {
+            if let Some(rastro) = &mut self.rastro {
+                rastro.entrega(self.agora, Sentido::DoControlador, placa, &bytes);
+            }
+            match decodifica(&bytes) {
+                Some(Quadro::DoControlador(pacote, mac)) => return Some((pacote, mac)),
+                Some(_) => descarta("mensagem que não é do controlador chegou a um veículo"),
+                None => (),
+            }
         }
+        None
+    }
+
+    // Permite um veículo receber uma mensagem difundida por outro veículo
+    // Quem chama passa a receber as próximas difusões, até 'esquece_veiculo'
+    pub fn receive_entre_veiculos(&mut self, placa: &str) -> Option<Pacote<MensagemEntreVeiculos>> {
+        let fila = match self.mensagens_entre_veiculos.get_mut(placa) {
+            Some(fila) => fila,
+            None => {
+                self.mensagens_entre_veiculos
+                    .insert(placa.to_string(), Fila::new());
+                return None;
+            }
+        };
+        while let Some(bytes) = fila.retira(self.agora) {
+            match decodifica(&bytes) {
+                Some(Quadro::EntreVeiculos(pacote)) => return Some(pacote),
+                Some(_) => descarta("mensagem que não é de veículo vizinho chegou a um veículo"),
+                None => (),
+            }
+        }
+        None
+    }
+
+    // Um veículo deixou o sistema, não recebe mais difusões
+    pub fn esquece_veiculo(&mut self, placa: &str) {
+        self.mensagens_entre_veiculos.remove(placa);
+        self.situacoes.insert(placa.to_string(), None);
     }
 
     // Permite ao controlador receber uma mensagem vinda de veículo
-    pub fn receive_por_controlador(&mut self) -> Option<MensagemDeVeiculo> {
-        if self.mensagens_de_veiculo.is_empty() {
-            Option::None
-        } else {
-            Option::Some(self.mensagens_de_veiculo.swap_remove(0))
+    pub fn receive_por_controlador(&mut self) -> Option<Pacote<MensagemDeVeiculo>> {
+        if let Transporte::ParaVeiculos { recebidos, .. } = &mut self.transporte {
+            return recebidos.pop_front();
+        }
+        while let Some(bytes) = self.mensagens_de_veiculo.retira(self.agora) {
+            self.rastreia_entrega_ao_controlador(&bytes);
+            match decodifica(&bytes) {
+                Some(Quadro::DeVeiculo(pacote)) => {
+                    anota_situacao(&mut self.situacoes, &pacote);
+                    return Some(pacote);
+                }
+                Some(_) => descarta("mensagem que não é de veículo chegou ao controlador"),
+                None => (),
+            }
+        }
+        None
+    }
+
+    // Lado da simulação: troca com o controlador remoto as mensagens do tick atual
+    // Não faz nada com o controlador no mesmo processo
+    pub fn sincroniza_controlador(&mut self) -> Result<(), String> {
+        let conexao = match &mut self.transporte {
+            Transporte::ParaControlador(conexao) => conexao,
+            _ => return Ok(()),
+        };
+
+        // Mensagens que já chegaram ao controlador, seguidas do tick
+        while let Some(bytes) = self.mensagens_de_veiculo.retira(self.agora) {
+            if let Some(rastro) = &mut self.rastro {
+                rastro.entrega(self.agora, Sentido::DeVeiculo, &remetente(&bytes), &bytes);
+            }
+            if let Ok((Quadro::DeVeiculo(pacote), _)) = Quadro::decodifica(&bytes) {
+                anota_situacao(&mut self.situacoes, &pacote);
+            }
+            conexao.envia_codificado(&bytes);
+        }
+        conexao.envia(&Quadro::Tick(self.agora));
+        conexao.descarrega()?;
+
+        // Respostas do controlador, até ele terminar o tick
+        let mut respostas = Vec::new();
+        loop {
+            match conexao.recebe()? {
+                None => return Err("o controlador fechou a conexão".to_string()),
+                Some(Quadro::DoControlador(pacote, mac)) => respostas.push((pacote, mac)),
+                Some(Quadro::FimTick(tempo)) if tempo == self.agora => break,
+                Some(outro) => {
+                    return Err(format!(
+                        "o controlador respondeu fora de sincronia: {}",
+                        outro.json()
+                    ))
+                }
+            }
+        }
+        for (pacote, mac) in respostas {
+            self.transmite_do_controlador(pacote, mac);
+        }
+        Ok(())
+    }
+
+    // Lado do controlador: espera a simulação pedir o próximo tick, guardando as
+    // mensagens que chegaram ao controlador. Devolve false quando a simulação termina
+    pub fn espera_tick(&mut self) -> Result<bool, String> {
+        let (conexao, recebidos) = match &mut self.transporte {
+            Transporte::ParaVeiculos { conexao, recebidos } => (conexao, recebidos),
+            _ => return Err("só o controlador remoto espera ticks".to_string()),
+        };
+        loop {
+            match conexao.recebe()? {
+                None => return Ok(false),
+                Some(Quadro::DeVeiculo(pacote)) => recebidos.push_back(pacote),
+                Some(Quadro::Tick(tempo)) => {
+                    self.agora = tempo;
+                    return Ok(true);
+                }
+                Some(outro) => descarta(&format!("inesperado no controlador: {}", outro.json())),
+            }
+        }
+    }
+
+    // Lado do controlador: envia as mensagens do tick e avisa que terminou
+    pub fn termina_tick(&mut self) -> Result<(), String> {
+        match &mut self.transporte {
+            Transporte::ParaVeiculos { conexao, .. } => {
+                conexao.envia(&Quadro::FimTick(self.agora));
+                conexao.descarrega()
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// Anota o envio da situação entregue ao controlador, para a idade; veículos que já saíram
+// ficam de fora
+fn anota_situacao(
+    situacoes: &mut BTreeMap<String, Option<f64>>,
+    pacote: &Pacote<MensagemDeVeiculo>,
+) {
+    if let MensagemDeVeiculo::SituacaoAtual { placa, .. } = &pacote.mensagem {
+        let anotada = situacoes
+            .entry(placa.clone())
+            .or_insert(Some(pacote.enviada_em));
+        if let Some(enviada_em) = anotada {
+            *enviada_em = enviada_em.max(pacote.enviada_em);
+        }
+    }
+}
+
+// Interpreta um quadro recebido, quadros inválidos são descartados
+fn decodifica(bytes: &[u8]) -> Option<Quadro> {
+    match Quadro::decodifica(bytes) {
+        Ok((quadro, _)) => Some(quadro),
+        Err(e) => {
+            descarta(&e.to_string());
+            None
         }
     }
 }
+
+// Placa do veículo que enviou um quadro, para o rastro; vazia se não dá para ler
+fn remetente(bytes: &[u8]) -> String {
+    match Quadro::decodifica(bytes) {
+        Ok((Quadro::DeVeiculo(pacote), _)) => pacote.mensagem.placa().to_string(),
+        _ => String::new(),
+    }
+}
+
+// Registra um quadro recebido que não pode ser usado
+fn descarta(motivo: &str) {
+    registra!(
+        Categoria::Comunicacao,
+        Nivel::Erro,
+        "quadro descartado: {}",
+        motivo
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registro::{self, ConfiguracaoLog};
+
+    fn comunicacao(de_veiculo: ParametrosCanal) -> Comunicacao {
+        registro::configura(ConfiguracaoLog::interpreta("nenhum").unwrap());
+        let parametros = ParametrosComunicacao {
+            de_veiculo,
+            ..Default::default()
+        };
+        Comunicacao::new(parametros, 1, Transporte::Memoria)
+    }
+
+    fn situacao(placa: &str, pos_atual: f64) -> MensagemDeVeiculo {
+        MensagemDeVeiculo::SituacaoAtual {
+            placa: placa.to_string(),
+            pos_atual,
+            vel_atual: 10.0,
+            acel_atual: 0.0,
+        }
+    }
+
+    // Todos os pacotes que chegaram ao controlador até 'agora'
+    fn recebidos(comunicacao: &mut Comunicacao, agora: f64) -> Vec<Pacote<MensagemDeVeiculo>> {
+        comunicacao.define_tempo(agora);
+        std::iter::from_fn(|| comunicacao.receive_por_controlador()).collect()
+    }
+
+    // Com jitter e reordenação, mensagens de remetentes diferentes trocam de ordem, mas
+    // as de cada remetente chegam na ordem em que foram enviadas
+    #[test]
+    fn ordem_de_cada_remetente_mantida_com_reordenacao() {
+        let mut comunicacao = comunicacao(ParametrosCanal {
+            latencia: 10.0,
+            jitter: 300.0,
+            reordenacao: 0.5,
+            ..Default::default()
+        });
+        for i in 0..50 {
+            for (j, placa) in ["AAA0001", "BBB0002"].into_iter().enumerate() {
+                comunicacao.define_tempo(10.0 * (2 * i + j) as f64);
+                comunicacao.send_por_veiculo(situacao(placa, i as f64));
+            }
+        }
+
+        let pacotes = recebidos(&mut comunicacao, 10_000.0);
+        assert_eq!(pacotes.len(), 100);
+        let enviadas_em: Vec<f64> = pacotes.iter().map(|p| p.enviada_em).collect();
+        assert!(enviadas_em.windows(2).any(|par| par[0] > par[1]));
+        for placa in ["AAA0001", "BBB0002"] {
+            let sequencias: Vec<u64> = pacotes
+                .iter()
+                .filter(|p| p.mensagem.placa() == placa)
+                .map(|p| p.sequencia)
+                .collect();
+            assert_eq!(sequencias, (1..=50).collect::<Vec<u64>>(), "{}", placa);
+        }
+    }
+
+    // A 100 mensagens por segundo, cada uma ocupa o meio por 10 ms: de 20 enviadas
+    // juntas, as que terminariam até a fila máxima de 50 ms esperam a sua vez e as
+    // demais são descartadas
+    #[test]
+    fn mensagens_alem_da_capacidade_esperam_ou_sao_descartadas() {
+        let mut comunicacao = comunicacao(ParametrosCanal {
+            mensagens_por_segundo: 100.0,
+            fila_maxima: 50.0,
+            ..Default::default()
+        });
+        for i in 0..20 {
+            comunicacao.send_por_veiculo(situacao(&format!("AAA{:04}", i), 0.0));
+        }
+
+        let estatisticas = comunicacao.estatisticas().de_veiculo;
+        assert_eq!(estatisticas.enviadas, 20);
+        assert_eq!(estatisticas.enfileiradas, 4);
+        assert_eq!(estatisticas.descartadas, 15);
+        assert_eq!(estatisticas.espera_total, 10.0 + 20.0 + 30.0 + 40.0);
+        assert_eq!(recebidos(&mut comunicacao, 45.0).len(), 4);
+        assert_eq!(recebidos(&mut comunicacao, 50.0).len(), 1);
+
+        // Com o meio livre de novo, passa sem esperar
+        comunicacao.send_por_veiculo(situacao("BBB0001", 0.0));
+        assert_eq!(comunicacao.estatisticas().de_veiculo.enfileiradas, 4);
+        assert_eq!(recebidos(&mut comunicacao, 60.0).len(), 1);
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use super::semaforo::{
+    cruzamento_ocupado, ordem_da_via, para_no_vermelho, passa_ou_para_no_amarelo, segue_no_verde,
+};
+use super::{atraso_controle, idade_esperada, Controlador, Situacao};
+
+use crate::cenario::Cenario;
+use crate::registro::{registra, Categoria, Nivel};
+
+use crate::transito::{Geometria, Via};
+
+pub const VERDE_MINIMO: f64 = 8000.0; // Tempo padrão de verde mínimo, em ms
+pub const VERDE_MAXIMO: f64 = 30000.0; // Tempo padrão de verde máximo, em ms
+pub const PASSAGEM: f64 = 2000.0; // Tempo padrão de extensão do verde a cada detecção, em ms
+pub const DETECTORES: [f64; 2] = [100.0, 5.0]; // Distâncias padrão dos laços antes do cruzamento, em metros
+
+// Tempos e detectores do semáforo atuado, o amarelo é o do semáforo
+#[derive(Debug, Clone)]
+pub struct ParametrosAtuado {
+    pub verde_minimo: f64,    // ms
+    pub verde_maximo: f64,    // ms
+    pub passagem: f64,        // ms sem detecção na via verde até encerrar o verde
+    pub detectores: Vec<f64>, // metros antes do cruzamento, iguais nas duas vias
+}
+
+impl Default for ParametrosAtuado {
+    fn default() -> Self {
+        Self {
+            verde_minimo: VERDE_MINIMO,
+            verde_maximo: VERDE_MAXIMO,
+            passagem: PASSAGEM,
+            detectores: DETECTORES.to_vec(),
+        }
+    }
+}
+
+// Por que o verde terminou
+#[derive(Debug, Copy, Clone)]
+enum FimVerde {
+    Intervalo, // passou o tempo de passagem sem detecção na via verde
+    Maximo,    // chegou ao verde máximo com veículos detectados na via verde
+}
+
+// Algoritmo de controle que imita um semáforo atuado por laços detectores
+// Cada via tem laços virtuais a algumas distâncias antes do cruzamento, acionados
+// por quem está sobre eles ou passou por eles desde a última estratégia. O verde dura
+// pelo menos 'verde_minimo', é estendido enquanto a via verde aciona os detectores
+// a intervalos menores que 'passagem' e termina em 'verde_maximo'. Sem detecção na
+// via vermelha não há para quem dar o verde, e a via verde continua com ele
+pub struct Atuado {
+    verde_minimo: f64,    // ms
+    verde_maximo: f64,    // ms
+    passagem: f64,        // ms
+    tempo_amarelo: f64,   // ms
+    detectores: Vec<f64>, // metros antes do cruzamento
+
+    tempo_verde: f64,    // desde o início deste verde, ms
+    sem_deteccao: f64,   // desde a última detecção na via verde, ms
+    restam_amarelo: f64, // ms
+    chamada: bool,       // via vermelha acionou um detector desde que ficou vermelha
+
+    via_verde: Via,    // qual via esta verde ou amarelo
+    via_vermelho: Via, // qual via esta vermelho
+
+    amarelo: bool, // Se amarelo está ligado
+
+    posicoes: HashMap<String, f64>, // posição de cada veículo na estratégia anterior, metros
+
+    geometria: Geometria,     // geometria do cruzamento
+    velocidade_cruzeiro: f64, // metros por segundo
+    atraso: f64,              // até a aceleração decidida ser aplicada, em s
+    idade_esperada: f64,      // da situação dos veículos, em ms
+}
+
+impl Atuado {
+    // Detectores acionados em cada via desde a estratégia anterior, (H, V)
+    fn detecta(&mut self, situacao: &HashMap<String, Situacao>) -> (bool, bool) {
+        let mut acionada_h = false;
+        let mut acionada_v = false;
+        for (placa, v) in situacao.iter() {
+            // Antes de informar, a posição é só um chute
+            if !v.informou {
+                continue;
+            }
+            let anterior = *self.posicoes.get(placa).unwrap_or(&v.pos_atual);
+            // Sobre o laço agora ou passou por ele: a frente chegou e a traseira ainda
+            // não tinha passado
+            let acionou = self.detectores.iter().any(|distancia| {
+                let laco = -distancia;
+                v.pos_atual >= laco && anterior - v.comprimento < laco
+            });
+            if acionou {
+                registra!(
+                    Categoria::Controlador,
+                    Nivel::Detalhe,
+                    placa = placa,
+                    "#ATU @{} aciona detector da {:?}",
+                    placa,
+                    v.via
+                );
+                match v.via {
+                    Via::ViaH => acionada_h = true,
+                    Via::ViaV => acionada_v = true,
+                }
+            }
+            self.posicoes.insert(placa.clone(), v.pos_atual);
+        }
+        self.posicoes
+            .retain(|placa, _| situacao.contains_key(placa));
+        (acionada_h, acionada_v)
+    }
+}
+
+impl Controlador for Atuado {
+    // Cria um novo semáforo atuado, com verde para a via H
+    fn new(cenario: &Cenario) -> Self {
+        Self {
+            verde_minimo: cenario.atuado.verde_minimo,
+            verde_maximo: cenario.atuado.verde_maximo,
+            passagem: cenario.atuado.passagem,
+            tempo_amarelo: cenario.semaforo.tempo_amarelo,
+            detectores: cenario.atuado.detectores.clone(),
+            tempo_verde: 0.0,
+            sem_deteccao: 0.0,
+            restam_amarelo: 0.0,
+            chamada: false,
+            via_verde: Via::ViaH,
+            via_vermelho: Via::ViaV,
+            amarelo: false,
+            posicoes: HashMap::new(),
+            geometria: cenario.geometria,
+            velocidade_cruzeiro: cenario.veiculo.velocidade_cruzeiro,
+            atraso: atraso_controle(cenario) / 1000.0,
+            idade_esperada: idade_esperada(cenario),
+        }
+    }
+
+    // Fase atual e o que mantém o verde
+    fn painel(&self) -> Option<String> {
+        let fase = if self.amarelo {
+            format!(
+                "amarelo {:?}, restam {:.1} s",
+                self.via_verde,
+                self.restam_amarelo / 1000.0
+            )
+        } else {
+            format!(
+                "verde {:?} há {:.1} s, sem detecção há {:.1} s",
+                self.via_verde,
+                self.tempo_verde / 1000.0,
+                self.sem_deteccao / 1000.0
+            )
+        };
+        Some(format!(
+            "semáforo atuado {}{}",
+            fase,
+            if self.chamada {
+                ", chamada da via vermelha"
+            } else {
+                ""
+            }
+        ))
+    }
+
+    // Cálcula ações de controle, tempos em milissegundos !!!
+    fn estrategia(&mut self, tempo_decorrido: f64, situacao: &mut HashMap<String, Situacao>) {
+        let (acionada_h, acionada_v) = self.detecta(situacao);
+        let (acionada_verde, acionada_vermelho) = match self.via_verde {
+            Via::ViaH => (acionada_h, acionada_v),
+            Via::ViaV => (acionada_v, acionada_h),
+        };
+        if acionada_vermelho {
+            self.chamada = true;
+        }
+
+        if self.amarelo {
+            // Avança o tempo em amarelo
+            self.restam_amarelo -= tempo_decorrido;
+            if self.restam_amarelo <= 0.0 {
+                // Acabou o amarelo, troca via do verde
+                self.amarelo = false;
+                std::mem::swap(&mut self.via_verde, &mut self.via_vermelho);
+                self.restam_amarelo = 0.0;
+                self.tempo_verde = 0.0;
+                self.sem_deteccao = 0.0;
+                // Quem parou no amarelo volta a chamar quando estiver sobre um laço
+                self.chamada = false;
+                registra!(
+                    Categoria::Controlador,
+                    Nivel::Info,
+                    "#ATU verde para {:?}, vermelho para {:?}",
+                    self.via_verde,
+                    self.via_vermelho
+                );
+            }
+        } else {
+            // Avança o tempo em verde
+            self.tempo_verde += tempo_decorrido;
+            if acionada_verde {
+                self.sem_deteccao = 0.0;
+            } else {
+                self.sem_deteccao += tempo_decorrido;
+            }
+
+            let fim = if !self.chamada || self.tempo_verde < self.verde_minimo {
+                None
+            } else if self.sem_deteccao >= self.passagem {
+                Some(FimVerde::Intervalo)
+            } else if self.tempo_verde >= self.verde_maximo {
+                Some(FimVerde::Maximo)
+            } else {
+                None
+            };
+            if let Some(fim) = fim {
+                // Vai começar o amarelo deste verde
+                self.amarelo = true;
+                self.restam_amarelo = self.tempo_amarelo;
+                registra!(
+                    Categoria::Controlador,
+                    Nivel::Info,
+                    "#ATU amarelo para {:?} depois de {:.1} s de verde, fim por {:?}",
+                    self.via_verde,
+                    self.tempo_verde / 1000.0,
+                    fim
+                );
+            }
+        }
+
+        registra!(
+            Categoria::Controlador,
+            Nivel::Depuracao,
+            "#ATU verde {:?} há {:.2}, sem detecção há {:.2}, restam amarelo {:.2} {}, vermelho {:?} chamada {}",
+            self.via_verde,
+            self.tempo_verde,
+            self.sem_deteccao,
+            self.restam_amarelo,
+            self.amarelo,
+            self.via_vermelho,
+            self.chamada
+        );
+
+        let ordem_via_vermelho = ordem_da_via(situacao, self.via_vermelho);
+        let ordem_via_verde = ordem_da_via(situacao, self.via_verde);
+
+        para_no_vermelho(
+            &ordem_via_vermelho,
+            situacao,
+            self.atraso,
+            self.idade_esperada,
+        );
+        if self.amarelo {
+            passa_ou_para_no_amarelo(
+                self.via_verde,
+                &ordem_via_verde,
+                situacao,
+                self.geometria.largura_cruzamento(self.via_verde),
+                self.restam_amarelo,
+                self.atraso,
+                self.idade_esperada,
+            );
+        } else if cruzamento_ocupado(
+            &ordem_via_vermelho,
+            situacao,
+            self.geometria.largura_cruzamento(self.via_vermelho),
+            self.atraso,
+            self.idade_esperada,
+        ) {
+            para_no_vermelho(&ordem_via_verde, situacao, self.atraso, self.idade_esperada);
+        } else {
+            segue_no_verde(
+                self.via_verde,
+                &ordem_via_verde,
+                situacao,
+                self.velocidade_cruzeiro,
+                self.atraso,
+                self.idade_esperada,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tempo de verde da via H até começar o amarelo, com carros parados sobre o laço
+    // de 5 m nas vias dadas, ou None se o verde passa de um minuto
+    fn verde_da_via_h(parados: &[Via]) -> Option<f64> {
+        let mut atuado = Atuado::new(&Cenario::default());
+        let mut situacao = HashMap::new();
+        for via in parados {
+            let placa = format!("{:?}", via);
+            situacao.insert(placa.clone(), Situacao::de_teste(&placa, *via, -5.0, 0.0));
+        }
+        while atuado.tempo_verde < 60_000.0 {
+            atuado.estrategia(50.0, &mut situacao);
+            if atuado.amarelo {
+                return Some(atuado.tempo_verde);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn verde_termina_por_intervalo_por_maximo_ou_continua() {
+        // Ninguém na via verde: acaba no verde mínimo
+        assert_eq!(verde_da_via_h(&[Via::ViaV]), Some(VERDE_MINIMO));
+        // Detecções seguidas na via verde: acaba no verde máximo
+        assert_eq!(verde_da_via_h(&[Via::ViaH, Via::ViaV]), Some(VERDE_MAXIMO));
+        // Sem chamada da via vermelha, o verde não acaba
+        assert_eq!(verde_da_via_h(&[Via::ViaH]), None);
+        assert_eq!(verde_da_via_h(&[]), None);
+    }
+}
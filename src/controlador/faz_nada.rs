@@ -2,22 +2,26 @@ use std::collections::HashMap;
 
 use super::{Controlador, Situacao};
 
+use crate::cenario::Cenario;
+use crate::registro::{registra, Categoria, Nivel};
+
 // Algoritmo de controle que não faz nada
-pub struct FazNada {
-    display_tudo: bool,
-}
+pub struct FazNada {}
 
 impl Controlador for FazNada {
     // Cria um novo faz nada
-    fn new(display_tudo: bool) -> Self {
-        Self { display_tudo }
+    fn new(_cenario: &Cenario) -> Self {
+        Self {}
     }
 
     // Cálcula ações de controle
     fn estrategia(&mut self, tempo_decorrido: f64, situacao: &mut HashMap<String, Situacao>) {
-        if self.display_tudo {
-            println!("FazNada tempo decorrido {}", tempo_decorrido);
-        }
+        registra!(
+            Categoria::Controlador,
+            Nivel::Depuracao,
+            "FazNada tempo decorrido {}",
+            tempo_decorrido
+        );
 
         // Monta uma lista ordenada para as duas vias
         struct MiniSituacao {
@@ -30,7 +34,14 @@ impl Controlador for FazNada {
         // Versão mais eficiente
         for (key, val) in situacao.iter_mut() {
             val.acel_desejada = 0.0;
-            println!("key: {}    val: {:?}", key, val);
+            registra!(
+                Categoria::Controlador,
+                Nivel::Detalhe,
+                placa = key,
+                "key: {}    val: {:?}",
+                key,
+                val
+            );
         }
 
         // Versão copiada e simplificada do Semáforo
@@ -39,7 +50,14 @@ impl Controlador for FazNada {
                 placa: val.placa.clone(),
                 pos_atual: val.pos_atual,
             });
-            println!("key: {}    val: {:?}", key, val);
+            registra!(
+                Categoria::Controlador,
+                Nivel::Detalhe,
+                placa = key,
+                "key: {}    val: {:?}",
+                key,
+                val
+            );
         }
         ordem_duas_vias.sort_unstable_by(|a, b| b.pos_atual.partial_cmp(&a.pos_atual).unwrap());
         // Ações para veículos nas duas vias
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use super::reserva::{acel_para_chegar, tempo_para_percorrer};
+use super::semaforo::{acel_seguindo, ordem_da_via};
+use super::{atraso_controle, idade_esperada, Controlador, Situacao};
+
+use crate::cenario::Cenario;
+use crate::registro::{registra, Categoria, Nivel};
+
+use crate::transito::{Geometria, Via};
+
+pub const INTERVALO: f64 = 1000.0; // Distância em tempo padrão até o líder virtual, em ms
+const FOLGA_PARADA: f64 = 0.5; // metros antes do cruzamento onde o veículo para, se precisar
+
+// Parâmetros do controlador por ordem de chegada
+#[derive(Debug, Copy, Clone)]
+pub struct ParametrosFifo {
+    pub intervalo: f64, // ms mínimos até o carro da frente na ordem de passagem
+}
+
+impl Default for ParametrosFifo {
+    fn default() -> Self {
+        Self {
+            intervalo: INTERVALO,
+        }
+    }
+}
+
+// Algoritmo de controle em que o primeiro a chegar é o primeiro a passar
+// A ordem de passagem é a do instante estimado de chegada ao cruzamento, calculado quando
+// o veículo aparece. Cada veículo segue um líder virtual, o anterior na ordem de passagem
+// em qualquer das vias, pelo tempo: só chega ao cruzamento 'intervalo' depois da
+// traseira do líder passar por ele, ou sair da zona de conflito se o líder é da outra
+// via, no instante projetado com a velocidade e a aceleração informadas pelo líder.
+// Quem chegaria antes desacelera de forma constante para chegar nesse instante, ou
+// para antes do cruzamento, sem pedir mais que a desaceleração máxima. Também não
+// alcança o carro da frente na sua própria via
+pub struct Fifo {
+    agora: f64,                     // tempo desde a criação do controlador, em ms
+    chegadas: HashMap<String, f64>, // instante estimado de chegada ao cruzamento, em ms
+    ordem: Vec<String>,             // placas em ordem de passagem, na última chamada
+    intervalo: f64,                 // ms
+    geometria: Geometria,           // geometria do cruzamento
+    velocidade_cruzeiro: f64,       // metros por segundo
+    atraso: f64,                    // até a aceleração decidida ser aplicada, em s
+    idade_esperada: f64,            // da situação dos veículos, em ms
+}
+
+// Tempo até a frente de 'veiculo' percorrer 'distancia' metros, em s, mantendo a
+// aceleração informada até a velocidade de cruzeiro ou até parar
+fn tempo_projetado(veiculo: &Situacao, distancia: f64, vel_cruzeiro: f64) -> f64 {
+    let (vel, acel) = (veiculo.vel_atual, veiculo.acel_atual);
+    if distancia <= 0.0 {
+        0.0
+    } else if acel > 0.0 {
+        tempo_para_percorrer(distancia, vel, acel, vel_cruzeiro.max(vel))
+    } else if acel < 0.0 && vel.powi(2) > 2.0 * -acel * distancia {
+        (vel - (vel.powi(2) + 2.0 * acel * distancia).sqrt()) / -acel
+    } else if acel == 0.0 && vel > 0.0 {
+        distancia / vel
+    } else {
+        f64::MAX // nunca, para antes
+    }
+}
+
+impl Fifo {
+    // Ordem de passagem dos veículos que ainda não saíram da zona de conflito, e em cada
+    // via sempre da frente para trás
+    fn ordena(&mut self, situacao: &HashMap<String, Situacao>) {
+        self.chegadas
+            .retain(|placa, _| situacao.contains_key(placa));
+        for (placa, v) in situacao.iter() {
+            // Antes de informar, a posição é só um chute
+            if v.informou && !self.chegadas.contains_key(placa) {
+                let t_min = tempo_para_percorrer(
+                    -v.pos_atual,
+                    v.vel_atual,
+                    v.acel_max,
+                    self.velocidade_cruzeiro,
+                );
+                self.chegadas
+                    .insert(placa.clone(), self.agora + 1000.0 * t_min);
+            }
+        }
+
+        let mut ordem: Vec<(String, f64)> = self
+            .chegadas
+            .iter()
+            .filter(|(placa, _)| {
+                let v = &situacao[*placa];
+                v.pos_atual < self.geometria.largura_cruzamento(v.via) + v.comprimento
+            })
+            .map(|(placa, chegada)| (placa.clone(), *chegada))
+            .collect();
+        ordem.sort_unstable_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.cmp(&b.0)));
+        self.ordem = ordem.into_iter().map(|(placa, _)| placa).collect();
+
+        // Ninguém passa o carro da frente: os lugares de cada via na ordem vão para os
+        // veículos dela da frente para trás
+        for via in [Via::ViaH, Via::ViaV] {
+            let lugares: Vec<usize> = (0..self.ordem.len())
+                .filter(|i| situacao[&self.ordem[*i]].via == via)
+                .collect();
+            let mut placas: Vec<String> = lugares.iter().map(|i| self.ordem[*i].clone()).collect();
+            placas.sort_by(|a, b| {
+                situacao[b]
+                    .pos_atual
+                    .partial_cmp(&situacao[a].pos_atual)
+                    .unwrap()
+            });
+            for (i, placa) in lugares.into_iter().zip(placas) {
+                self.ordem[i] = placa;
+            }
+        }
+    }
+}
+
+impl Controlador for Fifo {
+    // Cria um novo controlador por ordem de chegada
+    fn new(cenario: &Cenario) -> Self {
+        Self {
+            agora: 0.0,
+            chegadas: HashMap::new(),
+            ordem: Vec::new(),
+            intervalo: cenario.fifo.intervalo,
+            geometria: cenario.geometria,
+            velocidade_cruzeiro: cenario.veiculo.velocidade_cruzeiro,
+            atraso: atraso_controle(cenario) / 1000.0,
+            idade_esperada: idade_esperada(cenario),
+        }
+    }
+
+    // Os próximos na ordem de passagem
+    fn painel(&self) -> Option<String> {
+        let proximos: Vec<&str> = self.ordem.iter().take(4).map(|p| p.as_str()).collect();
+        Some(format!(
+            "ordem de chegada, intervalo {:.1} s, próximos: {}",
+            self.intervalo / 1000.0,
+            proximos.join(" ")
+        ))
+    }
+
+    // Cálcula ações de controle, tempos em milissegundos !!!
+    fn estrategia(&mut self, tempo_decorrido: f64, situacao: &mut HashMap<String, Situacao>) {
+        self.agora += tempo_decorrido;
+        self.ordena(situacao);
+
+        // Líder virtual de cada veículo: o anterior na ordem de passagem, com o tempo até
+        // ele liberar o cruzamento, em s
+        let mut lideres: HashMap<String, (String, f64)> = HashMap::new();
+        for i in 1..self.ordem.len() {
+            let lider = &situacao[&self.ordem[i - 1]];
+            let veiculo = &situacao[&self.ordem[i]];
+            let mut distancia = lider.comprimento - lider.pos_atual;
+            if lider.via != veiculo.via {
+                distancia += self.geometria.largura_cruzamento(lider.via);
+            }
+            let livre_em = tempo_projetado(lider, distancia, self.velocidade_cruzeiro);
+            lideres.insert(self.ordem[i].clone(), (lider.placa.clone(), livre_em));
+        }
+
+        for via in [Via::ViaH, Via::ViaV] {
+            let mut afrente: Option<(f64, f64)> = None; // traseira e velocidade do carro da frente na via
+            for placa in ordem_da_via(situacao, via) {
+                let veiculo = situacao.get_mut(&placa).unwrap(); // Sei que a placa existe
+                let (mut acel, _) = acel_seguindo(
+                    veiculo,
+                    afrente,
+                    self.velocidade_cruzeiro,
+                    self.intervalo,
+                    self.atraso,
+                    self.idade_esperada,
+                );
+
+                // Quem já entrou na zona de conflito não espera mais ninguém
+                let lider = lideres.get(&placa).filter(|_| veiculo.pos_atual <= 0.0);
+                if let Some((_, livre_em)) = lider {
+                    let tempo = livre_em + self.intervalo / 1000.0;
+                    let cedo = tempo
+                        > tempo_para_percorrer(
+                            -veiculo.pos_atual,
+                            veiculo.vel_atual,
+                            veiculo.acel_max,
+                            self.velocidade_cruzeiro,
+                        );
+                    if cedo {
+                        let folga = FOLGA_PARADA
+                            + veiculo.vel_atual * veiculo.atraso(self.atraso, self.idade_esperada);
+                        let acel_lider =
+                            acel_para_chegar(veiculo, -veiculo.pos_atual, tempo, folga);
+                        acel = acel.min(acel_lider).max(veiculo.acel_min);
+                    }
+                }
+                veiculo.acel_desejada = acel;
+
+                registra!(
+                    Categoria::Controlador,
+                    Nivel::Depuracao,
+                    placa = &placa,
+                    "#FIFO @{} {:?} atrás de @{}, pos {:.2}, vel {:.2}, acel {:.2}",
+                    placa,
+                    via,
+                    lider.map_or("-", |(l, _)| l.as_str()),
+                    veiculo.pos_atual,
+                    veiculo.vel_atual,
+                    veiculo.acel_desejada
+                );
+                afrente = Some((veiculo.pos_atual - veiculo.comprimento, veiculo.vel_atual));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cenario::Cenario;
+    use crate::controlador::TipoControlador;
+    use crate::registro::{self, ConfiguracaoLog};
+    use crate::simulacao::{cria_simulacao, laco_simulacao};
+
+    // Simula até 'fim' ms com chegadas a cada 'entre_chegadas' ms (mínimo e máximo) nas
+    // duas vias e confere que ninguém colide e que o trânsito escoa
+    fn sem_colisao(entre_chegadas: (f64, f64), semente: u64, fim: f64) {
+        registro::configura(ConfiguracaoLog::interpreta("nenhum").unwrap());
+        let mut cenario = Cenario::default();
+        cenario.controle.tipo = TipoControlador::Fifo;
+        for chegadas in [&mut cenario.chegadas_h, &mut cenario.chegadas_v] {
+            (chegadas.tec_min, chegadas.tec_max) = entre_chegadas;
+        }
+        let mut simul = cria_simulacao(&cenario, semente);
+        while simul.tempo_simulado < fim {
+            laco_simulacao(&mut simul);
+            assert!(
+                simul.transito.ocorreu_colisao().is_none(),
+                "semente {}: {:?}",
+                semente,
+                simul.colisao
+            );
+        }
+        assert!(simul.transito.get_carros_saidos() as f64 > fim / entre_chegadas.1);
+    }
+
+    // Chegadas do cenário padrão
+    #[test]
+    fn demanda_baixa_sem_colisao() {
+        for semente in 1..=3 {
+            sem_colisao((3000.0, 6000.0), semente, 200_000.0);
+        }
+    }
+
+    // Seguindo o líder virtual pela posição, colidia aos 363,8 s
+    #[test]
+    fn cenario_padrao_semente_4_sem_colisao() {
+        sem_colisao((3000.0, 6000.0), 4, 400_000.0);
+    }
+
+    #[test]
+    fn demanda_moderada_sem_colisao() {
+        for semente in 1..=3 {
+            sem_colisao((2000.0, 3000.0), semente, 120_000.0);
+        }
+    }
+}
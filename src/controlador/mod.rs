@@ -21,79 +21,455 @@
 */
 
 use std::collections::HashMap;
+use std::fmt;
 
-use std::time::Instant;
+use crate::autenticacao::{chave_do_veiculo, cifra_chave, Chave};
+use crate::cenario::Cenario;
 
-use crate::comunicacao::{Comunicacao, MensagemDeVeiculo, MensagemDoControlador};
+use crate::comunicacao::{
+    Comunicacao, MensagemDeVeiculo, MensagemDoControlador, ParametrosComunicacao,
+};
 
-use crate::transito::{self, Via};
+use crate::registro::{registra, Categoria, Nivel};
+use crate::transito::veiculos::REPETE_CHEGADA;
+use crate::transito::{Geometria, Via};
 
 mod faz_nada;
 use faz_nada::FazNada;
 
 mod semaforo;
 use semaforo::Semaforo;
+pub use semaforo::{ParametrosSemaforo, TipoPlano};
 
-const TEMPO_ENTRE_CONTROLES: f64 = 50.0; // Tempo entre ações de controle, em ms
+mod reserva;
+use reserva::Reservas;
+
+mod atuado;
+use atuado::Atuado;
+pub use atuado::ParametrosAtuado;
+
+mod mpc;
+use mpc::Mpc;
+
+mod fifo;
+use fifo::Fifo;
+pub use fifo::ParametrosFifo;
+
+mod pelotao;
+pub use pelotao::ParametrosPelotoes;
+use pelotao::Pelotoes;
+
+pub const TEMPO_ENTRE_CONTROLES: f64 = 50.0; // Tempo padrão entre ações de controle, em ms
+const CHANCE_RETIRADA_INDEVIDA: f64 = 1e-6; // aceitável para retirar um veículo que só ficou sem resposta
+const CHANCE_ATRASO_MAIOR: f64 = 1e-2; // aceitável para um comando chegar depois de 'atraso_controle'
+const CHANCE_ALARME_FALSO: f64 = 1e-3; // aceitável para acusar falta de resposta só por perdas
 
 // Descreve funções exigidas de um Controlador implementado como submódulo
 pub trait Controlador {
-    // Cria um novo semáforo
-    fn new(display_tudo: bool) -> Self;
+    // Cria um novo controlador, configurado pelo cenário
+    fn new(cenario: &Cenario) -> Self;
 
     // Cálcula ações de controle
     fn estrategia(&mut self, tempo_decorrido: f64, situacao: &mut HashMap<String, Situacao>);
+
+    // Estado do controlador para mostrar na janela, se ele tem algo a mostrar
+    fn painel(&self) -> Option<String> {
+        None
+    }
 }
 
 // Usado para definir o tipo de controlador
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum TipoControlador {
     Semaforo,
     FazNada,
+    Reservas,
+    Atuado,
+    Mpc,
+    Fifo,
+    Pelotoes,
+}
+
+impl TipoControlador {
+    // Todos os controladores disponíveis
+    pub fn todos() -> Vec<Self> {
+        vec![
+            TipoControlador::Semaforo,
+            TipoControlador::FazNada,
+            TipoControlador::Reservas,
+            TipoControlador::Atuado,
+            TipoControlador::Mpc,
+            TipoControlador::Fifo,
+            TipoControlador::Pelotoes,
+        ]
+    }
+
+    // Nome usado no cenário e na linha de comando
+    pub fn nome(&self) -> &'static str {
+        match self {
+            TipoControlador::Semaforo => "semaforo",
+            TipoControlador::FazNada => "faz_nada",
+            TipoControlador::Reservas => "reservas",
+            TipoControlador::Atuado => "atuado",
+            TipoControlador::Mpc => "mpc",
+            TipoControlador::Fifo => "fifo",
+            TipoControlador::Pelotoes => "pelotoes",
+        }
+    }
+
+    // Letra usada como abreviação do nome
+    pub fn letra(&self) -> char {
+        match self {
+            TipoControlador::Semaforo => 's',
+            TipoControlador::FazNada => 'n',
+            TipoControlador::Reservas => 'o',
+            TipoControlador::Atuado => 'a',
+            TipoControlador::Mpc => 'm',
+            TipoControlador::Fifo => 'f',
+            TipoControlador::Pelotoes => 'p',
+        }
+    }
+
+    // Descrição curta do algoritmo
+    pub fn descricao(&self) -> &'static str {
+        match self {
+            TipoControlador::Semaforo => {
+                "semáforo com verde e amarelo do cenário, ou verde calculado por Webster"
+            }
+            TipoControlador::FazNada => "não controla, todos os veículos com aceleração zero",
+            TipoControlador::Reservas => {
+                "reserva de intervalos exclusivos no cruzamento, em ordem de chegada"
+            }
+            TipoControlador::Atuado => {
+                "semáforo atuado por detectores, verde entre mínimo e máximo estendido a cada passagem"
+            }
+            TipoControlador::Mpc => {
+                "controle preditivo, otimiza as acelerações num horizonte curto, em ordem de chegada"
+            }
+            TipoControlador::Fifo => {
+                "primeiro a chegar passa primeiro, seguindo o anterior de qualquer via a um intervalo"
+            }
+            TipoControlador::Pelotoes => {
+                "agrupa veículos seguidos de cada via em pelotões, que passam um de cada vez"
+            }
+        }
+    }
+
+    // Interpreta o nome do controlador, por extenso ou pela letra da linha de comando
+    pub fn de_nome(nome: &str) -> Option<Self> {
+        match nome {
+            "semaforo" | "s" => Some(TipoControlador::Semaforo),
+            "faz_nada" | "n" => Some(TipoControlador::FazNada),
+            "reservas" | "o" => Some(TipoControlador::Reservas),
+            "atuado" | "a" => Some(TipoControlador::Atuado),
+            "mpc" | "m" => Some(TipoControlador::Mpc),
+            "fifo" | "f" => Some(TipoControlador::Fifo),
+            "pelotoes" | "p" => Some(TipoControlador::Pelotoes),
+            _ => None,
+        }
+    }
+}
+
+// Parâmetros gerais do controle
+#[derive(Debug, Copy, Clone)]
+pub struct ParametrosControle {
+    pub tipo: TipoControlador,
+    pub tempo_entre_controles: f64, // ms
+}
+
+impl Default for ParametrosControle {
+    fn default() -> Self {
+        Self {
+            tipo: TipoControlador::Semaforo,
+            tempo_entre_controles: TEMPO_ENTRE_CONTROLES,
+        }
+    }
 }
 
 // Usado neste módulo para armazenar o controlador usado
 enum MeuControlador {
     Semaforo(Semaforo),
     FazNada(FazNada),
+    Reservas(Reservas),
+    Atuado(Atuado),
+    Mpc(Mpc),
+    Fifo(Fifo),
+    Pelotoes(Pelotoes),
 }
 
 // Descreve a situação de um veículo em particular
 #[derive(Debug)]
 pub struct Situacao {
-    placa: String,      // placa deste carro
-    via: Via,           // via deste carro
-    acel_max: f64,      // metros por segundo ao quadrado
-    acel_min: f64,      // metros por segundo ao quadrado
-    vel_max: f64,       // metros por segundo
-    comprimento: f64,   // metros
-    pos_atual: f64,     // metros do cruzamento
-    vel_atual: f64,     // metros por segundo
-    acel_atual: f64,    // metros por segundo ao quadrado
-    acel_desejada: f64, // aceleração desejada pelo controle, metros por segundo ao quadrado
-    estou_vivo: i32,    // recarrega quando tem comunicação
+    placa: String,       // placa deste carro
+    via: Via,            // via deste carro
+    acel_max: f64,       // metros por segundo ao quadrado
+    acel_min: f64,       // metros por segundo ao quadrado
+    vel_max: f64,        // metros por segundo
+    comprimento: f64,    // metros
+    pos_atual: f64,      // metros do cruzamento
+    vel_atual: f64,      // metros por segundo
+    acel_atual: f64,     // metros por segundo ao quadrado
+    acel_desejada: f64,  // aceleração desejada pelo controle, metros por segundo ao quadrado
+    estou_vivo: i32,     // recarrega quando tem comunicação
+    informou: bool,      // já recebeu a situação atual, antes disto posição e velocidade são chutes
+    sequencia: u64,      // da última mensagem aceita deste veículo
+    medida_em: f64,      // tempo simulado em que o veículo mediu a situação, ms
+    idade: f64,          // idade da situação quando a estratégia é calculada, ms
+    sem_resposta: bool,  // perdeu mais respostas seguidas que o aceitável, antes de sair
+    chave: Chave,        // autentica as mensagens para este veículo
+    espontaneo: bool,    // informa a situação sem ser pedido, não precisa de PedeSituacao
+    intervalo: f64,      // esperado entre situações, ms: o ciclo, ou o período dos informes
+    idade_esperada: f64, // da situação quando nada se perde, ms
+    vidas: i32,          // ciclos sem notícias até retirar o veículo
+}
+
+impl Situacao {
+    // Atraso a considerar para este veículo, em s: o 'previsto' para todos mais o quanto
+    // a situação dele passou da 'idade_esperada', como depois de respostas perdidas
+    fn atraso(&self, previsto: f64, idade_esperada: f64) -> f64 {
+        previsto + (self.idade - idade_esperada).max(0.0) / 1000.0
+    }
+
+    // Veículo do cenário padrão que já informou a situação, para os testes das estratégias
+    #[cfg(test)]
+    fn de_teste(placa: &str, via: Via, pos_atual: f64, vel_atual: f64) -> Self {
+        let veiculo = crate::transito::ParametrosVeiculo::default();
+        Self {
+            placa: placa.to_string(),
+            via,
+            acel_max: veiculo.aceleracao_maxima,
+            acel_min: veiculo.aceleracao_minima,
+            vel_max: veiculo.velocidade_maxima,
+            comprimento: veiculo.comprimento,
+            pos_atual,
+            vel_atual,
+            acel_atual: 0.0,
+            acel_desejada: 0.0,
+            estou_vivo: 1,
+            informou: true,
+            sequencia: 1,
+            medida_em: 0.0,
+            idade: 0.0,
+            sem_resposta: false,
+            chave: [0; 32],
+            espontaneo: false,
+            intervalo: 50.0,
+            idade_esperada: 0.0,
+            vidas: 1,
+        }
+    }
+}
+
+// Aceleração decidida para um veículo, com a situação que o controlador conhecia
+#[derive(Debug, Clone)]
+pub struct Decisao {
+    pub placa: String,
+    pub pos_atual: f64,     // metros do cruzamento
+    pub vel_atual: f64,     // metros por segundo
+    pub acel_desejada: f64, // metros por segundo ao quadrado
+}
+
+// Algo que o controlador percebeu na comunicação com um veículo
+#[derive(Debug, Clone)]
+pub enum EventoControle {
+    SemResposta { placa: String, ciclos: i32 }, // ciclos seguidos sem resposta, antes de sair
+    VoltouAResponder { placa: String, ausencia: f64 }, // ms desde a resposta anterior
+    Retirado { placa: String }, // retirado ainda sem resposta, sem ter saído do cruzamento
+}
+
+impl EventoControle {
+    // Placa do veículo do evento
+    pub fn placa(&self) -> &str {
+        match self {
+            EventoControle::SemResposta { placa, .. }
+            | EventoControle::VoltouAResponder { placa, .. }
+            | EventoControle::Retirado { placa } => placa,
+        }
+    }
+}
+
+impl fmt::Display for EventoControle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EventoControle::SemResposta { placa, ciclos } => {
+                write!(f, "@{} sem resposta há {} ciclos", placa, ciclos)
+            }
+            EventoControle::VoltouAResponder { placa, ausencia } => write!(
+                f,
+                "@{} voltou a responder depois de {:.3} s",
+                placa,
+                ausencia / 1000.0
+            ),
+            EventoControle::Retirado { placa } => {
+                write!(
+                    f,
+                    "@{} retirado sem resposta antes de sair do cruzamento",
+                    placa
+                )
+            }
+        }
+    }
+}
+
+// Ciclos de controle sem notícias até um veículo ser considerado fora do cruzamento,
+// para quem informa a situação a cada 'intervalo' ms, que demora até 'demora' ms para
+// chegar. Com comunicação ideal e pedidos a cada ciclo bastam 2, a demora e as perdas
+// pedem mais, e informes mais espaçados multiplicam as perdas
+fn vidas(comunicacao: &ParametrosComunicacao, ciclo: f64, intervalo: f64, demora: f64) -> i32 {
+    let por_informe = (intervalo / ciclo).ceil() as i32;
+    1 + por_informe * (1 + comunicacao.perdas_seguidas(CHANCE_RETIRADA_INDEVIDA))
+        + (demora / ciclo).ceil() as i32
+}
+
+// Atraso máximo, com alta probabilidade, entre a medida da situação de um veículo e a
+// aplicação da aceleração decidida com ela, em ms. Zero com comunicação ideal
+pub fn atraso_controle(cenario: &Cenario) -> f64 {
+    let comunicacao = &cenario.comunicacao;
+    comunicacao.ida_e_volta()
+        + cenario.controle.tempo_entre_controles
+            * comunicacao.perdas_seguidas(CHANCE_ATRASO_MAIOR) as f64
+}
+
+// Informes seguidos sem resposta de um veículo até o controlador avisar que algo está
+// errado; com comunicação ideal basta um, perdas comuns não devem disparar o aviso
+fn respostas_toleradas(cenario: &Cenario) -> i32 {
+    1 + cenario.comunicacao.perdas_seguidas(CHANCE_ALARME_FALSO)
+}
+
+// Tempo que um controlador reiniciado só escuta, sem comandar ninguém, em ms: os
+// veículos em falha segura repetem a chegada a cada REPETE_CHEGADA, e a situação de
+// cada um ainda precisa ser pedida e chegar, em 'tentativas' rodadas
+fn tempo_reconhecimento(cenario: &Cenario, tentativas: i32) -> f64 {
+    (REPETE_CHEGADA + cenario.controle.tempo_entre_controles) * tentativas as f64
+        + 2.0 * cenario.comunicacao.ida_e_volta()
+}
+
+// Idade máxima da situação de um veículo quando nenhuma mensagem se perde, em ms:
+// o pedido sai até um ciclo antes da estratégia, mais a ida e volta
+pub fn idade_esperada(cenario: &Cenario) -> f64 {
+    cenario.controle.tempo_entre_controles + cenario.comunicacao.ida_e_volta()
 }
 
 // Informações necessárias para realizar o controle
 pub struct Controle {
     situacao: HashMap<String, Situacao>,
     controlador: MeuControlador,
-    display_tudo: bool,
     tempo_ateh_proxima_solicitacao: f64,
     tempo_ateh_proxima_estrategia: f64,
+    tempo_entre_controles: f64, // ms
+    respostas_toleradas: i32,   // informes perdidos seguidos até avisar
+    comunicacao: ParametrosComunicacao,
+    geometria: Geometria,
+    chave: Chave, // do cruzamento, sorteada a cada início, dela saem as chaves dos veículos
+    segredo: Chave, // do cruzamento, combinado com os veículos, dele saem as credenciais
+    reconhecimento: f64, // ms que ainda faltam só escutando, depois de um reinício
+    reconhecimento_minimo: f64, // ms até poder terminar antes, se todos já informaram
+    decisoes: Option<Vec<Decisao>>, // decisões tomadas, se alguém está observando
+    eventos: Vec<EventoControle>, // percebidos desde a última 'retira_eventos'
 }
 
 impl Controle {
-    // Cria um novo controlador
-    pub fn new(tipo: TipoControlador) -> Self {
+    // Cria um novo controlador, do tipo definido no cenário, com o 'segredo' combinado
+    // com os veículos e a 'chave' do cruzamento
+    pub fn new(cenario: &Cenario, segredo: Chave, chave: Chave) -> Self {
+        let tempo_entre_controles = cenario.controle.tempo_entre_controles;
         Self {
             situacao: HashMap::new(),
-            controlador: match tipo {
-                TipoControlador::Semaforo => MeuControlador::Semaforo(Semaforo::new(true)),
-                TipoControlador::FazNada => MeuControlador::FazNada(FazNada::new(true)),
+            controlador: match cenario.controle.tipo {
+                TipoControlador::Semaforo => MeuControlador::Semaforo(Semaforo::new(cenario)),
+                TipoControlador::FazNada => MeuControlador::FazNada(FazNada::new(cenario)),
+                TipoControlador::Reservas => MeuControlador::Reservas(Reservas::new(cenario)),
+                TipoControlador::Atuado => MeuControlador::Atuado(Atuado::new(cenario)),
+                TipoControlador::Mpc => MeuControlador::Mpc(Mpc::new(cenario)),
+                TipoControlador::Fifo => MeuControlador::Fifo(Fifo::new(cenario)),
+                TipoControlador::Pelotoes => MeuControlador::Pelotoes(Pelotoes::new(cenario)),
             },
-            display_tudo: true,
-            tempo_ateh_proxima_solicitacao: TEMPO_ENTRE_CONTROLES - 100.0,
-            tempo_ateh_proxima_estrategia: TEMPO_ENTRE_CONTROLES,
+            tempo_ateh_proxima_solicitacao: tempo_entre_controles - 100.0,
+            tempo_ateh_proxima_estrategia: tempo_entre_controles,
+            tempo_entre_controles,
+            respostas_toleradas: respostas_toleradas(cenario),
+            comunicacao: cenario.comunicacao,
+            geometria: cenario.geometria,
+            chave,
+            segredo,
+            reconhecimento: 0.0,
+            reconhecimento_minimo: 0.0,
+            decisoes: None,
+            eventos: Vec::new(),
+        }
+    }
+
+    // Cria um controlador que volta de uma queda, sem memória: primeiro só escuta as
+    // chegadas repetidas pelos veículos em falha segura e a situação deles, e só comanda
+    // alguém quando todos os veículos conhecidos informaram a posição, depois de pelo
+    // menos uma rodada de chegadas repetidas. Se alguém não informa, por perdas, desiste
+    // de esperar depois das rodadas que as perdas comuns pedem
+    pub fn reiniciado(cenario: &Cenario, segredo: Chave, chave: Chave) -> Self {
+        let mut controle = Controle::new(cenario, segredo, chave);
+        let tentativas = 1 + cenario.comunicacao.perdas_seguidas(CHANCE_ALARME_FALSO);
+        controle.reconhecimento = tempo_reconhecimento(cenario, tentativas);
+        controle.reconhecimento_minimo = tempo_reconhecimento(cenario, 1);
+        controle
+    }
+
+    // Passa a guardar as decisões tomadas a cada estratégia
+    pub fn registra_decisoes(&mut self) {
+        self.decisoes = Some(Vec::new());
+    }
+
+    // Retira as decisões tomadas desde a última chamada
+    pub fn retira_decisoes(&mut self) -> Vec<Decisao> {
+        match &mut self.decisoes {
+            None => Vec::new(),
+            Some(decisoes) => std::mem::take(decisoes),
+        }
+    }
+
+    // Retira os eventos percebidos desde a última chamada
+    pub fn retira_eventos(&mut self) -> Vec<EventoControle> {
+        std::mem::take(&mut self.eventos)
+    }
+
+    // Registra e guarda um evento
+    fn emite(&mut self, evento: EventoControle) {
+        registra!(
+            Categoria::Controlador,
+            Nivel::Aviso,
+            placa = evento.placa(),
+            "{}",
+            evento
+        );
+        self.eventos.push(evento);
+    }
+
+    // Placas conhecidas, sempre na mesma ordem para a simulação poder ser repetida
+    fn placas_em_ordem(&self) -> Vec<String> {
+        let mut placas: Vec<String> = self.situacao.keys().cloned().collect();
+        placas.sort_unstable();
+        placas
+    }
+
+    // Entrega a 'chave' de um veículo cifrada e autenticada com a credencial dele, que
+    // só ele e quem tem o segredo do cruzamento conhecem
+    fn entrega_chave(&self, placa: String, chave: &Chave, comunicacao: &mut Comunicacao) {
+        let credencial = chave_do_veiculo(&self.segredo, &placa);
+        let msg = MensagemDoControlador::Chave {
+            placa: placa.clone(),
+            chave: cifra_chave(chave, &credencial, comunicacao.agora()),
+        };
+        comunicacao.send_por_controlador(placa, msg, &credencial);
+    }
+
+    // Estado do controlador para mostrar na janela
+    pub fn painel(&self) -> Option<String> {
+        match &self.controlador {
+            MeuControlador::Semaforo(ss) => ss.painel(),
+            MeuControlador::FazNada(nn) => nn.painel(),
+            MeuControlador::Reservas(rr) => rr.painel(),
+            MeuControlador::Atuado(aa) => aa.painel(),
+            MeuControlador::Mpc(mm) => mm.painel(),
+            MeuControlador::Fifo(ff) => ff.painel(),
+            MeuControlador::Pelotoes(pp) => pp.painel(),
         }
     }
 
@@ -103,8 +479,10 @@ impl Controle {
         loop {
             match comunicacao.receive_por_controlador() {
                 None => break,
-                Some(msg) => {
-                    match msg {
+                Some(pacote) => {
+                    let sequencia = pacote.sequencia;
+                    let enviada_em = pacote.enviada_em;
+                    match pacote.mensagem {
                         MensagemDeVeiculo::Chegada {
                             placa,
                             via,
@@ -112,7 +490,39 @@ impl Controle {
                             acel_min,
                             vel_max,
                             comprimento,
+                            periodo_informe,
                         } => {
+                            // Chegada repetida ou duplicada não apaga o que já se sabe, mas
+                            // a chave vai de novo: o veículo repete a chegada até recebê-la
+                            if let Some(veiculo) = self.situacao.get(&placa) {
+                                self.entrega_chave(placa, &veiculo.chave, comunicacao);
+                                continue;
+                            }
+                            registra!(
+                                Categoria::Controlador,
+                                Nivel::Info,
+                                placa = &placa,
+                                "chegada de @{} na {:?}",
+                                placa,
+                                via
+                            );
+                            let chave = chave_do_veiculo(&self.chave, &placa);
+                            self.entrega_chave(placa.clone(), &chave, comunicacao);
+
+                            // Quem informa sozinho manda a situação a cada período, e ela
+                            // só faz o caminho de ida
+                            let espontaneo = periodo_informe > 0.0;
+                            let (intervalo, demora) = if espontaneo {
+                                (periodo_informe, self.comunicacao.de_veiculo.atraso_maximo())
+                            } else {
+                                (self.tempo_entre_controles, self.comunicacao.ida_e_volta())
+                            };
+                            let vidas = vidas(
+                                &self.comunicacao,
+                                self.tempo_entre_controles,
+                                intervalo,
+                                demora,
+                            );
                             let novo = Situacao {
                                 placa,
                                 via,
@@ -120,15 +530,21 @@ impl Controle {
                                 acel_min,
                                 vel_max,
                                 comprimento,
-                                pos_atual: match via {
-                                    // Na entrada está longe !!!
-                                    Via::ViaH => -transito::VIAH_PERIMETRO,
-                                    Via::ViaV => -transito::VIAV_PERIMETRO,
-                                },
+                                pos_atual: -self.geometria.perimetro(via), // Na entrada está longe !!!
                                 vel_atual: 0.0,
                                 acel_atual: 0.0,
                                 acel_desejada: 0.0,
-                                estou_vivo: 2,
+                                estou_vivo: vidas,
+                                informou: false,
+                                sequencia,
+                                medida_em: enviada_em,
+                                idade: 0.0,
+                                sem_resposta: false,
+                                chave,
+                                espontaneo,
+                                intervalo,
+                                idade_esperada: intervalo + demora,
+                                vidas,
                             };
                             self.situacao.insert(novo.placa.clone(), novo);
                         }
@@ -143,11 +559,35 @@ impl Controle {
                             let velho = self.situacao.get_mut(&placa);
                             match velho {
                                 None => (),
+                                // Cópia repetida ou situação mais velha do que a já conhecida
+                                Some(veiculo) if sequencia <= veiculo.sequencia => {
+                                    registra!(
+                                        Categoria::Controlador,
+                                        Nivel::Depuracao,
+                                        placa = &placa,
+                                        "descarta situação antiga de @{}, enviada em {:.3} s",
+                                        placa,
+                                        enviada_em / 1000.0
+                                    );
+                                }
                                 Some(veiculo) => {
+                                    let voltou = veiculo.sem_resposta.then(|| {
+                                        EventoControle::VoltouAResponder {
+                                            placa: placa.clone(),
+                                            ausencia: enviada_em - veiculo.medida_em,
+                                        }
+                                    });
+                                    veiculo.sem_resposta = false;
+                                    veiculo.sequencia = sequencia;
+                                    veiculo.medida_em = enviada_em;
                                     veiculo.pos_atual = pos_atual;
                                     veiculo.vel_atual = vel_atual;
                                     veiculo.acel_atual = acel_atual;
-                                    veiculo.estou_vivo = 2;
+                                    veiculo.estou_vivo = veiculo.vidas;
+                                    veiculo.informou = true;
+                                    if let Some(evento) = voltou {
+                                        self.emite(evento);
+                                    }
                                 }
                             }
                         }
@@ -156,18 +596,47 @@ impl Controle {
             }
         }
 
-        // Solicita nova situação de todos os veículos conhecidos
+        // Solicita nova situação dos veículos conhecidos que só informam quando pedida
         self.tempo_ateh_proxima_solicitacao -= tempo_decorrido;
         if self.tempo_ateh_proxima_solicitacao <= 0.0 {
-            self.tempo_ateh_proxima_solicitacao += TEMPO_ENTRE_CONTROLES;
-            for placa in self.situacao.keys() {
-                // só precisa das chaves
-                println!("#controlador solicita situacao de @{}", placa);
+            self.tempo_ateh_proxima_solicitacao += self.tempo_entre_controles;
+            for placa in &self.placas_em_ordem() {
+                if self.situacao[placa].espontaneo {
+                    continue;
+                }
+                registra!(
+                    Categoria::Controlador,
+                    Nivel::Detalhe,
+                    placa = placa,
+                    "solicita situacao de @{}",
+                    placa
+                );
                 let msg = MensagemDoControlador::PedeSituacao {
                     placa: placa.to_string(),
                 };
-                comunicacao.send_por_controlador(placa.to_string(), msg);
+                let chave = &self.situacao[placa].chave;
+                comunicacao.send_por_controlador(placa.to_string(), msg, chave);
+            }
+        }
+
+        // Depois de um reinício, nenhuma estratégia antes de saber onde estão os veículos
+        if self.reconhecimento > 0.0 {
+            self.reconhecimento -= tempo_decorrido;
+            self.reconhecimento_minimo -= tempo_decorrido;
+            let todos_informaram = self.situacao.values().all(|v| v.informou);
+            if self.reconhecimento_minimo <= 0.0 && todos_informaram {
+                self.reconhecimento = 0.0;
+            }
+            if self.reconhecimento <= 0.0 {
+                registra!(
+                    Categoria::Controlador,
+                    Nivel::Aviso,
+                    "reconhecimento terminado, {} veículos conhecidos, {} informaram a situação",
+                    self.situacao.len(),
+                    self.situacao.values().filter(|v| v.informou).count()
+                );
             }
+            return;
         }
 
         // Se está na hora:
@@ -176,8 +645,13 @@ impl Controle {
         // 		(3) Envia novas acelerações para os veículos
         self.tempo_ateh_proxima_estrategia -= tempo_decorrido;
         if self.tempo_ateh_proxima_estrategia <= 0.0 {
-            self.tempo_ateh_proxima_estrategia += TEMPO_ENTRE_CONTROLES;
-            println!("#controlador: depois do sleep (s): {:?}", Instant::now());
+            self.tempo_ateh_proxima_estrategia += self.tempo_entre_controles;
+            registra!(
+                Categoria::Controlador,
+                Nivel::Detalhe,
+                "calcula estrategia com {} veículos",
+                self.situacao.len()
+            );
 
             // (1) Retira da 'situacao' veículos que já sairam do cruzamento
             let mut retirar: Vec<String> = Vec::new();
@@ -187,35 +661,96 @@ impl Controle {
                     retirar.push(v.placa.clone());
                 }
             }
+            retirar.sort_unstable();
             for k in retirar {
-                println!("#controlador retira da base de dados veículo @{}", k);
+                if self.situacao[&k].sem_resposta {
+                    self.emite(EventoControle::Retirado { placa: k.clone() });
+                }
+                registra!(
+                    Categoria::Controlador,
+                    Nivel::Info,
+                    placa = &k,
+                    "retira da base de dados veículo @{}",
+                    k
+                );
                 self.situacao.remove(&k);
             }
 
             // (2) Calcula as ações de controle
+            let agora = comunicacao.agora();
+            for v in self.situacao.values_mut() {
+                v.idade = agora - v.medida_em;
+            }
+
+            // Silêncio de quem já passou pelo cruzamento é normal, é só a saída da via
+            let mut silenciosos = Vec::new();
+            for k in &self.placas_em_ordem() {
+                let v = &self.situacao[k];
+                let ciclos = ((v.idade - v.idade_esperada) / v.intervalo).floor() as i32;
+                let saiu = v.pos_atual > self.geometria.largura_cruzamento(v.via) + v.comprimento;
+                if !v.sem_resposta && !saiu && ciclos >= self.respostas_toleradas {
+                    silenciosos.push((k.clone(), ciclos));
+                }
+            }
+            for (placa, ciclos) in silenciosos {
+                self.situacao.get_mut(&placa).unwrap().sem_resposta = true;
+                self.emite(EventoControle::SemResposta { placa, ciclos });
+            }
             match &mut self.controlador {
                 MeuControlador::Semaforo(ss) => {
-                    ss.estrategia(TEMPO_ENTRE_CONTROLES, &mut self.situacao)
+                    ss.estrategia(self.tempo_entre_controles, &mut self.situacao)
                 }
                 MeuControlador::FazNada(nn) => {
-                    nn.estrategia(TEMPO_ENTRE_CONTROLES, &mut self.situacao)
+                    nn.estrategia(self.tempo_entre_controles, &mut self.situacao)
+                }
+                MeuControlador::Reservas(rr) => {
+                    rr.estrategia(self.tempo_entre_controles, &mut self.situacao)
+                }
+                MeuControlador::Atuado(aa) => {
+                    aa.estrategia(self.tempo_entre_controles, &mut self.situacao)
+                }
+                MeuControlador::Mpc(mm) => {
+                    mm.estrategia(self.tempo_entre_controles, &mut self.situacao)
+                }
+                MeuControlador::Fifo(ff) => {
+                    ff.estrategia(self.tempo_entre_controles, &mut self.situacao)
+                }
+                MeuControlador::Pelotoes(pp) => {
+                    pp.estrategia(self.tempo_entre_controles, &mut self.situacao)
                 }
             }
 
             // (3) Envia novas acelerações para os veículos
-            for (k, v) in &self.situacao {
+            //     Decisões sobre quem ainda não informou a situação não valem nada
+            for k in &self.placas_em_ordem() {
+                let v = &self.situacao[k];
+                if !v.informou {
+                    continue;
+                }
                 let msg = MensagemDoControlador::SetAcel {
                     placa: k.to_string(),
                     acel: v.acel_desejada,
                 };
-                comunicacao.send_por_controlador(k.to_string(), msg);
+                comunicacao.send_por_controlador(k.to_string(), msg, &v.chave);
 
-                if self.display_tudo {
-                    println!(
-                        "#controlador setAceleracao de @{} em {:.2}",
-                        k, v.acel_desejada
-                    );
+                if let Some(decisoes) = &mut self.decisoes {
+                    decisoes.push(Decisao {
+                        placa: k.to_string(),
+                        pos_atual: v.pos_atual,
+                        vel_atual: v.vel_atual,
+                        acel_desejada: v.acel_desejada,
+                    });
                 }
+
+                registra!(
+                    Categoria::Controlador,
+                    Nivel::Depuracao,
+                    placa = k,
+                    "setAceleracao de @{} em {:.2}, situação de {:.0} ms atrás",
+                    k,
+                    v.acel_desejada,
+                    v.idade
+                );
             }
         }
     }
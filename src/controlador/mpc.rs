@@ -0,0 +1,553 @@
+use std::collections::HashMap;
+
+use super::reserva::tempo_para_percorrer;
+use super::{atraso_controle, idade_esperada, Controlador, Situacao};
+
+use crate::cenario::Cenario;
+use crate::registro::{registra, Categoria, Nivel};
+
+use crate::transito::{Geometria, Via};
+
+const HORIZONTE: usize = 20; // passos do horizonte de previsão
+const PASSO: f64 = 0.25; // duração de cada passo do horizonte, em s
+const PESO_VELOCIDADE: f64 = 1.0; // custo do quadrado da falta (ou excesso) de velocidade de cruzeiro
+const PESO_ACELERACAO: f64 = 1.0; // custo do quadrado da aceleração
+const PESO_VARIACAO: f64 = 0.5; // custo do quadrado da variação da aceleração entre passos
+const MARGEM_ZONA: f64 = 0.5; // entre a saída de um veículo da zona de conflito e a entrada do próximo, em s
+const FOLGA_PARADA: f64 = 0.5; // metros antes do cruzamento onde o carro para, se precisar
+const ESPACAMENTO: f64 = 2.0; // metros mínimos até a traseira do carro da frente na mesma via
+const FOLGA_SOLUCAO: f64 = 0.1; // metros a menos nos limites de posição, pela solução aproximada
+const TOLERANCIA: f64 = 0.2; // violação aceita na solução aproximada, em metros ou m/s
+
+// Parâmetros do método ADMM
+const ITERACOES: usize = 400; // máximo por otimização
+const RHO: f64 = 1.0; // penalidade das restrições
+const SIGMA: f64 = 1e-6; // regularização
+const ALFA: f64 = 1.6; // relaxação
+const CONVERGENCIA: f64 = 1e-4; // resíduo para parar antes das 'ITERACOES'
+
+// Posição e velocidade previstas de um veículo no início de cada passo do horizonte
+struct Trajetoria {
+    pos: Vec<f64>, // metros, HORIZONTE + 1 pontos, o primeiro é o estado inicial
+    vel: Vec<f64>, // metros por segundo
+}
+
+impl Trajetoria {
+    // Integra as acelerações, constantes durante cada passo
+    fn integra(pos: f64, vel: f64, acel: &[f64]) -> Self {
+        let mut t = Trajetoria {
+            pos: vec![pos],
+            vel: vec![vel],
+        };
+        for a in acel {
+            let x = *t.pos.last().unwrap();
+            let v = *t.vel.last().unwrap();
+            t.pos.push(x + v * PASSO + 0.5 * a * PASSO * PASSO);
+            t.vel.push(v + a * PASSO);
+        }
+        t
+    }
+
+    // Onde a frente pararia freando com 'acel_min' a partir do fim do horizonte, em metros
+    fn parada(&self, acel_min: f64) -> f64 {
+        let v = self.vel[HORIZONTE].max(0.0);
+        self.pos[HORIZONTE] + v * v / (2.0 * -acel_min)
+    }
+
+    // Primeiro instante do horizonte com a frente em 'posicao' ou além, em s
+    fn chega_em(&self, posicao: f64) -> Option<f64> {
+        self.pos
+            .iter()
+            .position(|x| *x >= posicao)
+            .map(|k| k as f64 * PASSO)
+    }
+}
+
+// Plano já decidido de um veículo, restringe os que vêm depois na ordem
+struct Planejado {
+    via: Via,
+    comprimento: f64, // metros
+    acel_min: f64,    // metros por segundo ao quadrado
+    fim_zona: f64,    // posição da frente quando a traseira sai da zona de conflito, metros
+    trajetoria: Trajetoria,
+}
+
+// Até onde a frente de um veículo pode ir em cada ponto do horizonte
+struct Limites {
+    pos: Vec<f64>, // metros, HORIZONTE + 1 pontos, o primeiro não é usado
+    parada: f64,   // onde precisa conseguir parar depois do horizonte, metros
+}
+
+// Restrição linear 'min <= linha . acel <= max' do problema de otimização
+struct Restricao {
+    linha: Vec<f64>,
+    min: f64,
+    max: f64,
+}
+
+// Decomposição de Cholesky de uma matriz simétrica positiva definida, L tal que M = L Lt
+fn cholesky(m: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let n = m.len();
+    let mut l = vec![vec![0.0; n]; n];
+    for i in 0..n {
+        for j in 0..=i {
+            let soma: f64 = (0..j).map(|k| l[i][k] * l[j][k]).sum();
+            if i == j {
+                l[i][i] = (m[i][i] - soma).max(1e-12).sqrt();
+            } else {
+                l[i][j] = (m[i][j] - soma) / l[j][j];
+            }
+        }
+    }
+    l
+}
+
+// Resolve L Lt x = b
+fn resolve_cholesky(l: &[Vec<f64>], b: &[f64]) -> Vec<f64> {
+    let n = b.len();
+    let mut y = vec![0.0; n];
+    for i in 0..n {
+        let soma: f64 = (0..i).map(|k| l[i][k] * y[k]).sum();
+        y[i] = (b[i] - soma) / l[i][i];
+    }
+    let mut x = vec![0.0; n];
+    for i in (0..n).rev() {
+        let soma: f64 = (i + 1..n).map(|k| l[k][i] * x[k]).sum();
+        x[i] = (y[i] - soma) / l[i][i];
+    }
+    x
+}
+
+// Minimiza 1/2 xt P x + qt x com as restrições, pelo método ADMM (como o OSQP),
+// partindo de 'inicial'. A solução é aproximada: quem usa confere as restrições
+fn resolve_qp(p: &[Vec<f64>], q: &[f64], restricoes: &[Restricao], inicial: &[f64]) -> Vec<f64> {
+    let n = q.len();
+
+    // Linhas normalizadas, para a mesma penalidade servir para todas as restrições
+    let mut linhas: Vec<Vec<f64>> = Vec::new();
+    let mut min: Vec<f64> = Vec::new();
+    let mut max: Vec<f64> = Vec::new();
+    for r in restricoes {
+        let norma = r.linha.iter().map(|c| c * c).sum::<f64>().sqrt();
+        linhas.push(r.linha.iter().map(|c| c / norma).collect());
+        min.push(r.min / norma);
+        max.push(r.max / norma);
+    }
+    let multiplica = |x: &[f64]| -> Vec<f64> {
+        linhas
+            .iter()
+            .map(|linha| linha.iter().zip(x).map(|(c, x)| c * x).sum())
+            .collect()
+    };
+    let multiplica_transposta = |z: &[f64]| -> Vec<f64> {
+        let mut r = vec![0.0; n];
+        for (linha, z) in linhas.iter().zip(z) {
+            for j in 0..n {
+                r[j] += linha[j] * z;
+            }
+        }
+        r
+    };
+
+    // Matriz do sistema resolvido a cada iteração, fatorada uma vez: P + sigma I + rho At A
+    let mut k = p.to_vec();
+    for i in 0..n {
+        k[i][i] += SIGMA;
+        for linha in &linhas {
+            for j in 0..n {
+                k[i][j] += RHO * linha[i] * linha[j];
+            }
+        }
+    }
+    let l = cholesky(&k);
+
+    let mut x = inicial.to_vec();
+    let mut z: Vec<f64> = multiplica(&x)
+        .iter()
+        .enumerate()
+        .map(|(i, z)| z.clamp(min[i], max[i]))
+        .collect();
+    let mut y = vec![0.0; linhas.len()];
+
+    for _ in 0..ITERACOES {
+        let rz: Vec<f64> = z.iter().zip(&y).map(|(z, y)| RHO * z - y).collect();
+        let at_rz = multiplica_transposta(&rz);
+        let b: Vec<f64> = (0..n).map(|i| SIGMA * x[i] - q[i] + at_rz[i]).collect();
+        let x_til = resolve_cholesky(&l, &b);
+        let z_til = multiplica(&x_til);
+
+        for i in 0..n {
+            x[i] = ALFA * x_til[i] + (1.0 - ALFA) * x[i];
+        }
+        let z_anterior = z.clone();
+        for i in 0..z.len() {
+            let relaxado = ALFA * z_til[i] + (1.0 - ALFA) * z_anterior[i];
+            z[i] = (relaxado + y[i] / RHO).clamp(min[i], max[i]);
+            y[i] += RHO * (relaxado - z[i]);
+        }
+
+        // Resíduos primal (restrições) e dual (otimalidade)
+        let ax = multiplica(&x);
+        let primal = ax
+            .iter()
+            .zip(&z)
+            .map(|(a, z)| (a - z).abs())
+            .fold(0.0, f64::max);
+        let dz: Vec<f64> = z
+            .iter()
+            .zip(&z_anterior)
+            .map(|(z, za)| RHO * (z - za))
+            .collect();
+        let dual = multiplica_transposta(&dz)
+            .iter()
+            .fold(0.0, |m: f64, d| m.max(d.abs()));
+        if primal < CONVERGENCIA && dual < CONVERGENCIA {
+            break;
+        }
+    }
+    x
+}
+
+// Plano de 'plano' visto 'passos' passos (não necessariamente inteiros) mais tarde,
+// para partir dele na próxima otimização: cada ponto recebe o valor interpolado
+// 'passos' adiante, e depois do fim do horizonte repete o último
+fn desloca(plano: &[f64], passos: f64) -> Vec<f64> {
+    let ultimo = plano.len() - 1;
+    (0..plano.len())
+        .map(|k| {
+            let t = k as f64 + passos;
+            let i = (t.floor() as usize).min(ultimo);
+            let j = (i + 1).min(ultimo);
+            plano[i] + (t - i as f64).min(1.0) * (plano[j] - plano[i])
+        })
+        .collect()
+}
+
+// Plano seguro quando a otimização não encontra solução viável: quem está dentro da zona
+// de conflito sai dela o quanto antes, os outros freiam até parar
+fn frenagem(veiculo: &Situacao, pos: f64, vel: f64, fim_zona: f64) -> Vec<f64> {
+    if pos > 0.0 && pos < fim_zona {
+        return vec![veiculo.acel_max; HORIZONTE];
+    }
+    let mut acel = Vec::new();
+    let mut v = vel;
+    for _ in 0..HORIZONTE {
+        let a = veiculo.acel_min.max(-v / PASSO);
+        v += a * PASSO;
+        acel.push(a);
+    }
+    acel
+}
+
+// Algoritmo de controle preditivo (MPC)
+// A cada chamada, otimiza as acelerações de todos os veículos num horizonte de
+// HORIZONTE passos de PASSO segundos e aplica só a primeira. O custo soma o atraso
+// (distância da velocidade de cruzeiro) e o esforço (aceleração e sua variação),
+// com aceleração e velocidade dentro dos limites de cada veículo, sem alcançar o carro
+// da frente na mesma via e com um só veículo por vez na zona de conflito.
+// Não resolve o problema conjunto: os veículos são otimizados um de cada vez, em ordem
+// de chegada, como nas reservas. Cada um otimiza sua trajetória respeitando as já
+// decididas para os anteriores, sem poder mudá-las, só entra na zona de conflito
+// depois que os anteriores da outra via saíram dela e, no fim do horizonte, precisa
+// conseguir parar atrás de onde eles parariam. O resultado é viável mas não é o ótimo
+// conjunto: um anterior nunca cede nada para reduzir o custo dos que vêm depois.
+// Sem solução viável, o veículo recebe um plano de frenagem
+pub struct Mpc {
+    ordem: Vec<String>,                // placas em ordem de chegada
+    planos: HashMap<String, Vec<f64>>, // acelerações decididas na última chamada, ponto de partida da próxima
+    frenagens: i32,                    // veículos sem solução viável na última chamada
+    geometria: Geometria,              // geometria do cruzamento
+    velocidade_cruzeiro: f64,          // metros por segundo
+    atraso: f64,                       // até a aceleração decidida ser aplicada, em s
+    idade_esperada: f64,               // da situação dos veículos, em ms
+}
+
+impl Mpc {
+    // Ordem de chegada: antigos na ordem em que estavam, novos pelo tempo mínimo até o
+    // cruzamento, e em cada via sempre da frente para trás
+    fn ordena(&mut self, situacao: &HashMap<String, Situacao>) {
+        self.ordem
+            .retain(|placa| situacao.get(placa).is_some_and(|v| v.informou));
+        self.planos.retain(|placa, _| situacao.contains_key(placa));
+
+        let mut novos: Vec<(String, f64)> = Vec::new();
+        for (placa, v) in situacao.iter() {
+            // Antes de informar, a posição é só um chute
+            if v.informou && !self.ordem.contains(placa) {
+                let t_min = tempo_para_percorrer(
+                    -v.pos_atual,
+                    v.vel_atual,
+                    v.acel_max,
+                    self.velocidade_cruzeiro,
+                );
+                novos.push((placa.clone(), t_min));
+            }
+        }
+        novos.sort_unstable_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.cmp(&b.0)));
+        self.ordem.extend(novos.into_iter().map(|(placa, _)| placa));
+
+        // Ninguém passa o carro da frente: os lugares de cada via na ordem vão para os
+        // veículos dela da frente para trás
+        for via in [Via::ViaH, Via::ViaV] {
+            let lugares: Vec<usize> = (0..self.ordem.len())
+                .filter(|i| situacao[&self.ordem[*i]].via == via)
+                .collect();
+            let mut placas: Vec<String> = lugares.iter().map(|i| self.ordem[*i].clone()).collect();
+            placas.sort_by(|a, b| {
+                situacao[b]
+                    .pos_atual
+                    .partial_cmp(&situacao[a].pos_atual)
+                    .unwrap()
+            });
+            for (i, placa) in lugares.into_iter().zip(placas) {
+                self.ordem[i] = placa;
+            }
+        }
+    }
+
+    // Limites de posição de um veículo, dados os planos dos anteriores na ordem
+    fn limites(&self, via: Via, pos: f64, fim_zona: f64, planejados: &[Planejado]) -> Limites {
+        let mut limites = Limites {
+            pos: vec![f64::INFINITY; HORIZONTE + 1],
+            parada: f64::INFINITY,
+        };
+
+        // Não alcança o carro da frente, nem onde ele pararia depois do horizonte
+        if let Some(lider) = planejados.iter().rev().find(|p| p.via == via) {
+            let distancia = lider.comprimento + ESPACAMENTO;
+            for k in 1..=HORIZONTE {
+                limites.pos[k] = lider.trajetoria.pos[k] - distancia;
+            }
+            limites.parada = lider.trajetoria.parada(lider.acel_min) - distancia;
+        }
+
+        // Só entra na zona de conflito depois que os anteriores da outra via saírem dela
+        if pos < fim_zona {
+            let mut livre_em: f64 = 0.0;
+            let mut ocupada = false; // além do horizonte
+            for p in planejados.iter().filter(|p| p.via != via) {
+                if p.trajetoria.pos[0] >= p.fim_zona {
+                    continue;
+                }
+                match p.trajetoria.chega_em(p.fim_zona) {
+                    Some(t) => livre_em = livre_em.max(t + MARGEM_ZONA + self.atraso),
+                    None => ocupada = true,
+                }
+            }
+            for k in 1..=HORIZONTE {
+                if ocupada || (k as f64) * PASSO < livre_em {
+                    limites.pos[k] = limites.pos[k].min(-FOLGA_PARADA);
+                }
+            }
+            if ocupada || livre_em > HORIZONTE as f64 * PASSO {
+                limites.parada = limites.parada.min(-FOLGA_PARADA);
+            }
+        }
+        limites
+    }
+
+    // Acelerações ótimas de um veículo partindo de 'pos' e 'vel', ou None se o
+    // problema não tem solução viável
+    fn otimiza(
+        &self,
+        veiculo: &Situacao,
+        pos: f64,
+        vel: f64,
+        limites: &Limites,
+        inicial: &[f64],
+    ) -> Option<Vec<f64>> {
+        let n = HORIZONTE;
+        // Nunca passa da velocidade de cruzeiro, a não ser que já esteja acima dela
+        let vel_teto = veiculo.vel_max.min(vel.max(self.velocidade_cruzeiro));
+
+        // Velocidade e posição no fim do passo k são lineares nas acelerações:
+        // v = vel + B_v acel, x = pos + vel t + B_x acel
+        let mut b_v = vec![vec![0.0; n]; n];
+        let mut b_x = vec![vec![0.0; n]; n];
+        for k in 0..n {
+            for j in 0..=k {
+                b_v[k][j] = PASSO;
+                b_x[k][j] = PASSO * PASSO * ((k - j) as f64 + 0.5);
+            }
+        }
+
+        // Custo: velocidade longe da de cruzeiro, aceleração e variação da aceleração,
+        // a primeira variação a partir da aceleração atual
+        let mut p = vec![vec![0.0; n]; n];
+        let mut q = vec![0.0; n];
+        for i in 0..n {
+            for j in 0..n {
+                let btb: f64 = (0..n).map(|k| b_v[k][i] * b_v[k][j]).sum();
+                p[i][j] += 2.0 * PESO_VELOCIDADE * btb;
+            }
+            let falta = vel - self.velocidade_cruzeiro;
+            q[i] += 2.0 * PESO_VELOCIDADE * falta * (0..n).map(|k| b_v[k][i]).sum::<f64>();
+            p[i][i] += 2.0 * PESO_ACELERACAO;
+            p[i][i] += 2.0 * PESO_VARIACAO * if i + 1 < n { 2.0 } else { 1.0 };
+            if i + 1 < n {
+                p[i][i + 1] -= 2.0 * PESO_VARIACAO;
+                p[i + 1][i] -= 2.0 * PESO_VARIACAO;
+            }
+        }
+        q[0] -= 2.0 * PESO_VARIACAO * veiculo.acel_atual;
+
+        let mut restricoes: Vec<Restricao> = Vec::new();
+        for j in 0..n {
+            let mut linha = vec![0.0; n];
+            linha[j] = 1.0;
+            restricoes.push(Restricao {
+                linha,
+                min: veiculo.acel_min,
+                max: veiculo.acel_max,
+            });
+        }
+        for k in 0..n {
+            restricoes.push(Restricao {
+                linha: b_v[k].clone(),
+                min: -vel,
+                max: vel_teto - vel,
+            });
+            let limite = limites.pos[k + 1];
+            if limite.is_finite() {
+                restricoes.push(Restricao {
+                    linha: b_x[k].clone(),
+                    min: f64::NEG_INFINITY,
+                    max: limite - FOLGA_SOLUCAO - pos - vel * PASSO * (k + 1) as f64,
+                });
+            }
+        }
+        if limites.parada.is_finite() {
+            // A distância de parada v2 / 2|acel_min| é no máximo vel_teto v / 2|acel_min|,
+            // que é linear em v: a restrição fica mais conservadora, mas continua linear
+            let c = vel_teto / (2.0 * -veiculo.acel_min);
+            restricoes.push(Restricao {
+                linha: (0..n).map(|j| b_x[n - 1][j] + c * b_v[n - 1][j]).collect(),
+                min: f64::NEG_INFINITY,
+                max: limites.parada - FOLGA_SOLUCAO - pos - vel * PASSO * n as f64 - c * vel,
+            });
+        }
+
+        let acel: Vec<f64> = resolve_qp(&p, &q, &restricoes, inicial)
+            .iter()
+            .map(|a| a.clamp(veiculo.acel_min, veiculo.acel_max))
+            .collect();
+
+        // Confere a solução aproximada
+        let t = Trajetoria::integra(pos, vel, &acel);
+        let viavel = (1..=n).all(|k| {
+            t.vel[k] >= -TOLERANCIA
+                && t.vel[k] <= vel_teto + TOLERANCIA
+                && t.pos[k] <= limites.pos[k] + TOLERANCIA
+        }) && t.parada(veiculo.acel_min) <= limites.parada + TOLERANCIA;
+        if viavel {
+            Some(acel)
+        } else {
+            None
+        }
+    }
+}
+
+impl Controlador for Mpc {
+    // Cria um novo controlador preditivo
+    fn new(cenario: &Cenario) -> Self {
+        Self {
+            ordem: Vec::new(),
+            planos: HashMap::new(),
+            frenagens: 0,
+            geometria: cenario.geometria,
+            velocidade_cruzeiro: cenario.veiculo.velocidade_cruzeiro,
+            atraso: atraso_controle(cenario) / 1000.0,
+            idade_esperada: idade_esperada(cenario),
+        }
+    }
+
+    // Veículos planejados e quantos ficaram sem solução viável
+    fn painel(&self) -> Option<String> {
+        Some(format!(
+            "controle preditivo, {} veículos em {:.1} s de horizonte, {} em frenagem",
+            self.ordem.len(),
+            HORIZONTE as f64 * PASSO,
+            self.frenagens
+        ))
+    }
+
+    // Cálcula ações de controle, tempos em milissegundos !!!
+    fn estrategia(&mut self, tempo_decorrido: f64, situacao: &mut HashMap<String, Situacao>) {
+        self.ordena(situacao);
+        self.frenagens = 0;
+
+        // O horizonte andou 'tempo_decorrido' desde a última chamada
+        let passos = tempo_decorrido / 1000.0 / PASSO;
+
+        let mut planejados: Vec<Planejado> = Vec::new();
+        for placa in &self.ordem {
+            let veiculo = situacao.get(placa).unwrap(); // Sei que a placa existe
+
+            // Estado previsto para quando a aceleração decidida for aplicada
+            let t = veiculo.atraso(self.atraso, self.idade_esperada);
+            let vel = (veiculo.vel_atual + veiculo.acel_atual * t).max(0.0);
+            let pos = veiculo.pos_atual + (veiculo.vel_atual + vel) / 2.0 * t;
+            let fim_zona = self.geometria.largura_cruzamento(veiculo.via) + veiculo.comprimento;
+
+            let limites = self.limites(veiculo.via, pos, fim_zona, &planejados);
+            let inicial = match self.planos.get(placa) {
+                Some(plano) => desloca(plano, passos),
+                None => vec![0.0; HORIZONTE],
+            };
+            let acel = match self.otimiza(veiculo, pos, vel, &limites, &inicial) {
+                Some(acel) => acel,
+                None => {
+                    self.frenagens += 1;
+                    registra!(
+                        Categoria::Controlador,
+                        Nivel::Detalhe,
+                        placa = placa,
+                        "#MPC @{} {:?} sem solução viável em pos {:.2}, vel {:.2}, usa frenagem",
+                        placa,
+                        veiculo.via,
+                        pos,
+                        vel
+                    );
+                    frenagem(veiculo, pos, vel, fim_zona)
+                }
+            };
+
+            planejados.push(Planejado {
+                via: veiculo.via,
+                comprimento: veiculo.comprimento,
+                acel_min: veiculo.acel_min,
+                fim_zona,
+                trajetoria: Trajetoria::integra(pos, vel, &acel),
+            });
+
+            let veiculo = situacao.get_mut(placa).unwrap(); // Sei que a placa existe
+            veiculo.acel_desejada = acel[0];
+            registra!(
+                Categoria::Controlador,
+                Nivel::Depuracao,
+                placa = placa,
+                "#MPC @{} {:?} pos {:.2}, vel {:.2}, acel {:.2}",
+                placa,
+                veiculo.via,
+                veiculo.pos_atual,
+                veiculo.vel_atual,
+                veiculo.acel_desejada
+            );
+            self.planos.insert(placa.clone(), acel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plano_anterior_adianta_o_tempo_do_ciclo() {
+        let plano = [1.0, 2.0, 4.0];
+        assert_eq!(desloca(&plano, 0.0), vec![1.0, 2.0, 4.0]);
+        assert_eq!(desloca(&plano, 1.0), vec![2.0, 4.0, 4.0]);
+        assert_eq!(desloca(&plano, 0.5), vec![1.5, 3.0, 4.0]);
+        assert_eq!(desloca(&plano, 5.0), vec![4.0, 4.0, 4.0]);
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::HashMap;
+
+use super::reserva::{acel_para_chegar, tempo_para_percorrer};
+use super::semaforo::{acel_seguindo, ordem_da_via};
+use super::{atraso_controle, idade_esperada, Controlador, Situacao};
+
+use crate::cenario::Cenario;
+use crate::registro::{registra, Categoria, Nivel};
+
+use crate::transito::{Geometria, Via};
+
+pub const INTERVALO: f64 = 500.0; // Distância em tempo padrão dentro de um pelotão, em ms
+pub const AGRUPAMENTO: f64 = 1500.0; // Diferença padrão de chegada para entrar no pelotão da frente, em ms
+pub const TAMANHO_MAXIMO: usize = 8; // Veículos por pelotão, padrão
+const MARGEM_PELOTAO: f64 = 500.0; // entre a saída de um pelotão e a entrada do próximo da outra via, em ms
+const FOLGA_PARADA: f64 = 0.5; // metros antes do cruzamento onde o líder para, se precisar
+const ESPACAMENTO: f64 = 4.0; // metros entre veículos seguidos, como no semáforo
+
+// Parâmetros do controlador por pelotões
+#[derive(Debug, Copy, Clone)]
+pub struct ParametrosPelotoes {
+    pub intervalo: f64,        // ms mínimos até o carro da frente no mesmo pelotão
+    pub agrupamento: f64,      // ms entre chegadas estimadas para entrar no pelotão da frente
+    pub tamanho_maximo: usize, // veículos
+}
+
+impl Default for ParametrosPelotoes {
+    fn default() -> Self {
+        Self {
+            intervalo: INTERVALO,
+            agrupamento: AGRUPAMENTO,
+            tamanho_maximo: TAMANHO_MAXIMO,
+        }
+    }
+}
+
+// Veículos seguidos de uma via que passam juntos pelo cruzamento
+#[derive(Debug)]
+struct Pelotao {
+    via: Via,
+    placas: Vec<String>, // da frente para trás
+    chegada: f64,        // instante estimado de chegada do último ao cruzamento, em ms
+    entrada: f64,        // instante liberado para o líder entrar no cruzamento, em ms
+    saida: f64,          // instante estimado de saída do último da zona de conflito, em ms
+}
+
+// Algoritmo de controle que agrupa os veículos de cada via em pelotões
+// Um veículo que chega entra no último pelotão da sua via, que tem no máximo
+// 'tamanho_maximo' veículos, se chegaria ao cruzamento antes do último do pelotão, ou
+// até 'agrupamento' depois dele se os líderes dos pelotões da outra via formados depois
+// ainda conseguem parar antes do cruzamento; se não, forma um novo pelotão. Enquanto um
+// pelotão espera, a chegada do seu último é a programada, e assim quem para na fila atrás
+// dele entra no pelotão. Os pelotões passam pelo cruzamento na ordem em que foram formados: o líder de cada um só entra depois da
+// saída estimada do pelotão anterior da outra via, chegando no instante liberado com
+// desaceleração constante, e os demais o seguem a 'intervalo'. Assim a zona de conflito
+// troca de via uma vez por pelotão, e não a cada veículo
+pub struct Pelotoes {
+    agora: f64,               // tempo desde a criação do controlador, em ms
+    pelotoes: Vec<Pelotao>,   // em ordem de passagem
+    intervalo: f64,           // ms
+    agrupamento: f64,         // ms
+    tamanho_maximo: usize,    // veículos
+    geometria: Geometria,     // geometria do cruzamento
+    velocidade_cruzeiro: f64, // metros por segundo
+    atraso: f64,              // até a aceleração decidida ser aplicada, em s
+    idade_esperada: f64,      // da situação dos veículos, em ms
+}
+
+impl Pelotoes {
+    // Posição da frente quando a traseira sai da zona de conflito, em metros
+    fn fim_zona(&self, veiculo: &Situacao) -> f64 {
+        self.geometria.largura_cruzamento(veiculo.via) + veiculo.comprimento
+    }
+
+    // Se o líder de um pelotão ainda para antes do cruzamento, caso a entrada dele atrase
+    fn pode_esperar(&self, lider: &Situacao) -> bool {
+        let vel = lider.vel_atual;
+        let parada = vel * lider.atraso(self.atraso, self.idade_esperada)
+            + vel.powi(2) / (-2.0 * lider.acel_min)
+            + FOLGA_PARADA;
+        lider.pos_atual + parada < 0.0
+    }
+
+    // Instante mais cedo em que a frente do veículo pode chegar em 'posicao', em ms
+    fn chega_em(&self, veiculo: &Situacao, posicao: f64) -> f64 {
+        self.agora
+            + 1000.0
+                * tempo_para_percorrer(
+                    posicao - veiculo.pos_atual,
+                    veiculo.vel_atual,
+                    veiculo.acel_max,
+                    self.velocidade_cruzeiro,
+                )
+    }
+
+    // Esquece quem saiu da zona de conflito e põe os novos veículos nos pelotões
+    fn agrupa(&mut self, situacao: &HashMap<String, Situacao>) {
+        let saiu = |placa: &String| {
+            let v = &situacao[placa];
+            v.pos_atual >= self.geometria.largura_cruzamento(v.via) + v.comprimento
+        };
+        let mut pelotoes = std::mem::take(&mut self.pelotoes);
+        for pelotao in pelotoes.iter_mut() {
+            pelotao
+                .placas
+                .retain(|placa| situacao.contains_key(placa) && !saiu(placa));
+        }
+        pelotoes.retain(|p| !p.placas.is_empty());
+
+        // Novos veículos, pela chegada estimada, antes de informar a posição é só um chute
+        let mut novos: Vec<(String, f64)> = situacao
+            .iter()
+            .filter(|(placa, v)| {
+                v.informou && !saiu(placa) && !pelotoes.iter().any(|p| p.placas.contains(placa))
+            })
+            .map(|(placa, v)| (placa.clone(), self.chega_em(v, 0.0)))
+            .collect();
+        novos.sort_unstable_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.cmp(&b.0)));
+
+        for (placa, chegada) in novos {
+            let v = &situacao[&placa];
+            let ultimo = pelotoes.iter().rposition(|p| p.via == v.via);
+            // Quem chegaria antes do último do pelotão ficaria na fila atrás dele de
+            // qualquer jeito; os demais só entram se os pelotões da outra via que
+            // esperam depois deste ainda podem esperar mais, para não avançarem sobre ele
+            let entra = ultimo.filter(|&i| {
+                let p = &pelotoes[i];
+                p.placas.len() < self.tamanho_maximo
+                    && (chegada <= p.chegada
+                        || (chegada - p.chegada <= self.agrupamento
+                            && pelotoes[i + 1..]
+                                .iter()
+                                .all(|q| self.pode_esperar(&situacao[&q.placas[0]]))))
+            });
+            match entra.map(|i| &mut pelotoes[i]) {
+                Some(p) => {
+                    p.placas.push(placa.clone());
+                    p.chegada = p.chegada.max(chegada);
+                    registra!(
+                        Categoria::Controlador,
+                        Nivel::Depuracao,
+                        placa = &placa,
+                        "#PEL @{} entra no pelotão de @{} na {:?}, {} veículos",
+                        placa,
+                        p.placas[0],
+                        v.via,
+                        p.placas.len()
+                    );
+                }
+                _ => {
+                    registra!(
+                        Categoria::Controlador,
+                        Nivel::Depuracao,
+                        placa = &placa,
+                        "#PEL @{} forma um pelotão na {:?}",
+                        placa,
+                        v.via
+                    );
+                    pelotoes.push(Pelotao {
+                        via: v.via,
+                        placas: vec![placa],
+                        chegada,
+                        entrada: self.agora,
+                        saida: self.agora,
+                    });
+                }
+            }
+        }
+
+        // Em cada pelotão, da frente para trás, como estão na via
+        for pelotao in pelotoes.iter_mut() {
+            pelotao.placas.sort_by(|a, b| {
+                situacao[b]
+                    .pos_atual
+                    .partial_cmp(&situacao[a].pos_atual)
+                    .unwrap()
+            });
+        }
+        self.pelotoes = pelotoes;
+    }
+
+    // Instantes de entrada do líder e de saída do último de cada pelotão, em ordem
+    fn programa(&mut self, situacao: &HashMap<String, Situacao>) {
+        // Com atraso na comunicação, os veículos não estão onde o controlador pensa
+        let margem = MARGEM_PELOTAO + 1000.0 * self.atraso;
+        let mut saida_h = f64::NEG_INFINITY;
+        let mut saida_v = f64::NEG_INFINITY;
+
+        let mut pelotoes = std::mem::take(&mut self.pelotoes);
+        for pelotao in pelotoes.iter_mut() {
+            let liberado = match pelotao.via {
+                Via::ViaH => saida_v + margem,
+                Via::ViaV => saida_h + margem,
+            };
+            let lider = &situacao[&pelotao.placas[0]];
+            pelotao.entrada = if lider.pos_atual > 0.0 {
+                self.agora
+            } else {
+                self.chega_em(lider, 0.0).max(liberado)
+            };
+
+            // O último entra depois dos da frente, cada um a 'intervalo' do anterior e
+            // com seu comprimento, e então atravessa a zona de conflito
+            let mut entrada_ultimo = pelotao.entrada;
+            for placa in &pelotao.placas[1..] {
+                let v = &situacao[placa];
+                entrada_ultimo += self.intervalo
+                    + 1000.0 * (v.comprimento + ESPACAMENTO) / self.velocidade_cruzeiro;
+            }
+            // Quem chegar perto do último enquanto o pelotão espera ainda entra nele
+            pelotao.chegada = pelotao.chegada.max(entrada_ultimo);
+            let ultimo = &situacao[pelotao.placas.last().unwrap()];
+            let travessia = 1000.0 * self.fim_zona(ultimo) / self.velocidade_cruzeiro;
+            pelotao.saida = self
+                .chega_em(ultimo, self.fim_zona(ultimo))
+                .max(entrada_ultimo + travessia);
+
+            match pelotao.via {
+                Via::ViaH => saida_h = saida_h.max(pelotao.saida),
+                Via::ViaV => saida_v = saida_v.max(pelotao.saida),
+            }
+        }
+        self.pelotoes = pelotoes;
+    }
+}
+
+impl Controlador for Pelotoes {
+    // Cria um novo controlador por pelotões
+    fn new(cenario: &Cenario) -> Self {
+        Self {
+            agora: 0.0,
+            pelotoes: Vec::new(),
+            intervalo: cenario.pelotoes.intervalo,
+            agrupamento: cenario.pelotoes.agrupamento,
+            tamanho_maximo: cenario.pelotoes.tamanho_maximo,
+            geometria: cenario.geometria,
+            velocidade_cruzeiro: cenario.veiculo.velocidade_cruzeiro,
+            atraso: atraso_controle(cenario) / 1000.0,
+            idade_esperada: idade_esperada(cenario),
+        }
+    }
+
+    // Os próximos pelotões, com tamanho e quando o líder entra
+    fn painel(&self) -> Option<String> {
+        let proximos: Vec<String> = self
+            .pelotoes
+            .iter()
+            .take(4)
+            .map(|p| {
+                format!(
+                    "{:?} {} em {:.1} s",
+                    p.via,
+                    p.placas.len(),
+                    ((p.entrada - self.agora) / 1000.0).max(0.0)
+                )
+            })
+            .collect();
+        Some(format!("pelotões: {}", proximos.join(", ")))
+    }
+
+    // Cálcula ações de controle, tempos em milissegundos !!!
+    fn estrategia(&mut self, tempo_decorrido: f64, situacao: &mut HashMap<String, Situacao>) {
+        self.agora += tempo_decorrido;
+        self.agrupa(situacao);
+        self.programa(situacao);
+
+        // Instante liberado para cada líder
+        let mut liberados: HashMap<String, f64> = HashMap::new();
+        for pelotao in &self.pelotoes {
+            liberados.insert(pelotao.placas[0].clone(), pelotao.entrada);
+        }
+
+        for via in [Via::ViaH, Via::ViaV] {
+            let mut afrente: Option<(f64, f64)> = None; // traseira e velocidade do carro da frente
+            for placa in ordem_da_via(situacao, via) {
+                let veiculo = situacao.get_mut(&placa).unwrap(); // Sei que a placa existe
+                let (mut acel, _) = acel_seguindo(
+                    veiculo,
+                    afrente,
+                    self.velocidade_cruzeiro,
+                    self.intervalo,
+                    self.atraso,
+                    self.idade_esperada,
+                );
+
+                // Líder que chegaria cedo demais segura a via até o instante liberado
+                if let Some(entrada) = liberados.get(&placa) {
+                    let cedo = *entrada - tempo_decorrido
+                        > self.agora
+                            + 1000.0
+                                * tempo_para_percorrer(
+                                    -veiculo.pos_atual,
+                                    veiculo.vel_atual,
+                                    veiculo.acel_max,
+                                    self.velocidade_cruzeiro,
+                                );
+                    if veiculo.pos_atual <= 0.0 && cedo {
+                        let folga = FOLGA_PARADA
+                            + veiculo.vel_atual * veiculo.atraso(self.atraso, self.idade_esperada);
+                        let acel_segura = acel_para_chegar(
+                            veiculo,
+                            -veiculo.pos_atual,
+                            (*entrada - self.agora) / 1000.0,
+                            folga,
+                        );
+                        acel = acel.min(acel_segura).max(veiculo.acel_min);
+                    }
+                    registra!(
+                        Categoria::Controlador,
+                        Nivel::Depuracao,
+                        placa = &placa,
+                        "#PEL @{} líder na {:?}, entrada em {:.0} ms, pos {:.2}, vel {:.2}, acel {:.2}",
+                        placa,
+                        via,
+                        *entrada - self.agora,
+                        veiculo.pos_atual,
+                        veiculo.vel_atual,
+                        acel
+                    );
+                }
+                veiculo.acel_desejada = acel;
+                afrente = Some((veiculo.pos_atual - veiculo.comprimento, veiculo.vel_atual));
+            }
+        }
+    }
+}
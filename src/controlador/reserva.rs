@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+
+use super::{atraso_controle, idade_esperada, Controlador, Situacao};
+
+use crate::cenario::Cenario;
+use crate::registro::{registra, Categoria, Nivel};
+
+use crate::transito::{Geometria, Via};
+
+const MARGEM_RESERVA: f64 = 500.0; // folga entre duas reservas da zona de conflito, em ms
+const FOLGA_PARADA: f64 = 0.5; // metros antes do cruzamento onde o carro para, se precisar
+const ESPACAMENTO: f64 = 2.0; // metros mínimos até a traseira do carro da frente na mesma via
+const HEADWAY: f64 = 1.0; // distância em tempo até o carro da frente na mesma via, em s
+
+// Intervalo de tempo em que um veículo ocupa a zona de conflito
+#[derive(Debug)]
+struct Reserva {
+    entrada: f64, // instante de entrada na zona de conflito, em ms
+    saida: f64,   // instante de saída da zona de conflito, em ms
+}
+
+// Algoritmo de controle por reserva de intervalos de tempo no cruzamento
+// Cada veículo recebe, em ordem de chegada, um intervalo exclusivo para ocupar a
+// zona de conflito (0 .. largura da outra via + comprimento). As reservas são
+// recalculadas a cada chamada, sempre na mesma ordem, a partir da situação atual
+pub struct Reservas {
+    agora: f64,                         // tempo desde a criação do controlador, em ms
+    ordem: Vec<String>,                 // placas em ordem de reserva
+    reservas: HashMap<String, Reserva>, // reservas calculadas na última chamada
+    geometria: Geometria,               // geometria do cruzamento
+    velocidade_cruzeiro: f64,           // metros por segundo
+    atraso: f64,                        // até a aceleração decidida ser aplicada, em s
+    idade_esperada: f64,                // da situação dos veículos, em ms
+}
+
+// Tempo para percorrer 'distancia' metros partindo de 'vel' m/s, acelerando com
+// 'acel' m/s2 até 'vel_cruzeiro' e depois mantendo esta velocidade, em s
+pub(super) fn tempo_para_percorrer(distancia: f64, vel: f64, acel: f64, vel_cruzeiro: f64) -> f64 {
+    if distancia <= 0.0 {
+        return 0.0;
+    }
+
+    if vel >= vel_cruzeiro {
+        return distancia / vel;
+    }
+
+    // Trecho acelerando
+    let t_acel = (vel_cruzeiro - vel) / acel;
+    let d_acel = vel * t_acel + acel * t_acel.powi(2) / 2.0;
+    if d_acel >= distancia {
+        return (-vel + (vel.powi(2) + 2.0 * acel * distancia).sqrt()) / acel;
+    }
+
+    // Trecho em velocidade de cruzeiro
+    t_acel + (distancia - d_acel) / vel_cruzeiro
+}
+
+// Aceleração para manter a velocidade de cruzeiro com pista livre
+fn acel_cruzeiro(veiculo: &Situacao, vel_cruzeiro: f64) -> f64 {
+    if veiculo.vel_atual > 1.1 * vel_cruzeiro {
+        veiculo.acel_min
+    } else if veiculo.vel_atual < vel_cruzeiro {
+        veiculo.acel_max
+    } else {
+        0.0
+    }
+}
+
+// Aceleração para chegar em 'distancia' metros exatamente após 'tempo' segundos,
+// parando 'folga' metros antes do cruzamento quando não for possível chegar no tempo certo
+pub(super) fn acel_para_chegar(veiculo: &Situacao, distancia: f64, tempo: f64, folga: f64) -> f64 {
+    let vel = veiculo.vel_atual;
+
+    if vel * tempo >= 2.0 * distancia {
+        // Mesmo freando constantemente chegaria cedo: para antes do cruzamento
+        let parar_em = distancia - folga;
+        if parar_em <= 0.0 {
+            if vel <= 0.0005 {
+                0.0
+            } else {
+                veiculo.acel_min
+            }
+        } else {
+            -vel.powi(2) / (2.0 * parar_em)
+        }
+    } else {
+        2.0 * (distancia - vel * tempo) / tempo.powi(2)
+    }
+}
+
+impl Controlador for Reservas {
+    // Cria um novo controlador por reservas
+    fn new(cenario: &Cenario) -> Self {
+        Self {
+            agora: 0.0,
+            ordem: Vec::new(),
+            reservas: HashMap::new(),
+            geometria: cenario.geometria,
+            velocidade_cruzeiro: cenario.veiculo.velocidade_cruzeiro,
+            atraso: atraso_controle(cenario) / 1000.0,
+            idade_esperada: idade_esperada(cenario),
+        }
+    }
+
+    // Cálcula ações de controle, tempos em milissegundos !!!
+    fn estrategia(&mut self, tempo_decorrido: f64, situacao: &mut HashMap<String, Situacao>) {
+        self.agora += tempo_decorrido;
+        let vel_cruzeiro = self.velocidade_cruzeiro;
+
+        // Com atraso na comunicação, os veículos não estão onde o controlador pensa,
+        // e demoram a receber a aceleração: reservas e distâncias ganham folga
+        let margem_reserva = MARGEM_RESERVA + 1000.0 * self.atraso;
+
+        // Esquece veículos que não estão mais na situação
+        self.ordem.retain(|placa| situacao.contains_key(placa));
+
+        // Novos veículos entram no final da ordem, pelo tempo mínimo até o cruzamento
+        // Ninguém passa antes do da frente na mesma via, o que importa quando vários
+        // chegam juntos, como depois de um reinício com filas paradas
+        let mut novos: Vec<(String, Via, f64, f64)> = Vec::new();
+        for (placa, v) in situacao.iter() {
+            if !self.ordem.contains(placa) {
+                let t_min =
+                    tempo_para_percorrer(-v.pos_atual, v.vel_atual, v.acel_max, vel_cruzeiro);
+                novos.push((placa.clone(), v.via, v.pos_atual, t_min));
+            }
+        }
+        novos.sort_unstable_by(|a, b| b.2.partial_cmp(&a.2).unwrap().then(a.0.cmp(&b.0)));
+        for via in [Via::ViaH, Via::ViaV] {
+            let mut da_frente = f64::NEG_INFINITY;
+            for novo in novos.iter_mut().filter(|n| n.1 == via) {
+                novo.3 = novo.3.max(da_frente);
+                da_frente = novo.3;
+            }
+        }
+        novos.sort_by(|a, b| a.3.partial_cmp(&b.3).unwrap());
+        for (placa, ..) in novos {
+            self.ordem.push(placa);
+        }
+
+        // Recalcula as reservas, na ordem, a partir de quando a zona de conflito estará livre
+        self.reservas.clear();
+        let mut zona_livre_em = self.agora;
+
+        for placa in &self.ordem {
+            let veiculo = situacao.get_mut(placa).unwrap(); // Sei que a placa existe
+            let extensao = self.geometria.largura_cruzamento(veiculo.via) + veiculo.comprimento;
+
+            if veiculo.pos_atual >= extensao {
+                // Já saiu da zona de conflito
+                veiculo.acel_desejada = acel_cruzeiro(veiculo, vel_cruzeiro);
+                continue;
+            }
+
+            if veiculo.pos_atual > 0.0 {
+                // Dentro da zona de conflito: sai o quanto antes
+                let saida = self.agora
+                    + 1000.0
+                        * tempo_para_percorrer(
+                            extensao - veiculo.pos_atual,
+                            veiculo.vel_atual,
+                            veiculo.acel_max,
+                            vel_cruzeiro,
+                        );
+                veiculo.acel_desejada = acel_cruzeiro(veiculo, vel_cruzeiro);
+                zona_livre_em = zona_livre_em.max(saida + margem_reserva);
+                self.reservas.insert(
+                    placa.clone(),
+                    Reserva {
+                        entrada: self.agora,
+                        saida,
+                    },
+                );
+                continue;
+            }
+
+            // Antes da zona de conflito: reserva o primeiro intervalo livre
+            let distancia = -veiculo.pos_atual;
+            let t_min = self.agora
+                + 1000.0
+                    * tempo_para_percorrer(
+                        distancia,
+                        veiculo.vel_atual,
+                        veiculo.acel_max,
+                        vel_cruzeiro,
+                    );
+            let entrada = t_min.max(zona_livre_em);
+
+            let vel_entrada;
+            if entrada - t_min <= tempo_decorrido {
+                // Nada a esperar, segue livre
+                veiculo.acel_desejada = acel_cruzeiro(veiculo, vel_cruzeiro);
+                vel_entrada =
+                    veiculo.vel_atual.max(vel_cruzeiro.min(
+                        (veiculo.vel_atual.powi(2) + 2.0 * veiculo.acel_max * distancia).sqrt(),
+                    ));
+            } else {
+                // Ajusta a aceleração para chegar no início da reserva
+                let tempo = (entrada - self.agora) / 1000.0;
+                let folga = FOLGA_PARADA
+                    + veiculo.vel_atual * veiculo.atraso(self.atraso, self.idade_esperada);
+                veiculo.acel_desejada = acel_para_chegar(veiculo, distancia, tempo, folga);
+                vel_entrada = if veiculo.vel_atual * tempo >= 2.0 * distancia {
+                    0.0
+                } else {
+                    (2.0 * distancia / tempo - veiculo.vel_atual).max(0.0)
+                };
+            }
+
+            let saida = entrada
+                + 1000.0
+                    * tempo_para_percorrer(extensao, vel_entrada, veiculo.acel_max, vel_cruzeiro);
+            zona_livre_em = saida + margem_reserva;
+            self.reservas
+                .insert(placa.clone(), Reserva { entrada, saida });
+        }
+
+        // Nenhum veículo pode alcançar o carro da frente na mesma via
+        for via in [Via::ViaH, Via::ViaV] {
+            let mut ordem_via: Vec<(String, f64)> = situacao
+                .values()
+                .filter(|v| v.via == via)
+                .map(|v| (v.placa.clone(), v.pos_atual))
+                .collect();
+            ordem_via.sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+
+            for i in 1..ordem_via.len() {
+                let afrente = situacao.get(&ordem_via[i - 1].0).unwrap(); // Sei que a placa existe
+                let afrente_traseira = afrente.pos_atual - afrente.comprimento;
+                let afrente_vel = afrente.vel_atual; // empréstimo de 'situacao' p/ afrente termina aqui
+
+                let veiculo = situacao.get_mut(&ordem_via[i].0).unwrap(); // Sei que a placa existe
+                let distancia = afrente_traseira
+                    - ESPACAMENTO
+                    - veiculo.pos_atual
+                    - veiculo.vel_atual * veiculo.atraso(self.atraso, self.idade_esperada);
+
+                let acel_seguir = if distancia <= 0.0 {
+                    veiculo.acel_min
+                } else if veiculo.vel_atual > afrente_vel {
+                    // Freia para chegar na velocidade do carro da frente antes de alcançá-lo
+                    (afrente_vel.powi(2) - veiculo.vel_atual.powi(2)) / (2.0 * distancia)
+                } else if distancia < HEADWAY * veiculo.vel_atual {
+                    0.0
+                } else {
+                    veiculo.acel_max
+                };
+
+                veiculo.acel_desejada = veiculo
+                    .acel_desejada
+                    .min(acel_seguir)
+                    .clamp(veiculo.acel_min, veiculo.acel_max);
+            }
+        }
+
+        for placa in &self.ordem {
+            let veiculo = situacao.get(placa).unwrap(); // Sei que a placa existe
+            match self.reservas.get(placa) {
+                None => registra!(
+                    Categoria::Controlador,
+                    Nivel::Depuracao,
+                    placa = placa,
+                    "#RES @{} {:?} livre, pos {:.2}, acel {:.2}",
+                    placa,
+                    veiculo.via,
+                    veiculo.pos_atual,
+                    veiculo.acel_desejada
+                ),
+                Some(r) => registra!(
+                    Categoria::Controlador,
+                    Nivel::Depuracao,
+                    placa = placa,
+                    "#RES @{} {:?} reserva {:.0}..{:.0} ms, pos {:.2}, acel {:.2}",
+                    placa,
+                    veiculo.via,
+                    r.entrada - self.agora,
+                    r.saida - self.agora,
+                    veiculo.pos_atual,
+                    veiculo.acel_desejada
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tempo_para_percorrer_acelerando_e_em_cruzeiro() {
+        assert_eq!(tempo_para_percorrer(-1.0, 10.0, 2.0, 20.0), 0.0);
+        assert_eq!(tempo_para_percorrer(100.0, 20.0, 2.0, 20.0), 5.0);
+        // Parado, 2 m/s2: 4 s até 8 m
+        assert!((tempo_para_percorrer(16.0, 0.0, 2.0, 20.0) - 4.0).abs() < 1e-9);
+        // Chega aos 20 m/s em 10 s e 100 m, o resto em cruzeiro
+        assert!((tempo_para_percorrer(300.0, 0.0, 2.0, 20.0) - 20.0).abs() < 1e-9);
+    }
+
+    // Dois veículos que chegariam juntos ao cruzamento, um em cada via, recebem
+    // intervalos separados pela margem, e o segundo desacelera
+    #[test]
+    fn reservas_das_duas_vias_nao_se_sobrepoem() {
+        let cenario = Cenario::default();
+        let mut reservas = Reservas::new(&cenario);
+        let vel = cenario.veiculo.velocidade_cruzeiro;
+        let mut situacao = HashMap::new();
+        for (placa, via) in [("H1", Via::ViaH), ("V1", Via::ViaV)] {
+            situacao.insert(
+                placa.to_string(),
+                Situacao::de_teste(placa, via, -60.0, vel),
+            );
+        }
+        reservas.estrategia(50.0, &mut situacao);
+
+        let h = &reservas.reservas["H1"];
+        let v = &reservas.reservas["V1"];
+        let (primeira, segunda) = if h.entrada < v.entrada {
+            (h, v)
+        } else {
+            (v, h)
+        };
+        let margem = MARGEM_RESERVA + 1000.0 * reservas.atraso;
+        assert!(primeira.saida + margem <= segunda.entrada + 1e-6);
+        let atrasado = if h.entrada < v.entrada { "V1" } else { "H1" };
+        assert!(situacao[atrasado].acel_desejada < 0.0);
+    }
+}
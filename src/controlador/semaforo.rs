@@ -1,16 +1,110 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
 
-use super::{Controlador, Situacao};
+use super::{atraso_controle, idade_esperada, Controlador, Situacao};
+
+use crate::cenario::Cenario;
+use crate::registro::{registra, Categoria, Nivel};
+
+use crate::transito::{Geometria, Via};
+
+pub const TEMPO_VERDE: f64 = 13000.0; // Tempo padrão de verde, em ms
+pub const TEMPO_AMARELO: f64 = 5000.0; // Tempo padrão de amarelo, em ms
+const ESPACAMENTO: f64 = 4.0; // metros entre um veículo parado e o da frente ou o cruzamento
+const INTERVALO_VERDE: f64 = 2000.0; // distância em tempo mínima até o carro da frente no verde, em ms
+
+// Padrões do plano calculado por Webster
+pub const FLUXO_SATURACAO: f64 = 1800.0; // veículos por hora de verde numa via com fila
+pub const TEMPO_PERDIDO: f64 = 5000.0; // por fase, na partida da fila e no fim do verde, em ms
+pub const PERIODO_PLANO: f64 = 60000.0; // entre cálculos do plano, em ms
+pub const JANELA_FLUXO: f64 = 120000.0; // chegadas contadas para estimar o fluxo, em ms
+pub const CICLO_MINIMO: f64 = 30000.0; // em ms
+pub const CICLO_MAXIMO: f64 = 120000.0; // em ms
+pub const VERDE_MINIMO: f64 = 5000.0; // em ms
+const SATURACAO_MAXIMA: f64 = 0.95; // soma das taxas de ocupação a partir da qual usa o ciclo máximo
+
+// Como o semáforo escolhe os tempos de verde
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TipoPlano {
+    Fixo,    // sempre o verde do cenário
+    Webster, // ciclo e verdes recalculados pelo fluxo de cada via
+}
+
+impl TipoPlano {
+    pub fn nome(&self) -> &'static str {
+        match self {
+            TipoPlano::Fixo => "fixo",
+            TipoPlano::Webster => "webster",
+        }
+    }
+
+    pub fn de_nome(nome: &str) -> Option<Self> {
+        match nome {
+            "fixo" => Some(TipoPlano::Fixo),
+            "webster" => Some(TipoPlano::Webster),
+            _ => None,
+        }
+    }
+}
+
+// Tempos do semáforo
+#[derive(Debug, Copy, Clone)]
+pub struct ParametrosSemaforo {
+    pub tempo_verde: f64,     // ms, o inicial com o plano Webster
+    pub tempo_amarelo: f64,   // ms
+    pub plano: TipoPlano,     // como escolhe os tempos de verde
+    pub fluxo_saturacao: f64, // veículos por hora
+    pub tempo_perdido: f64,   // por fase, ms
+    pub periodo_plano: f64,   // ms
+    pub janela_fluxo: f64,    // ms
+    pub ciclo_minimo: f64,    // ms
+    pub ciclo_maximo: f64,    // ms
+    pub verde_minimo: f64,    // ms
+}
+
+impl Default for ParametrosSemaforo {
+    fn default() -> Self {
+        Self {
+            tempo_verde: TEMPO_VERDE,
+            tempo_amarelo: TEMPO_AMARELO,
+            plano: TipoPlano::Fixo,
+            fluxo_saturacao: FLUXO_SATURACAO,
+            tempo_perdido: TEMPO_PERDIDO,
+            periodo_plano: PERIODO_PLANO,
+            janela_fluxo: JANELA_FLUXO,
+            ciclo_minimo: CICLO_MINIMO,
+            ciclo_maximo: CICLO_MAXIMO,
+            verde_minimo: VERDE_MINIMO,
+        }
+    }
+}
 
-use crate::transito::{Via, VIAH_LARGURA};
+// Tempos de verde de cada via, e os fluxos de onde vieram
+#[derive(Debug, Copy, Clone)]
+struct Plano {
+    verde_h: f64, // ms
+    verde_v: f64, // ms
+    fluxo_h: f64, // veículos por hora estimados quando o plano foi calculado
+    fluxo_v: f64, // veículos por hora
+}
 
-use crate::transito::veiculos::VELOCIDADE_CRUZEIRO;
+impl Plano {
+    fn verde(&self, via: Via) -> f64 {
+        match via {
+            Via::ViaH => self.verde_h,
+            Via::ViaV => self.verde_v,
+        }
+    }
+
+    // Duração do ciclo com 'amarelo' ms de amarelo em cada fase, em ms
+    fn ciclo(&self, amarelo: f64) -> f64 {
+        self.verde_h + self.verde_v + 2.0 * amarelo
+    }
+}
 
 // Algoritmo de controle que imita um semáforo
 pub struct Semaforo {
-    tempo_verde: f64,   // tempo de Verde em s
-    tempo_amarelo: f64, // tempo de Amarelo em s
+    tempo_amarelo: f64, // tempo de Amarelo em ms
 
     restam_verde: f64, // tempo que resta nesta fase
     restam_amarelo: f64,
@@ -19,26 +113,172 @@ pub struct Semaforo {
     via_vermelho: Via, // qual via esta vermelho
 
     amarelo: bool, // Se amarelo está ligado
-    display_tudo: bool,
+
+    // Plano em uso e o calculado para o próximo ciclo, que só começa com o verde da
+    // via H: verde e amarelo em andamento nunca mudam de duração
+    plano: Plano,
+    proximo_plano: Option<Plano>,
+    parametros: ParametrosSemaforo,
+    agora: f64,                  // tempo desde a criação do controlador, em ms
+    ateh_proximo_plano: f64,     // ms
+    conhecidos: HashSet<String>, // placas já contadas nas chegadas
+    chegadas_h: VecDeque<f64>,   // instantes das chegadas dentro da janela, ms
+    chegadas_v: VecDeque<f64>,   // idem, via V
+
+    geometria: Geometria,     // geometria do cruzamento
+    velocidade_cruzeiro: f64, // metros por segundo
+    atraso: f64,              // até a aceleração decidida ser aplicada, em s
+    idade_esperada: f64,      // da situação dos veículos, em ms
+}
+
+impl Semaforo {
+    // Conta as chegadas de veículos ainda não vistos e esquece as que saíram da janela
+    fn conta_chegadas(&mut self, situacao: &HashMap<String, Situacao>) {
+        let mut novas: Vec<&Situacao> = situacao
+            .values()
+            .filter(|v| !self.conhecidos.contains(&v.placa))
+            .collect();
+        novas.sort_unstable_by(|a, b| a.placa.cmp(&b.placa));
+        for v in novas {
+            self.conhecidos.insert(v.placa.clone());
+            match v.via {
+                Via::ViaH => self.chegadas_h.push_back(self.agora),
+                Via::ViaV => self.chegadas_v.push_back(self.agora),
+            }
+        }
+        self.conhecidos.retain(|placa| situacao.contains_key(placa));
+
+        let inicio = self.agora - self.parametros.janela_fluxo;
+        for chegadas in [&mut self.chegadas_h, &mut self.chegadas_v] {
+            while chegadas.front().is_some_and(|t| *t < inicio) {
+                chegadas.pop_front();
+            }
+        }
+    }
+
+    // Plano de Webster para os fluxos estimados na janela
+    // Ciclo ótimo C = (1.5 L + 5 s) / (1 - Y), com L o tempo perdido nas duas fases e
+    // Y a soma das taxas de ocupação fluxo/saturação; o verde efetivo C - L é dividido
+    // na proporção das taxas, e o verde aceso é o efetivo mais o perdido menos o amarelo
+    fn calcula_plano(&self) -> Plano {
+        let p = &self.parametros;
+        let janela = self.agora.min(p.janela_fluxo).max(1.0);
+        let fluxo_h = self.chegadas_h.len() as f64 * 3_600_000.0 / janela;
+        let fluxo_v = self.chegadas_v.len() as f64 * 3_600_000.0 / janela;
+        let y_h = fluxo_h / p.fluxo_saturacao;
+        let y_v = fluxo_v / p.fluxo_saturacao;
+        let y = y_h + y_v;
+
+        let perdido = 2.0 * p.tempo_perdido;
+        let ciclo = if y >= SATURACAO_MAXIMA {
+            p.ciclo_maximo
+        } else {
+            ((1.5 * perdido + 5000.0) / (1.0 - y)).clamp(p.ciclo_minimo, p.ciclo_maximo)
+        };
+        let efetivo = ciclo - perdido;
+        let (efetivo_h, efetivo_v) = if y > 0.0 {
+            (efetivo * y_h / y, efetivo * y_v / y)
+        } else {
+            (efetivo / 2.0, efetivo / 2.0)
+        };
+        let aceso =
+            |efetivo: f64| (efetivo + p.tempo_perdido - self.tempo_amarelo).max(p.verde_minimo);
+        Plano {
+            verde_h: aceso(efetivo_h),
+            verde_v: aceso(efetivo_v),
+            fluxo_h,
+            fluxo_v,
+        }
+    }
 }
 
 impl Controlador for Semaforo {
     // Cria um novo semáforo
-    fn new(display_tudo: bool) -> Self {
+    fn new(cenario: &Cenario) -> Self {
+        let parametros = cenario.semaforo;
         Self {
-            tempo_verde: 13000.0,   // ms
-            tempo_amarelo: 5000.0,  // ms
-            restam_verde: 13000.0,  // ms
-            restam_amarelo: 5000.0, // ms
+            tempo_amarelo: parametros.tempo_amarelo,  // ms
+            restam_verde: parametros.tempo_verde,     // ms
+            restam_amarelo: parametros.tempo_amarelo, // ms
             via_verde: Via::ViaH,
             via_vermelho: Via::ViaV,
             amarelo: false,
-            display_tudo,
+            plano: Plano {
+                verde_h: parametros.tempo_verde,
+                verde_v: parametros.tempo_verde,
+                fluxo_h: 0.0,
+                fluxo_v: 0.0,
+            },
+            proximo_plano: None,
+            parametros,
+            agora: 0.0,
+            ateh_proximo_plano: parametros.periodo_plano,
+            conhecidos: HashSet::new(),
+            chegadas_h: VecDeque::new(),
+            chegadas_v: VecDeque::new(),
+            geometria: cenario.geometria,
+            velocidade_cruzeiro: cenario.veiculo.velocidade_cruzeiro,
+            atraso: atraso_controle(cenario) / 1000.0,
+            idade_esperada: idade_esperada(cenario),
+        }
+    }
+
+    // Fase atual e plano em uso
+    fn painel(&self) -> Option<String> {
+        let fase = if self.amarelo {
+            format!(
+                "amarelo {:?}, restam {:.1} s",
+                self.via_verde,
+                self.restam_amarelo / 1000.0
+            )
+        } else {
+            format!(
+                "verde {:?}, restam {:.1} s",
+                self.via_verde,
+                self.restam_verde / 1000.0
+            )
+        };
+        let mut texto = format!(
+            "semáforo {}   plano {}: ciclo {:.1} s, verde H {:.1} s, V {:.1} s",
+            fase,
+            self.parametros.plano.nome(),
+            self.plano.ciclo(self.tempo_amarelo) / 1000.0,
+            self.plano.verde_h / 1000.0,
+            self.plano.verde_v / 1000.0
+        );
+        if self.parametros.plano == TipoPlano::Webster {
+            texto.push_str(&format!(
+                ", fluxo H {:.0} V {:.0} veículos/h",
+                self.plano.fluxo_h, self.plano.fluxo_v
+            ));
         }
+        Some(texto)
     }
 
     // Cálcula ações de controle, tempos em milissegundos !!!
     fn estrategia(&mut self, tempo_decorrido: f64, situacao: &mut HashMap<String, Situacao>) {
+        // Com o plano Webster, estima os fluxos e recalcula o plano periodicamente
+        if self.parametros.plano == TipoPlano::Webster {
+            self.agora += tempo_decorrido;
+            self.conta_chegadas(situacao);
+            self.ateh_proximo_plano -= tempo_decorrido;
+            if self.ateh_proximo_plano <= 0.0 {
+                self.ateh_proximo_plano += self.parametros.periodo_plano;
+                let plano = self.calcula_plano();
+                registra!(
+                    Categoria::Controlador,
+                    Nivel::Info,
+                    "#SEM novo plano: fluxo H {:.0} V {:.0} veículos/h, verde H {:.1} s V {:.1} s, ciclo {:.1} s, a partir do próximo verde H",
+                    plano.fluxo_h,
+                    plano.fluxo_v,
+                    plano.verde_h / 1000.0,
+                    plano.verde_v / 1000.0,
+                    plano.ciclo(self.tempo_amarelo) / 1000.0
+                );
+                self.proximo_plano = Some(plano);
+            }
+        }
+
         // Atualiza tempos de amarelo e verde, conforme o caso
         if self.amarelo {
             // Avança o tempo em amarelo
@@ -56,9 +296,22 @@ impl Controlador for Semaforo {
                     self.via_verde = Via::ViaH;
                     self.via_vermelho = Via::ViaV;
                 }
+                // Um ciclo novo começa com o verde da via H, e só então muda o plano
+                if self.via_verde == Via::ViaH {
+                    if let Some(plano) = self.proximo_plano.take() {
+                        self.plano = plano;
+                    }
+                }
                 // Inicia novo tempo de verde
                 self.restam_amarelo = 0.0;
-                self.restam_verde = self.tempo_verde;
+                self.restam_verde = self.plano.verde(self.via_verde);
+                registra!(
+                    Categoria::Controlador,
+                    Nivel::Info,
+                    "#SEM verde para {:?}, vermelho para {:?}",
+                    self.via_verde,
+                    self.via_vermelho
+                );
             }
         } else {
             // Avança o tempo em verde
@@ -71,212 +324,362 @@ impl Controlador for Semaforo {
                 // Inicia novo tempo de amarelo
                 self.restam_verde = 0.0;
                 self.restam_amarelo = self.tempo_amarelo;
+                registra!(
+                    Categoria::Controlador,
+                    Nivel::Info,
+                    "#SEM amarelo para {:?}",
+                    self.via_verde
+                );
             }
         }
 
-        if self.display_tudo {
-            println!(
-                "#SEM restam verde {:?} {:.2}   restam amarelo {:.2} {}    vermelho {:?}",
+        registra!(
+            Categoria::Controlador,
+            Nivel::Depuracao,
+            "#SEM restam verde {:?} {:.2}   restam amarelo {:.2} {}    vermelho {:?}",
+            self.via_verde,
+            self.restam_verde,
+            self.restam_amarelo,
+            self.amarelo,
+            self.via_vermelho
+        );
+
+        let ordem_via_vermelho = ordem_da_via(situacao, self.via_vermelho);
+        let ordem_via_verde = ordem_da_via(situacao, self.via_verde);
+
+        para_no_vermelho(
+            &ordem_via_vermelho,
+            situacao,
+            self.atraso,
+            self.idade_esperada,
+        );
+        if self.amarelo {
+            passa_ou_para_no_amarelo(
                 self.via_verde,
-                self.restam_verde,
+                &ordem_via_verde,
+                situacao,
+                self.geometria.largura_cruzamento(self.via_verde),
                 self.restam_amarelo,
-                self.amarelo,
-                self.via_vermelho
+                self.atraso,
+                self.idade_esperada,
+            );
+        } else if cruzamento_ocupado(
+            &ordem_via_vermelho,
+            situacao,
+            self.geometria.largura_cruzamento(self.via_vermelho),
+            self.atraso,
+            self.idade_esperada,
+        ) {
+            para_no_vermelho(&ordem_via_verde, situacao, self.atraso, self.idade_esperada);
+        } else {
+            segue_no_verde(
+                self.via_verde,
+                &ordem_via_verde,
+                situacao,
+                self.velocidade_cruzeiro,
+                self.atraso,
+                self.idade_esperada,
             );
         }
+    }
+}
 
-        // Monta uma lista ordenada para cada via
-        #[derive(Debug)]
-        struct MiniSituacao {
-            placa: String,  // placa deste carro
-            pos_atual: f64, // metros do cruzamento
+// Placas dos veículos de uma via, do mais adiantado para o mais atrasado
+pub(super) fn ordem_da_via(situacao: &HashMap<String, Situacao>, via: Via) -> Vec<String> {
+    // Monta uma lista ordenada para a via
+    #[derive(Debug)]
+    struct MiniSituacao {
+        placa: String,  // placa deste carro
+        pos_atual: f64, // metros do cruzamento
+    }
+
+    let mut ordem: Vec<MiniSituacao> = Vec::new();
+    for (_k, v) in situacao.iter() {
+        if v.via == via {
+            ordem.push(MiniSituacao {
+                placa: v.placa.clone(),
+                pos_atual: v.pos_atual,
+            });
         }
+    }
+    ordem.sort_unstable_by(|a, b| b.pos_atual.partial_cmp(&a.pos_atual).unwrap());
+    ordem.into_iter().map(|mini| mini.placa).collect()
+}
 
-        let mut ordem_via_h: Vec<MiniSituacao> = Vec::new();
-        let mut ordem_via_v: Vec<MiniSituacao> = Vec::new();
+// Algum veículo da via vermelha, em 'ordem', ainda está no cruzamento de 'largura'
+// metros ou já não consegue parar antes dele: com comandos atrasados ou perdidos, quem
+// parava no amarelo pode ter passado da linha, e o verde da outra via espera ele sair.
+// 'atraso' em s e 'idade_esperada' em ms, como em 'Situacao::atraso'
+pub(super) fn cruzamento_ocupado(
+    ordem: &[String],
+    situacao: &HashMap<String, Situacao>,
+    largura: f64,
+    atraso: f64,
+    idade_esperada: f64,
+) -> bool {
+    ordem.iter().any(|placa| {
+        let veiculo = &situacao[placa];
+        let parada = veiculo.vel_atual * veiculo.atraso(atraso, idade_esperada)
+            + veiculo.vel_atual.powi(2) / (-2.0 * veiculo.acel_min);
+        veiculo.pos_atual < largura + veiculo.comprimento && veiculo.pos_atual + parada > 0.0
+    })
+}
 
-        let situacao_iter = situacao.iter();
-        for (_k, v) in situacao_iter {
-            if v.via == Via::ViaH {
-                ordem_via_h.push(MiniSituacao {
-                    placa: v.placa.clone(),
-                    pos_atual: v.pos_atual,
-                });
+// Ações para veículos na via vermelha, em 'ordem'
+// Primeiro carro vai até 'um espaçamento' antes do cruzamento
+// Demais ficam sempre 'um espaçamento' atrás do anterior na via
+// 'atraso' em s e 'idade_esperada' em ms, como em 'Situacao::atraso'
+pub(super) fn para_no_vermelho(
+    ordem: &[String],
+    situacao: &mut HashMap<String, Situacao>,
+    atraso: f64,
+    idade_esperada: f64,
+) {
+    let mut pos_alvo = -ESPACAMENTO;
+
+    for placa in ordem {
+        let veiculo = situacao.get_mut(placa).unwrap(); // Sei que a placa existe
+
+        // Até receber a aceleração o veículo ainda anda, então para antes do alvo
+        let alvo = pos_alvo - veiculo.vel_atual * veiculo.atraso(atraso, idade_esperada);
+
+        if veiculo.pos_atual > 0.0 {
+            veiculo.acel_desejada = veiculo.vel_max;
+        } else if veiculo.pos_atual >= alvo {
+            if veiculo.vel_atual <= 0.0005 {
+                veiculo.acel_desejada = 0.0;
             } else {
-                ordem_via_v.push(MiniSituacao {
-                    placa: v.placa.clone(),
-                    pos_atual: v.pos_atual,
-                });
+                veiculo.acel_desejada = veiculo.acel_min;
             }
+        } else {
+            veiculo.acel_desejada = veiculo.vel_atual.powi(2) / (2.0 * (veiculo.pos_atual - alvo));
         }
 
-        ordem_via_h.sort_unstable_by(|a, b| b.pos_atual.partial_cmp(&a.pos_atual).unwrap());
-        ordem_via_v.sort_unstable_by(|a, b| b.pos_atual.partial_cmp(&a.pos_atual).unwrap());
+        registra!(
+            Categoria::Controlador,
+            Nivel::Depuracao,
+            placa = &veiculo.placa,
+            "#SEM @{}  atual:{:.2}  alvo:{:.2}  acel:{:.2}->{:.2}",
+            veiculo.placa,
+            veiculo.pos_atual,
+            pos_alvo,
+            veiculo.acel_atual,
+            veiculo.acel_desejada
+        );
+        // O próximo para atrás deste, mesmo que este tenha parado antes do seu alvo
+        pos_alvo = pos_alvo.min(veiculo.pos_atual) - veiculo.comprimento - ESPACAMENTO;
+    }
+}
 
-        // Chama de via_vermelho e via_verde
-        let ordem_via_vermelho: &Vec<MiniSituacao>;
-        let ordem_via_verde: &Vec<MiniSituacao>;
-        if self.via_vermelho == Via::ViaH {
-            ordem_via_vermelho = &ordem_via_h; // Basta um empréstimo/referência !!!
-            ordem_via_verde = &ordem_via_v; // Basta um empréstimo/referência
+// Ações para veículos na 'via' verde com amarelo, em 'ordem': quem chega ao fim do
+// cruzamento de 'largura' metros antes de acabarem os 'restam_amarelo' ms passa, os
+// outros param antes do cruzamento
+pub(super) fn passa_ou_para_no_amarelo(
+    via: Via,
+    ordem: &[String],
+    situacao: &mut HashMap<String, Situacao>,
+    largura: f64,
+    restam_amarelo: f64,
+    atraso: f64,
+    idade_esperada: f64,
+) {
+    let pos_alvo = -ESPACAMENTO; // alguns em amarelo vão parar antes do cruzamento
+    let mut afrente: Option<(f64, f64)> = None; // traseira e velocidade do carro da frente
+
+    for placa in ordem {
+        let veiculo = situacao.get_mut(placa).unwrap(); // Sei que a placa existe
+
+        // Problema: saber se os carros conseguem passar no amarelo.
+        // Para isto, compara-se o tempo estimado de percurso ateh o cruzamento
+        // e compara-se com o tempo restante.  Se for menor, acelerar para
+        // passar.  Senao, parar.
+
+        let tpassar = (0.0 + veiculo.comprimento + largura - veiculo.pos_atual) / veiculo.vel_atual;
+        registra!(
+            Categoria::Controlador,
+            Nivel::Depuracao,
+            placa = &veiculo.placa,
+            "#SEM @{}, {:?} em amarelo, t/passar {:.2}",
+            veiculo.placa,
+            via,
+            1000.0 * tpassar
+        );
+
+        // Calcula a aceleracao com base no passa/nao-passa no amarelo
+        if 1000.0 * tpassar <= restam_amarelo * 0.9 {
+            veiculo.acel_desejada = veiculo.acel_max;
         } else {
-            ordem_via_vermelho = &ordem_via_v; // Basta um empréstimo/referência
-            ordem_via_verde = &ordem_via_h; // Basta um empréstimo/referência
+            veiculo.acel_desejada = veiculo.vel_atual.powi(2)
+                / (2.0
+                    * (veiculo.pos_atual
+                        + veiculo.vel_atual * veiculo.atraso(atraso, idade_esperada)));
         }
 
-        // Ações para veículos na via vermelha
-
-        // Primeiro carro vai até 'um espaçamento' antes do cruzamento
-        // Demais ficam sempre 'um espaçamento' atrás do anterior na via
-        let espacamento = 4.0; // metros
-        let mut pos_alvo = -espacamento;
-
-        for mini in ordem_via_vermelho {
-            let veiculo = situacao.get_mut(&mini.placa).unwrap(); // Sei que a placa existe
-
-            if veiculo.pos_atual > 0.0 {
-                veiculo.acel_desejada = veiculo.vel_max;
-            } else if veiculo.pos_atual >= pos_alvo {
-                if veiculo.vel_atual <= 0.0005 {
-                    veiculo.acel_desejada = 0.0;
-                } else {
-                    veiculo.acel_desejada = veiculo.acel_min;
-                }
-            } else {
-                veiculo.acel_desejada =
-                    veiculo.vel_atual.powi(2) / (2.0 * (veiculo.pos_atual - pos_alvo));
+        // Passando ou parando, não pode alcançar o carro da frente
+        if let Some((traseira, vel_afrente)) = afrente {
+            let distancia = traseira
+                - ESPACAMENTO
+                - veiculo.pos_atual
+                - veiculo.vel_atual * veiculo.atraso(atraso, idade_esperada);
+            if distancia <= 0.0 {
+                veiculo.acel_desejada = veiculo.acel_min;
+            } else if veiculo.vel_atual > vel_afrente {
+                veiculo.acel_desejada = veiculo
+                    .acel_desejada
+                    .min((vel_afrente.powi(2) - veiculo.vel_atual.powi(2)) / (2.0 * distancia));
             }
-
-            if self.display_tudo {
-                println!(
-                    "#SEM @{}  atual:{:.2}  alvo:{:.2}  acel:{:.2}->{:.2}",
-                    veiculo.placa,
-                    veiculo.pos_atual,
-                    pos_alvo,
-                    veiculo.acel_atual,
-                    veiculo.acel_desejada
-                );
-            }
-            pos_alvo -= veiculo.comprimento + espacamento;
         }
+        afrente = Some((veiculo.pos_atual - veiculo.comprimento, veiculo.vel_atual));
+
+        // Se vai parar
+        if veiculo.acel_desejada <= 0.0
+            && veiculo.pos_atual >= pos_alvo
+            && veiculo.vel_atual <= 0.0005
+        {
+            veiculo.acel_desejada = 0.0;
+        }
+    }
+}
 
-        // Ações para veículos na via verde com amarelo
-
-        if self.amarelo {
-            pos_alvo = -espacamento; // alguns em amarelo vão parar antes do cruzamento
-
-            for mini in ordem_via_verde {
-                let veiculo = situacao.get_mut(&mini.placa).unwrap(); // Sei que a placa existe
-
-                // Problema: saber se os carros conseguem passar no amarelo.
-                // Para isto, compara-se o tempo estimado de percurso ateh o cruzamento
-                // e compara-se com o tempo restante.  Se for menor, acelerar para
-                // passar.  Senao, parar.
-
-                let tpassar = (0.0 + veiculo.comprimento + VIAH_LARGURA - veiculo.pos_atual)
-                    / veiculo.vel_atual;
-                if self.display_tudo {
-                    println!(
-                        "#SEM @{}, {:?} em amarelo, t/passar {:.2}",
-                        veiculo.placa,
-                        self.via_verde,
-                        1000.0 * tpassar
-                    );
-                }
+// Ações para veículos na 'via' verde sem amarelo, em 'ordem': o primeiro segue na
+// 'velocidade_cruzeiro', em metros por segundo, e os demais sem alcançar o da frente
+pub(super) fn segue_no_verde(
+    via: Via,
+    ordem: &[String],
+    situacao: &mut HashMap<String, Situacao>,
+    velocidade_cruzeiro: f64,
+    atraso: f64,
+    idade_esperada: f64,
+) {
+    let mut afrente: Option<(f64, f64)> = None; // traseira e velocidade do carro da frente
+
+    for placa in ordem {
+        let veiculo = situacao.get_mut(placa).unwrap(); // Sei que a placa existe
+        let (acel, intervalo_atual) = acel_seguindo(
+            veiculo,
+            afrente,
+            velocidade_cruzeiro,
+            INTERVALO_VERDE,
+            atraso,
+            idade_esperada,
+        );
+        veiculo.acel_desejada = acel;
+
+        match intervalo_atual {
+            // Primeiro: acelera livremente
+            None => registra!(
+                Categoria::Controlador,
+                Nivel::Depuracao,
+                placa = &veiculo.placa,
+                "#SEM @{}, {:?} em verde, vel {:.2}, acel {:.2}",
+                veiculo.placa,
+                via,
+                veiculo.vel_atual,
+                veiculo.acel_desejada
+            ),
+            Some(delta_t_atual) => registra!(
+                Categoria::Controlador,
+                Nivel::Depuracao,
+                placa = &veiculo.placa,
+                "#SEM @{}, {:?} em verde, vel {:.2}, delta_t {:.2}, acel {:.2}",
+                veiculo.placa,
+                via,
+                veiculo.vel_atual,
+                delta_t_atual,
+                veiculo.acel_desejada
+            ),
+        }
+        afrente = Some((veiculo.pos_atual - veiculo.comprimento, veiculo.vel_atual));
+    }
+}
 
-                // Calcula a aceleracao com base no passa/nao-passa no amarelo
-                if 1000.0 * tpassar <= self.restam_amarelo * 0.9 {
-                    veiculo.acel_desejada = veiculo.acel_max;
-                } else {
-                    veiculo.acel_desejada = veiculo.vel_atual.powi(2) / (2.0 * (veiculo.pos_atual));
-                }
+// Aceleração para seguir na 'velocidade_cruzeiro', em metros por segundo, sem alcançar
+// o carro da frente, dado pela posição da traseira e pela velocidade, e ficando pelo
+// menos 'intervalo' ms atrás dele. Devolve também a distância em tempo atual até o da
+// frente, em ms. 'atraso' em s e 'idade_esperada' em ms, como em 'Situacao::atraso'
+pub(super) fn acel_seguindo(
+    veiculo: &Situacao,
+    afrente: Option<(f64, f64)>,
+    velocidade_cruzeiro: f64,
+    intervalo: f64,
+    atraso: f64,
+    idade_esperada: f64,
+) -> (f64, Option<f64>) {
+    // Supõe pista livre
+    let mut acel = if veiculo.vel_atual > 1.1 * velocidade_cruzeiro {
+        veiculo.acel_min
+    } else if veiculo.vel_atual < 0.9 * velocidade_cruzeiro {
+        veiculo.acel_max
+    } else {
+        0.0
+    };
+
+    let Some((afrente_traseira, afrente_vel)) = afrente else {
+        return (acel, None);
+    };
+
+    // Distância em tempo do veículo da frente
+    let delta_t_atual = 1000.0 * (afrente_traseira - veiculo.pos_atual) / veiculo.vel_atual;
+    if delta_t_atual < intervalo {
+        acel = veiculo.acel_min / 2.0;
+    } else if veiculo.vel_atual > afrente_vel {
+        let delta_t_colisao =
+            1000.0 * (afrente_traseira - veiculo.pos_atual) / (veiculo.vel_atual - afrente_vel);
+        if delta_t_colisao < 10000.0 {
+            // Colisão em menos de 10s
+            acel = veiculo.acel_min / 2.0;
+        }
+    }
 
-                // Se vai parar
-                if veiculo.acel_desejada <= 0.0
-                    && veiculo.pos_atual >= pos_alvo
-                    && veiculo.vel_atual <= 0.0005
-                {
-                    veiculo.acel_desejada = 0.0;
-                }
-            }
-        } else {
-            // Ações para veículos na via verde sem amarelo
-            for i in 0..ordem_via_verde.len() {
-                let mini = &ordem_via_verde[i];
-
-                if i == 0 {
-                    // Primeiro: acelera livremente
-                    let veiculo = situacao.get_mut(&mini.placa).unwrap(); // Sei que a placa existe
-
-                    if veiculo.vel_atual > 1.1 * VELOCIDADE_CRUZEIRO {
-                        veiculo.acel_desejada = veiculo.acel_min;
-                    } else if veiculo.vel_atual < 0.9 * VELOCIDADE_CRUZEIRO {
-                        veiculo.acel_desejada = veiculo.acel_max;
-                    } else {
-                        veiculo.acel_desejada = 0.0;
-                    }
-                    if self.display_tudo {
-                        println!(
-                            "#SEM @{}, {:?} em verde, vel {:.2}, acel {:.2}",
-                            veiculo.placa, self.via_verde, veiculo.vel_atual, veiculo.acel_desejada
-                        );
-                    }
-                } else {
-                    // Veículos seguintes: acelerar mas sem bater no da frente !!!
-
-                    let afrente = situacao.get(&ordem_via_verde[i - 1].placa).unwrap(); // Sei que a placa existe
-                    let afrente_pos = afrente.pos_atual;
-                    let afrente_vel = afrente.vel_atual;
-                    let afrente_comp = afrente.comprimento; // empréstimo de 'situacao' p/ afrente termina aqui
-
-                    let veiculo = situacao.get_mut(&mini.placa).unwrap(); // Sei que a placa existe
-
-                    if veiculo.vel_atual > 1.1 * VELOCIDADE_CRUZEIRO {
-                        veiculo.acel_desejada = veiculo.acel_min;
-                    } else if veiculo.vel_atual < 0.9 * VELOCIDADE_CRUZEIRO {
-                        veiculo.acel_desejada = veiculo.acel_max;
-                    } else {
-                        #[allow(clippy::no_effect)]
-                        veiculo.acel_desejada;
-                    }
+    // Freia o quanto for preciso para não alcançar o da frente, considerando
+    // o quanto anda até receber a aceleração
+    let distancia = afrente_traseira
+        - ESPACAMENTO
+        - veiculo.pos_atual
+        - veiculo.vel_atual * veiculo.atraso(atraso, idade_esperada);
+    if distancia <= 0.0 {
+        acel = veiculo.acel_min;
+    } else if veiculo.vel_atual > afrente_vel {
+        acel = acel
+            .min((afrente_vel.powi(2) - veiculo.vel_atual.powi(2)) / (2.0 * distancia))
+            .max(veiculo.acel_min);
+    }
+    (acel, Some(delta_t_atual))
+}
 
-                    // Supõe pista livre
-                    if veiculo.vel_atual > 1.1 * VELOCIDADE_CRUZEIRO {
-                        veiculo.acel_desejada = veiculo.acel_min;
-                    } else if veiculo.vel_atual < 0.9 * VELOCIDADE_CRUZEIRO {
-                        veiculo.acel_desejada = veiculo.acel_max;
-                    } else {
-                        veiculo.acel_desejada = 0.0;
-                    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Plano com 'chegadas_h' e 'chegadas_v' veículos contados na janela de 120 s
+    fn plano(chegadas_h: usize, chegadas_v: usize) -> Plano {
+        let mut semaforo = Semaforo::new(&Cenario::default());
+        semaforo.agora = JANELA_FLUXO;
+        semaforo.chegadas_h = vec![0.0; chegadas_h].into();
+        semaforo.chegadas_v = vec![0.0; chegadas_v].into();
+        semaforo.calcula_plano()
+    }
 
-                    // Distância em tempo do veículo da frente
-                    let delta_t_atual = 1000.0 * (afrente_pos - afrente_comp - veiculo.pos_atual)
-                        / veiculo.vel_atual;
-                    if delta_t_atual < 2000.0 {
-                        // Distância de segurança é 2s
-                        veiculo.acel_desejada = veiculo.acel_min / 2.0;
-                    } else if veiculo.vel_atual > afrente_vel {
-                        let delta_t_colisao = 1000.0
-                            * (afrente_pos - afrente_comp - veiculo.pos_atual)
-                            / (veiculo.vel_atual - afrente_vel);
-                        if delta_t_colisao < 10000.0 {
-                            // Colisão em menos de 10s
-                            veiculo.acel_desejada = veiculo.acel_min / 2.0;
-                        }
-                    }
+    // Com 600 e 300 veículos/h e saturação de 1800, Y = 1/3 + 1/6 = 0,5 e o ciclo é
+    // (1,5 x 10 s + 5 s) / (1 - 0,5) = 40 s; os 30 s de verde efetivo vão 2 para 1
+    #[test]
+    fn plano_de_webster_para_fluxos_conhecidos() {
+        let p = plano(20, 10);
+        assert_eq!((p.fluxo_h, p.fluxo_v), (600.0, 300.0));
+        assert!((p.verde_h - 20_000.0).abs() < 1e-6, "{:?}", p);
+        assert!((p.verde_v - 10_000.0).abs() < 1e-6, "{:?}", p);
+        assert!((p.ciclo(TEMPO_AMARELO) - 40_000.0).abs() < 1e-6);
+    }
 
-                    if self.display_tudo {
-                        println!(
-                            "#SEM @{}, {:?} em verde, vel {:.2}, delta_t {:.2}, acel {:.2}",
-                            veiculo.placa,
-                            self.via_verde,
-                            veiculo.vel_atual,
-                            delta_t_atual,
-                            veiculo.acel_desejada
-                        );
-                    }
-                }
-            }
-        }
+    #[test]
+    fn ciclo_de_webster_dentro_dos_limites() {
+        // Sem ninguém, ciclo mínimo dividido ao meio
+        let p = plano(0, 0);
+        assert_eq!(p.ciclo(TEMPO_AMARELO), CICLO_MINIMO);
+        assert_eq!(p.verde_h, p.verde_v);
+        // Saturado, ciclo máximo, e a via quase vazia fica com o verde mínimo
+        let p = plano(60, 1);
+        assert!(p.ciclo(TEMPO_AMARELO) >= CICLO_MAXIMO);
+        assert_eq!(p.verde_v, VERDE_MINIMO);
     }
 }
@@ -0,0 +1,165 @@
+/* Desenho do cruzamento na janela gráfica
+
+    Usado tanto pela simulação com janela quanto pela reprodução de uma gravação
+*/
+
+use speedy2d::color::Color; // Para gráficos
+use speedy2d::dimen::Vector2;
+use speedy2d::font::{Font, TextLayout, TextOptions};
+use speedy2d::Graphics2D;
+
+use crate::transito::{Carro, Geometria};
+
+// O necessário para desenhar um carro
+pub struct VistaCarro<'a> {
+    pub placa: &'a str,
+    pub comprimento: f64, // metros
+    pub pos_atual: f64,   // metros do cruzamento
+    pub vel_atual: f64,   // metros por segundo
+    pub acel_atual: f64,  // metros por segundo ao quadrado
+}
+
+impl<'a> VistaCarro<'a> {
+    pub fn de_carro(carro: &'a Carro) -> Self {
+        Self {
+            placa: &carro.placa,
+            comprimento: carro.comprimento,
+            pos_atual: carro.pos_atual,
+            vel_atual: carro.vel_atual,
+            acel_atual: carro.acel_atual,
+        }
+    }
+}
+
+// Cor do carro conforme a aceleração
+fn cor_carro(acel: f64) -> Color {
+    if acel == 0.0 {
+        Color::WHITE
+    } else if acel < 0.0 {
+        Color::RED
+    } else {
+        Color::GREEN
+    }
+}
+
+// Escreve uma linha de texto na janela
+pub fn escreve(graphics: &mut Graphics2D, fonte: &Font, escala: f32, pos: (f32, f32), texto: &str) {
+    let layout = fonte.layout_text(texto, escala, TextOptions::new());
+    graphics.draw_text(pos, Color::BLACK, &layout);
+}
+
+// Desenha as vias e os carros, com os dados de cada carro se 'mostra_dados'
+#[allow(clippy::too_many_arguments)]
+pub fn desenha_cruzamento(
+    graphics: &mut Graphics2D,
+    fonte: &Font,
+    largura_total: f64,
+    altura_total: f64,
+    g: &Geometria,
+    via_h: &[VistaCarro],
+    via_v: &[VistaCarro],
+    mostra_dados: bool,
+) {
+    // Limpa a tela
+    //graphics.clear_screen(Color::WHITE);
+    graphics.clear_screen(Color::from_rgb(0.9, 0.9, 0.9));
+
+    // Calcula resolução
+    let resolucao_h = largura_total / g.viah_total(); // relacao pixel por metro
+    let resolucao_v = altura_total / g.viav_total(); // relação pixel por metro
+    let escala = (largura_total / 40.0) as f32;
+
+    // Desenha linhas via H
+    let y1 = (g.viav_margem * resolucao_v) as f32;
+    let y2 = ((g.viav_margem + g.viah_largura) * resolucao_v) as f32;
+    graphics.draw_line((0.0, y1), (largura_total as f32, y1), 4.0, Color::BLACK);
+    graphics.draw_line((0.0, y2), (largura_total as f32, y2), 4.0, Color::BLACK);
+
+    // Desenha linhas via V
+    let zero_h = (largura_total - (g.viah_margem + g.viav_largura) * resolucao_h) as f32;
+    let x = (largura_total - (g.viah_margem) * resolucao_h) as f32;
+    graphics.draw_line(
+        (zero_h, 0.0),
+        (zero_h, altura_total as f32),
+        4.0,
+        Color::BLACK,
+    );
+    graphics.draw_line((x, 0.0), (x, altura_total as f32), 4.0, Color::BLACK);
+
+    // Desenha os carros da Via H
+    let mut lado = 0.0;
+    for carro in via_h {
+        let x = zero_h + ((carro.pos_atual - carro.comprimento) * resolucao_h) as f32;
+        let y = ((g.viav_margem + 1.0) * resolucao_v) as f32;
+        let w = (carro.comprimento * resolucao_h) as f32;
+        //let h = (carro.largura * resolucao_v) as f32;
+        let h = (2.0 * resolucao_v) as f32;
+
+        let vertices = [
+            Vector2 { x, y },
+            Vector2 { x: x + w, y },
+            Vector2 { x: x + w, y: y + h },
+            Vector2 { x, y: y + h },
+        ];
+        graphics.draw_quad(vertices, cor_carro(carro.acel_atual));
+
+        // Se foi pausada ou finalizada escreve na janela dados do carro
+        if mostra_dados {
+            if lado == 0.0 {
+                // Para espalhar texto na tela
+                lado = -80.0;
+            } else {
+                lado = 0.0;
+            }
+
+            let linhas = dados_carro(carro);
+            for (i, linha) in linhas.iter().enumerate() {
+                let dy = 10.0 + 15.0 * i as f32 + lado;
+                escreve(graphics, fonte, escala, (x - 10.0, y + dy), linha);
+            }
+        }
+    }
+
+    // Desenha os carros da Via V
+    let mut lado = 0.0;
+    for carro in via_v {
+        let x = (largura_total - (g.viah_margem + g.viav_largura - 1.0) * resolucao_h) as f32;
+        let y = ((g.viav_margem + g.viah_largura - carro.pos_atual) * resolucao_v) as f32;
+        let w = (2.0 * resolucao_h) as f32;
+        let h = (carro.comprimento * resolucao_v) as f32;
+
+        let vertices = [
+            Vector2 { x, y },
+            Vector2 { x: x + w, y },
+            Vector2 { x: x + w, y: y + h },
+            Vector2 { x, y: y + h },
+        ];
+        graphics.draw_quad(vertices, cor_carro(carro.acel_atual));
+
+        // Se foi pausada ou finalizada escreve na janela dados do carro
+        if mostra_dados {
+            if lado == 0.0 {
+                // Para espalhar texto na tela
+                lado = 70.0;
+            } else {
+                lado = 0.0;
+            }
+
+            let linhas = dados_carro(carro);
+            for (i, linha) in linhas.iter().enumerate() {
+                let dy = 5.0 + 15.0 * i as f32;
+                escreve(graphics, fonte, escala, (x - 50.0 + lado, y + dy), linha);
+            }
+        }
+    }
+}
+
+// Placa, aceleração, velocidade e posição de um carro, uma por linha
+fn dados_carro(carro: &VistaCarro) -> [String; 4] {
+    [
+        carro.placa.to_string(),
+        format!("A:{:.2}", carro.acel_atual),
+        format!("V:{:.2}", carro.vel_atual),
+        format!("P:{:.2}", carro.pos_atual),
+    ]
+}
@@ -0,0 +1,550 @@
+/* Gravação de uma simulação, para reprodução e análise posterior
+
+    Arquivo binário, números little-endian, textos com tamanho (u16) na frente:
+
+        cabeçalho   "CRZG", versão (u8), semente (u64), descrição (texto),
+                    geometria (6 x f64)
+        registros   tipo (u8) seguido dos campos:
+            VEICULO         id (u32), via (u8), comprimento (f32), placa (texto)
+            QUADRO          tempo (f64), n (u32), n x [id (u32), pos, vel, acel (f32)]
+            DE_VEICULO      mensagem de veículo para o controlador
+            DO_CONTROLADOR  mensagem do controlador para um veículo
+            DECISAO         id (u32), pos, vel, acel desejada (f32), como o controlador via
+            COLISAO         descrição (texto)
+
+    Cada veículo é descrito uma vez, com um id, e os quadros usam só o id.
+    As mensagens e decisões gravadas depois de um quadro aconteceram no mesmo tick.
+*/
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use crate::comunicacao::{MensagemDeVeiculo, MensagemDoControlador, MensagemEnviada};
+use crate::controlador::Decisao;
+use crate::transito::{Geometria, Transito, Via};
+
+const MAGICO: &[u8; 4] = b"CRZG";
+const VERSAO: u8 = 2;
+
+const VEICULO: u8 = 1;
+const QUADRO: u8 = 2;
+const DE_VEICULO: u8 = 3;
+const DO_CONTROLADOR: u8 = 4;
+const DECISAO: u8 = 5;
+const COLISAO: u8 = 6;
+
+// Tipos das mensagens, dentro de DE_VEICULO e DO_CONTROLADOR
+const CHEGADA: u8 = 1;
+const SITUACAO_ATUAL: u8 = 2;
+const SET_ACEL: u8 = 1;
+const PEDE_SITUACAO: u8 = 2;
+const CHAVE: u8 = 3;
+
+// Monta os bytes de um registro
+struct Escritor {
+    bytes: Vec<u8>,
+}
+
+impl Escritor {
+    fn new() -> Self {
+        Self { bytes: Vec::new() }
+    }
+
+    fn u8(&mut self, x: u8) {
+        self.bytes.push(x);
+    }
+
+    fn u32(&mut self, x: u32) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn f32(&mut self, x: f64) {
+        self.bytes.extend_from_slice(&(x as f32).to_le_bytes());
+    }
+
+    fn f64(&mut self, x: f64) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn texto(&mut self, texto: &str) {
+        let bytes = &texto.as_bytes()[..texto.len().min(u16::MAX as usize)];
+        self.bytes
+            .extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn via(&mut self, via: Via) {
+        self.u8(match via {
+            Via::ViaH => 0,
+            Via::ViaV => 1,
+        });
+    }
+}
+
+// Lê os campos de um arquivo de gravação já carregado na memória
+struct Leitor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Leitor<'a> {
+    fn fim(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn pedaco(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.pos + n > self.bytes.len() {
+            return Err(format!("gravação truncada no byte {}", self.pos));
+        }
+        let pedaco = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(pedaco)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.pedaco(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.pedaco(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.pedaco(8)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f64, String> {
+        Ok(f32::from_le_bytes(self.pedaco(4)?.try_into().unwrap()) as f64)
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.pedaco(8)?.try_into().unwrap()))
+    }
+
+    fn texto(&mut self) -> Result<String, String> {
+        let n = u16::from_le_bytes(self.pedaco(2)?.try_into().unwrap()) as usize;
+        let bytes = self.pedaco(n)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| "texto inválido na gravação".to_string())
+    }
+
+    fn via(&mut self) -> Result<Via, String> {
+        match self.u8()? {
+            0 => Ok(Via::ViaH),
+            1 => Ok(Via::ViaV),
+            x => Err(format!("via inválida na gravação: {}", x)),
+        }
+    }
+}
+
+// Grava uma simulação, tick a tick
+pub struct Gravador {
+    arquivo: BufWriter<File>,
+    caminho: String,
+    ids: HashMap<String, u32>, // id de cada placa já descrita
+    erro: Option<String>,      // primeiro erro de escrita, as demais escritas são ignoradas
+}
+
+impl Gravador {
+    // Cria o arquivo e grava o cabeçalho
+    pub fn new(
+        caminho: &str,
+        semente: u64,
+        descricao: &str,
+        geometria: &Geometria,
+    ) -> Result<Self, String> {
+        let arquivo = File::create(caminho)
+            .map_err(|e| format!("Não foi possível criar a gravação {}: {}", caminho, e))?;
+        let mut gravador = Self {
+            arquivo: BufWriter::new(arquivo),
+            caminho: caminho.to_string(),
+            ids: HashMap::new(),
+            erro: None,
+        };
+
+        let mut e = Escritor::new();
+        e.bytes.extend_from_slice(MAGICO);
+        e.u8(VERSAO);
+        e.u64(semente);
+        e.texto(descricao);
+        for x in [
+            geometria.viah_margem,
+            geometria.viav_margem,
+            geometria.viah_largura,
+            geometria.viav_largura,
+            geometria.viah_perimetro,
+            geometria.viav_perimetro,
+        ] {
+            e.f64(x);
+        }
+        gravador.escreve(e);
+        gravador.erro.take().map_or(Ok(gravador), Err)
+    }
+
+    fn escreve(&mut self, escritor: Escritor) {
+        if self.erro.is_none() {
+            if let Err(e) = self.arquivo.write_all(&escritor.bytes) {
+                self.erro = Some(format!("Erro gravando {}: {}", self.caminho, e));
+            }
+        }
+    }
+
+    // Id de uma placa, descreve o veículo na primeira vez
+    fn id(&mut self, placa: &str, via: Via, comprimento: f64) -> u32 {
+        if let Some(id) = self.ids.get(placa) {
+            return *id;
+        }
+        let id = self.ids.len() as u32;
+        self.ids.insert(placa.to_string(), id);
+
+        let mut e = Escritor::new();
+        e.u8(VEICULO);
+        e.u32(id);
+        e.via(via);
+        e.f32(comprimento);
+        e.texto(placa);
+        self.escreve(e);
+        id
+    }
+
+    // Grava um tick: estado de todos os carros, mensagens trocadas e decisões do controlador
+    pub fn grava_quadro(
+        &mut self,
+        tempo: f64,
+        transito: &Transito,
+        mensagens: Vec<MensagemEnviada>,
+        decisoes: Vec<Decisao>,
+    ) {
+        let mut estados = Escritor::new();
+        let mut n = 0;
+        for via in [Via::ViaH, Via::ViaV] {
+            for carro in transito.get_iterador(via) {
+                let id = self.id(&carro.placa, carro.via, carro.comprimento);
+                estados.u32(id);
+                estados.f32(carro.pos_atual);
+                estados.f32(carro.vel_atual);
+                estados.f32(carro.acel_atual);
+                n += 1;
+            }
+        }
+
+        let mut e = Escritor::new();
+        e.u8(QUADRO);
+        e.f64(tempo);
+        e.u32(n);
+        e.bytes.append(&mut estados.bytes);
+
+        for msg in mensagens {
+            grava_mensagem(&mut e, &msg);
+        }
+
+        for decisao in decisoes {
+            // Decisão sobre um veículo que já saiu não tem mais id, é ignorada
+            if let Some(id) = self.ids.get(&decisao.placa) {
+                e.u8(DECISAO);
+                e.u32(*id);
+                e.f32(decisao.pos_atual);
+                e.f32(decisao.vel_atual);
+                e.f32(decisao.acel_desejada);
+            }
+        }
+
+        self.escreve(e);
+    }
+
+    // Grava a descrição de uma colisão
+    pub fn grava_colisao(&mut self, descricao: &str) {
+        let mut e = Escritor::new();
+        e.u8(COLISAO);
+        e.texto(descricao);
+        self.escreve(e);
+    }
+
+    // Termina a gravação, informando se houve algum erro
+    pub fn encerra(&mut self) -> Result<(), String> {
+        if self.erro.is_none() {
+            if let Err(e) = self.arquivo.flush() {
+                self.erro = Some(format!("Erro gravando {}: {}", self.caminho, e));
+            }
+        }
+        match &self.erro {
+            None => Ok(()),
+            Some(e) => Err(e.clone()),
+        }
+    }
+}
+
+// Grava uma mensagem com todos os seus campos
+fn grava_mensagem(e: &mut Escritor, msg: &MensagemEnviada) {
+    match msg {
+        MensagemEnviada::DeVeiculo(m) => {
+            e.u8(DE_VEICULO);
+            match m {
+                MensagemDeVeiculo::Chegada {
+                    placa,
+                    via,
+                    acel_max,
+                    acel_min,
+                    vel_max,
+                    comprimento,
+                    periodo_informe,
+                } => {
+                    e.u8(CHEGADA);
+                    e.texto(placa);
+                    e.via(*via);
+                    e.f32(*acel_max);
+                    e.f32(*acel_min);
+                    e.f32(*vel_max);
+                    e.f32(*comprimento);
+                    e.f32(*periodo_informe);
+                }
+                MensagemDeVeiculo::SituacaoAtual {
+                    placa,
+                    pos_atual,
+                    vel_atual,
+                    acel_atual,
+                } => {
+                    e.u8(SITUACAO_ATUAL);
+                    e.texto(placa);
+                    e.f32(*pos_atual);
+                    e.f32(*vel_atual);
+                    e.f32(*acel_atual);
+                }
+            }
+        }
+        MensagemEnviada::DoControlador(m) => {
+            e.u8(DO_CONTROLADOR);
+            match m {
+                MensagemDoControlador::SetAcel { placa, acel } => {
+                    e.u8(SET_ACEL);
+                    e.texto(placa);
+                    e.f32(*acel);
+                }
+                MensagemDoControlador::PedeSituacao { placa } => {
+                    e.u8(PEDE_SITUACAO);
+                    e.texto(placa);
+                }
+                // Só a entrega fica gravada, a chave não sai da comunicação
+                MensagemDoControlador::Chave { placa, .. } => {
+                    e.u8(CHAVE);
+                    e.texto(placa);
+                }
+            }
+        }
+    }
+}
+
+// Lê uma mensagem gravada por 'grava_mensagem', depois do tipo do registro
+fn le_mensagem(l: &mut Leitor, registro: u8) -> Result<MensagemEnviada, String> {
+    let tipo = l.u8()?;
+    let msg = match (registro, tipo) {
+        (DE_VEICULO, CHEGADA) => MensagemEnviada::DeVeiculo(MensagemDeVeiculo::Chegada {
+            placa: l.texto()?,
+            via: l.via()?,
+            acel_max: l.f32()?,
+            acel_min: l.f32()?,
+            vel_max: l.f32()?,
+            comprimento: l.f32()?,
+            periodo_informe: l.f32()?,
+        }),
+        (DE_VEICULO, SITUACAO_ATUAL) => {
+            MensagemEnviada::DeVeiculo(MensagemDeVeiculo::SituacaoAtual {
+                placa: l.texto()?,
+                pos_atual: l.f32()?,
+                vel_atual: l.f32()?,
+                acel_atual: l.f32()?,
+            })
+        }
+        (DO_CONTROLADOR, SET_ACEL) => {
+            MensagemEnviada::DoControlador(MensagemDoControlador::SetAcel {
+                placa: l.texto()?,
+                acel: l.f32()?,
+            })
+        }
+        (DO_CONTROLADOR, PEDE_SITUACAO) => {
+            MensagemEnviada::DoControlador(MensagemDoControlador::PedeSituacao {
+                placa: l.texto()?,
+            })
+        }
+        (DO_CONTROLADOR, CHAVE) => MensagemEnviada::DoControlador(MensagemDoControlador::Chave {
+            placa: l.texto()?,
+            chave: [0; 32],
+        }),
+        _ => {
+            return Err(format!(
+                "mensagem de tipo desconhecido na gravação: {}",
+                tipo
+            ))
+        }
+    };
+    Ok(msg)
+}
+
+// Veículo que aparece na gravação
+pub struct VeiculoGravado {
+    pub placa: String,
+    pub via: Via,
+    pub comprimento: f64, // metros
+}
+
+// Estado de um veículo em um quadro
+pub struct EstadoGravado {
+    pub id: usize,       // índice em 'veiculos' da gravação
+    pub pos_atual: f64,  // metros do cruzamento
+    pub vel_atual: f64,  // metros por segundo
+    pub acel_atual: f64, // metros por segundo ao quadrado
+}
+
+// Decisão do controlador sobre um veículo
+pub struct DecisaoGravada {
+    pub id: usize,
+    pub pos_atual: f64,     // como o controlador conhecia
+    pub vel_atual: f64,     // como o controlador conhecia
+    pub acel_desejada: f64, // metros por segundo ao quadrado
+}
+
+// Tudo que aconteceu em um tick
+pub struct QuadroGravado {
+    pub tempo: f64, // ms
+    pub estados: Vec<EstadoGravado>,
+    pub mensagens: Vec<MensagemEnviada>,
+    pub decisoes: Vec<DecisaoGravada>,
+    pub colisao: Option<String>,
+}
+
+// Uma gravação completa, lida do arquivo
+pub struct Gravacao {
+    pub semente: u64,
+    pub descricao: String,
+    pub geometria: Geometria,
+    pub veiculos: Vec<VeiculoGravado>,
+    pub quadros: Vec<QuadroGravado>,
+}
+
+impl Gravacao {
+    // Lê uma gravação inteira
+    pub fn carrega(caminho: &str) -> Result<Self, String> {
+        let bytes = fs::read(caminho)
+            .map_err(|e| format!("Não foi possível ler a gravação {}: {}", caminho, e))?;
+        Gravacao::interpreta(&bytes).map_err(|e| format!("Gravação {}: {}", caminho, e))
+    }
+
+    fn interpreta(bytes: &[u8]) -> Result<Self, String> {
+        let mut l = Leitor { bytes, pos: 0 };
+
+        if l.pedaco(4)? != MAGICO {
+            return Err("não é um arquivo de gravação".to_string());
+        }
+        let versao = l.u8()?;
+        if versao != VERSAO {
+            return Err(format!("versão {} não suportada", versao));
+        }
+
+        let semente = l.u64()?;
+        let descricao = l.texto()?;
+        let geometria = Geometria {
+            viah_margem: l.f64()?,
+            viav_margem: l.f64()?,
+            viah_largura: l.f64()?,
+            viav_largura: l.f64()?,
+            viah_perimetro: l.f64()?,
+            viav_perimetro: l.f64()?,
+        };
+
+        let mut gravacao = Self {
+            semente,
+            descricao,
+            geometria,
+            veiculos: Vec::new(),
+            quadros: Vec::new(),
+        };
+
+        while !l.fim() {
+            if let Err(e) = gravacao.le_registro(&mut l) {
+                // Gravação interrompida no meio, aproveita o que foi lido
+                if gravacao.quadros.is_empty() {
+                    return Err(e);
+                }
+                eprintln!("Aviso: {}, reproduzindo até o último quadro lido", e);
+                break;
+            }
+        }
+
+        if gravacao.quadros.is_empty() {
+            return Err("gravação sem nenhum quadro".to_string());
+        }
+        Ok(gravacao)
+    }
+
+    // Lê um registro e acrescenta o que ele descreve
+    fn le_registro(&mut self, l: &mut Leitor) -> Result<(), String> {
+        let registro = l.u8()?;
+        match registro {
+            VEICULO => {
+                let id = l.u32()? as usize;
+                if id != self.veiculos.len() {
+                    return Err(format!("id de veículo fora de ordem: {}", id));
+                }
+                let via = l.via()?;
+                let comprimento = l.f32()?;
+                self.veiculos.push(VeiculoGravado {
+                    placa: l.texto()?,
+                    via,
+                    comprimento,
+                });
+            }
+
+            QUADRO => {
+                let tempo = l.f64()?;
+                let n = l.u32()?;
+                let mut estados = Vec::new();
+                for _ in 0..n {
+                    let id = l.u32()? as usize;
+                    if id >= self.veiculos.len() {
+                        return Err(format!("veículo desconhecido: {}", id));
+                    }
+                    estados.push(EstadoGravado {
+                        id,
+                        pos_atual: l.f32()?,
+                        vel_atual: l.f32()?,
+                        acel_atual: l.f32()?,
+                    });
+                }
+                self.quadros.push(QuadroGravado {
+                    tempo,
+                    estados,
+                    mensagens: Vec::new(),
+                    decisoes: Vec::new(),
+                    colisao: None,
+                });
+            }
+
+            DE_VEICULO | DO_CONTROLADOR | DECISAO | COLISAO => {
+                let quadro = self
+                    .quadros
+                    .last_mut()
+                    .ok_or("registro antes do primeiro quadro".to_string())?;
+                match registro {
+                    DECISAO => {
+                        let id = l.u32()? as usize;
+                        quadro.decisoes.push(DecisaoGravada {
+                            id,
+                            pos_atual: l.f32()?,
+                            vel_atual: l.f32()?,
+                            acel_desejada: l.f32()?,
+                        });
+                    }
+                    COLISAO => quadro.colisao = Some(l.texto()?),
+                    _ => quadro.mensagens.push(le_mensagem(l, registro)?),
+                }
+            }
+
+            x => return Err(format!("registro desconhecido: {}", x)),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,289 @@
+/* Execução em lote (Monte Carlo)
+
+    Simula, sem janela, todas as combinações de uma grade de parâmetros, com várias
+    sementes para cada ponto da grade, usando todos os núcleos do processador.
+    Cada simulação gera uma linha CSV com os parâmetros do ponto e o resumo da simulação.
+*/
+
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::cenario::Cenario;
+use crate::chegadas::TipoProcesso;
+use crate::controlador::{TipoControlador, TipoPlano};
+use crate::simulacao::{cria_simulacao, simula_sem_janela, LimitesSimulacao, ResumoSimulacao};
+use crate::transito::{ControleABordo, ModoInforme};
+
+// Valores a combinar, lista vazia mantém o valor do cenário base
+#[derive(Default)]
+pub struct Grade {
+    pub controladores: Vec<TipoControlador>,
+    pub a_bordo: Vec<ControleABordo>,
+    pub informe: Vec<ModoInforme>,
+    pub tec_min: Vec<f64>,       // ms, as duas vias, chegadas uniformes
+    pub tec_max: Vec<f64>,       // ms, as duas vias, chegadas uniformes
+    pub tempo_verde: Vec<f64>,   // ms
+    pub tempo_amarelo: Vec<f64>, // ms
+    pub plano: Vec<TipoPlano>,
+    pub largura: Vec<f64>,    // metros, as duas vias
+    pub perimetro: Vec<f64>,  // metros, as duas vias
+    pub latencia: Vec<f64>,   // ms, todos os canais da comunicação
+    pub perda: Vec<f64>,      // probabilidade, todos os canais da comunicação
+    pub capacidade: Vec<f64>, // bytes/s, todos os canais da comunicação, zero é ilimitada
+}
+
+// Tudo que define um lote
+pub struct ParametrosLote {
+    pub cenario: Cenario, // cenário base, modificado pela grade
+    pub grade: Grade,
+    pub sementes: u64,        // simulações por ponto da grade
+    pub semente_inicial: u64, // sementes usadas são semente_inicial, semente_inicial + 1, ...
+    pub limites: LimitesSimulacao,
+    pub threads: usize,
+    pub saida: Option<String>, // arquivo CSV, ou a saída padrão
+}
+
+// Troca cada ponto por uma cópia para cada valor, lista vazia não muda nada
+fn expande<T>(pontos: &mut Vec<Cenario>, valores: &[T], aplica: impl Fn(&mut Cenario, &T)) {
+    if valores.is_empty() {
+        return;
+    }
+    let mut novos = Vec::new();
+    for ponto in pontos.iter() {
+        for valor in valores {
+            let mut c = ponto.clone();
+            aplica(&mut c, valor);
+            novos.push(c);
+        }
+    }
+    *pontos = novos;
+}
+
+impl Grade {
+    // Todas as combinações válidas, aplicadas ao cenário base
+    fn pontos(&self, base: &Cenario) -> Vec<Cenario> {
+        let mut pontos = vec![base.clone()];
+        expande(&mut pontos, &self.controladores, |c, tipo| {
+            c.controle.tipo = *tipo
+        });
+        expande(&mut pontos, &self.a_bordo, |c, a_bordo| {
+            c.veiculo.controle_a_bordo = *a_bordo
+        });
+        expande(&mut pontos, &self.informe, |c, informe| {
+            c.veiculo.informe = *informe
+        });
+        expande(&mut pontos, &self.tec_min, |c, t| {
+            for chegadas in [&mut c.chegadas_h, &mut c.chegadas_v] {
+                chegadas.processo = TipoProcesso::Uniforme;
+                chegadas.tec_min = *t;
+            }
+        });
+        expande(&mut pontos, &self.tec_max, |c, t| {
+            for chegadas in [&mut c.chegadas_h, &mut c.chegadas_v] {
+                chegadas.processo = TipoProcesso::Uniforme;
+                chegadas.tec_max = *t;
+            }
+        });
+        expande(&mut pontos, &self.tempo_verde, |c, t| {
+            c.semaforo.tempo_verde = *t
+        });
+        expande(&mut pontos, &self.tempo_amarelo, |c, t| {
+            c.semaforo.tempo_amarelo = *t
+        });
+        expande(&mut pontos, &self.plano, |c, plano| {
+            c.semaforo.plano = *plano
+        });
+        expande(&mut pontos, &self.largura, |c, l| {
+            c.geometria.viah_largura = *l;
+            c.geometria.viav_largura = *l;
+        });
+        expande(&mut pontos, &self.perimetro, |c, p| {
+            c.geometria.viah_perimetro = *p;
+            c.geometria.viav_perimetro = *p;
+        });
+        expande(&mut pontos, &self.latencia, |c, l| {
+            for canal in c.comunicacao.canais() {
+                canal.latencia = *l;
+            }
+        });
+        expande(&mut pontos, &self.perda, |c, p| {
+            for canal in c.comunicacao.canais() {
+                canal.perda = *p;
+            }
+        });
+        expande(&mut pontos, &self.capacidade, |c, b| {
+            for canal in c.comunicacao.canais() {
+                canal.capacidade = *b;
+            }
+        });
+
+        // Combinações impossíveis, como mínimo maior que máximo, ficam de fora
+        pontos.retain(|c| match c.valida() {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Ponto da grade ignorado: {}", e);
+                false
+            }
+        });
+        pontos
+    }
+}
+
+// Cabeçalho das colunas de 'linha_csv'
+fn cabecalho_csv() -> String {
+    format!(
+        "ponto,controlador,a_bordo,informe,min_chegadas_s,max_chegadas_s,tempo_verde_s,tempo_amarelo_s,\
+         plano,largura_m,perimetro_m,latencia_s,perda,capacidade_bytes_s,{}",
+        ResumoSimulacao::cabecalho_csv()
+    )
+}
+
+// Parâmetros do ponto e resumo de uma simulação, em uma linha CSV
+fn linha_csv(ponto: usize, c: &Cenario, resumo: &ResumoSimulacao) -> String {
+    format!(
+        "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+        ponto,
+        c.controle.tipo.nome(),
+        c.veiculo.controle_a_bordo.nome(),
+        c.veiculo.informe.nome(),
+        c.chegadas_h.tec_min / 1000.0,
+        c.chegadas_h.tec_max / 1000.0,
+        c.semaforo.tempo_verde / 1000.0,
+        c.semaforo.tempo_amarelo / 1000.0,
+        c.semaforo.plano.nome(),
+        c.geometria.viah_largura,
+        c.geometria.viah_perimetro,
+        c.comunicacao.de_veiculo.latencia / 1000.0,
+        c.comunicacao.de_veiculo.perda,
+        c.comunicacao.de_veiculo.capacidade,
+        resumo.linha_csv()
+    )
+}
+
+// Executa todas as simulações do lote e grava o CSV
+pub fn executa_lote(lote: &ParametrosLote) -> Result<(), String> {
+    let pontos = lote.grade.pontos(&lote.cenario);
+    if pontos.is_empty() {
+        return Err("nenhum ponto válido na grade".to_string());
+    }
+
+    // Cada simulação é um par (ponto, semente)
+    let total = pontos.len() * lote.sementes as usize;
+    let proxima = AtomicUsize::new(0);
+    let concluidas = AtomicUsize::new(0);
+    let resumos: Mutex<Vec<Option<ResumoSimulacao>>> =
+        Mutex::new((0..total).map(|_| None).collect());
+
+    eprintln!(
+        "Lote: {} pontos x {} sementes = {} simulações em {} threads",
+        pontos.len(),
+        lote.sementes,
+        total,
+        lote.threads
+    );
+
+    thread::scope(|escopo| {
+        for _ in 0..lote.threads.min(total) {
+            escopo.spawn(|| loop {
+                let i = proxima.fetch_add(1, Ordering::SeqCst);
+                if i >= total {
+                    break;
+                }
+                let cenario = &pontos[i / lote.sementes as usize];
+                let semente = lote.semente_inicial + (i as u64 % lote.sementes);
+
+                let mut simul = cria_simulacao(cenario, semente);
+                let resumo = simula_sem_janela(&mut simul, &lote.limites);
+                resumos.lock().unwrap()[i] = Some(resumo);
+
+                // Mostra o progresso a cada 10%
+                let feitas = concluidas.fetch_add(1, Ordering::SeqCst) + 1;
+                if feitas == total || feitas.is_multiple_of((total / 10).max(1)) {
+                    eprintln!("Lote: {}/{} simulações concluídas", feitas, total);
+                }
+            });
+        }
+    });
+
+    let resumos = resumos.into_inner().unwrap();
+    let mut texto = cabecalho_csv();
+    texto.push('\n');
+    for (i, resumo) in resumos.iter().enumerate() {
+        let ponto = i / lote.sementes as usize;
+        let resumo = resumo.as_ref().unwrap(); // Todas as simulações foram feitas
+        texto.push_str(&linha_csv(ponto, &pontos[ponto], resumo));
+        texto.push('\n');
+    }
+
+    match &lote.saida {
+        None => print!("{}", texto),
+        Some(caminho) => {
+            fs::write(caminho, texto)
+                .map_err(|e| format!("Não foi possível gravar {}: {}", caminho, e))?;
+            mostra_pontos(&pontos, &resumos, lote.sementes as usize);
+        }
+    }
+    Ok(())
+}
+
+// Mostra as médias de cada ponto da grade
+fn mostra_pontos(pontos: &[Cenario], resumos: &[Option<ResumoSimulacao>], sementes: usize) {
+    println!("___Médias por ponto da grade___");
+    for (ponto, cenario) in pontos.iter().enumerate() {
+        let do_ponto: Vec<&ResumoSimulacao> = resumos[ponto * sementes..(ponto + 1) * sementes]
+            .iter()
+            .flatten()
+            .collect();
+        let n = do_ponto.len() as f64;
+        let colisoes = do_ponto.iter().filter(|r| r.colisao.is_some()).count();
+        let vazao = do_ponto.iter().map(|r| r.metricas.vazao).sum::<f64>() / n;
+        let (passagens, grupos) = do_ponto.iter().fold((0, 0), |(p, g), r| {
+            (p + r.metricas.passagens, g + r.metricas.grupos)
+        });
+        let atraso = do_ponto
+            .iter()
+            .map(|r| r.metricas.atraso_medio)
+            .sum::<f64>()
+            / n;
+        let (enviadas, descartadas) = do_ponto
+            .iter()
+            .flat_map(|r| r.comunicacao.canais())
+            .fold((0, 0), |(e, d), c| (e + c.enviadas, d + c.descartadas));
+        let criados: i32 = do_ponto.iter().map(|r| r.veiculos_criados).sum();
+        let com_controlador: u64 = do_ponto
+            .iter()
+            .map(|r| r.comunicacao.de_veiculo.enviadas + r.comunicacao.do_controlador.enviadas)
+            .sum();
+        let (soma_idade, amostras) = do_ponto
+            .iter()
+            .map(|r| r.comunicacao.idade_situacao)
+            .fold((0.0, 0), |(s, a), i| (s + i.soma, a + i.amostras));
+        println!(
+            "   ponto {}: {}, verde {} s, amarelo {} s, largura {} m, perímetro {} m",
+            ponto,
+            cenario.descricao(),
+            cenario.semaforo.tempo_verde / 1000.0,
+            cenario.semaforo.tempo_amarelo / 1000.0,
+            cenario.geometria.viah_largura,
+            cenario.geometria.viah_perimetro
+        );
+        println!(
+            "      colisões {}/{}, vazão {:.0} veículos/h, atraso médio {:.2} s, \
+             veículos por grupo {:.2}, mensagens descartadas {:.1}%",
+            colisoes,
+            do_ponto.len(),
+            vazao,
+            atraso / 1000.0,
+            passagens as f64 / (grupos as f64).max(1.0),
+            100.0 * descartadas as f64 / (enviadas as f64).max(1.0)
+        );
+        println!(
+            "      mensagens com o controlador por veículo {:.0}, \
+             idade média da situação no controlador {:.1} ms",
+            com_controlador as f64 / (criados as f64).max(1.0),
+            soma_idade / (amostras as f64).max(1.0)
+        );
+    }
+}
@@ -2,37 +2,51 @@
 
 */
 
-use core::f32;
 use std::thread::sleep;
 use std::time::Duration;
 
-use rand::Rng; // Para gerar números aleatórios, não é 'std::'
-               // Requer [dependencies] rand = "0.8.5"
-
 use std::env; // Para acessar os argumentos da linha de comando, exemplo:
-              // cargo run -- s/n/o 2.0 3.0
+              // cargo run -- run --controlador reservas --min-chegadas 2 --max-chegadas 3
+              // cargo run -- headless --controlador semaforo --duracao 600 --semente 42
               /*
               use device_query::{DeviceQuery, DeviceState, Keycode};	// Para acessar o teclado, não é 'std::'
                                                                       // Requer [dependencies] device_query = "1.1.3"
               */
 
-use speedy2d::color::Color; // Para gráficos
-use speedy2d::dimen::Vector2;
-use speedy2d::font::{Font, TextLayout, TextOptions}; // requer [dependencies] speedy2d = "1.12.0"
+use speedy2d::font::Font; // requer [dependencies] speedy2d = "1.12.0"
 use speedy2d::window::{WindowHandler, WindowHelper};
 use speedy2d::{Graphics2D, Window};
 
+mod atacante;
+mod autenticacao;
+mod cenario;
+mod chegadas;
+mod cli;
 mod comunicacao;
 mod controlador;
+mod desenho;
+mod gravacao;
+mod lote;
+mod metricas;
+mod protocolo;
+mod rastro;
+mod rede;
+mod registro;
+mod reproducao;
+mod servidor;
+mod simulacao;
 mod transito;
 
-use transito::{Transito, Via, VIAH_LARGURA, VIAV_LARGURA};
-use transito::{VIAH_MARGEM, VIAH_TOTAL, VIAV_MARGEM, VIAV_TOTAL};
+use transito::Via;
 //use transito::veiculos::Carro;
 
-use controlador::{Controle, TipoControlador};
+use cli::{Comando, OpcoesSimulacao};
+use controlador::TipoControlador;
+use desenho::{desenha_cruzamento, escreve, VistaCarro};
 
-use comunicacao::Comunicacao;
+use simulacao::{ativa_atacante, cria_simulacao_com, encerra_gravacao, laco_simulacao};
+use simulacao::{simula_sem_janela, LimitesSimulacao};
+use simulacao::{ResumoSimulacao, Simulacao, TICKMS};
 
 /* Geometria do cruzamento
     Zero de cada via é o início do cruzamento
@@ -56,8 +70,6 @@ use comunicacao::Comunicacao;
 
 */
 
-const TICKMS: f64 = 5.0; // Passo da simulação, em ms
-
 // Struct necessária para a biblioteca gráfica
 struct MyWindowHandler {
     simular: bool,        // true significa não está pausado
@@ -68,6 +80,16 @@ struct MyWindowHandler {
     fonte: Font,          // Fonte a ser usado na janela grafica
 }
 
+impl MyWindowHandler {
+    // Mostra as métricas e fecha a gravação, se houver
+    fn finaliza(&mut self) {
+        self.simulacao.transito.relatorio_metricas().mostra();
+        if let Err(msg) = encerra_gravacao(&mut self.simulacao) {
+            eprintln!("Erro: {}", msg);
+        }
+    }
+}
+
 // Callbacks da biblioteca gráfica chegam nestes métodos
 impl WindowHandler for MyWindowHandler {
     fn on_draw(&mut self, helper: &mut WindowHelper, graphics: &mut Graphics2D) {
@@ -76,148 +98,41 @@ impl WindowHandler for MyWindowHandler {
             return;
         }
 
+        // Mantém o tempo simulado próximo do tempo real, dorme TICKMS ms
+        sleep(Duration::from_millis(TICKMS.round() as u64));
+
         // Executa um passo de simulação
         self.finalizada = !laco_simulacao(&mut self.simulacao);
-
-        // Limpa a tela
-        //graphics.clear_screen(Color::WHITE);
-        graphics.clear_screen(Color::from_rgb(0.9, 0.9, 0.9));
-
-        // Calcula resolução
-        let resolucao_h = self.largura_total / VIAH_TOTAL; // relacao pixel por metro
-        let resolucao_v = self.altura_total / VIAV_TOTAL; // relação pixel por metro
-        let escala = (self.largura_total / 40.0) as f32;
-
-        // Desenha linhas via H
-        let y1 = (VIAV_MARGEM * resolucao_v) as f32;
-        let y2 = ((VIAV_MARGEM + VIAH_LARGURA) * resolucao_v) as f32;
-        graphics.draw_line(
-            (0.0, y1),
-            (self.largura_total as f32, y1),
-            4.0,
-            Color::BLACK,
-        );
-        graphics.draw_line(
-            (0.0, y2),
-            (self.largura_total as f32, y2),
-            4.0,
-            Color::BLACK,
-        );
-
-        // Desenha linhas via V
-        let zero_h = (self.largura_total - (VIAH_MARGEM + VIAV_LARGURA) * resolucao_h) as f32;
-        let x = (self.largura_total - (VIAH_MARGEM) * resolucao_h) as f32;
-        graphics.draw_line(
-            (zero_h, 0.0),
-            (zero_h, self.altura_total as f32),
-            4.0,
-            Color::BLACK,
-        );
-        graphics.draw_line((x, 0.0), (x, self.altura_total as f32), 4.0, Color::BLACK);
-
-        // Desenha os carros da Via H
-        let mut lado = 0.0;
-        for carro in self.simulacao.transito.get_iterador(Via::ViaH) {
-            let x = zero_h + ((carro.pos_atual - carro.comprimento) * resolucao_h) as f32;
-            let y = ((VIAV_MARGEM + 1.0) * resolucao_v) as f32;
-            let w = (carro.comprimento * resolucao_h) as f32;
-            //let h = (carro.largura * resolucao_v) as f32;
-            let h = (2.0 * resolucao_v) as f32;
-
-            let cor: Color;
-            if carro.acel_atual == 0.0 {
-                cor = Color::WHITE;
-            } else if carro.acel_atual < 0.0 {
-                cor = Color::RED;
-            } else {
-                cor = Color::GREEN;
-            }
-
-            let vertices = [
-                Vector2 { x, y },
-                Vector2 { x: x + w, y },
-                Vector2 { x: x + w, y: y + h },
-                Vector2 { x, y: y + h },
-            ];
-            graphics.draw_quad(vertices, cor);
-
-            // Se foi pausada ou finalizada escreve na janela dados do carro
-            if self.finalizada || !self.simular {
-                if lado == 0.0 {
-                    // Para espalhar texto na tela
-                    lado = -80.0;
-                } else {
-                    lado = 0.0;
-                }
-
-                let tex_1 = carro.placa.to_string();
-                let texto_1 = self.fonte.layout_text(&tex_1, escala, TextOptions::new());
-                graphics.draw_text((x - 10.0, y + 10.0 + lado), Color::BLACK, &texto_1);
-
-                let tex_2 = format!("A:{:.2}", carro.acel_atual);
-                let texto_2 = self.fonte.layout_text(&tex_2, escala, TextOptions::new());
-                graphics.draw_text((x - 10.0, y + 25.0 + lado), Color::BLACK, &texto_2);
-
-                let tex_3 = format!("V:{:.2}", carro.vel_atual);
-                let texto_3 = self.fonte.layout_text(&tex_3, escala, TextOptions::new());
-                graphics.draw_text((x - 10.0, y + 40.0 + lado), Color::BLACK, &texto_3);
-
-                let tex_4 = format!("P:{:.2}", carro.pos_atual);
-                let texto_4 = self.fonte.layout_text(&tex_4, escala, TextOptions::new());
-                graphics.draw_text((x - 10.0, y + 55.0 + lado), Color::BLACK, &texto_4);
-            }
+        if self.finalizada {
+            self.finaliza();
         }
 
-        // Desenha os carros da Via V
-        let mut lado = 0.0;
-        for carro in self.simulacao.transito.get_iterador(Via::ViaV) {
-            let x = (self.largura_total - (VIAH_MARGEM + VIAV_LARGURA - 1.0) * resolucao_h) as f32;
-            let y = ((VIAV_MARGEM + VIAH_LARGURA - carro.pos_atual) * resolucao_v) as f32;
-            let w = (2.0 * resolucao_h) as f32;
-            let h = (carro.comprimento * resolucao_v) as f32;
-
-            let cor: Color;
-            if carro.acel_atual == 0.0 {
-                cor = Color::WHITE;
-            } else if carro.acel_atual < 0.0 {
-                cor = Color::RED;
-            } else {
-                cor = Color::GREEN;
-            }
+        // Desenha o cruzamento com os carros das duas vias
+        let transito = &self.simulacao.transito;
+        let via_h: Vec<VistaCarro> = transito
+            .get_iterador(Via::ViaH)
+            .map(VistaCarro::de_carro)
+            .collect();
+        let via_v: Vec<VistaCarro> = transito
+            .get_iterador(Via::ViaV)
+            .map(VistaCarro::de_carro)
+            .collect();
+        desenha_cruzamento(
+            graphics,
+            &self.fonte,
+            self.largura_total,
+            self.altura_total,
+            transito.geometria(),
+            &via_h,
+            &via_v,
+            // Se foi pausada ou finalizada escreve na janela dados dos carros
+            self.finalizada || !self.simular,
+        );
 
-            let vertices = [
-                Vector2 { x, y },
-                Vector2 { x: x + w, y },
-                Vector2 { x: x + w, y: y + h },
-                Vector2 { x, y: y + h },
-            ];
-            graphics.draw_quad(vertices, cor);
-
-            // Se foi pausada ou finalizada escreve na janela dados do carro
-            if self.finalizada || !self.simular {
-                if lado == 0.0 {
-                    // Para espalhar texto na tela
-                    lado = 70.0;
-                } else {
-                    lado = 0.0;
-                }
-
-                let tex_1 = carro.placa.to_string();
-                let texto_1 = self.fonte.layout_text(&tex_1, escala, TextOptions::new());
-                graphics.draw_text((x - 50.0 + lado, y + 5.0), Color::BLACK, &texto_1);
-
-                let tex_2 = format!("A:{:.2}", carro.acel_atual);
-                let texto_2 = self.fonte.layout_text(&tex_2, escala, TextOptions::new());
-                graphics.draw_text((x - 50.0 + lado, y + 20.0), Color::BLACK, &texto_2);
-
-                let tex_3 = format!("V:{:.2}", carro.vel_atual);
-                let texto_3 = self.fonte.layout_text(&tex_3, escala, TextOptions::new());
-                graphics.draw_text((x - 50.0 + lado, y + 35.0), Color::BLACK, &texto_3);
-
-                let tex_4 = format!("P:{:.2}", carro.pos_atual);
-                let texto_4 = self.fonte.layout_text(&tex_4, escala, TextOptions::new());
-                graphics.draw_text((x - 50.0 + lado, y + 50.0), Color::BLACK, &texto_4);
-            }
+        // Estado do controlador próprio no canto da janela
+        if let Some(texto) = self.simulacao.controle.as_ref().and_then(|c| c.painel()) {
+            let escala = (self.largura_total / 40.0) as f32;
+            escreve(graphics, &self.fonte, escala, (5.0, 5.0), &texto);
         }
 
         // O que acontece agora ?
@@ -233,8 +148,9 @@ impl WindowHandler for MyWindowHandler {
     }
 
     fn on_keyboard_char(&mut self, helper: &mut WindowHelper<()>, tecla: char) {
-        if tecla == 'x' {
+        if tecla == 'x' && !self.finalizada {
             self.finalizada = true;
+            self.finaliza();
         }
 
         if self.finalizada {
@@ -251,119 +167,27 @@ impl WindowHandler for MyWindowHandler {
     }
 }
 
-// Sorteia tempo até a chegada do próximo veículo
-fn tempo_entre_chegadas(min: f64, max: f64) -> f64 {
-    rand::thread_rng().gen_range(min..=max)
-}
-
-// Descritor da simulação como um todo
-struct Simulacao {
-    cont: char,
-    tec_min: f64,
-    tec_max: f64,
-    transito: Transito,
-    comunicacao: Comunicacao,
-    controle: Controle,
-    tempo_ateh_proxima_chegada: f64,
-}
-
-// Laço de simulação, returna false no caso de finalizar a simulação
-fn laco_simulacao(simul: &mut Simulacao) -> bool {
-    // Mantém o tempo simulado próximo do tempo real, dorme TICKMS ms
-    sleep(Duration::from_millis(TICKMS.round() as u64));
-
-    // Atualiza estado do trânsito
-    simul.transito.tick(TICKMS, &mut simul.comunicacao);
-
-    // Atualiza estado do controlador
-    simul.controle.acao_controle(TICKMS, &mut simul.comunicacao);
-
-    // Mostra estado das vias
-    simul.transito.mostra_vias();
-
-    // Aborta a simulação se ocorreu colisão
-    if let Some(m) = simul.transito.ocorreu_colisao() {
-        println!(
-            "Ocorreu colisao, controlador {}, tempos entre {} e {}: {}",
-            simul.cont, simul.tec_min, simul.tec_max, m
-        );
-        return false;
+// Cria a simulação pedida, gravando, com o controlador remoto e com a queda do
+// controlador se foi pedido
+fn cria(opcoes: &OpcoesSimulacao) -> Result<Simulacao, String> {
+    let mut simul = cria_simulacao_com(
+        &opcoes.cenario,
+        opcoes.semente,
+        opcoes.gravacao.as_deref(),
+        opcoes.rastro.as_deref(),
+        opcoes.servidor.as_deref(),
+    )?;
+    simul.parada_controlador = opcoes.falha_controlador;
+    if let Some(periodo) = opcoes.atacante {
+        ativa_atacante(&mut simul, periodo);
     }
-
-    // Verifica se tem algum carro no sistema
-    if simul.transito.vazio() {
-        println!("Nenhum carro no perímetro");
-        return false;
-    }
-
-    // Verifica se está na hora de chegar novos carros
-    simul.tempo_ateh_proxima_chegada -= TICKMS;
-
-    if simul.tempo_ateh_proxima_chegada <= 0.0 {
-        match simul
-            .transito
-            .chega_carro(Via::ViaH, &mut simul.comunicacao)
-        {
-            Ok(_) => (),
-            Err(msg) => println!("Falha em chegar um carro via H: {}", msg),
-        }
-
-        match simul
-            .transito
-            .chega_carro(Via::ViaV, &mut simul.comunicacao)
-        {
-            Ok(_) => (),
-            Err(msg) => println!("Falha em chegar um carro via V: {}", msg),
-        }
-
-        simul.tempo_ateh_proxima_chegada += tempo_entre_chegadas(simul.tec_min, simul.tec_max);
-    }
-
-    println!(
-        "#main: tempo_ateh_proxima_chegada {}",
-        simul.tempo_ateh_proxima_chegada
-    );
-
-    true
+    Ok(simul)
 }
 
-// Cria os principais componentes da simulação, cria a janela para visualização, aciona laço da biblioteca gráfica
-fn simula_mundo(cont: char, tec_min: f64, tec_max: f64, tam_janela: f64) {
-    // Cria um sistema de comunicação
-    let mut comunicacao = Comunicacao::new();
-
-    // Cria uma descrição de trânsito
-    let mut transito = Transito::new();
-
-    // Cria o primeiro carro da via H			!!!
-    match transito.chega_carro(Via::ViaH, &mut comunicacao) {
-        Ok(_) => (),
-        Err(msg) => println!("Via H: {}", msg),
-    };
-
-    // Cria o primeiro carro da via V			!!!
-    match transito.chega_carro(Via::ViaV, &mut comunicacao) {
-        Ok(_) => (),
-        Err(msg) => println!("Via V: {}", msg),
-    };
-
-    // Cria uma estrutura de controle
-    let controle = match cont {
-        's' => Controle::new(TipoControlador::Semaforo),
-        'n' => Controle::new(TipoControlador::FazNada),
-        _other => panic!("Tipo de controlador não é s|n."),
-    };
-
+// Cria a simulação e a janela para visualização, aciona laço da biblioteca gráfica
+fn simula_mundo(opcoes: &OpcoesSimulacao, tam_janela: f64) -> Result<(), String> {
     // Descritor da simulação
-    let simul = Simulacao {
-        cont,
-        tec_min, // Tempo entre chegadas
-        tec_max,
-        transito,
-        comunicacao,
-        controle,
-        tempo_ateh_proxima_chegada: tempo_entre_chegadas(tec_min, tec_max),
-    };
+    let simul = cria(opcoes)?;
 
     // Cria janela sem evento de usuario
     let window =
@@ -372,7 +196,6 @@ fn simula_mundo(cont: char, tec_min: f64, tec_max: f64, tam_janela: f64) {
     let fonte = Font::new(include_bytes!("../assets/fonts/NotoSans-Regular.ttf")).unwrap();
 
     // Laço ficará com o WindowHandler
-    println!("simula_transito");
     window.run_loop(MyWindowHandler {
         simular: true,
         finalizada: false,
@@ -383,55 +206,106 @@ fn simula_mundo(cont: char, tec_min: f64, tec_max: f64, tam_janela: f64) {
     });
 }
 
-// Confere os argumentos da linha de comando e chama 'simula_mundo'
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 5 {
-        panic!("Uso: <s|n>  <min entre chegadas>  <max entre chegadas> <tam janela>");
+// Cria a simulação e executa sem janela, o mais rápido possível, mostra e grava o resumo
+fn simula_sem_janela_e_mostra(
+    opcoes: &OpcoesSimulacao,
+    limites: LimitesSimulacao,
+    saida: Option<String>,
+) -> Result<bool, String> {
+    let mut simul = cria(opcoes)?;
+    let resumo = simula_sem_janela(&mut simul, &limites);
+    encerra_gravacao(&mut simul)?;
+    resumo.mostra();
+    if let Some(msg) = simul.falha {
+        return Err(msg);
+    }
+
+    if let Some(caminho) = saida {
+        let texto = format!(
+            "{}\n{}\n",
+            ResumoSimulacao::cabecalho_csv(),
+            resumo.linha_csv()
+        );
+        std::fs::write(&caminho, texto)
+            .map_err(|e| format!("Não foi possível gravar {}: {}", caminho, e))?;
     }
 
-    let cont = args[1]
-        .trim()
-        .chars()
-        .next()
-        .expect("Uso: <s|n>  <min entre chegadas>  <max entre chegadas> <tam janela>");
+    Ok(resumo.colisao.is_none())
+}
 
-    match cont {
-        's' | 'n' => (),
-        _other => panic!("Uso: <s|n>  <min entre chegadas>  <max entre chegadas> <tam janela>"),
-    };
+// Executa o que foi pedido na linha de comando
+fn executa(comando: Comando) -> Result<(), String> {
+    match comando {
+        Comando::Ajuda => println!("{}", cli::AJUDA),
+
+        Comando::ListaControladores => {
+            for tipo in TipoControlador::todos() {
+                println!(
+                    "{:10} ({})  {}",
+                    tipo.nome(),
+                    tipo.letra(),
+                    tipo.descricao()
+                );
+            }
+        }
 
-    let result_tec_min = args[2].trim().parse::<f64>();
-    let tec_min = result_tec_min
-        .expect("Uso: <s|n>  <min entre chegadas>  <max entre chegadas> <tam janela>");
+        Comando::Executa {
+            simulacao,
+            tam_janela,
+        } => {
+            registro::configura(simulacao.log.clone());
+            println!("Semente da simulação: {}", simulacao.semente);
+            println!("Inicio da simulação de cruzamento automático");
+            simula_mundo(&simulacao, tam_janela)?;
+            println!("Fim da simulação de cruzamento automático");
+        }
 
-    let result_tec_max = args[3].trim().parse::<f64>();
-    let tec_max = result_tec_max
-        .expect("Uso: <s|n>  <min entre chegadas>  <max entre chegadas> <tam janela>");
+        Comando::SemJanela {
+            simulacao,
+            limites,
+            saida,
+        } => {
+            registro::configura(simulacao.log.clone());
+            println!("Semente da simulação: {}", simulacao.semente);
+            println!("Inicio da simulação de cruzamento automático sem janela");
+            let sem_colisao = simula_sem_janela_e_mostra(&simulacao, limites, saida)?;
+            println!("Fim da simulação de cruzamento automático");
+
+            // Permite detectar colisões em scripts e na integração contínua
+            if !sem_colisao {
+                std::process::exit(2);
+            }
+        }
 
-    if tec_min < 2.0 || tec_max < 2.0 {
-        println!("Tempo entre chegadas deve ser no mínimo 2 segundos.");
-        return;
-    }
+        Comando::Lote { lote, log } => {
+            registro::configura(log);
+            lote::executa_lote(&lote)?;
+        }
 
-    if tec_min > tec_max {
-        println!(
-            "Tempo entre chegadas mínimo não pode ser maior que o tempo entre chegadas máximo."
-        );
-        return;
-    }
+        Comando::Servidor { servidor, log } => {
+            registro::configura(log);
+            servidor::serve(&servidor)?;
+        }
+
+        Comando::Reproduz {
+            gravacao,
+            tam_janela,
+        } => reproducao::reproduz(&gravacao, tam_janela)?,
 
-    let result_tam_janela = args[4].trim().parse::<f64>();
-    let tam_janela = result_tam_janela
-        .expect("Uso: <s|n>  <min entre chegadas>  <max entre chegadas> <tam janela>");
-    if !(200.0..=1000.0).contains(&tam_janela) {
-        println!("Tamanho da janela deve estar entre 200 e 1000.");
-        return;
+        Comando::Rastro(rastro) => rastro::examina(&rastro)?,
     }
+    Ok(())
+}
 
-    println!("Inicio da simulação de cruzamento automático");
+// Confere os argumentos da linha de comando e executa o subcomando pedido
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    simula_mundo(cont, 1000.0 * tec_min, 1000.0 * tec_max, tam_janela);
+    let resultado = cli::interpreta(&args).and_then(executa);
 
-    println!("Fim da simulação de cruzamento automático");
+    if let Err(msg) = resultado {
+        eprintln!("Erro: {}", msg);
+        eprintln!("Use --help para ver as opções.");
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+
+use crate::transito::{Carro, Geometria, Via};
+
+const VEL_PARADO: f64 = 0.1; // abaixo desta velocidade o carro está parado, m/s
+const VEL_ANDANDO: f64 = 1.0; // acima desta velocidade o carro voltou a andar, m/s
+
+// Dados coletados de um veículo ao longo da simulação
+struct RegistroVeiculo {
+    via: Via,
+    entrada: f64,       // instante de chegada no perímetro, ms
+    saida: Option<f64>, // instante em que deixou o sistema, ms
+    paradas: i32,       // quantas vezes parou
+    tempo_parado: f64,  // tempo total parado, ms
+    parado: bool,       // se está parado agora
+    limitado: bool,     // se o controle a bordo está limitando a aceleração agora
+    intervencoes: i32,  // quantas vezes o controle a bordo passou a limitar
+    em_falha: bool,     // se está em falha segura agora
+    falhas: i32,        // quantas vezes entrou em falha segura
+    forjadas: i32,      // mensagens rejeitadas por MAC errado
+    repetidas: i32,     // mensagens rejeitadas por serem cópias antigas
+    comprimento: f64,   // metros
+    entrou: bool,       // se a frente já passou do início do cruzamento
+}
+
+// Dados coletados de uma via ao longo da simulação
+#[derive(Default)]
+struct RegistroVia {
+    chegadas: i32,           // carros que entraram no perímetro
+    chegadas_recusadas: i32, // carros que não entraram por via congestionada
+    saidas: i32,             // carros que deixaram o sistema
+    soma_fila: f64,          // soma do tamanho da fila em todas as amostras
+    amostras_fila: i32,      // número de amostras do tamanho da fila
+    max_fila: i32,           // maior fila observada
+}
+
+// Coletor de métricas de desempenho do trânsito
+pub struct Metricas {
+    veiculos: HashMap<String, RegistroVeiculo>,
+    via_h: RegistroVia,
+    via_v: RegistroVia,
+    ultima_via: Option<Via>,  // via do último carro que entrou no cruzamento
+    passagens: i32,           // carros que entraram no cruzamento
+    grupos: i32,              // sequências de passagens seguidas pela mesma via
+    geometria: Geometria,     // para calcular o tempo de viagem com via livre
+    velocidade_cruzeiro: f64, // m/s
+}
+
+// Métricas de uma via no final da simulação
+#[derive(Debug, Clone, Default)]
+pub struct RelatorioVia {
+    pub chegadas: i32,
+    pub chegadas_recusadas: i32,
+    pub saidas: i32,
+    pub vazao: f64,       // veículos por hora
+    pub fila_media: f64,  // veículos parados antes do cruzamento, em média
+    pub fila_maxima: i32, // maior número de veículos parados antes do cruzamento
+}
+
+// Métricas de desempenho no final da simulação
+#[derive(Debug, Clone, Default)]
+pub struct RelatorioMetricas {
+    pub duracao: f64,              // tempo simulado, ms
+    pub veiculos_concluidos: i32,  // veículos que deixaram o sistema
+    pub veiculos_em_transito: i32, // veículos ainda no sistema no final
+    pub tempo_viagem_medio: f64,   // ms
+    pub atraso_medio: f64,         // em relação à velocidade de cruzeiro, ms
+    pub atraso_maximo: f64,        // ms
+    pub paradas_medias: f64,       // paradas por veículo
+    pub tempo_parado_medio: f64,   // ms por veículo
+    pub intervencoes: i32,         // vezes que o controle a bordo passou a limitar a aceleração
+    pub veiculos_limitados: i32,   // veículos que o controle a bordo limitou alguma vez
+    pub falhas_seguras: i32,       // vezes que um veículo entrou em falha segura
+    pub veiculos_em_falha: i32,    // veículos que entraram em falha segura alguma vez
+    pub mensagens_forjadas: i32,   // rejeitadas pelos veículos: forjadas ou adulteradas
+    pub mensagens_repetidas: i32,  // rejeitadas pelos veículos: cópias antigas repetidas
+    pub vazao: f64,                // veículos por hora, as duas vias
+    pub passagens: i32,            // carros que entraram no cruzamento
+    pub grupos: i32,               // sequências de passagens seguidas pela mesma via
+    pub via_h: RelatorioVia,
+    pub via_v: RelatorioVia,
+}
+
+impl RegistroVia {
+    // Monta o relatório desta via
+    fn relatorio(&self, duracao: f64) -> RelatorioVia {
+        RelatorioVia {
+            chegadas: self.chegadas,
+            chegadas_recusadas: self.chegadas_recusadas,
+            saidas: self.saidas,
+            vazao: if duracao > 0.0 {
+                self.saidas as f64 * 3_600_000.0 / duracao
+            } else {
+                0.0
+            },
+            fila_media: if self.amostras_fila > 0 {
+                self.soma_fila / self.amostras_fila as f64
+            } else {
+                0.0
+            },
+            fila_maxima: self.max_fila,
+        }
+    }
+}
+
+impl Metricas {
+    // Cria um novo coletor de métricas
+    pub fn new(geometria: Geometria, velocidade_cruzeiro: f64) -> Self {
+        Self {
+            veiculos: HashMap::new(),
+            via_h: RegistroVia::default(),
+            via_v: RegistroVia::default(),
+            ultima_via: None,
+            passagens: 0,
+            grupos: 0,
+            geometria,
+            velocidade_cruzeiro,
+        }
+    }
+
+    // Tempo para percorrer todo o sistema na velocidade de cruzeiro, em ms
+    fn tempo_livre(&self, via: Via, comprimento: f64) -> f64 {
+        let distancia = self.geometria.perimetro(via)
+            + self.geometria.largura_cruzamento(via)
+            + self.geometria.margem(via)
+            + comprimento;
+        1000.0 * distancia / self.velocidade_cruzeiro
+    }
+
+    fn registro_via(&mut self, via: Via) -> &mut RegistroVia {
+        match via {
+            Via::ViaH => &mut self.via_h,
+            Via::ViaV => &mut self.via_v,
+        }
+    }
+
+    // Um carro entrou no perímetro
+    pub fn chegada(&mut self, carro: &Carro, agora: f64) {
+        self.registro_via(carro.via).chegadas += 1;
+        self.veiculos.insert(
+            carro.placa.clone(),
+            RegistroVeiculo {
+                via: carro.via,
+                entrada: agora,
+                saida: None,
+                paradas: 0,
+                tempo_parado: 0.0,
+                parado: false,
+                limitado: false,
+                intervencoes: 0,
+                em_falha: false,
+                falhas: 0,
+                forjadas: 0,
+                repetidas: 0,
+                comprimento: carro.comprimento,
+                entrou: false,
+            },
+        );
+    }
+
+    // Um carro não conseguiu entrar no perímetro
+    pub fn chegada_recusada(&mut self, via: Via) {
+        self.registro_via(via).chegadas_recusadas += 1;
+    }
+
+    // Observa o estado de um carro depois de um tick de 'tickms' ms
+    pub fn observa_carro(&mut self, carro: &Carro, tickms: f64) {
+        if let Some(registro) = self.veiculos.get_mut(&carro.placa) {
+            if carro.limitado && !registro.limitado {
+                registro.intervencoes += 1;
+            }
+            registro.limitado = carro.limitado;
+            if carro.em_falha_segura && !registro.em_falha {
+                registro.falhas += 1;
+            }
+            registro.em_falha = carro.em_falha_segura;
+            registro.forjadas = carro.forjadas;
+            registro.repetidas = carro.repetidas;
+            if registro.parado {
+                registro.tempo_parado += tickms;
+                if carro.vel_atual > VEL_ANDANDO {
+                    registro.parado = false;
+                }
+            } else if carro.vel_atual < VEL_PARADO {
+                registro.parado = true;
+                registro.paradas += 1;
+            }
+            if !registro.entrou && carro.pos_atual > 0.0 {
+                registro.entrou = true;
+                self.passagens += 1;
+                if self.ultima_via != Some(carro.via) {
+                    self.grupos += 1;
+                    self.ultima_via = Some(carro.via);
+                }
+            }
+        }
+    }
+
+    // Observa o tamanho da fila de uma via: carros parados antes do cruzamento
+    pub fn observa_fila<'a>(&mut self, via: Via, carros: impl Iterator<Item = &'a Carro>) {
+        let fila = carros
+            .filter(|c| c.pos_atual <= 0.0 && c.vel_atual < VEL_ANDANDO)
+            .count() as i32;
+        let registro = self.registro_via(via);
+        registro.soma_fila += fila as f64;
+        registro.amostras_fila += 1;
+        registro.max_fila = registro.max_fila.max(fila);
+    }
+
+    // Um carro deixou o sistema
+    pub fn saida(&mut self, placa: &str, agora: f64) {
+        if let Some(registro) = self.veiculos.get_mut(placa) {
+            registro.saida = Some(agora);
+            let via = registro.via;
+            self.registro_via(via).saidas += 1;
+        }
+    }
+
+    // Monta o relatório final, 'duracao' é o tempo simulado em ms
+    pub fn relatorio(&self, duracao: f64) -> RelatorioMetricas {
+        let mut relatorio = RelatorioMetricas {
+            duracao,
+            via_h: self.via_h.relatorio(duracao),
+            via_v: self.via_v.relatorio(duracao),
+            passagens: self.passagens,
+            grupos: self.grupos,
+            ..Default::default()
+        };
+
+        for registro in self.veiculos.values() {
+            relatorio.intervencoes += registro.intervencoes;
+            if registro.intervencoes > 0 {
+                relatorio.veiculos_limitados += 1;
+            }
+            relatorio.falhas_seguras += registro.falhas;
+            if registro.falhas > 0 {
+                relatorio.veiculos_em_falha += 1;
+            }
+            relatorio.mensagens_forjadas += registro.forjadas;
+            relatorio.mensagens_repetidas += registro.repetidas;
+            match registro.saida {
+                None => relatorio.veiculos_em_transito += 1,
+                Some(saida) => {
+                    let viagem = saida - registro.entrada;
+                    let atraso =
+                        (viagem - self.tempo_livre(registro.via, registro.comprimento)).max(0.0);
+                    relatorio.veiculos_concluidos += 1;
+                    relatorio.tempo_viagem_medio += viagem;
+                    relatorio.atraso_medio += atraso;
+                    relatorio.atraso_maximo = relatorio.atraso_maximo.max(atraso);
+                    relatorio.paradas_medias += registro.paradas as f64;
+                    relatorio.tempo_parado_medio += registro.tempo_parado;
+                }
+            }
+        }
+
+        if relatorio.veiculos_concluidos > 0 {
+            let n = relatorio.veiculos_concluidos as f64;
+            relatorio.tempo_viagem_medio /= n;
+            relatorio.atraso_medio /= n;
+            relatorio.paradas_medias /= n;
+            relatorio.tempo_parado_medio /= n;
+        }
+        relatorio.vazao = relatorio.via_h.vazao + relatorio.via_v.vazao;
+
+        relatorio
+    }
+}
+
+impl RelatorioMetricas {
+    // Carros que passam pelo cruzamento em seguida pela mesma via, em média
+    pub fn veiculos_por_grupo(&self) -> f64 {
+        if self.grupos > 0 {
+            self.passagens as f64 / self.grupos as f64
+        } else {
+            0.0
+        }
+    }
+
+    // Mostra o relatório na tela
+    pub fn mostra(&self) {
+        println!(
+            "___Métricas de desempenho em {:.1} s___",
+            self.duracao / 1000.0
+        );
+        println!(
+            "   veículos concluídos: {}, ainda em trânsito: {}",
+            self.veiculos_concluidos, self.veiculos_em_transito
+        );
+        println!(
+            "   tempo de viagem médio: {:.2} s",
+            self.tempo_viagem_medio / 1000.0
+        );
+        println!(
+            "   atraso médio: {:.2} s   máximo: {:.2} s",
+            self.atraso_medio / 1000.0,
+            self.atraso_maximo / 1000.0
+        );
+        println!(
+            "   paradas por veículo: {:.2}   tempo parado médio: {:.2} s",
+            self.paradas_medias,
+            self.tempo_parado_medio / 1000.0
+        );
+        println!("   vazão total: {:.0} veículos/h", self.vazao);
+        println!(
+            "   passagens pelo cruzamento: {} em {} grupos seguidos da mesma via, {:.2} veículos por grupo",
+            self.passagens,
+            self.grupos,
+            self.veiculos_por_grupo()
+        );
+        println!(
+            "   controle a bordo: {} intervenções em {} veículos",
+            self.intervencoes, self.veiculos_limitados
+        );
+        println!(
+            "   falha segura sem o controlador: {} vezes em {} veículos",
+            self.falhas_seguras, self.veiculos_em_falha
+        );
+        println!(
+            "   mensagens rejeitadas pelos veículos: {} forjadas ou adulteradas, {} repetidas",
+            self.mensagens_forjadas, self.mensagens_repetidas
+        );
+        for (nome, via) in [("H", &self.via_h), ("V", &self.via_v)] {
+            println!(
+                "   via {}: chegadas {} (recusadas {}), saídas {}, vazão {:.0} veículos/h, fila média {:.2}, fila máxima {}",
+                nome,
+                via.chegadas,
+                via.chegadas_recusadas,
+                via.saidas,
+                via.vazao,
+                via.fila_media,
+                via.fila_maxima
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transito::ParametrosVeiculo;
+
+    fn carro(placa: &str, via: Via) -> Carro {
+        let parametros = ParametrosVeiculo::default();
+        Carro::new(
+            placa.to_string(),
+            [1; 32],
+            via,
+            parametros.velocidade_cruzeiro,
+            0.0,
+            &parametros,
+            150.0,
+            4.0,
+        )
+    }
+
+    // Um carro que para uma vez por 2 s e sai 10 s depois do tempo com a via livre,
+    // e três passagens pelo cruzamento em dois grupos
+    #[test]
+    fn atraso_paradas_vazao_e_grupos() {
+        let geometria = Geometria::default();
+        let parametros = ParametrosVeiculo::default();
+        let mut metricas = Metricas::new(geometria, parametros.velocidade_cruzeiro);
+
+        let mut h1 = carro("AAA0001", Via::ViaH);
+        let h2 = carro("AAA0002", Via::ViaH);
+        let v1 = carro("AAA0003", Via::ViaV);
+        for c in [&h1, &h2, &v1] {
+            metricas.chegada(c, 0.0);
+        }
+        metricas.chegada_recusada(Via::ViaV);
+
+        h1.vel_atual = 0.0;
+        for _ in 0..400 {
+            metricas.observa_carro(&h1, 5.0);
+        }
+        h1.vel_atual = parametros.velocidade_cruzeiro;
+        metricas.observa_carro(&h1, 5.0);
+
+        for mut c in [h1, h2, v1] {
+            c.pos_atual = 1.0;
+            metricas.observa_carro(&c, 5.0);
+        }
+        let livre = metricas.tempo_livre(Via::ViaH, parametros.comprimento);
+        metricas.saida("AAA0001", livre + 10_000.0);
+
+        let relatorio = metricas.relatorio(3_600_000.0);
+        assert_eq!(relatorio.veiculos_concluidos, 1);
+        assert_eq!(relatorio.veiculos_em_transito, 2);
+        assert!((relatorio.atraso_medio - 10_000.0).abs() < 1e-6);
+        assert_eq!(relatorio.paradas_medias, 1.0);
+        assert_eq!(relatorio.tempo_parado_medio, 2_000.0);
+        assert_eq!(relatorio.vazao, 1.0);
+        assert_eq!(relatorio.via_v.chegadas_recusadas, 1);
+        assert_eq!((relatorio.passagens, relatorio.grupos), (3, 2));
+        assert_eq!(relatorio.veiculos_por_grupo(), 1.5);
+    }
+}
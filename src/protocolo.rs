@@ -0,0 +1,752 @@
+/* Protocolo das mensagens entre veículos e controlador
+
+    Cada mensagem vai num quadro binário, números little-endian, textos com tamanho
+    (u16) na frente:
+
+        tamanho     u32, bytes do quadro depois deste campo
+        versão      u8, VERSAO
+        tipo        u8, veículo -> controlador: 1 CHEGADA, 2 SITUACAO_ATUAL
+                        controlador -> veículo: 129 SET_ACEL, 130 PEDE_SITUACAO, 131 CHAVE
+                        veículo -> vizinhos: 32 ESTADO_VEICULO
+                        sincronia do tempo: 64 TICK, 192 FIM_TICK
+        enviada_em  f64, tempo simulado do envio, ms
+        sequencia   u64, conta as mensagens de cada remetente a partir de 1
+        campos do tipo:
+            CHEGADA         placa (texto), via (u8, 0 H e 1 V), acel_max, acel_min,
+                            vel_max, comprimento, periodo_informe (f64, ms, zero se o
+                            veículo só informa a situação quando pedida)
+            SITUACAO_ATUAL  placa (texto), pos_atual, vel_atual, acel_atual (f64)
+            SET_ACEL        placa (texto), acel (f64)
+            PEDE_SITUACAO   placa (texto)
+            CHAVE           placa (texto), chave (32 bytes, cifrada com a credencial)
+            ESTADO_VEICULO  placa (texto), via (u8), pos_atual, vel_atual, acel_atual,
+                            acel_min, comprimento (f64)
+            TICK            nenhum, a simulação pede ao controlador o tick 'enviada_em'
+            FIM_TICK        nenhum, o controlador terminou o tick 'enviada_em'
+        mac         32 bytes, só nas mensagens do controlador para veículos: HMAC-SHA-256
+                    de versão até o fim dos campos, com a chave do veículo, ou com a
+                    credencial dele na CHAVE
+
+    Unidades como no resto do programa: metros, m/s e m/s2.
+    O tamanho na frente permite ler quadros seguidos de um fluxo de bytes, e quem
+    recebe um quadro de versão desconhecida pode pulá-lo.
+    A forma JSON é só para depuração, com os mesmos campos e nomes.
+    A sequência de um veículo conta juntas as mensagens para o controlador e as difundidas.
+
+    O MAC cobre o instante do envio e a sequência, então um comando não pode ser
+    adulterado nem repetido mais tarde com outra sequência sem que o veículo perceba.
+    A mensagem CHAVE entrega a chave do veículo cifrada e autenticada com a credencial
+    que o veículo recebeu fora da rede; quem não tem o segredo do cruzamento não a lê
+    nem a forja (veja src/autenticacao.rs).
+
+    TICK e FIM_TICK só aparecem quando o controlador está em outro processo: a
+    simulação manda as mensagens que chegaram ao controlador e um TICK, o controlador
+    responde com as suas mensagens e um FIM_TICK, e só então o tempo avança.
+    Sequência zero, não contam como mensagens.
+*/
+
+use std::fmt;
+
+use crate::autenticacao::{hexadecimal, hmac, Chave, Mac};
+use crate::comunicacao::{MensagemDeVeiculo, MensagemDoControlador, MensagemEntreVeiculos, Pacote};
+use crate::transito::Via;
+
+pub const VERSAO: u8 = 4;
+const TAMANHO_MAXIMO: usize = 64 * 1024; // bytes depois do tamanho, protege de lixo no fluxo
+
+const CHEGADA: u8 = 1;
+const SITUACAO_ATUAL: u8 = 2;
+const SET_ACEL: u8 = 129;
+const PEDE_SITUACAO: u8 = 130;
+const CHAVE: u8 = 131;
+const ESTADO_VEICULO: u8 = 32;
+const TICK: u8 = 64;
+const FIM_TICK: u8 = 192;
+
+// Uma mensagem do protocolo, em qualquer sentido
+#[derive(Debug, Clone)]
+pub enum Quadro {
+    DeVeiculo(Pacote<MensagemDeVeiculo>),
+    DoControlador(Pacote<MensagemDoControlador>, Mac),
+    EntreVeiculos(Pacote<MensagemEntreVeiculos>),
+    Tick(f64),    // simulação -> controlador, instante do tick em ms
+    FimTick(f64), // controlador -> simulação, instante do tick em ms
+}
+
+// O que pode estar errado num quadro recebido
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErroProtocolo {
+    // Faltam bytes, o resto do quadro ainda pode chegar
+    Incompleto {
+        necessarios: usize,
+        disponiveis: usize,
+    },
+    Tamanho(usize),      // tamanho declarado impossível
+    Versao(u8),          // versão que não sabemos ler
+    Tipo(u8),            // tipo de mensagem desconhecido
+    Campo(&'static str), // valor inválido ou faltando no campo
+    Sobra(usize),        // bytes além dos campos do tipo
+}
+
+impl fmt::Display for ErroProtocolo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErroProtocolo::Incompleto {
+                necessarios,
+                disponiveis,
+            } => write!(
+                f,
+                "quadro incompleto: precisa de {} bytes, tem {}",
+                necessarios, disponiveis
+            ),
+            ErroProtocolo::Tamanho(n) => write!(f, "tamanho de quadro inválido: {}", n),
+            ErroProtocolo::Versao(v) => write!(f, "versão de protocolo desconhecida: {}", v),
+            ErroProtocolo::Tipo(t) => write!(f, "tipo de mensagem desconhecido: {}", t),
+            ErroProtocolo::Campo(campo) => write!(f, "valor inválido no campo {}", campo),
+            ErroProtocolo::Sobra(n) => write!(f, "{} bytes sobrando no quadro", n),
+        }
+    }
+}
+
+// Monta os bytes de um quadro
+struct Escritor {
+    bytes: Vec<u8>,
+}
+
+impl Escritor {
+    fn u8(&mut self, x: u8) {
+        self.bytes.push(x);
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    fn f64(&mut self, x: f64) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+    }
+
+    // Textos longos demais perdem o final, sem cortar um caractere no meio
+    fn texto(&mut self, texto: &str) {
+        let mut fim = texto.len().min(u16::MAX as usize);
+        while !texto.is_char_boundary(fim) {
+            fim -= 1;
+        }
+        let bytes = &texto.as_bytes()[..fim];
+        self.bytes
+            .extend_from_slice(&(bytes.len() as u16).to_le_bytes());
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn via(&mut self, via: Via) {
+        self.u8(match via {
+            Via::ViaH => 0,
+            Via::ViaV => 1,
+        });
+    }
+
+    fn bytes32(&mut self, x: &[u8; 32]) {
+        self.bytes.extend_from_slice(x);
+    }
+}
+
+// Lê os campos de um quadro, que já se sabe estar completo
+struct Leitor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Leitor<'a> {
+    fn pedaco(&mut self, n: usize, campo: &'static str) -> Result<&'a [u8], ErroProtocolo> {
+        if self.pos + n > self.bytes.len() {
+            return Err(ErroProtocolo::Campo(campo));
+        }
+        let pedaco = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(pedaco)
+    }
+
+    fn u8(&mut self, campo: &'static str) -> Result<u8, ErroProtocolo> {
+        Ok(self.pedaco(1, campo)?[0])
+    }
+
+    fn u64(&mut self, campo: &'static str) -> Result<u64, ErroProtocolo> {
+        Ok(u64::from_le_bytes(
+            self.pedaco(8, campo)?.try_into().unwrap(),
+        ))
+    }
+
+    fn f64(&mut self, campo: &'static str) -> Result<f64, ErroProtocolo> {
+        Ok(f64::from_le_bytes(
+            self.pedaco(8, campo)?.try_into().unwrap(),
+        ))
+    }
+
+    fn texto(&mut self, campo: &'static str) -> Result<String, ErroProtocolo> {
+        let n = u16::from_le_bytes(self.pedaco(2, campo)?.try_into().unwrap()) as usize;
+        let bytes = self.pedaco(n, campo)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ErroProtocolo::Campo(campo))
+    }
+
+    fn via(&mut self) -> Result<Via, ErroProtocolo> {
+        match self.u8("via")? {
+            0 => Ok(Via::ViaH),
+            1 => Ok(Via::ViaV),
+            _ => Err(ErroProtocolo::Campo("via")),
+        }
+    }
+
+    fn bytes32(&mut self, campo: &'static str) -> Result<[u8; 32], ErroProtocolo> {
+        Ok(self.pedaco(32, campo)?.try_into().unwrap())
+    }
+}
+
+// Texto entre aspas, com os escapes do JSON
+fn texto_json(texto: &str) -> String {
+    let mut s = String::from("\"");
+    for c in texto.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            c if (c as u32) < 0x20 => s.push_str(&format!("\\u{:04x}", c as u32)),
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+    s
+}
+
+// Número em JSON, que não tem infinito nem NaN
+fn numero_json(x: f64) -> String {
+    if x.is_finite() {
+        format!("{}", x)
+    } else {
+        "null".to_string()
+    }
+}
+
+impl Quadro {
+    // Tipo da mensagem no protocolo
+    fn tipo(&self) -> u8 {
+        match self {
+            Quadro::DeVeiculo(p) => match p.mensagem {
+                MensagemDeVeiculo::Chegada { .. } => CHEGADA,
+                MensagemDeVeiculo::SituacaoAtual { .. } => SITUACAO_ATUAL,
+            },
+            Quadro::DoControlador(p, _) => match p.mensagem {
+                MensagemDoControlador::SetAcel { .. } => SET_ACEL,
+                MensagemDoControlador::PedeSituacao { .. } => PEDE_SITUACAO,
+                MensagemDoControlador::Chave { .. } => CHAVE,
+            },
+            Quadro::EntreVeiculos(p) => match p.mensagem {
+                MensagemEntreVeiculos::Estado { .. } => ESTADO_VEICULO,
+            },
+            Quadro::Tick(_) => TICK,
+            Quadro::FimTick(_) => FIM_TICK,
+        }
+    }
+
+    // Nome do tipo da mensagem, o mesmo da forma JSON
+    pub fn nome(&self) -> &'static str {
+        match self.tipo() {
+            CHEGADA => "Chegada",
+            SITUACAO_ATUAL => "SituacaoAtual",
+            SET_ACEL => "SetAcel",
+            PEDE_SITUACAO => "PedeSituacao",
+            CHAVE => "Chave",
+            ESTADO_VEICULO => "EstadoVeiculo",
+            TICK => "Tick",
+            _ => "FimTick",
+        }
+    }
+
+    // Instante do envio e sequência, comuns a todos os tipos
+    pub fn carimbo(&self) -> (f64, u64) {
+        match self {
+            Quadro::DeVeiculo(p) => (p.enviada_em, p.sequencia),
+            Quadro::DoControlador(p, _) => (p.enviada_em, p.sequencia),
+            Quadro::EntreVeiculos(p) => (p.enviada_em, p.sequencia),
+            Quadro::Tick(tempo) | Quadro::FimTick(tempo) => (*tempo, 0),
+        }
+    }
+
+    // Bytes do quadro, já com o tamanho na frente
+    pub fn codifica(&self) -> Vec<u8> {
+        let mut e = Escritor {
+            bytes: vec![0; 4], // tamanho, preenchido no final
+        };
+        let (enviada_em, sequencia) = self.carimbo();
+        e.u8(VERSAO);
+        e.u8(self.tipo());
+        e.f64(enviada_em);
+        e.u64(sequencia);
+
+        match self {
+            Quadro::DeVeiculo(p) => match &p.mensagem {
+                MensagemDeVeiculo::Chegada {
+                    placa,
+                    via,
+                    acel_max,
+                    acel_min,
+                    vel_max,
+                    comprimento,
+                    periodo_informe,
+                } => {
+                    e.texto(placa);
+                    e.via(*via);
+                    e.f64(*acel_max);
+                    e.f64(*acel_min);
+                    e.f64(*vel_max);
+                    e.f64(*comprimento);
+                    e.f64(*periodo_informe);
+                }
+                MensagemDeVeiculo::SituacaoAtual {
+                    placa,
+                    pos_atual,
+                    vel_atual,
+                    acel_atual,
+                } => {
+                    e.texto(placa);
+                    e.f64(*pos_atual);
+                    e.f64(*vel_atual);
+                    e.f64(*acel_atual);
+                }
+            },
+            Quadro::DoControlador(p, mac) => {
+                match &p.mensagem {
+                    MensagemDoControlador::SetAcel { placa, acel } => {
+                        e.texto(placa);
+                        e.f64(*acel);
+                    }
+                    MensagemDoControlador::PedeSituacao { placa } => e.texto(placa),
+                    MensagemDoControlador::Chave { placa, chave } => {
+                        e.texto(placa);
+                        e.bytes32(chave);
+                    }
+                }
+                e.bytes32(mac);
+            }
+            Quadro::EntreVeiculos(p) => match &p.mensagem {
+                MensagemEntreVeiculos::Estado {
+                    placa,
+                    via,
+                    pos_atual,
+                    vel_atual,
+                    acel_atual,
+                    acel_min,
+                    comprimento,
+                } => {
+                    e.texto(placa);
+                    e.via(*via);
+                    e.f64(*pos_atual);
+                    e.f64(*vel_atual);
+                    e.f64(*acel_atual);
+                    e.f64(*acel_min);
+                    e.f64(*comprimento);
+                }
+            },
+            Quadro::Tick(_) | Quadro::FimTick(_) => (),
+        }
+
+        let tamanho = (e.bytes.len() - 4) as u32;
+        e.bytes[..4].copy_from_slice(&tamanho.to_le_bytes());
+        e.bytes
+    }
+
+    // Lê o quadro no início de 'bytes', devolve também quantos bytes ocupou
+    // Com Incompleto, basta esperar mais bytes e tentar de novo; com os demais erros,
+    // se o tamanho era válido, o quadro pode ser pulado
+    pub fn decodifica(bytes: &[u8]) -> Result<(Quadro, usize), ErroProtocolo> {
+        if bytes.len() < 4 {
+            return Err(ErroProtocolo::Incompleto {
+                necessarios: 4,
+                disponiveis: bytes.len(),
+            });
+        }
+        let tamanho = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+        if !(2..=TAMANHO_MAXIMO).contains(&tamanho) {
+            return Err(ErroProtocolo::Tamanho(tamanho));
+        }
+        if bytes.len() < 4 + tamanho {
+            return Err(ErroProtocolo::Incompleto {
+                necessarios: 4 + tamanho,
+                disponiveis: bytes.len(),
+            });
+        }
+
+        let mut l = Leitor {
+            bytes: &bytes[4..4 + tamanho],
+            pos: 0,
+        };
+        let versao = l.u8("versão")?;
+        if versao != VERSAO {
+            return Err(ErroProtocolo::Versao(versao));
+        }
+        let tipo = l.u8("tipo")?;
+        let enviada_em = l.f64("enviada_em")?;
+        let sequencia = l.u64("sequencia")?;
+
+        let quadro = match tipo {
+            CHEGADA => Quadro::DeVeiculo(Pacote {
+                enviada_em,
+                sequencia,
+                mensagem: MensagemDeVeiculo::Chegada {
+                    placa: l.texto("placa")?,
+                    via: l.via()?,
+                    acel_max: l.f64("acel_max")?,
+                    acel_min: l.f64("acel_min")?,
+                    vel_max: l.f64("vel_max")?,
+                    comprimento: l.f64("comprimento")?,
+                    periodo_informe: l.f64("periodo_informe")?,
+                },
+            }),
+            SITUACAO_ATUAL => Quadro::DeVeiculo(Pacote {
+                enviada_em,
+                sequencia,
+                mensagem: MensagemDeVeiculo::SituacaoAtual {
+                    placa: l.texto("placa")?,
+                    pos_atual: l.f64("pos_atual")?,
+                    vel_atual: l.f64("vel_atual")?,
+                    acel_atual: l.f64("acel_atual")?,
+                },
+            }),
+            SET_ACEL | PEDE_SITUACAO | CHAVE => {
+                let mensagem = match tipo {
+                    SET_ACEL => MensagemDoControlador::SetAcel {
+                        placa: l.texto("placa")?,
+                        acel: l.f64("acel")?,
+                    },
+                    PEDE_SITUACAO => MensagemDoControlador::PedeSituacao {
+                        placa: l.texto("placa")?,
+                    },
+                    _ => MensagemDoControlador::Chave {
+                        placa: l.texto("placa")?,
+                        chave: l.bytes32("chave")?,
+                    },
+                };
+                let pacote = Pacote {
+                    enviada_em,
+                    sequencia,
+                    mensagem,
+                };
+                Quadro::DoControlador(pacote, l.bytes32("mac")?)
+            }
+            ESTADO_VEICULO => Quadro::EntreVeiculos(Pacote {
+                enviada_em,
+                sequencia,
+                mensagem: MensagemEntreVeiculos::Estado {
+                    placa: l.texto("placa")?,
+                    via: l.via()?,
+                    pos_atual: l.f64("pos_atual")?,
+                    vel_atual: l.f64("vel_atual")?,
+                    acel_atual: l.f64("acel_atual")?,
+                    acel_min: l.f64("acel_min")?,
+                    comprimento: l.f64("comprimento")?,
+                },
+            }),
+            TICK => Quadro::Tick(enviada_em),
+            FIM_TICK => Quadro::FimTick(enviada_em),
+            x => return Err(ErroProtocolo::Tipo(x)),
+        };
+
+        if l.pos != tamanho {
+            return Err(ErroProtocolo::Sobra(tamanho - l.pos));
+        }
+        Ok((quadro, 4 + tamanho))
+    }
+
+    // Forma JSON, em uma linha, para depuração
+    pub fn json(&self) -> String {
+        let (enviada_em, sequencia) = self.carimbo();
+        let mut campos = vec![
+            ("versao", VERSAO.to_string()),
+            ("enviada_em", numero_json(enviada_em)),
+            ("sequencia", sequencia.to_string()),
+        ];
+        match self {
+            Quadro::DeVeiculo(p) => match &p.mensagem {
+                MensagemDeVeiculo::Chegada {
+                    placa,
+                    via,
+                    acel_max,
+                    acel_min,
+                    vel_max,
+                    comprimento,
+                    periodo_informe,
+                } => {
+                    campos.push(("placa", texto_json(placa)));
+                    campos.push(("via", texto_json(&format!("{:?}", via))));
+                    campos.push(("acel_max", numero_json(*acel_max)));
+                    campos.push(("acel_min", numero_json(*acel_min)));
+                    campos.push(("vel_max", numero_json(*vel_max)));
+                    campos.push(("comprimento", numero_json(*comprimento)));
+                    campos.push(("periodo_informe", numero_json(*periodo_informe)));
+                }
+                MensagemDeVeiculo::SituacaoAtual {
+                    placa,
+                    pos_atual,
+                    vel_atual,
+                    acel_atual,
+                } => {
+                    campos.push(("placa", texto_json(placa)));
+                    campos.push(("pos_atual", numero_json(*pos_atual)));
+                    campos.push(("vel_atual", numero_json(*vel_atual)));
+                    campos.push(("acel_atual", numero_json(*acel_atual)));
+                }
+            },
+            Quadro::DoControlador(p, mac) => {
+                match &p.mensagem {
+                    MensagemDoControlador::SetAcel { placa, acel } => {
+                        campos.push(("placa", texto_json(placa)));
+                        campos.push(("acel", numero_json(*acel)));
+                    }
+                    MensagemDoControlador::PedeSituacao { placa } => {
+                        campos.push(("placa", texto_json(placa)));
+                    }
+                    // A chave não aparece nem na depuração
+                    MensagemDoControlador::Chave { placa, .. } => {
+                        campos.push(("placa", texto_json(placa)));
+                        campos.push(("chave", texto_json("oculta")));
+                    }
+                };
+                campos.push(("mac", texto_json(&hexadecimal(mac))));
+            }
+            Quadro::EntreVeiculos(p) => match &p.mensagem {
+                MensagemEntreVeiculos::Estado {
+                    placa,
+                    via,
+                    pos_atual,
+                    vel_atual,
+                    acel_atual,
+                    acel_min,
+                    comprimento,
+                } => {
+                    campos.push(("placa", texto_json(placa)));
+                    campos.push(("via", texto_json(&format!("{:?}", via))));
+                    campos.push(("pos_atual", numero_json(*pos_atual)));
+                    campos.push(("vel_atual", numero_json(*vel_atual)));
+                    campos.push(("acel_atual", numero_json(*acel_atual)));
+                    campos.push(("acel_min", numero_json(*acel_min)));
+                    campos.push(("comprimento", numero_json(*comprimento)));
+                }
+            },
+            Quadro::Tick(_) | Quadro::FimTick(_) => (),
+        }
+        campos.insert(1, ("tipo", texto_json(self.nome())));
+
+        let campos: Vec<String> = campos
+            .iter()
+            .map(|(nome, valor)| format!("\"{}\":{}", nome, valor))
+            .collect();
+        format!("{{{}}}", campos.join(","))
+    }
+}
+
+// MAC de um pacote do controlador com a chave do veículo: HMAC dos bytes do quadro
+// depois do tamanho e antes do próprio MAC
+pub fn autentica(pacote: &Pacote<MensagemDoControlador>, chave: &Chave) -> Mac {
+    let bytes = Quadro::DoControlador(pacote.clone(), [0; 32]).codifica();
+    hmac(chave, &bytes[4..bytes.len() - 32])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pacote<T>(mensagem: T) -> Pacote<T> {
+        Pacote {
+            enviada_em: 1234.5,
+            sequencia: 42,
+            mensagem,
+        }
+    }
+
+    // Um quadro de cada tipo
+    fn quadros() -> Vec<Quadro> {
+        vec![
+            Quadro::DeVeiculo(pacote(MensagemDeVeiculo::Chegada {
+                placa: "CCC0001".to_string(),
+                via: Via::ViaV,
+                acel_max: 3.0,
+                acel_min: -10.0,
+                vel_max: 111.1,
+                comprimento: 4.0,
+                periodo_informe: 100.0,
+            })),
+            Quadro::DeVeiculo(pacote(MensagemDeVeiculo::SituacaoAtual {
+                placa: "CCC0001".to_string(),
+                pos_atual: -87.25,
+                vel_atual: 22.2,
+                acel_atual: -1.5,
+            })),
+            Quadro::DoControlador(
+                pacote(MensagemDoControlador::SetAcel {
+                    placa: "CCC0002".to_string(),
+                    acel: 0.75,
+                }),
+                [7; 32],
+            ),
+            Quadro::DoControlador(
+                pacote(MensagemDoControlador::PedeSituacao {
+                    placa: "CCC0003".to_string(),
+                }),
+                [8; 32],
+            ),
+            Quadro::DoControlador(
+                pacote(MensagemDoControlador::Chave {
+                    placa: "CCC0004".to_string(),
+                    chave: [9; 32],
+                }),
+                [10; 32],
+            ),
+            Quadro::EntreVeiculos(pacote(MensagemEntreVeiculos::Estado {
+                placa: "CCC0005".to_string(),
+                via: Via::ViaH,
+                pos_atual: 3.5,
+                vel_atual: 20.0,
+                acel_atual: 0.0,
+                acel_min: -10.0,
+                comprimento: 4.5,
+            })),
+            Quadro::Tick(50.0),
+            Quadro::FimTick(50.0),
+        ]
+    }
+
+    // Quadro de SET_ACEL com um byte trocado
+    fn com_byte(posicao: usize, valor: u8) -> Vec<u8> {
+        let mut bytes = quadros()[2].codifica();
+        bytes[posicao] = valor;
+        bytes
+    }
+
+    #[test]
+    fn ida_e_volta_de_todos_os_tipos() {
+        for quadro in quadros() {
+            let bytes = quadro.codifica();
+            let (lido, usados) = Quadro::decodifica(&bytes).unwrap();
+            assert_eq!(usados, bytes.len());
+            assert_eq!(format!("{:?}", lido), format!("{:?}", quadro));
+            assert_eq!(lido.codifica(), bytes);
+        }
+    }
+
+    #[test]
+    fn quadros_seguidos_num_fluxo() {
+        let fluxo: Vec<u8> = quadros().iter().flat_map(|q| q.codifica()).collect();
+        let mut pos = 0;
+        for quadro in quadros() {
+            let (lido, usados) = Quadro::decodifica(&fluxo[pos..]).unwrap();
+            assert_eq!(lido.nome(), quadro.nome());
+            pos += usados;
+        }
+        assert_eq!(pos, fluxo.len());
+    }
+
+    #[test]
+    fn quadro_incompleto() {
+        let bytes = quadros()[0].codifica();
+        assert_eq!(
+            Quadro::decodifica(&bytes[..3]).unwrap_err(),
+            ErroProtocolo::Incompleto {
+                necessarios: 4,
+                disponiveis: 3
+            }
+        );
+        assert_eq!(
+            Quadro::decodifica(&bytes[..bytes.len() - 1]).unwrap_err(),
+            ErroProtocolo::Incompleto {
+                necessarios: bytes.len(),
+                disponiveis: bytes.len() - 1
+            }
+        );
+    }
+
+    #[test]
+    fn tamanho_invalido() {
+        let grande = (TAMANHO_MAXIMO + 1) as u32;
+        for tamanho in [0, 1, grande] {
+            let mut bytes = quadros()[0].codifica();
+            bytes[..4].copy_from_slice(&tamanho.to_le_bytes());
+            assert_eq!(
+                Quadro::decodifica(&bytes).unwrap_err(),
+                ErroProtocolo::Tamanho(tamanho as usize)
+            );
+        }
+    }
+
+    #[test]
+    fn versao_desconhecida() {
+        assert_eq!(
+            Quadro::decodifica(&com_byte(4, VERSAO + 1)).unwrap_err(),
+            ErroProtocolo::Versao(VERSAO + 1)
+        );
+    }
+
+    // SET_ACEL da versão 1, ainda sem o mac, seguido de um quadro atual
+    #[test]
+    fn versao_antiga_recusada_e_pulada() {
+        let mut e = Escritor { bytes: vec![0; 4] };
+        e.u8(1);
+        e.u8(SET_ACEL);
+        e.f64(1234.5);
+        e.u64(42);
+        e.texto("CCC0002");
+        e.f64(0.75);
+        let tamanho = (e.bytes.len() - 4) as u32;
+        e.bytes[..4].copy_from_slice(&tamanho.to_le_bytes());
+        let mut fluxo = e.bytes;
+        fluxo.extend(quadros()[2].codifica());
+
+        assert_eq!(
+            Quadro::decodifica(&fluxo).unwrap_err(),
+            ErroProtocolo::Versao(1)
+        );
+        let (lido, _) = Quadro::decodifica(&fluxo[4 + tamanho as usize..]).unwrap();
+        assert_eq!(format!("{:?}", lido), format!("{:?}", quadros()[2]));
+    }
+
+    #[test]
+    fn tipo_desconhecido() {
+        assert_eq!(
+            Quadro::decodifica(&com_byte(5, 77)).unwrap_err(),
+            ErroProtocolo::Tipo(77)
+        );
+    }
+
+    #[test]
+    fn bytes_sobrando() {
+        let mut bytes = quadros()[6].codifica();
+        let tamanho = (bytes.len() - 4 + 3) as u32;
+        bytes[..4].copy_from_slice(&tamanho.to_le_bytes());
+        bytes.extend_from_slice(&[0; 3]);
+        assert_eq!(
+            Quadro::decodifica(&bytes).unwrap_err(),
+            ErroProtocolo::Sobra(3)
+        );
+    }
+
+    #[test]
+    fn campo_invalido() {
+        // Via da CHEGADA logo depois da placa de 7 bytes
+        let mut bytes = quadros()[0].codifica();
+        bytes[4 + 1 + 1 + 8 + 8 + 2 + 7] = 2;
+        assert_eq!(
+            Quadro::decodifica(&bytes).unwrap_err(),
+            ErroProtocolo::Campo("via")
+        );
+    }
+
+    #[test]
+    fn texto_longo_cortado_entre_caracteres() {
+        // 'ç' ocupa 2 bytes e atravessa o limite de u16::MAX bytes
+        let placa = format!("{}ç", "a".repeat(u16::MAX as usize - 1));
+        let mut e = Escritor { bytes: Vec::new() };
+        e.texto(&placa);
+        let mut l = Leitor {
+            bytes: &e.bytes,
+            pos: 0,
+        };
+        assert_eq!(l.texto("placa").unwrap(), placa[..placa.len() - 2]);
+    }
+}
@@ -0,0 +1,501 @@
+/* Rastro das mensagens, como um pcap do nosso protocolo
+
+    Arquivo binário, números little-endian, textos com tamanho (u16) na frente:
+
+        cabeçalho   "CRZR", versão (u8), versão do protocolo (u8), semente (u64),
+                    descrição (texto)
+        registros   tempo (f64, ms), evento (u8), sentido (u8), cópias (u16),
+                    placa (texto), quadro do protocolo (com o tamanho na frente)
+
+    Eventos: ENVIO quando a mensagem entra no canal, com quantas cópias o canal vai
+    entregar (zero se perdida, duas se duplicada, uma por vizinho na difusão), e
+    ENTREGA quando o destino a retira, com uma cópia. Entregas de difusões não são
+    registradas, seriam uma por vizinho a cada período.
+    Sentidos: do veículo para o controlador, do controlador para o veículo, do
+    veículo para os vizinhos e do atacante para o veículo. A placa é sempre a do
+    veículo envolvido, remetente ou destino.
+
+    O subcomando trace lê o rastro, filtra por veículo, tipo de mensagem e intervalo
+    de tempo e mostra uma linha do tempo ou um diagrama de sequência.
+*/
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use crate::comunicacao::{MensagemDeVeiculo, MensagemDoControlador};
+use crate::protocolo::{self, ErroProtocolo, Quadro};
+
+const MAGICO: &[u8; 4] = b"CRZR";
+const VERSAO: u8 = 1;
+
+const ENVIO: u8 = 1;
+const ENTREGA: u8 = 2;
+
+const LARGURA_RAIA: usize = 16; // colunas de cada participante no diagrama de sequência
+const MAX_RAIAS: usize = 8; // participantes no diagrama de sequência
+
+// Sentido de uma mensagem no rastro
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sentido {
+    DeVeiculo,     // veículo -> controlador
+    DoControlador, // controlador -> veículo
+    EntreVeiculos, // veículo -> vizinhos
+    DoAtacante,    // atacante -> veículo
+}
+
+impl Sentido {
+    fn codigo(&self) -> u8 {
+        match self {
+            Sentido::DeVeiculo => 1,
+            Sentido::DoControlador => 2,
+            Sentido::EntreVeiculos => 3,
+            Sentido::DoAtacante => 4,
+        }
+    }
+
+    fn de_codigo(codigo: u8) -> Result<Self, String> {
+        match codigo {
+            1 => Ok(Sentido::DeVeiculo),
+            2 => Ok(Sentido::DoControlador),
+            3 => Ok(Sentido::EntreVeiculos),
+            4 => Ok(Sentido::DoAtacante),
+            x => Err(format!("sentido inválido no rastro: {}", x)),
+        }
+    }
+
+    // Remetente e destino, com a placa do veículo envolvido
+    fn pontas(&self, placa: &str) -> (String, String) {
+        let veiculo = format!("@{}", placa);
+        match self {
+            Sentido::DeVeiculo => (veiculo, "controlador".to_string()),
+            Sentido::DoControlador => ("controlador".to_string(), veiculo),
+            Sentido::EntreVeiculos => (veiculo, "vizinhos".to_string()),
+            Sentido::DoAtacante => ("atacante".to_string(), veiculo),
+        }
+    }
+}
+
+// Grava o rastro enquanto a simulação roda
+pub struct Rastreador {
+    arquivo: BufWriter<File>,
+    caminho: String,
+    erro: Option<String>, // primeiro erro de escrita, as demais escritas são ignoradas
+}
+
+impl Rastreador {
+    // Cria o arquivo e grava o cabeçalho
+    pub fn new(caminho: &str, semente: u64, descricao: &str) -> Result<Self, String> {
+        let arquivo = File::create(caminho)
+            .map_err(|e| format!("Não foi possível criar o rastro {}: {}", caminho, e))?;
+        let mut rastreador = Self {
+            arquivo: BufWriter::new(arquivo),
+            caminho: caminho.to_string(),
+            erro: None,
+        };
+
+        let mut bytes = MAGICO.to_vec();
+        bytes.push(VERSAO);
+        bytes.push(protocolo::VERSAO);
+        bytes.extend_from_slice(&semente.to_le_bytes());
+        escreve_texto(&mut bytes, descricao);
+        rastreador.escreve(&bytes);
+        rastreador.erro.take().map_or(Ok(rastreador), Err)
+    }
+
+    fn escreve(&mut self, bytes: &[u8]) {
+        if self.erro.is_none() {
+            if let Err(e) = self.arquivo.write_all(bytes) {
+                self.erro = Some(format!("Erro gravando {}: {}", self.caminho, e));
+            }
+        }
+    }
+
+    // Uma mensagem entrou no canal, que vai entregar 'copias' cópias
+    pub fn envio(
+        &mut self,
+        tempo: f64,
+        sentido: Sentido,
+        placa: &str,
+        copias: usize,
+        quadro: &[u8],
+    ) {
+        self.registra(tempo, ENVIO, sentido, placa, copias, quadro);
+    }
+
+    // Uma mensagem chegou ao destino
+    pub fn entrega(&mut self, tempo: f64, sentido: Sentido, placa: &str, quadro: &[u8]) {
+        self.registra(tempo, ENTREGA, sentido, placa, 1, quadro);
+    }
+
+    fn registra(
+        &mut self,
+        tempo: f64,
+        evento: u8,
+        sentido: Sentido,
+        placa: &str,
+        copias: usize,
+        quadro: &[u8],
+    ) {
+        let mut bytes = Vec::with_capacity(16 + placa.len() + quadro.len());
+        bytes.extend_from_slice(&tempo.to_le_bytes());
+        bytes.push(evento);
+        bytes.push(sentido.codigo());
+        bytes.extend_from_slice(&(copias.min(u16::MAX as usize) as u16).to_le_bytes());
+        escreve_texto(&mut bytes, placa);
+        bytes.extend_from_slice(quadro);
+        self.escreve(&bytes);
+    }
+
+    // Termina o rastro, informando se houve algum erro
+    pub fn encerra(&mut self) -> Result<(), String> {
+        if self.erro.is_none() {
+            if let Err(e) = self.arquivo.flush() {
+                self.erro = Some(format!("Erro gravando {}: {}", self.caminho, e));
+            }
+        }
+        match &self.erro {
+            None => Ok(()),
+            Some(e) => Err(e.clone()),
+        }
+    }
+}
+
+fn escreve_texto(bytes: &mut Vec<u8>, texto: &str) {
+    let texto = &texto.as_bytes()[..texto.len().min(u16::MAX as usize)];
+    bytes.extend_from_slice(&(texto.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(texto);
+}
+
+// Lê os campos de um rastro já carregado na memória
+struct Leitor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Leitor<'a> {
+    fn fim(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn pedaco(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.pos + n > self.bytes.len() {
+            return Err(format!("rastro truncado no byte {}", self.pos));
+        }
+        let pedaco = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(pedaco)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.pedaco(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.pedaco(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.pedaco(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.pedaco(8)?.try_into().unwrap()))
+    }
+
+    fn texto(&mut self) -> Result<String, String> {
+        let n = self.u16()? as usize;
+        let bytes = self.pedaco(n)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| "texto inválido no rastro".to_string())
+    }
+
+    // Quadro do protocolo, com o tamanho na frente; um quadro que não se consegue ler
+    // continua no rastro, com o erro
+    fn quadro(&mut self) -> Result<Result<Quadro, ErroProtocolo>, String> {
+        let tamanho = u32::from_le_bytes(self.pedaco(4)?.try_into().unwrap()) as usize;
+        self.pos -= 4;
+        let bytes = self.pedaco(4 + tamanho)?;
+        Ok(Quadro::decodifica(bytes).map(|(quadro, _)| quadro))
+    }
+}
+
+// Um evento do rastro
+pub struct Registro {
+    pub tempo: f64, // ms
+    pub entrega: bool,
+    pub sentido: Sentido,
+    pub copias: u16,
+    pub placa: String,
+    pub quadro: Result<Quadro, ErroProtocolo>,
+}
+
+impl Registro {
+    // Nome do tipo da mensagem, ou '?' se o quadro não pôde ser lido
+    fn tipo(&self) -> &'static str {
+        match &self.quadro {
+            Ok(quadro) => quadro.nome(),
+            Err(_) => "?",
+        }
+    }
+}
+
+// Um rastro completo, lido do arquivo
+pub struct Rastro {
+    pub versao_protocolo: u8,
+    pub semente: u64,
+    pub descricao: String,
+    pub registros: Vec<Registro>,
+}
+
+impl Rastro {
+    // Lê um rastro inteiro
+    pub fn carrega(caminho: &str) -> Result<Self, String> {
+        let bytes = fs::read(caminho)
+            .map_err(|e| format!("Não foi possível ler o rastro {}: {}", caminho, e))?;
+        Rastro::interpreta(&bytes).map_err(|e| format!("Rastro {}: {}", caminho, e))
+    }
+
+    fn interpreta(bytes: &[u8]) -> Result<Self, String> {
+        let mut l = Leitor { bytes, pos: 0 };
+        if l.pedaco(4)? != MAGICO {
+            return Err("não é um arquivo de rastro".to_string());
+        }
+        let versao = l.u8()?;
+        if versao != VERSAO {
+            return Err(format!("versão {} não suportada", versao));
+        }
+        let mut rastro = Self {
+            versao_protocolo: l.u8()?,
+            semente: l.u64()?,
+            descricao: l.texto()?,
+            registros: Vec::new(),
+        };
+
+        while !l.fim() {
+            match le_registro(&mut l) {
+                Ok(registro) => rastro.registros.push(registro),
+                // Rastro interrompido no meio, aproveita o que foi lido
+                Err(e) => {
+                    eprintln!("Aviso: {}, mostrando até o último registro lido", e);
+                    break;
+                }
+            }
+        }
+        Ok(rastro)
+    }
+}
+
+fn le_registro(l: &mut Leitor) -> Result<Registro, String> {
+    let tempo = l.f64()?;
+    let entrega = match l.u8()? {
+        ENVIO => false,
+        ENTREGA => true,
+        x => return Err(format!("evento inválido no rastro: {}", x)),
+    };
+    Ok(Registro {
+        tempo,
+        entrega,
+        sentido: Sentido::de_codigo(l.u8()?)?,
+        copias: l.u16()?,
+        placa: l.texto()?,
+        quadro: l.quadro()?,
+    })
+}
+
+// Como mostrar o rastro
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Formato {
+    LinhaDoTempo, // um evento por linha, com o conteúdo em JSON
+    Sequencia,    // diagrama de sequência, um envio por linha
+}
+
+impl Formato {
+    pub fn de_nome(nome: &str) -> Option<Self> {
+        match nome {
+            "linha" => Some(Formato::LinhaDoTempo),
+            "sequencia" => Some(Formato::Sequencia),
+            _ => None,
+        }
+    }
+}
+
+// Parâmetros do subcomando trace; listas vazias não filtram
+pub struct ParametrosRastro {
+    pub caminho: String,
+    pub placas: Vec<String>,
+    pub tipos: Vec<String>, // nomes como na forma JSON, sem diferenciar maiúsculas
+    pub inicio: f64,        // ms
+    pub fim: f64,           // ms
+    pub formato: Formato,
+}
+
+impl ParametrosRastro {
+    fn aceita(&self, registro: &Registro) -> bool {
+        (self.inicio..=self.fim).contains(&registro.tempo)
+            && (self.placas.is_empty() || self.placas.contains(&registro.placa))
+            && (self.tipos.is_empty()
+                || self
+                    .tipos
+                    .iter()
+                    .any(|t| t.eq_ignore_ascii_case(registro.tipo())))
+    }
+}
+
+// Lê o rastro e mostra os registros que passam pelos filtros
+pub fn examina(parametros: &ParametrosRastro) -> Result<(), String> {
+    let rastro = Rastro::carrega(&parametros.caminho)?;
+    println!("Rastro de {}", rastro.descricao);
+    println!("   semente: {}", rastro.semente);
+    if rastro.versao_protocolo != protocolo::VERSAO {
+        println!(
+            "   protocolo versão {}, este programa lê a {}: quadros podem aparecer como inválidos",
+            rastro.versao_protocolo,
+            protocolo::VERSAO
+        );
+    }
+
+    let registros: Vec<&Registro> = rastro
+        .registros
+        .iter()
+        .filter(|r| parametros.aceita(r))
+        .collect();
+    println!(
+        "   {} de {} registros passam pelos filtros",
+        registros.len(),
+        rastro.registros.len()
+    );
+    match parametros.formato {
+        Formato::LinhaDoTempo => mostra_linha_do_tempo(&registros),
+        Formato::Sequencia => mostra_sequencia(&registros)?,
+    }
+    Ok(())
+}
+
+// Resumo curto do conteúdo, para o diagrama
+fn rotulo(registro: &Registro) -> String {
+    let quadro = match &registro.quadro {
+        Err(_) => return "inválido".to_string(),
+        Ok(quadro) => quadro,
+    };
+    match quadro {
+        Quadro::DoControlador(p, _) => match &p.mensagem {
+            MensagemDoControlador::SetAcel { acel, .. } => format!("SetAcel {:.2}", acel),
+            _ => quadro.nome().to_string(),
+        },
+        Quadro::DeVeiculo(p) => match &p.mensagem {
+            MensagemDeVeiculo::SituacaoAtual { pos_atual, .. } => {
+                format!("Situacao {:.1}", pos_atual)
+            }
+            _ => quadro.nome().to_string(),
+        },
+        _ => quadro.nome().to_string(),
+    }
+}
+
+// Como o canal tratou um envio
+fn destino_do_envio(registro: &Registro) -> String {
+    match (registro.sentido, registro.copias) {
+        (_, 0) => "perdida".to_string(),
+        (Sentido::EntreVeiculos, n) => format!("{} vizinhos", n),
+        (_, 1) => String::new(),
+        (_, n) => format!("{} cópias", n),
+    }
+}
+
+// Um evento por linha: instante, evento, pontas, tipo, sequência e conteúdo
+fn mostra_linha_do_tempo(registros: &[&Registro]) {
+    for r in registros {
+        let (de, para) = r.sentido.pontas(&r.placa);
+        let (evento, observacao) = if r.entrega {
+            let atraso = match &r.quadro {
+                Ok(quadro) => format!("atraso {:.0} ms", r.tempo - quadro.carimbo().0),
+                Err(_) => String::new(),
+            };
+            ("entrega", atraso)
+        } else {
+            ("envio  ", destino_do_envio(r))
+        };
+        let (sequencia, conteudo) = match &r.quadro {
+            Ok(quadro) => (format!("#{}", quadro.carimbo().1), quadro.json()),
+            Err(e) => (String::new(), format!("quadro inválido: {}", e)),
+        };
+        println!(
+            "[{:9.3}] {} {:>12} -> {:<12} {:13} {:>7} {:14} {}",
+            r.tempo / 1000.0,
+            evento,
+            de,
+            para,
+            r.tipo(),
+            sequencia,
+            observacao,
+            conteudo
+        );
+    }
+}
+
+// Diagrama de sequência: uma raia por participante, uma seta por envio
+// A ponta da seta diz o que o canal fez: '>' entregou, 'x' perdeu, '»' duplicou
+fn mostra_sequencia(registros: &[&Registro]) -> Result<(), String> {
+    let envios: Vec<&&Registro> = registros.iter().filter(|r| !r.entrega).collect();
+
+    // Participantes na ordem em que aparecem, o controlador sempre primeiro
+    let mut raias = vec!["controlador".to_string()];
+    for r in &envios {
+        let (de, para) = r.sentido.pontas(&r.placa);
+        for ponta in [de, para] {
+            if !raias.contains(&ponta) {
+                raias.push(ponta);
+            }
+        }
+    }
+    if raias.len() > MAX_RAIAS {
+        return Err(format!(
+            "{} participantes não cabem no diagrama, use --placa para escolher veículos",
+            raias.len()
+        ));
+    }
+
+    let cabecalho: String = raias
+        .iter()
+        .map(|raia| format!("{:^largura$}", raia, largura = LARGURA_RAIA))
+        .collect();
+    println!("{:11}{}", "", cabecalho);
+
+    for r in envios {
+        let (de, para) = r.sentido.pontas(&r.placa);
+        let i = raias.iter().position(|raia| *raia == de).unwrap();
+        let j = raias.iter().position(|raia| *raia == para).unwrap();
+        let mut linha: Vec<char> = " ".repeat(LARGURA_RAIA * raias.len()).chars().collect();
+        for k in 0..raias.len() {
+            linha[k * LARGURA_RAIA + LARGURA_RAIA / 2] = '|';
+        }
+
+        // Seta entre as duas raias, com o rótulo no meio
+        let (a, b) = (
+            i.min(j) * LARGURA_RAIA + LARGURA_RAIA / 2,
+            i.max(j) * LARGURA_RAIA + LARGURA_RAIA / 2,
+        );
+        for c in &mut linha[a + 1..b] {
+            *c = '-';
+        }
+        let ponta = match (r.sentido, r.copias) {
+            (_, 0) => 'x',
+            (Sentido::EntreVeiculos, _) | (_, 1) if j > i => '>',
+            (Sentido::EntreVeiculos, _) | (_, 1) => '<',
+            _ => '»',
+        };
+        if j > i {
+            linha[b - 1] = ponta;
+        } else {
+            linha[a + 1] = if ponta == '»' { '«' } else { ponta };
+        }
+        let rotulo: Vec<char> = format!(" {} ", rotulo(r)).chars().collect();
+        let espaco = b - a - 3; // entre as pontas, sem encostar nelas
+        let rotulo = &rotulo[..rotulo.len().min(espaco)];
+        let inicio = a + 2 + (espaco - rotulo.len()) / 2;
+        linha[inicio..inicio + rotulo.len()].copy_from_slice(rotulo);
+
+        let linha: String = linha.into_iter().collect();
+        println!("[{:9.3}]{}", r.tempo / 1000.0, linha.trim_end());
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+/* Conexão TCP entre a simulação e um controlador em outro processo
+
+    Os dois lados trocam quadros do protocolo, um atrás do outro no fluxo de bytes.
+    As escritas ficam guardadas até 'descarrega', assim cada tick vai de uma vez.
+*/
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::protocolo::{ErroProtocolo, Quadro};
+use crate::registro::{registra, Categoria, Nivel};
+
+pub const ENDERECO_PADRAO: &str = "127.0.0.1:7878";
+
+// Uma ponta da conexão
+pub struct Conexao {
+    fluxo: TcpStream,
+    entrada: Vec<u8>, // bytes recebidos que ainda não formam um quadro
+    saida: Vec<u8>,   // quadros esperando 'descarrega'
+    outra_ponta: String,
+}
+
+impl Conexao {
+    fn new(fluxo: TcpStream) -> Result<Self, String> {
+        // Cada tick espera a resposta do outro lado, não vale a pena juntar pacotes
+        fluxo
+            .set_nodelay(true)
+            .map_err(|e| format!("Erro configurando a conexão: {}", e))?;
+        let outra_ponta = fluxo.peer_addr().map_or("?".to_string(), |a| a.to_string());
+        Ok(Self {
+            fluxo,
+            entrada: Vec::new(),
+            saida: Vec::new(),
+            outra_ponta,
+        })
+    }
+
+    // Conecta a um controlador que espera em 'endereco'
+    pub fn conecta(endereco: &str) -> Result<Self, String> {
+        let fluxo = TcpStream::connect(endereco).map_err(|e| {
+            format!(
+                "Não foi possível conectar ao controlador em {}: {}",
+                endereco, e
+            )
+        })?;
+        Conexao::new(fluxo)
+    }
+
+    // Espera a próxima simulação que se conectar
+    pub fn aceita(escuta: &TcpListener) -> Result<Self, String> {
+        let (fluxo, _) = escuta
+            .accept()
+            .map_err(|e| format!("Erro esperando conexões: {}", e))?;
+        Conexao::new(fluxo)
+    }
+
+    // Endereço do outro lado, para mensagens
+    pub fn outra_ponta(&self) -> &str {
+        &self.outra_ponta
+    }
+
+    // Guarda um quadro para enviar
+    pub fn envia(&mut self, quadro: &Quadro) {
+        self.saida.extend_from_slice(&quadro.codifica());
+    }
+
+    // Guarda um quadro já codificado para enviar
+    pub fn envia_codificado(&mut self, bytes: &[u8]) {
+        self.saida.extend_from_slice(bytes);
+    }
+
+    // Envia tudo que foi guardado
+    pub fn descarrega(&mut self) -> Result<(), String> {
+        let resultado = self.fluxo.write_all(&self.saida);
+        self.saida.clear();
+        resultado.map_err(|e| format!("Erro enviando para {}: {}", self.outra_ponta, e))
+    }
+
+    // Espera o próximo quadro, None se o outro lado fechou a conexão entre dois quadros
+    // Quadros malformados são pulados, desde que o tamanho deles faça sentido
+    pub fn recebe(&mut self) -> Result<Option<Quadro>, String> {
+        loop {
+            match Quadro::decodifica(&self.entrada) {
+                Ok((quadro, n)) => {
+                    self.entrada.drain(..n);
+                    return Ok(Some(quadro));
+                }
+                Err(ErroProtocolo::Incompleto { .. }) => {
+                    let mut bytes = [0; 4096];
+                    let n = self
+                        .fluxo
+                        .read(&mut bytes)
+                        .map_err(|e| format!("Erro recebendo de {}: {}", self.outra_ponta, e))?;
+                    if n == 0 {
+                        if self.entrada.is_empty() {
+                            return Ok(None);
+                        }
+                        return Err(format!(
+                            "{} fechou a conexão no meio de um quadro",
+                            self.outra_ponta
+                        ));
+                    }
+                    self.entrada.extend_from_slice(&bytes[..n]);
+                }
+                Err(ErroProtocolo::Tamanho(n)) => {
+                    return Err(format!(
+                        "fluxo de {} perdeu o sincronismo, tamanho de quadro {}",
+                        self.outra_ponta, n
+                    ));
+                }
+                Err(e) => {
+                    registra!(
+                        Categoria::Comunicacao,
+                        Nivel::Erro,
+                        "quadro de {} descartado: {}",
+                        self.outra_ponta,
+                        e
+                    );
+                    let tamanho = u32::from_le_bytes(self.entrada[..4].try_into().unwrap());
+                    self.entrada.drain(..4 + tamanho as usize);
+                }
+            }
+        }
+    }
+}
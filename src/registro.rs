@@ -0,0 +1,199 @@
+/* Registro de eventos (log)
+
+    Cada mensagem tem uma categoria (o subsistema que a gerou) e um nível de detalhe.
+    A configuração diz, por categoria, até qual nível as mensagens aparecem, por exemplo:
+
+        info                                tudo até info
+        aviso,controlador=depuracao         só avisos, menos o controlador que mostra mais
+        nenhum,veiculos=detalhe             só os veículos, com todos os detalhes
+
+    Níveis:     nenhum < erro < aviso < info < depuracao < detalhe
+    Categorias: comunicacao, controlador, veiculos, transito, simulacao
+
+    Mensagens vão para a saída de erro, com o tempo simulado no início da linha,
+    para não misturar com resumos e relatórios.
+*/
+
+use std::cell::Cell;
+use std::fmt;
+use std::sync::OnceLock;
+
+pub const VARIAVEL_AMBIENTE: &str = "CRUZAMENTO_LOG"; // configuração quando não há --log
+
+// Nível de detalhe de uma mensagem, ou limite de uma categoria
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Nivel {
+    Nenhum, // só usado como limite, desliga a categoria
+    Erro,
+    Aviso,
+    Info,
+    Depuracao,
+    Detalhe,
+}
+
+impl Nivel {
+    pub fn de_nome(nome: &str) -> Option<Self> {
+        match nome {
+            "nenhum" => Some(Nivel::Nenhum),
+            "erro" => Some(Nivel::Erro),
+            "aviso" => Some(Nivel::Aviso),
+            "info" => Some(Nivel::Info),
+            "depuracao" => Some(Nivel::Depuracao),
+            "detalhe" => Some(Nivel::Detalhe),
+            _ => None,
+        }
+    }
+
+    fn nome(&self) -> &'static str {
+        match self {
+            Nivel::Nenhum => "NENHUM",
+            Nivel::Erro => "ERRO",
+            Nivel::Aviso => "AVISO",
+            Nivel::Info => "INFO",
+            Nivel::Depuracao => "DEPUR",
+            Nivel::Detalhe => "DETAL",
+        }
+    }
+}
+
+// Subsistema que gerou a mensagem
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Categoria {
+    Comunicacao,
+    Controlador,
+    Veiculos,
+    Transito,
+    Simulacao,
+}
+
+const CATEGORIAS: [Categoria; 5] = [
+    Categoria::Comunicacao,
+    Categoria::Controlador,
+    Categoria::Veiculos,
+    Categoria::Transito,
+    Categoria::Simulacao,
+];
+
+impl Categoria {
+    fn nome(&self) -> &'static str {
+        match self {
+            Categoria::Comunicacao => "comunicacao",
+            Categoria::Controlador => "controlador",
+            Categoria::Veiculos => "veiculos",
+            Categoria::Transito => "transito",
+            Categoria::Simulacao => "simulacao",
+        }
+    }
+
+    fn indice(&self) -> usize {
+        *self as usize
+    }
+}
+
+// O que deve ser mostrado
+#[derive(Debug, Clone)]
+pub struct ConfiguracaoLog {
+    limites: [Nivel; CATEGORIAS.len()], // nível máximo mostrado, por categoria
+    placa: Option<String>,              // se presente, só mostra mensagens deste veículo
+}
+
+impl Default for ConfiguracaoLog {
+    fn default() -> Self {
+        Self {
+            limites: [Nivel::Info; CATEGORIAS.len()],
+            placa: None,
+        }
+    }
+}
+
+impl ConfiguracaoLog {
+    // Interpreta uma configuração como "aviso,controlador=depuracao"
+    pub fn interpreta(texto: &str) -> Result<Self, String> {
+        let mut config = ConfiguracaoLog::default();
+        for item in texto.split(',').map(|i| i.trim()).filter(|i| !i.is_empty()) {
+            match item.split_once('=') {
+                None => {
+                    let nivel = Nivel::de_nome(item)
+                        .ok_or(format!("nível de log desconhecido: {}", item))?;
+                    config.limites = [nivel; CATEGORIAS.len()];
+                }
+                Some((nome, nivel)) => {
+                    let categoria = CATEGORIAS
+                        .iter()
+                        .find(|c| c.nome() == nome.trim())
+                        .ok_or(format!("categoria de log desconhecida: {}", nome))?;
+                    config.limites[categoria.indice()] = Nivel::de_nome(nivel.trim())
+                        .ok_or(format!("nível de log desconhecido: {}", nivel))?;
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    // Mostra apenas mensagens deste veículo, além de erros e avisos
+    pub fn filtra_placa(&mut self, placa: Option<String>) {
+        self.placa = placa;
+    }
+}
+
+static CONFIGURACAO: OnceLock<ConfiguracaoLog> = OnceLock::new();
+
+thread_local! {
+    // Tempo simulado da simulação que roda nesta thread, em ms
+    static AGORA: Cell<f64> = const { Cell::new(0.0) };
+}
+
+// Define a configuração usada por todo o programa, só vale a primeira chamada
+pub fn configura(config: ConfiguracaoLog) {
+    let _ = CONFIGURACAO.set(config);
+}
+
+// Informa o tempo simulado, para aparecer nas mensagens
+pub fn define_tempo(agora: f64) {
+    AGORA.with(|a| a.set(agora));
+}
+
+// Verifica se uma mensagem deve ser mostrada
+pub fn ativo(categoria: Categoria, nivel: Nivel, placa: Option<&str>) -> bool {
+    let padrao = ConfiguracaoLog::default();
+    let config = CONFIGURACAO.get().unwrap_or(&padrao);
+
+    if nivel > config.limites[categoria.indice()] {
+        return false;
+    }
+    match (&config.placa, placa) {
+        (None, _) => true,
+        (Some(filtro), Some(placa)) => filtro == placa,
+        (Some(_), None) => nivel <= Nivel::Aviso,
+    }
+}
+
+// Escreve uma mensagem, já filtrada por 'ativo'
+pub fn escreve(categoria: Categoria, nivel: Nivel, mensagem: fmt::Arguments) {
+    let agora = AGORA.with(|a| a.get());
+    eprintln!(
+        "[{:9.3}] {:<5} {:<11} {}",
+        agora / 1000.0,
+        nivel.nome(),
+        categoria.nome(),
+        mensagem
+    );
+}
+
+// Registra uma mensagem, formatada como em println!, se estiver ativa
+//      registra!(Categoria::Controlador, Nivel::Info, "verde para {:?}", via);
+//      registra!(Categoria::Veiculos, Nivel::Depuracao, placa = &placa, "acel {:.2}", acel);
+macro_rules! registra {
+    ($categoria:expr, $nivel:expr, placa = $placa:expr, $($arg:tt)+) => {
+        if $crate::registro::ativo($categoria, $nivel, Some($placa)) {
+            $crate::registro::escreve($categoria, $nivel, format_args!($($arg)+));
+        }
+    };
+    ($categoria:expr, $nivel:expr, $($arg:tt)+) => {
+        if $crate::registro::ativo($categoria, $nivel, None) {
+            $crate::registro::escreve($categoria, $nivel, format_args!($($arg)+));
+        }
+    };
+}
+
+pub(crate) use registra;
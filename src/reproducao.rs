@@ -0,0 +1,210 @@
+/* Reprodução de uma simulação gravada, na janela gráfica
+
+    Não executa o controlador de novo, apenas mostra o que foi gravado.
+    Teclas:
+        espaço      pausa ou continua
+        '.' ','     avança ou volta um tick, pausando
+        ']' '['     avança ou volta 1 s
+        '}' '{'     avança ou volta 10 s
+        '+' '-'     dobra ou divide a velocidade
+        'x'         termina
+
+    Ao avançar ou voltar com a reprodução pausada, as mensagens e decisões do tick
+    aparecem no terminal.
+*/
+
+use std::thread::sleep;
+use std::time::Duration;
+
+use speedy2d::font::Font;
+use speedy2d::window::{WindowHandler, WindowHelper};
+use speedy2d::{Graphics2D, Window};
+
+use crate::desenho::{desenha_cruzamento, escreve, VistaCarro};
+use crate::gravacao::Gravacao;
+use crate::simulacao::TICKMS;
+use crate::transito::Via;
+
+const VELOCIDADE_MAXIMA: usize = 64; // ticks por quadro desenhado
+
+// Estado da reprodução, recebe os callbacks da biblioteca gráfica
+struct Reproducao {
+    gravacao: Gravacao,
+    quadro: usize,     // índice do quadro mostrado
+    pausada: bool,     // true significa que não avança sozinha
+    velocidade: usize, // ticks avançados a cada quadro desenhado
+    largura_total: f64,
+    altura_total: f64,
+    fonte: Font,
+}
+
+impl Reproducao {
+    // Vai para o quadro mais próximo do tempo pedido, em ms
+    fn vai_para_tempo(&mut self, tempo: f64) {
+        let quadros = &self.gravacao.quadros;
+        let i = quadros.partition_point(|q| q.tempo < tempo);
+        self.quadro = i.min(quadros.len() - 1);
+    }
+
+    // Avança ou volta 'delta' ticks, sem sair da gravação
+    fn anda(&mut self, delta: i64) {
+        let ultimo = self.gravacao.quadros.len() as i64 - 1;
+        self.quadro = (self.quadro as i64 + delta).clamp(0, ultimo) as usize;
+    }
+
+    // Mostra no terminal as mensagens e decisões do quadro atual
+    fn mostra_quadro(&self) {
+        let quadro = &self.gravacao.quadros[self.quadro];
+        println!("___Tick em {:.3} s___", quadro.tempo / 1000.0);
+        for msg in &quadro.mensagens {
+            println!("   mensagem {:?}", msg);
+        }
+        for decisao in &quadro.decisoes {
+            let placa = match self.gravacao.veiculos.get(decisao.id) {
+                Some(v) => v.placa.as_str(),
+                None => "?",
+            };
+            println!(
+                "   decisão @{}: conhecia pos {:.2} vel {:.2}, acel {:.2}",
+                placa, decisao.pos_atual, decisao.vel_atual, decisao.acel_desejada
+            );
+        }
+        for estado in &quadro.estados {
+            println!(
+                "   @{} pos {:.3} vel {:.2} acel {:.2}",
+                self.gravacao.veiculos[estado.id].placa,
+                estado.pos_atual,
+                estado.vel_atual,
+                estado.acel_atual
+            );
+        }
+        if let Some(m) = &quadro.colisao {
+            println!("   colisão: {}", m);
+        }
+    }
+}
+
+impl WindowHandler for Reproducao {
+    fn on_draw(&mut self, helper: &mut WindowHelper, graphics: &mut Graphics2D) {
+        if !self.pausada {
+            // Mantém a reprodução próxima do tempo real na velocidade 1
+            sleep(Duration::from_millis(TICKMS.round() as u64));
+            self.anda(self.velocidade as i64);
+            if self.quadro == self.gravacao.quadros.len() - 1 {
+                self.pausada = true;
+                println!("Fim da gravação");
+            }
+        }
+
+        // Separa os carros do quadro por via
+        let quadro = &self.gravacao.quadros[self.quadro];
+        let mut via_h = Vec::new();
+        let mut via_v = Vec::new();
+        for estado in &quadro.estados {
+            let veiculo = &self.gravacao.veiculos[estado.id];
+            let vista = VistaCarro {
+                placa: &veiculo.placa,
+                comprimento: veiculo.comprimento,
+                pos_atual: estado.pos_atual,
+                vel_atual: estado.vel_atual,
+                acel_atual: estado.acel_atual,
+            };
+            match veiculo.via {
+                Via::ViaH => via_h.push(vista),
+                Via::ViaV => via_v.push(vista),
+            }
+        }
+
+        desenha_cruzamento(
+            graphics,
+            &self.fonte,
+            self.largura_total,
+            self.altura_total,
+            &self.gravacao.geometria,
+            &via_h,
+            &via_v,
+            self.pausada,
+        );
+
+        // Informações da reprodução no canto da janela
+        let escala = (self.largura_total / 40.0) as f32;
+        let mut linhas = vec![
+            self.gravacao.descricao.clone(),
+            format!(
+                "t = {:.3} s   tick {}/{}   {}x{}",
+                quadro.tempo / 1000.0,
+                self.quadro + 1,
+                self.gravacao.quadros.len(),
+                self.velocidade,
+                if self.pausada { "   pausada" } else { "" }
+            ),
+        ];
+        if let Some(m) = &quadro.colisao {
+            linhas.push(format!("Colisão: {}", m));
+        }
+        for (i, linha) in linhas.iter().enumerate() {
+            escreve(
+                graphics,
+                &self.fonte,
+                escala,
+                (5.0, 5.0 + 18.0 * i as f32),
+                linha,
+            );
+        }
+
+        if !self.pausada {
+            helper.request_redraw();
+        }
+    }
+
+    fn on_keyboard_char(&mut self, helper: &mut WindowHelper<()>, tecla: char) {
+        let um_segundo = 1000.0;
+        let tempo = self.gravacao.quadros[self.quadro].tempo;
+        match tecla {
+            ' ' => self.pausada = !self.pausada,
+            '.' | ',' => {
+                self.pausada = true;
+                self.anda(if tecla == '.' { 1 } else { -1 });
+                self.mostra_quadro();
+            }
+            ']' => self.vai_para_tempo(tempo + um_segundo),
+            '[' => self.vai_para_tempo(tempo - um_segundo),
+            '}' => self.vai_para_tempo(tempo + 10.0 * um_segundo),
+            '{' => self.vai_para_tempo(tempo - 10.0 * um_segundo),
+            '+' => self.velocidade = (2 * self.velocidade).min(VELOCIDADE_MAXIMA),
+            '-' => self.velocidade = (self.velocidade / 2).max(1),
+            'x' => std::process::exit(0),
+            _ => return,
+        }
+        helper.request_redraw();
+    }
+}
+
+// Carrega a gravação e abre a janela de reprodução
+pub fn reproduz(caminho: &str, tam_janela: f64) -> Result<(), String> {
+    let gravacao = Gravacao::carrega(caminho)?;
+    println!(
+        "Reproduzindo {}: {}, semente {}, {} ticks",
+        caminho,
+        gravacao.descricao,
+        gravacao.semente,
+        gravacao.quadros.len()
+    );
+
+    let window = Window::new_centered(
+        "Cruzamento - replay",
+        (tam_janela as u32, tam_janela as u32),
+    )
+    .map_err(|e| format!("Não foi possível abrir a janela: {:?}", e))?;
+    let fonte = Font::new(include_bytes!("../assets/fonts/NotoSans-Regular.ttf")).unwrap();
+
+    window.run_loop(Reproducao {
+        gravacao,
+        quadro: 0,
+        pausada: true,
+        velocidade: 1,
+        largura_total: tam_janela,
+        altura_total: tam_janela,
+        fonte,
+    });
+}
@@ -0,0 +1,95 @@
+/* Controlador do cruzamento como um processo separado
+
+    Espera conexões TCP de simulações (run ou headless com --servidor) e controla o
+    cruzamento para cada uma, uma de cada vez, com um controlador novo para cada.
+    O tempo simulado vem da simulação: cada tick pedido é executado e respondido antes
+    da simulação avançar, por isso o resultado é o mesmo do controlador no mesmo processo,
+    desde que os dois lados usem o mesmo cenário, que precisa ter o segredo do cruzamento.
+    As chaves de cada controlador são sorteadas do sistema operacional, não da semente do
+    cenário, que é pública: os quadros não se repetem entre execuções.
+*/
+
+use std::net::TcpListener;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::autenticacao::{nova_chave, Chave};
+use crate::cenario::Cenario;
+use crate::comunicacao::{Comunicacao, Transporte};
+use crate::controlador::Controle;
+use crate::rede::Conexao;
+use crate::registro;
+use crate::simulacao::TICKMS;
+
+// Tudo que define o servidor
+pub struct ParametrosServidor {
+    pub cenario: Cenario,          // deve ser o mesmo das simulações atendidas
+    pub endereco: String,          // onde esperar as conexões
+    pub atendimentos: Option<u32>, // termina depois de atender este número de simulações
+}
+
+// Atende simulações até completar os atendimentos pedidos, ou para sempre
+pub fn serve(parametros: &ParametrosServidor) -> Result<(), String> {
+    let segredo = parametros.cenario.segredo.ok_or(
+        "server precisa do segredo do cruzamento, na seção [autenticacao] do cenário, o \
+         mesmo das simulações",
+    )?;
+    let escuta = TcpListener::bind(&parametros.endereco)
+        .map_err(|e| format!("Não foi possível esperar em {}: {}", parametros.endereco, e))?;
+    println!(
+        "Controlador {} esperando simulações em {}",
+        parametros.cenario.controle.tipo.nome(),
+        parametros.endereco
+    );
+
+    // Chaves imprevisíveis, quem conhece a semente não as adivinha
+    let mut rng_chaves = StdRng::from_entropy();
+
+    let mut atendidas = 0;
+    while parametros.atendimentos.is_none_or(|n| atendidas < n) {
+        let conexao = Conexao::aceita(&escuta)?;
+        let origem = conexao.outra_ponta().to_string();
+        println!("Simulação conectada de {}", origem);
+        match atende(
+            &parametros.cenario,
+            segredo,
+            nova_chave(&mut rng_chaves),
+            conexao,
+        ) {
+            Ok(tempo) => println!(
+                "Simulação de {} terminou em {:.1} s simulados",
+                origem,
+                tempo / 1000.0
+            ),
+            // Uma simulação com problema não derruba o servidor
+            Err(msg) => eprintln!("Erro na simulação de {}: {}", origem, msg),
+        }
+        atendidas += 1;
+    }
+    Ok(())
+}
+
+// Controla uma simulação até ela desconectar com um controlador novo, de chave 'chave',
+// devolve o último tick executado, em ms
+fn atende(
+    cenario: &Cenario,
+    segredo: Chave,
+    chave: Chave,
+    conexao: Conexao,
+) -> Result<f64, String> {
+    let transporte = Transporte::ParaVeiculos {
+        conexao,
+        recebidos: Default::default(),
+    };
+    // As imperfeições são sorteadas do lado da simulação, a semente não importa aqui
+    let mut comunicacao = Comunicacao::new(cenario.comunicacao, 0, transporte);
+    let mut controle = Controle::new(cenario, segredo, chave);
+
+    while comunicacao.espera_tick()? {
+        registro::define_tempo(comunicacao.agora());
+        controle.acao_controle(TICKMS, &mut comunicacao);
+        comunicacao.termina_tick()?;
+    }
+    Ok(comunicacao.agora())
+}
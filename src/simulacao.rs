@@ -0,0 +1,721 @@
+use std::time::Instant;
+
+use rand::rngs::StdRng; // Para gerar números aleatórios, não é 'std::'
+use rand::{Rng, SeedableRng}; // Requer [dependencies] rand = "0.8.5"
+
+use crate::atacante::{Atacante, Injecoes};
+use crate::autenticacao::{nova_chave, sorteia_segredo, Chave};
+use crate::cenario::Cenario;
+use crate::chegadas::MeuProcesso;
+use crate::comunicacao::{Comunicacao, EstatisticasCanal, EstatisticasComunicacao, Transporte};
+use crate::controlador::{atraso_controle, Controle, EventoControle};
+use crate::gravacao::Gravador;
+use crate::metricas::RelatorioMetricas;
+use crate::rastro::Rastreador;
+use crate::rede::Conexao;
+use crate::registro::{self, registra, Categoria, Nivel};
+use crate::transito::{Transito, Via};
+
+pub const TICKMS: f64 = 5.0; // Passo da simulação, em ms
+
+// Sorteia uma semente nova, para quando nenhuma foi informada
+pub fn sorteia_semente() -> u64 {
+    rand::thread_rng().gen()
+}
+
+// Descritor da simulação como um todo
+pub struct Simulacao {
+    pub cenario: Cenario,
+    pub transito: Transito,
+    pub comunicacao: Comunicacao,
+    pub controle: Option<Controle>, // None com o controlador em outro processo
+    pub processo_h: MeuProcesso,    // chegadas da via H
+    pub processo_v: MeuProcesso,    // chegadas da via V
+    pub proxima_chegada_h: Option<f64>, // instante da próxima chegada na via H, ms
+    pub proxima_chegada_v: Option<f64>, // instante da próxima chegada na via V, ms
+    pub semente: u64,               // permite repetir exatamente a mesma simulação
+    pub rng: StdRng,                // sorteios das chegadas, a comunicação tem o seu
+    pub rng_chaves: StdRng,         // chave do controlador próprio a cada início
+    pub segredo: Chave,             // do cruzamento, o do cenário ou sorteado
+    pub tempo_simulado: f64,        // tempo desde o início da simulação, em ms
+    pub colisao: Option<String>,    // descrição da colisão, se ocorreu
+    pub falha: Option<String>,      // por que a simulação não pôde continuar
+    pub gravador: Option<Gravador>, // grava cada tick, se pedido
+    pub parada_controlador: Option<(f64, f64)>, // início e fim de uma queda do controlador, ms
+    pub controlador_parado: bool,   // dentro da queda agora
+    pub eventos_controle: Vec<EventoControle>, // percebidos pelo controlador próprio
+    pub atacante: Option<Atacante>, // injeta mensagens falsas nos veículos, para teste
+}
+
+// Tempo até um carro recém chegado receber a primeira aceleração do controlador, além
+// do que já existe com comunicação ideal, em ms
+fn tempo_reacao(cenario: &Cenario) -> f64 {
+    atraso_controle(cenario) + cenario.comunicacao.de_veiculo.atraso_maximo()
+}
+
+// Cria os principais componentes da simulação, com os carros que chegam no instante zero
+pub fn cria_simulacao(cenario: &Cenario, semente: u64) -> Simulacao {
+    monta_simulacao(cenario, semente, None, None, None)
+}
+
+// Cria a simulação gravando cada tick no arquivo 'gravacao' e cada mensagem no arquivo
+// 'rastro', se houver, e usando o controlador que espera em 'servidor', se houver, no
+// lugar de um controlador próprio
+pub fn cria_simulacao_com(
+    cenario: &Cenario,
+    semente: u64,
+    gravacao: Option<&str>,
+    rastro: Option<&str>,
+    servidor: Option<&str>,
+) -> Result<Simulacao, String> {
+    let gravador = match gravacao {
+        None => None,
+        Some(caminho) => Some(Gravador::new(
+            caminho,
+            semente,
+            &cenario.descricao(),
+            &cenario.geometria,
+        )?),
+    };
+    let conexao = match servidor {
+        None => None,
+        // O servidor não conhece um segredo sorteado aqui
+        Some(_) if cenario.segredo.is_none() => {
+            return Err("--servidor precisa do segredo do cruzamento, na seção \
+                [autenticacao] do cenário, o mesmo do server"
+                .to_string())
+        }
+        Some(endereco) => Some(Conexao::conecta(endereco)?),
+    };
+    let rastreador = match rastro {
+        None => None,
+        Some(caminho) => Some(Rastreador::new(caminho, semente, &cenario.descricao())?),
+    };
+    Ok(monta_simulacao(
+        cenario, semente, gravador, rastreador, conexao,
+    ))
+}
+
+fn monta_simulacao(
+    cenario: &Cenario,
+    semente: u64,
+    gravador: Option<Gravador>,
+    rastreador: Option<Rastreador>,
+    conexao: Option<Conexao>,
+) -> Simulacao {
+    // Todo sorteio da simulação vem deste gerador
+    let mut rng = StdRng::seed_from_u64(semente);
+    registro::define_tempo(0.0);
+
+    // Cada via tem seu próprio processo de chegadas
+    let mut processo_h = MeuProcesso::new(&cenario.chegadas_h);
+    let mut processo_v = MeuProcesso::new(&cenario.chegadas_v);
+    let proxima_chegada_h = processo_h.proxima_chegada(0.0, &mut rng);
+    let proxima_chegada_v = processo_v.proxima_chegada(0.0, &mut rng);
+
+    // Sem segredo no cenário, um sorteado que ninguém fora daqui conhece
+    let segredo = cenario.segredo.unwrap_or_else(sorteia_segredo);
+
+    // Controlador próprio ou em outro processo
+    // Gerador próprio também para as chaves, que não mudam as chegadas
+    let mut rng_chaves = StdRng::seed_from_u64(semente.wrapping_add(3));
+    let (transporte, controle) = match conexao {
+        None => (
+            Transporte::Memoria,
+            Some(Controle::new(cenario, segredo, nova_chave(&mut rng_chaves))),
+        ),
+        Some(conexao) => (Transporte::ParaControlador(conexao), None),
+    };
+
+    // Descritor da simulação
+    let mut simul = Simulacao {
+        cenario: cenario.clone(),
+        transito: Transito::new(
+            cenario.geometria,
+            cenario.veiculo,
+            tempo_reacao(cenario),
+            segredo,
+        ),
+        // Gerador próprio, para as imperfeições não mudarem as chegadas de uma semente
+        comunicacao: Comunicacao::new(cenario.comunicacao, semente.wrapping_add(1), transporte),
+        controle,
+        processo_h,
+        processo_v,
+        proxima_chegada_h,
+        proxima_chegada_v,
+        semente,
+        rng,
+        rng_chaves,
+        segredo,
+        tempo_simulado: 0.0,
+        colisao: None,
+        falha: None,
+        gravador,
+        parada_controlador: None,
+        controlador_parado: false,
+        eventos_controle: Vec::new(),
+        atacante: None,
+    };
+
+    // Mensagens e decisões só são copiadas quando há gravação
+    // Decisões de um controlador em outro processo não são conhecidas
+    if simul.gravador.is_some() {
+        simul.comunicacao.copia_mensagens();
+        if let Some(controle) = &mut simul.controle {
+            controle.registra_decisoes();
+        }
+    }
+    if let Some(rastreador) = rastreador {
+        simul.comunicacao.rastreia(rastreador);
+    }
+
+    chega_carros(&mut simul);
+    simul
+}
+
+// Coloca um atacante escutando o controlador e injetando mensagens a cada 'periodo' ms
+pub fn ativa_atacante(simul: &mut Simulacao, periodo: f64) {
+    simul.comunicacao.escuta_controlador();
+    simul.atacante = Some(Atacante::new(periodo, simul.semente.wrapping_add(2)));
+}
+
+// Cria os carros cuja hora de chegar já passou, em cada via
+fn chega_carros(simul: &mut Simulacao) {
+    for via in [Via::ViaH, Via::ViaV] {
+        let (processo, proxima) = match via {
+            Via::ViaH => (&mut simul.processo_h, &mut simul.proxima_chegada_h),
+            Via::ViaV => (&mut simul.processo_v, &mut simul.proxima_chegada_v),
+        };
+
+        while let Some(instante) = *proxima {
+            if instante > simul.tempo_simulado {
+                break;
+            }
+            match simul.transito.chega_carro(via, &mut simul.comunicacao) {
+                Ok(_) => (),
+                Err(msg) => registra!(
+                    Categoria::Transito,
+                    Nivel::Aviso,
+                    "Falha em chegar um carro {:?}: {}",
+                    via,
+                    msg
+                ),
+            }
+            *proxima = processo.proxima_chegada(instante, &mut simul.rng);
+        }
+    }
+}
+
+// Para o controlador próprio dentro da janela de 'parada_controlador' e, no final dela,
+// o reinicia sem memória, como um processo que caiu e voltou
+fn atualiza_parada_controlador(simul: &mut Simulacao) {
+    let parado = simul
+        .parada_controlador
+        .is_some_and(|(inicio, fim)| (inicio..fim).contains(&simul.tempo_simulado));
+    if parado == simul.controlador_parado {
+        return;
+    }
+    simul.controlador_parado = parado;
+    if parado {
+        registra!(Categoria::Simulacao, Nivel::Aviso, "controlador parou");
+        return;
+    }
+    registra!(
+        Categoria::Simulacao,
+        Nivel::Aviso,
+        "controlador reiniciado, sem os veículos que conhecia"
+    );
+    let mut controle = Controle::reiniciado(
+        &simul.cenario,
+        simul.segredo,
+        nova_chave(&mut simul.rng_chaves),
+    );
+    if simul.gravador.is_some() {
+        controle.registra_decisoes();
+    }
+    simul.controle = Some(controle);
+}
+
+// Laço de simulação, returna false no caso de finalizar a simulação
+pub fn laco_simulacao(simul: &mut Simulacao) -> bool {
+    // Atualiza estado do trânsito
+    simul.transito.tick(TICKMS, &mut simul.comunicacao);
+
+    // Controlador próprio pode estar parado, para simular uma queda
+    atualiza_parada_controlador(simul);
+
+    // Atualiza estado do controlador, aqui ou no outro processo
+    match &mut simul.controle {
+        Some(_) if simul.controlador_parado => {
+            // Processo parado não recebe: o que chegar nesse meio tempo se perde
+            while simul.comunicacao.receive_por_controlador().is_some() {}
+        }
+        Some(controle) => {
+            controle.acao_controle(TICKMS, &mut simul.comunicacao);
+            simul.eventos_controle.extend(controle.retira_eventos());
+        }
+        None => {
+            if let Err(msg) = simul.comunicacao.sincroniza_controlador() {
+                registra!(Categoria::Simulacao, Nivel::Erro, "{}", msg);
+                simul.falha = Some(msg);
+                return false;
+            }
+        }
+    }
+
+    simul.comunicacao.amostra_situacoes();
+
+    if let Some(atacante) = &mut simul.atacante {
+        atacante.tick(TICKMS, &mut simul.comunicacao);
+    }
+
+    simul.tempo_simulado += TICKMS;
+    registro::define_tempo(simul.tempo_simulado);
+    simul.comunicacao.define_tempo(simul.tempo_simulado);
+
+    // Grava o estado depois deste tick
+    if let Some(gravador) = &mut simul.gravador {
+        gravador.grava_quadro(
+            simul.tempo_simulado,
+            &simul.transito,
+            simul.comunicacao.retira_copias(),
+            simul
+                .controle
+                .as_mut()
+                .map_or(Vec::new(), |c| c.retira_decisoes()),
+        );
+    }
+
+    // Mostra estado das vias
+    simul.transito.mostra_vias();
+
+    // Aborta a simulação se ocorreu colisão
+    if let Some(m) = simul.transito.ocorreu_colisao() {
+        registra!(
+            Categoria::Simulacao,
+            Nivel::Erro,
+            "Ocorreu colisao, {}, semente {}: {}",
+            simul.cenario.descricao(),
+            simul.semente,
+            m
+        );
+        if let Some(gravador) = &mut simul.gravador {
+            gravador.grava_colisao(m);
+        }
+        simul.colisao = Some(m.to_string());
+        return false;
+    }
+
+    // Verifica se tem algum carro no sistema ou ainda por chegar
+    if simul.transito.vazio()
+        && simul.proxima_chegada_h.is_none()
+        && simul.proxima_chegada_v.is_none()
+    {
+        registra!(
+            Categoria::Simulacao,
+            Nivel::Info,
+            "Nenhum carro no perímetro"
+        );
+        return false;
+    }
+
+    // Verifica se está na hora de chegar novos carros
+    chega_carros(simul);
+
+    registra!(
+        Categoria::Simulacao,
+        Nivel::Detalhe,
+        "#main: proxima_chegada H {:?} V {:?}",
+        simul.proxima_chegada_h,
+        simul.proxima_chegada_v
+    );
+
+    true
+}
+
+// Termina a gravação e o rastro, se houver, informando erros de escrita
+pub fn encerra_gravacao(simul: &mut Simulacao) -> Result<(), String> {
+    let rastro = simul.comunicacao.encerra_rastro();
+    match simul.gravador.take() {
+        None => rastro,
+        Some(mut gravador) => gravador.encerra().and(rastro),
+    }
+}
+
+// Condições de parada de uma simulação sem janela
+pub struct LimitesSimulacao {
+    pub duracao: f64,              // tempo simulado máximo, em ms
+    pub max_veiculos: Option<i32>, // para quando este número de veículos sair do cruzamento
+}
+
+// Por que uma simulação sem janela terminou
+#[derive(Debug, PartialEq)]
+pub enum MotivoTermino {
+    Duracao,
+    Veiculos,
+    Colisao,
+    Vazio,
+    Falha,
+}
+
+// Resumo de uma simulação sem janela
+#[derive(Debug)]
+pub struct ResumoSimulacao {
+    pub semente: u64,
+    pub motivo: MotivoTermino,
+    pub colisao: Option<String>,
+    pub tempo_simulado: f64, // ms
+    pub ticks: u64,
+    pub veiculos_criados: i32,
+    pub veiculos_saidos: i32,
+    pub tempo_real: f64, // ms
+    pub metricas: RelatorioMetricas,
+    pub eventos_controle: Vec<EventoControle>,
+    pub injecoes: Option<Injecoes>, // do atacante, se havia um
+    pub comunicacao: EstatisticasComunicacao,
+}
+
+impl ResumoSimulacao {
+    // Mostra o resumo na tela
+    pub fn mostra(&self) {
+        println!("___Resumo da simulação___");
+        println!("   semente: {}", self.semente);
+        println!("   motivo do término: {:?}", self.motivo);
+        if let Some(m) = &self.colisao {
+            println!("   colisão: {}", m);
+        }
+        println!(
+            "   tempo simulado: {:.1} s em {} ticks",
+            self.tempo_simulado / 1000.0,
+            self.ticks
+        );
+        println!("   veículos criados: {}", self.veiculos_criados);
+        println!("   veículos que saíram: {}", self.veiculos_saidos);
+        println!(
+            "   tempo real: {:.3} s ({:.0}x tempo real)",
+            self.tempo_real / 1000.0,
+            self.tempo_simulado / self.tempo_real.max(0.001)
+        );
+        self.metricas.mostra();
+        self.comunicacao.mostra(self.tempo_simulado);
+        if !self.eventos_controle.is_empty() {
+            let conta = |f: fn(&EventoControle) -> bool| {
+                self.eventos_controle.iter().filter(|e| f(e)).count()
+            };
+            println!(
+                "   eventos do controlador: {} sem resposta, {} voltaram a responder, {} retirados sem resposta",
+                conta(|e| matches!(e, EventoControle::SemResposta { .. })),
+                conta(|e| matches!(e, EventoControle::VoltouAResponder { .. })),
+                conta(|e| matches!(e, EventoControle::Retirado { .. }))
+            );
+        }
+        if let Some(injecoes) = &self.injecoes {
+            println!(
+                "   atacante injetou: {} forjadas, {} adulteradas, {} repetidas, {} chaves forjadas, \
+                 {} com o segredo só de zeros",
+                injecoes.forjadas,
+                injecoes.adulteradas,
+                injecoes.repetidas,
+                injecoes.chaves,
+                injecoes.credenciais
+            );
+        }
+    }
+
+    // Cabeçalho das colunas de 'linha_csv'
+    pub fn cabecalho_csv() -> &'static str {
+        "semente,motivo,colisao,tempo_simulado_s,veiculos_criados,veiculos_saidos,\
+         vazao_veic_h,atraso_medio_s,atraso_maximo_s,paradas_medias,fila_maxima,\
+         mensagens_enviadas,mensagens_enfileiradas,mensagens_descartadas,\
+         idade_situacao_media_ms,idade_situacao_maxima_ms,veiculos_por_grupo"
+    }
+
+    // Resumo em uma linha CSV
+    pub fn linha_csv(&self) -> String {
+        let soma = |campo: fn(&EstatisticasCanal) -> u64| -> u64 {
+            self.comunicacao.canais().into_iter().map(campo).sum()
+        };
+        format!(
+            "{},{:?},{},{:.3},{},{},{:.1},{:.3},{:.3},{:.3},{},{},{},{},{:.1},{:.1},{:.2}",
+            self.semente,
+            self.motivo,
+            self.colisao.is_some(),
+            self.tempo_simulado / 1000.0,
+            self.veiculos_criados,
+            self.veiculos_saidos,
+            self.metricas.vazao,
+            self.metricas.atraso_medio / 1000.0,
+            self.metricas.atraso_maximo / 1000.0,
+            self.metricas.paradas_medias,
+            self.metricas
+                .via_h
+                .fila_maxima
+                .max(self.metricas.via_v.fila_maxima),
+            soma(|c| c.enviadas),
+            soma(|c| c.enfileiradas),
+            soma(|c| c.descartadas),
+            self.comunicacao.idade_situacao.media(),
+            self.comunicacao.idade_situacao.maxima,
+            self.metricas.veiculos_por_grupo()
+        )
+    }
+}
+
+// Executa a simulação sem janela e sem esperar o tempo real, até atingir um dos limites
+pub fn simula_sem_janela(simul: &mut Simulacao, limites: &LimitesSimulacao) -> ResumoSimulacao {
+    let inicio = Instant::now();
+    let mut ticks = 0;
+
+    let motivo = loop {
+        let continua = laco_simulacao(simul);
+        ticks += 1;
+
+        if !continua {
+            if simul.colisao.is_some() {
+                break MotivoTermino::Colisao;
+            }
+            if simul.falha.is_some() {
+                break MotivoTermino::Falha;
+            }
+            break MotivoTermino::Vazio;
+        }
+
+        if let Some(max) = limites.max_veiculos {
+            if simul.transito.get_carros_saidos() >= max {
+                break MotivoTermino::Veiculos;
+            }
+        }
+
+        if simul.tempo_simulado >= limites.duracao {
+            break MotivoTermino::Duracao;
+        }
+    };
+
+    ResumoSimulacao {
+        semente: simul.semente,
+        motivo,
+        colisao: simul.colisao.clone(),
+        tempo_simulado: simul.tempo_simulado,
+        ticks,
+        veiculos_criados: simul.transito.get_carros_criados(),
+        veiculos_saidos: simul.transito.get_carros_saidos(),
+        tempo_real: inicio.elapsed().as_secs_f64() * 1000.0,
+        metricas: simul.transito.relatorio_metricas(),
+        eventos_controle: std::mem::take(&mut simul.eventos_controle),
+        injecoes: simul.atacante.as_ref().map(|a| a.injecoes),
+        comunicacao: simul.comunicacao.estatisticas(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chegadas::TipoProcesso;
+    use crate::controlador::TipoControlador;
+    use crate::registro::ConfiguracaoLog;
+    use crate::transito::ControleABordo;
+
+    const QUEDA: (f64, f64) = (60_000.0, 75_000.0); // ms
+    const FIM: f64 = 150_000.0; // ms
+
+    // Derruba o controlador em QUEDA e confere que, enquanto ele está fora, os
+    // veículos param antes do cruzamento, e que depois da volta ninguém colide
+    fn queda_e_volta(tipo: TipoControlador, semente: u64) {
+        queda_e_volta_em(tipo, semente, QUEDA, FIM);
+    }
+
+    // Como 'queda_e_volta', com a queda e o fim dados, em ms
+    fn queda_e_volta_em(tipo: TipoControlador, semente: u64, queda: (f64, f64), fim: f64) {
+        registro::configura(ConfiguracaoLog::interpreta("nenhum").unwrap());
+        let mut cenario = Cenario::default();
+        cenario.controle.tipo = tipo;
+        let mut simul = cria_simulacao(&cenario, semente);
+        simul.parada_controlador = Some(queda);
+
+        // Quem consegue parar antes do cruzamento depois da falha segura começar não o
+        // alcança; quem já não conseguia segue em frente e desocupa o cruzamento
+        let mut antes_do_cruzamento: Vec<String> = Vec::new();
+        let inicio_falha = queda.0 + cenario.veiculo.tempo_falha_segura;
+        let parados_em = inicio_falha + 5_000.0;
+        while simul.tempo_simulado < queda.1 - TICKMS {
+            assert!(
+                laco_simulacao(&mut simul),
+                "{}: {:?}",
+                tipo.nome(),
+                simul.colisao
+            );
+            if simul.tempo_simulado < inicio_falha + TICKMS {
+                continue;
+            }
+            for via in [Via::ViaH, Via::ViaV] {
+                for carro in simul.transito.get_iterador(via) {
+                    let conhecido = antes_do_cruzamento.contains(&carro.placa);
+                    let parada = carro.vel_atual.powi(2) / (-2.0 * carro.acel_min);
+                    if carro.pos_atual + parada < 0.0 && !conhecido {
+                        antes_do_cruzamento.push(carro.placa.clone());
+                    }
+                    if conhecido {
+                        assert!(
+                            carro.pos_atual < 0.0,
+                            "{}: @{} entrou no cruzamento sem o controlador",
+                            tipo.nome(),
+                            carro.placa
+                        );
+                    }
+                }
+            }
+        }
+        assert!(simul.tempo_simulado > parados_em);
+
+        // No fim da queda, todos estão antes do cruzamento e o primeiro de cada via parou
+        for via in [Via::ViaH, Via::ViaV] {
+            let carros: Vec<_> = simul.transito.get_iterador(via).collect();
+            assert!(carros.iter().all(|c| c.pos_atual < 0.0));
+            if let Some(primeiro) = carros.first() {
+                assert_eq!(primeiro.vel_atual, 0.0, "{}: {:?}", tipo.nome(), via);
+            }
+        }
+
+        // O controlador volta e devolve o trânsito sem colisões
+        let saidos = simul.transito.get_carros_saidos();
+        while simul.tempo_simulado < fim {
+            assert!(
+                laco_simulacao(&mut simul),
+                "{}, semente {}: {:?}",
+                tipo.nome(),
+                semente,
+                simul.colisao
+            );
+        }
+        // Ao menos um veículo a cada 3,75 s depois da volta
+        let minimo = ((fim - queda.1) / 3_750.0) as i32;
+        assert!(simul.transito.get_carros_saidos() > saidos + minimo);
+        for via in [Via::ViaH, Via::ViaV] {
+            assert!(simul.transito.get_iterador(via).all(|c| !c.em_falha_segura));
+        }
+    }
+
+    #[test]
+    fn reservas_voltam_depois_da_queda() {
+        for semente in 1..=3 {
+            queda_e_volta(TipoControlador::Reservas, semente);
+        }
+    }
+
+    #[test]
+    fn outros_controladores_voltam_depois_da_queda() {
+        for tipo in [
+            TipoControlador::Semaforo,
+            TipoControlador::Atuado,
+            TipoControlador::Fifo,
+            TipoControlador::Pelotoes,
+        ] {
+            queda_e_volta(tipo, 1);
+        }
+    }
+
+    // Com comandos atrasados e perdidos, em todos os canais, o controlador 'tipo' não
+    // causa colisões até 'fim' ms
+    fn sem_colisao_com_canal_ruim(tipo: TipoControlador, semente: u64, fim: f64) {
+        registro::configura(ConfiguracaoLog::interpreta("nenhum").unwrap());
+        let mut cenario = Cenario::default();
+        cenario.controle.tipo = tipo;
+        for canal in cenario.comunicacao.canais() {
+            canal.latencia = 300.0;
+            canal.perda = 0.3;
+        }
+        let mut simul = cria_simulacao(&cenario, semente);
+        while simul.tempo_simulado < fim {
+            assert!(
+                laco_simulacao(&mut simul),
+                "{}, semente {}: {:?}",
+                tipo.nome(),
+                semente,
+                simul.colisao
+            );
+        }
+        assert!(simul.transito.get_carros_saidos() > 0);
+    }
+
+    // A semente 2 já fez um carro parar depois da linha no amarelo e o semáforo dar o
+    // verde à outra via com ele ainda no cruzamento
+    #[test]
+    fn controladores_sem_colisao_com_comandos_atrasados_e_perdidos() {
+        for tipo in TipoControlador::todos() {
+            if tipo != TipoControlador::FazNada && tipo != TipoControlador::Mpc {
+                sem_colisao_com_canal_ruim(tipo, 2, 240_000.0);
+            }
+        }
+    }
+
+    #[test]
+    fn mpc_sem_colisao_com_comandos_atrasados_e_perdidos() {
+        sem_colisao_com_canal_ruim(TipoControlador::Mpc, 2, 60_000.0);
+    }
+
+    // O mpc é lento sem otimização, então cai mais cedo e volta por menos tempo
+    #[test]
+    fn mpc_volta_depois_da_queda() {
+        queda_e_volta_em(TipoControlador::Mpc, 1, (15_000.0, 25_000.0), 50_000.0);
+    }
+
+    // Veículos que saíram e veículos por grupo em 'fim' ms, com chegadas a cada 2 s
+    fn saturado(tipo: TipoControlador, fim: f64) -> (i32, f64) {
+        registro::configura(ConfiguracaoLog::interpreta("nenhum").unwrap());
+        let mut cenario = Cenario::default();
+        cenario.controle.tipo = tipo;
+        for chegadas in [&mut cenario.chegadas_h, &mut cenario.chegadas_v] {
+            chegadas.processo = TipoProcesso::Uniforme;
+            chegadas.tec_min = 2_000.0;
+            chegadas.tec_max = 2_000.0;
+        }
+        let mut simul = cria_simulacao(&cenario, 1);
+        while simul.tempo_simulado < fim {
+            assert!(
+                laco_simulacao(&mut simul),
+                "{}: {:?}",
+                tipo.nome(),
+                simul.colisao
+            );
+        }
+        let grupo = simul.transito.relatorio_metricas().veiculos_por_grupo();
+        (simul.transito.get_carros_saidos(), grupo)
+    }
+
+    // Com fila nas duas vias, os pelotões trocam a via do cruzamento menos vezes que o
+    // fifo e escoam mais
+    #[test]
+    fn pelotoes_escoam_mais_que_fifo_com_fila() {
+        let (saidos_fifo, grupo_fifo) = saturado(TipoControlador::Fifo, 120_000.0);
+        let (saidos, grupo) = saturado(TipoControlador::Pelotoes, 120_000.0);
+        assert!(grupo_fifo < 1.5, "fifo: {} veículos por grupo", grupo_fifo);
+        assert!(grupo > 3.0, "pelotões: {} veículos por grupo", grupo);
+        assert!(
+            saidos as f64 > 1.15 * saidos_fifo as f64,
+            "pelotões {}, fifo {}",
+            saidos,
+            saidos_fifo
+        );
+    }
+
+    // Sem controle a bordo, um carro que chegava rápido atrás da fila no perímetro só
+    // freava com o primeiro comando, tarde demais; colidia aos 229 s com o semáforo
+    #[test]
+    fn semaforos_sem_colisao_sem_controle_a_bordo() {
+        registro::configura(ConfiguracaoLog::interpreta("nenhum").unwrap());
+        for tipo in [TipoControlador::Semaforo, TipoControlador::Atuado] {
+            let mut cenario = Cenario::default();
+            cenario.controle.tipo = tipo;
+            cenario.veiculo.controle_a_bordo = ControleABordo::Nenhum;
+            let mut simul = cria_simulacao(&cenario, 4);
+            while simul.tempo_simulado < 240_000.0 {
+                assert!(
+                    laco_simulacao(&mut simul),
+                    "{}: {:?}",
+                    tipo.nome(),
+                    simul.colisao
+                );
+            }
+        }
+    }
+}
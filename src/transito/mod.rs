@@ -20,11 +20,15 @@
 
 */
 
-use crate::comunicacao::{Comunicacao, MensagemDeVeiculo};
+use crate::autenticacao::{chave_do_veiculo, Chave};
+use crate::comunicacao::Comunicacao;
+use crate::metricas::{Metricas, RelatorioMetricas};
+use crate::registro::{registra, Categoria, Nivel};
 
 pub mod veiculos;
-pub use veiculos::Carro;
+pub use veiculos::{Carro, ControleABordo, ModoInforme, ParametrosVeiculo};
 
+// Valores padrão da geometria, usados quando o cenário não define outros
 pub const VIAH_MARGEM: f64 = 15.0; //metros
 pub const VIAV_MARGEM: f64 = 15.0; //metros
 
@@ -34,8 +38,65 @@ pub const VIAV_LARGURA: f64 = 4.0; //metros
 pub const VIAH_PERIMETRO: f64 = 150.0; //metros
 pub const VIAV_PERIMETRO: f64 = 150.0; //metros
 
-pub const VIAH_TOTAL: f64 = VIAH_PERIMETRO + VIAV_LARGURA + VIAH_MARGEM; // metros
-pub const VIAV_TOTAL: f64 = VIAV_PERIMETRO + VIAH_LARGURA + VIAV_MARGEM; // metros
+// Geometria do cruzamento, todas as medidas em metros
+#[derive(Debug, Copy, Clone)]
+pub struct Geometria {
+    pub viah_margem: f64,
+    pub viav_margem: f64,
+    pub viah_largura: f64,
+    pub viav_largura: f64,
+    pub viah_perimetro: f64,
+    pub viav_perimetro: f64,
+}
+
+impl Default for Geometria {
+    fn default() -> Self {
+        Self {
+            viah_margem: VIAH_MARGEM,
+            viav_margem: VIAV_MARGEM,
+            viah_largura: VIAH_LARGURA,
+            viav_largura: VIAV_LARGURA,
+            viah_perimetro: VIAH_PERIMETRO,
+            viav_perimetro: VIAV_PERIMETRO,
+        }
+    }
+}
+
+impl Geometria {
+    // Comprimento total visível da via H
+    pub fn viah_total(&self) -> f64 {
+        self.viah_perimetro + self.viav_largura + self.viah_margem
+    }
+
+    // Comprimento total visível da via V
+    pub fn viav_total(&self) -> f64 {
+        self.viav_perimetro + self.viah_largura + self.viav_margem
+    }
+
+    // Distância entre a entrada da via e o cruzamento
+    pub fn perimetro(&self, via: Via) -> f64 {
+        match via {
+            Via::ViaH => self.viah_perimetro,
+            Via::ViaV => self.viav_perimetro,
+        }
+    }
+
+    // Distância entre o cruzamento e a saída da via
+    pub fn margem(&self, via: Via) -> f64 {
+        match via {
+            Via::ViaH => self.viah_margem,
+            Via::ViaV => self.viav_margem,
+        }
+    }
+
+    // Largura da zona de conflito atravessada por um veículo da via
+    pub fn largura_cruzamento(&self, via: Via) -> f64 {
+        match via {
+            Via::ViaH => self.viav_largura,
+            Via::ViaV => self.viah_largura,
+        }
+    }
+}
 
 // Cruzamento entre duas vias
 // 'enum' tem semântica 'move', mas 'Via' é barato para fazer copy
@@ -47,23 +108,47 @@ pub enum Via {
 
 // Transito composto por carros nas vias
 pub struct Transito {
-    carros_via_h: Vec<Carro>, // Descrição dos carros na via H
-    carros_via_v: Vec<Carro>, // Descrição dos carros na via V
-    carros_criados: i32,      // Número de carros criados no total
+    carros_via_h: Vec<Carro>,   // Descrição dos carros na via H
+    carros_via_v: Vec<Carro>,   // Descrição dos carros na via V
+    carros_criados: i32,        // Número de carros criados no total
+    carros_saidos: i32,         // Número de carros que deixaram o sistema
+    tempo: f64,                 // Tempo simulado desde a criação, em ms
+    metricas: Metricas,         // Métricas de desempenho do trânsito
+    geometria: Geometria,       // Geometria do cruzamento
+    veiculo: ParametrosVeiculo, // Parâmetros dos carros que chegam
+    tempo_reacao: f64,          // Até um carro que chega receber a primeira aceleração, em s
+    segredo: Chave,             // Do cruzamento, dele saem as credenciais dos carros
 }
 
 impl Transito {
     // Cria um novo transito
-    pub fn new() -> Self {
+    pub fn new(
+        geometria: Geometria,
+        veiculo: ParametrosVeiculo,
+        tempo_reacao: f64,
+        segredo: Chave,
+    ) -> Self {
         Self {
             carros_via_h: Vec::new(),
             carros_via_v: Vec::new(),
             carros_criados: 0,
+            carros_saidos: 0,
+            tempo: 0.0,
+            metricas: Metricas::new(geometria, veiculo.velocidade_cruzeiro),
+            geometria,
+            veiculo,
+            tempo_reacao: tempo_reacao / 1000.0,
+            segredo,
         }
     }
 
+    // Geometria do cruzamento
+    pub fn geometria(&self) -> &Geometria {
+        &self.geometria
+    }
+
     // Retorna iterador sobre carros de uma via
-    pub fn get_iterador(&self, via: Via) -> std::slice::Iter<Carro> {
+    pub fn get_iterador(&self, via: Via) -> std::slice::Iter<'_, Carro> {
         match via {
             Via::ViaH => self.carros_via_h.iter(),
             Via::ViaV => self.carros_via_v.iter(),
@@ -101,13 +186,15 @@ impl Transito {
         for carro in &self.carros_via_h {
             cruzando_h = cruzando_h
                 || (carro.pos_atual > 0.0
-                    && carro.pos_atual < 0.0 + VIAV_LARGURA + carro.comprimento);
+                    && carro.pos_atual
+                        < 0.0 + self.geometria.largura_cruzamento(Via::ViaH) + carro.comprimento);
         }
 
         for carro in &self.carros_via_v {
             cruzando_v = cruzando_v
                 || (carro.pos_atual > 0.0
-                    && carro.pos_atual < 0.0 + VIAH_LARGURA + carro.comprimento);
+                    && carro.pos_atual
+                        < 0.0 + self.geometria.largura_cruzamento(Via::ViaV) + carro.comprimento);
         }
 
         if cruzando_h && cruzando_v {
@@ -119,53 +206,53 @@ impl Transito {
     }
 
     // Define a velocidade com a qual o veiculo ingressa no perímetro	!!!
+    // Enquanto o controlador não reage, o carro anda sem frear: este trecho não conta
     fn define_velocidade_chegada(&self, via: &Via) -> f64 {
+        let sem_controle = self.veiculo.velocidade_cruzeiro * self.tempo_reacao;
         match via {
             Via::ViaH => {
                 if self.carros_via_h.is_empty() {
-                    veiculos::VELOCIDADE_CRUZEIRO // Sozinho na via
+                    self.veiculo.velocidade_cruzeiro // Sozinho na via
                 } else {
                     let ultimo_carro = self.carros_via_h.last().unwrap();
-                    let distancia =
-                        VIAH_PERIMETRO + ultimo_carro.pos_atual - ultimo_carro.comprimento;
+                    let distancia = self.geometria.viah_perimetro + ultimo_carro.pos_atual
+                        - ultimo_carro.comprimento
+                        - sem_controle;
                     if distancia < 20.0 {
                         // Considera via parada, não chega
                         return 0.0;
                     }
                     // Qual velocidade de chegada para levar 2s até o da frente ?
                     let velocidade = distancia / 2.0;
-                    if velocidade < veiculos::VELOCIDADE_CRUZEIRO {
+                    if velocidade < self.veiculo.velocidade_cruzeiro {
                         velocidade
                     } else {
-                        veiculos::VELOCIDADE_CRUZEIRO
+                        self.veiculo.velocidade_cruzeiro
                     }
                 }
             }
             Via::ViaV => {
                 if self.carros_via_v.is_empty() {
-                    veiculos::VELOCIDADE_CRUZEIRO
+                    self.veiculo.velocidade_cruzeiro
                 } else {
                     let ultimo_carro = self.carros_via_v.last().unwrap();
-                    let distancia =
-                        VIAV_PERIMETRO + ultimo_carro.pos_atual - ultimo_carro.comprimento;
+                    let distancia = self.geometria.viav_perimetro + ultimo_carro.pos_atual
+                        - ultimo_carro.comprimento
+                        - sem_controle;
                     if distancia < 20.0 {
                         // Considera via parada, não chega
                         return 0.0;
                     }
                     let raiz = ultimo_carro.vel_atual.powi(2)
-                        - 2.0
-                            * ultimo_carro.acel_min
-                            * (VIAV_PERIMETRO + ultimo_carro.pos_atual
-                                - ultimo_carro.comprimento
-                                - 0.5);
+                        - 2.0 * ultimo_carro.acel_min * (distancia - 0.5);
                     if raiz <= 0.0 {
                         return 0.0;
                     }
                     let vel_dist = raiz.sqrt();
-                    if vel_dist < veiculos::VELOCIDADE_CRUZEIRO {
+                    if vel_dist < self.veiculo.velocidade_cruzeiro {
                         vel_dist
                     } else {
-                        veiculos::VELOCIDADE_CRUZEIRO
+                        self.veiculo.velocidade_cruzeiro
                     }
                 }
             }
@@ -177,6 +264,7 @@ impl Transito {
         let vel = self.define_velocidade_chegada(&via);
 
         if vel == 0.0 {
+            self.metricas.chegada_recusada(via);
             return Err("Via congestionada".to_string());
         }
 
@@ -184,16 +272,28 @@ impl Transito {
         nova_placa.push_str(&format!("{:04}", self.carros_criados));
         self.carros_criados += 1;
 
-        let novo_carro = Carro::new(nova_placa.clone(), via, 0.0);
-
-        comunicacao.send_por_veiculo(MensagemDeVeiculo::Chegada {
-            placa: nova_placa,
+        // A credencial é entregue fora da rede, junto com o carro
+        let novo_carro = Carro::new(
+            nova_placa.clone(),
+            chave_do_veiculo(&self.segredo, &nova_placa),
             via,
-            acel_max: novo_carro.acel_max,
-            acel_min: novo_carro.acel_min,
-            vel_max: novo_carro.vel_max,
-            comprimento: novo_carro.comprimento,
-        });
+            vel,
+            0.0,
+            &self.veiculo,
+            self.geometria.perimetro(via),
+            self.geometria.largura_cruzamento(via),
+        );
+        self.metricas.chegada(&novo_carro, self.tempo);
+        registra!(
+            Categoria::Transito,
+            Nivel::Info,
+            placa = &nova_placa,
+            "@{} chegou na {:?}",
+            nova_placa,
+            via
+        );
+
+        comunicacao.send_por_veiculo(novo_carro.mensagem_chegada());
 
         match via {
             Via::ViaH => {
@@ -209,26 +309,52 @@ impl Transito {
 
     // Avança o estado de todos os carros por tickms milissegundos
     pub fn tick(&mut self, tickms: f64, comunicacao: &mut Comunicacao) {
-        println!("transito.tick");
+        registra!(Categoria::Transito, Nivel::Detalhe, "transito.tick");
+        self.tempo += tickms;
 
         // Atualiza todos os carros da via H
+        let mut da_frente: Option<&Carro> = None;
         for carro in &mut self.carros_via_h {
-            carro.tick(tickms, comunicacao);
+            carro.tick(tickms, da_frente, comunicacao);
+            self.metricas.observa_carro(carro, tickms);
+            da_frente = Some(carro);
         }
 
         // Atualiza todos os carros da via V
+        let mut da_frente: Option<&Carro> = None;
         for carro in &mut self.carros_via_v {
-            carro.tick(tickms, comunicacao);
+            carro.tick(tickms, da_frente, comunicacao);
+            self.metricas.observa_carro(carro, tickms);
+            da_frente = Some(carro);
         }
 
+        // Amostra o tamanho das filas
+        self.metricas
+            .observa_fila(Via::ViaH, self.carros_via_h.iter());
+        self.metricas
+            .observa_fila(Via::ViaV, self.carros_via_v.iter());
+
         // Carro mais antigo na via H saiu do sistema ?
         // Obs: Seria melhor usar VeqDeque no lugar de Vec neste caso
         // https://doc.rust-lang.org/std/collections/struct.VecDeque.html#
         if !self.carros_via_h.is_empty() {
             let mais_antigo_h = self.carros_via_h.first().unwrap();
-            if mais_antigo_h.pos_atual > mais_antigo_h.comprimento + VIAV_LARGURA + VIAH_MARGEM {
-                println!("@{} saiu da via H", mais_antigo_h.placa);
+            if mais_antigo_h.pos_atual
+                > mais_antigo_h.comprimento
+                    + self.geometria.largura_cruzamento(Via::ViaH)
+                    + self.geometria.viah_margem
+            {
+                registra!(
+                    Categoria::Transito,
+                    Nivel::Info,
+                    placa = &mais_antigo_h.placa,
+                    "@{} saiu da via H",
+                    mais_antigo_h.placa
+                );
+                self.metricas.saida(&mais_antigo_h.placa, self.tempo);
+                comunicacao.esquece_veiculo(&mais_antigo_h.placa);
                 self.carros_via_h.remove(0);
+                self.carros_saidos += 1;
             }
         }
 
@@ -237,27 +363,54 @@ impl Transito {
         // https://doc.rust-lang.org/std/collections/struct.VecDeque.html#
         if !self.carros_via_v.is_empty() {
             let mais_antigo_v = self.carros_via_v.first().unwrap();
-            if mais_antigo_v.pos_atual > mais_antigo_v.comprimento + VIAH_LARGURA + VIAV_MARGEM {
-                println!("@{} saiu da via V", mais_antigo_v.placa);
+            if mais_antigo_v.pos_atual
+                > mais_antigo_v.comprimento
+                    + self.geometria.largura_cruzamento(Via::ViaV)
+                    + self.geometria.viav_margem
+            {
+                registra!(
+                    Categoria::Transito,
+                    Nivel::Info,
+                    placa = &mais_antigo_v.placa,
+                    "@{} saiu da via V",
+                    mais_antigo_v.placa
+                );
+                self.metricas.saida(&mais_antigo_v.placa, self.tempo);
+                comunicacao.esquece_veiculo(&mais_antigo_v.placa);
                 self.carros_via_v.remove(0);
+                self.carros_saidos += 1;
             }
         }
     }
 
     // Mostra estado das vias
     pub fn mostra_vias(&self) {
-        println!("___Carros na via H___");
-
+        registra!(Categoria::Transito, Nivel::Detalhe, "___Carros na via H___");
         for carro in &self.carros_via_h {
             carro.mostra();
         }
 
-        println!("___Carros na via V___");
+        registra!(Categoria::Transito, Nivel::Detalhe, "___Carros na via V___");
         for carro in &self.carros_via_v {
             carro.mostra();
         }
     }
 
+    // Número de carros criados no total
+    pub fn get_carros_criados(&self) -> i32 {
+        self.carros_criados
+    }
+
+    // Número de carros que deixaram o sistema
+    pub fn get_carros_saidos(&self) -> i32 {
+        self.carros_saidos
+    }
+
+    // Relatório das métricas de desempenho coletadas até agora
+    pub fn relatorio_metricas(&self) -> RelatorioMetricas {
+        self.metricas.relatorio(self.tempo)
+    }
+
     // Verifica se algum carro no sistema
     pub fn vazio(&self) -> bool {
         self.carros_via_h.is_empty() && self.carros_via_v.is_empty()
@@ -1,7 +1,15 @@
-use crate::comunicacao::{Comunicacao, MensagemDeVeiculo, MensagemDoControlador};
+use std::collections::HashMap;
+
+use crate::autenticacao::{cifra_chave, iguais, Chave, Mac};
+use crate::comunicacao::{
+    Comunicacao, MensagemDeVeiculo, MensagemDoControlador, MensagemEntreVeiculos, Pacote,
+};
+use crate::protocolo::autentica;
+use crate::registro::{registra, Categoria, Nivel};
 
 use super::Via;
 
+// Valores padrão dos parâmetros de um veículo, usados quando o cenário não define outros
 pub const _CARRO_LARGURA: f64 = 2.0; //metros
 pub const CARRO_COMPRIMENTO: f64 = 4.0; //metros
 
@@ -17,27 +25,182 @@ pub const ACELERACAO_MAXIMA: f64 = 3.0;
 // Aceleração mínima de qualquer veículo em metros por segundo ao quadrado
 pub const ACELERACAO_MINIMA: f64 = -10.0;
 
+// Intervalo entre as difusões do estado de um veículo para os vizinhos, em ms
+pub const PERIODO_V2V: f64 = 100.0;
+
+// Distância mínima até a traseira do veículo da frente, depois de parar, em metros
+pub const DISTANCIA_MINIMA: f64 = 2.0;
+
+// Tempo sem mensagens do controlador até entrar em falha segura, em ms
+pub const TEMPO_FALHA_SEGURA: f64 = 1000.0;
+
+// Intervalo máximo entre os informes espontâneos da situação ao controlador, em ms
+pub const PERIODO_INFORME: f64 = 100.0;
+
+// Mudança de aceleração, em m/s2, e de velocidade, em m/s, que faz o veículo informar a
+// situação antes do período
+pub const LIMIAR_ACELERACAO: f64 = 0.5;
+pub const LIMIAR_VELOCIDADE: f64 = 1.0;
+
+// Distâncias antes do início do cruzamento, em metros, onde o veículo informa a situação
+// ao passar; também informa ao entrar e ao sair do cruzamento
+const ZONAS_INFORME: [f64; 4] = [-100.0, -50.0, -25.0, -10.0];
+
+// Tempo sem notícias do controlador até repetir a mensagem de chegada, em ms
+pub const REPETE_CHEGADA: f64 = 250.0;
+
+// Distância antes do início do cruzamento onde a falha segura tenta parar, em metros
+const MARGEM_PARADA: f64 = 1.0;
+
+// Tempo até o controle a bordo perceber, só pelo sensor, que o da frente está freando, em ms
+const REACAO_SENSOR: f64 = 500.0;
+
+// Como o veículo limita a própria aceleração atrás do veículo da frente, qualquer que
+// seja a aceleração pedida pelo controlador
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ControleABordo {
+    Nenhum, // obedece o controlador
+    Acc,    // só o sensor de distância, supõe que o da frente freia como este
+    Cacc,   // sensor mais as difusões do da frente, quando estão em dia
+}
+
+impl ControleABordo {
+    pub fn nome(&self) -> &'static str {
+        match self {
+            ControleABordo::Nenhum => "nenhum",
+            ControleABordo::Acc => "acc",
+            ControleABordo::Cacc => "cacc",
+        }
+    }
+
+    pub fn de_nome(nome: &str) -> Option<Self> {
+        match nome {
+            "nenhum" => Some(ControleABordo::Nenhum),
+            "acc" => Some(ControleABordo::Acc),
+            "cacc" => Some(ControleABordo::Cacc),
+            _ => None,
+        }
+    }
+}
+
+// Quando o veículo informa a sua situação ao controlador
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ModoInforme {
+    Pedido,     // só quando o controlador pede
+    Espontaneo, // periodicamente, ao mudar a aceleração ou a velocidade e ao passar as zonas
+}
+
+impl ModoInforme {
+    pub fn nome(&self) -> &'static str {
+        match self {
+            ModoInforme::Pedido => "pedido",
+            ModoInforme::Espontaneo => "espontaneo",
+        }
+    }
+
+    pub fn de_nome(nome: &str) -> Option<Self> {
+        match nome {
+            "pedido" => Some(ModoInforme::Pedido),
+            "espontaneo" => Some(ModoInforme::Espontaneo),
+            _ => None,
+        }
+    }
+}
+
+// Parâmetros comuns aos veículos que chegam ao cruzamento
+#[derive(Debug, Copy, Clone)]
+pub struct ParametrosVeiculo {
+    pub comprimento: f64,         // metros
+    pub velocidade_cruzeiro: f64, // metros por segundo
+    pub velocidade_maxima: f64,   // metros por segundo
+    pub aceleracao_maxima: f64,   // metros por segundo ao quadrado
+    pub aceleracao_minima: f64,   // metros por segundo ao quadrado
+    pub controle_a_bordo: ControleABordo,
+    pub periodo_v2v: f64,      // entre difusões do estado, ms, zero não difunde
+    pub distancia_minima: f64, // metros
+    pub tempo_falha_segura: f64, // sem mensagens do controlador, ms, zero nunca entra
+    pub informe: ModoInforme,
+    pub periodo_informe: f64,   // máximo entre informes espontâneos, ms
+    pub limiar_aceleracao: f64, // metros por segundo ao quadrado
+    pub limiar_velocidade: f64, // metros por segundo
+}
+
+impl Default for ParametrosVeiculo {
+    fn default() -> Self {
+        Self {
+            comprimento: CARRO_COMPRIMENTO,
+            velocidade_cruzeiro: VELOCIDADE_CRUZEIRO,
+            velocidade_maxima: VELOCIDADE_MAXIMA,
+            aceleracao_maxima: ACELERACAO_MAXIMA,
+            aceleracao_minima: ACELERACAO_MINIMA,
+            controle_a_bordo: ControleABordo::Cacc,
+            periodo_v2v: PERIODO_V2V,
+            distancia_minima: DISTANCIA_MINIMA,
+            tempo_falha_segura: TEMPO_FALHA_SEGURA,
+            informe: ModoInforme::Pedido,
+            periodo_informe: PERIODO_INFORME,
+            limiar_aceleracao: LIMIAR_ACELERACAO,
+            limiar_velocidade: LIMIAR_VELOCIDADE,
+        }
+    }
+}
+
 // Descrição de um carro
 pub struct Carro {
-    pub placa: String,    // placa deste carro
-    pub via: Via,         // via deste carro
-    pub acel_max: f64,    // metros por segundo ao quadrado
-    pub acel_min: f64,    // metros por segundo ao quadrado
-    pub vel_max: f64,     // metros por segundo
-    pub comprimento: f64, // metros
-    pub pos_atual: f64,   // metros do cruzamento
-    pub vel_atual: f64,   // metros por segundo
-    pub acel_atual: f64,  // metros por segundo ao quadrado
+    pub placa: String,         // placa deste carro
+    pub via: Via,              // via deste carro
+    pub acel_max: f64,         // metros por segundo ao quadrado
+    pub acel_min: f64,         // metros por segundo ao quadrado
+    pub vel_max: f64,          // metros por segundo
+    pub comprimento: f64,      // metros
+    pub pos_atual: f64,        // metros do cruzamento
+    pub vel_atual: f64,        // metros por segundo
+    pub acel_atual: f64,       // metros por segundo ao quadrado
+    pub acel_pedida: f64,      // última pedida pelo controlador, metros por segundo ao quadrado
+    pub limitado: bool,        // controle a bordo está impedindo a aceleração pedida
+    pub conhecido: bool,       // já recebeu a chave do controlador
+    pub comandado: bool,       // já recebeu uma aceleração do controlador
+    pub espera_chegada: f64,   // tempo desde a última mensagem de chegada enviada, ms
+    pub ultima_sequencia: u64, // da última mensagem aceita do controlador
+    pub controle_a_bordo: ControleABordo,
+    pub periodo_v2v: f64,                                         // ms
+    pub distancia_minima: f64,                                    // metros
+    pub espera_v2v: f64, // tempo desde a última difusão do estado, ms
+    pub vizinhos: HashMap<String, Pacote<MensagemEntreVeiculos>>, // última difusão de cada um
+    pub tempo_falha_segura: f64, // ms
+    pub sem_controlador: f64, // tempo desde a última mensagem aceita do controlador, ms
+    pub em_falha_segura: bool, // parando antes do cruzamento por falta do controlador
+    pub chave: Option<Chave>, // recebida do controlador, autentica as mensagens dele
+    pub credencial: Chave, // recebida fora da rede, autentica e decifra a entrega da chave
+    pub forjadas: i32,   // mensagens rejeitadas por MAC errado: forjadas ou adulteradas
+    pub repetidas: i32,  // mensagens rejeitadas por serem cópias antigas de outras
+    pub periodo_informe: f64, // entre informes espontâneos, ms, zero só informa quando pedido
+    pub limiar_aceleracao: f64, // metros por segundo ao quadrado
+    pub limiar_velocidade: f64, // metros por segundo
+    pub espera_informe: f64, // tempo desde o último informe espontâneo, ms
+    pub informado: Option<(f64, f64, usize)>, // velocidade, aceleração e zona do último informe
+    pub fim_cruzamento: f64, // posição em que a traseira sai do cruzamento, metros
 }
 
 impl Carro {
-    // Cria um novo carro
-    pub fn new(placa: String, via: Via, acel: f64) -> Self {
+    // Cria um novo carro na entrada da via, a 'perimetro' metros do cruzamento
+    // de 'largura' metros, a 'vel' m/s, com a 'credencial' combinada com o cruzamento
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        placa: String,
+        credencial: Chave,
+        via: Via,
+        vel: f64,
+        acel: f64,
+        parametros: &ParametrosVeiculo,
+        perimetro: f64,
+        largura: f64,
+    ) -> Self {
         let (res, msg) = Carro::valida_placa(&placa);
         assert!(res, "   Placa inválida: {} @{}", msg, placa);
 
         assert!(
-            (ACELERACAO_MINIMA..=ACELERACAO_MAXIMA).contains(&acel),
+            (parametros.aceleracao_minima..=parametros.aceleracao_maxima).contains(&acel),
             "   Aceleração inválida: {} {}",
             placa,
             acel
@@ -46,18 +209,282 @@ impl Carro {
         Self {
             placa,
             via,
-            acel_max: ACELERACAO_MAXIMA,
-            acel_min: ACELERACAO_MINIMA,
-            vel_max: VELOCIDADE_MAXIMA,
-            comprimento: CARRO_COMPRIMENTO,
-            pos_atual: match via {
-                // Posso usar aqui pois foi clonado antes
-                Via::ViaH => -super::VIAH_PERIMETRO,
-                Via::ViaV => -super::VIAV_PERIMETRO,
-            },
-            vel_atual: VELOCIDADE_CRUZEIRO,
+            acel_max: parametros.aceleracao_maxima,
+            acel_min: parametros.aceleracao_minima,
+            vel_max: parametros.velocidade_maxima,
+            comprimento: parametros.comprimento,
+            pos_atual: -perimetro,
+            vel_atual: vel,
             acel_atual: acel,
+            acel_pedida: acel,
+            limitado: false,
+            conhecido: false,
+            comandado: false,
+            espera_chegada: 0.0,
+            ultima_sequencia: 0,
+            controle_a_bordo: parametros.controle_a_bordo,
+            periodo_v2v: parametros.periodo_v2v,
+            distancia_minima: parametros.distancia_minima,
+            espera_v2v: 0.0,
+            vizinhos: HashMap::new(),
+            tempo_falha_segura: parametros.tempo_falha_segura,
+            sem_controlador: 0.0,
+            em_falha_segura: false,
+            chave: None,
+            credencial,
+            forjadas: 0,
+            repetidas: 0,
+            periodo_informe: match parametros.informe {
+                ModoInforme::Pedido => 0.0,
+                ModoInforme::Espontaneo => parametros.periodo_informe,
+            },
+            limiar_aceleracao: parametros.limiar_aceleracao,
+            limiar_velocidade: parametros.limiar_velocidade,
+            espera_informe: 0.0,
+            informado: None,
+            fim_cruzamento: largura + parametros.comprimento,
+        }
+    }
+
+    // Confere se uma mensagem veio mesmo do controlador e ainda não foi vista
+    // A Chave tem que vir autenticada com a credencial deste veículo, e só ela; as
+    // outras, com a chave que ela entregou. Sequência já vista, dentro do atraso
+    // máximo do canal, é só cópia duplicada ou atrasada; mais velha que isso, alguém
+    // está repetindo um quadro capturado
+    fn aceita(
+        &mut self,
+        pacote: &Pacote<MensagemDoControlador>,
+        mac: &Mac,
+        tickms: f64,
+        comunicacao: &Comunicacao,
+    ) -> bool {
+        let chave = match pacote.mensagem {
+            MensagemDoControlador::Chave { .. } => self.credencial,
+            _ => match self.chave {
+                Some(chave) => chave,
+                // Sem a chave não dá para conferir; comum quando a Chave se perdeu no caminho
+                None => {
+                    registra!(
+                        Categoria::Veiculos,
+                        Nivel::Depuracao,
+                        placa = &self.placa,
+                        "#veiculo @{} descarta mensagem anterior à chave {:?}",
+                        self.placa,
+                        pacote.mensagem
+                    );
+                    return false;
+                }
+            },
+        };
+        if !iguais(&autentica(pacote, &chave), mac) {
+            self.forjadas += 1;
+            registra!(
+                Categoria::Veiculos,
+                Nivel::Depuracao,
+                placa = &self.placa,
+                "#veiculo @{} rejeita mensagem forjada ou adulterada {:?}",
+                self.placa,
+                pacote.mensagem
+            );
+            return false;
+        }
+
+        if pacote.sequencia <= self.ultima_sequencia {
+            let janela = comunicacao.parametros().do_controlador.atraso_maximo() + tickms;
+            if comunicacao.agora() - pacote.enviada_em > janela {
+                self.repetidas += 1;
+                registra!(
+                    Categoria::Veiculos,
+                    Nivel::Depuracao,
+                    placa = &self.placa,
+                    "#veiculo @{} rejeita mensagem repetida, enviada em {:.3} s",
+                    self.placa,
+                    pacote.enviada_em / 1000.0
+                );
+            } else {
+                registra!(
+                    Categoria::Veiculos,
+                    Nivel::Depuracao,
+                    placa = &self.placa,
+                    "#veiculo @{} descarta mensagem antiga {:?}",
+                    self.placa,
+                    pacote
+                );
+            }
+            return false;
+        }
+        if let MensagemDoControlador::Chave { chave, .. } = &pacote.mensagem {
+            self.chave = Some(cifra_chave(chave, &self.credencial, pacote.enviada_em));
+        }
+        true
+    }
+
+    // Mensagem que apresenta este carro ao controlador
+    pub fn mensagem_chegada(&self) -> MensagemDeVeiculo {
+        MensagemDeVeiculo::Chegada {
+            placa: self.placa.clone(),
+            via: self.via,
+            acel_max: self.acel_max,
+            acel_min: self.acel_min,
+            vel_max: self.vel_max,
+            comprimento: self.comprimento,
+            periodo_informe: self.periodo_informe,
+        }
+    }
+
+    // Mensagem que informa a situação deste carro ao controlador
+    pub fn mensagem_situacao(&self) -> MensagemDeVeiculo {
+        MensagemDeVeiculo::SituacaoAtual {
+            placa: self.placa.clone(),
+            pos_atual: self.pos_atual,
+            vel_atual: self.vel_atual,
+            acel_atual: self.acel_atual,
+        }
+    }
+
+    // Quantas fronteiras de zona o carro já passou
+    fn zona(&self) -> usize {
+        ZONAS_INFORME
+            .iter()
+            .chain(&[0.0, self.fim_cruzamento])
+            .filter(|fronteira| self.pos_atual >= **fronteira)
+            .count()
+    }
+
+    // Informa a situação ao controlador sem ser pedido, se está na hora: passou o
+    // período, a aceleração ou a velocidade mudaram mais que o limiar ou mudou de zona
+    // Só depois de conhecido, antes disso o controlador não sabe deste carro
+    fn informa_se_preciso(&mut self, tickms: f64, comunicacao: &mut Comunicacao) {
+        if self.periodo_informe <= 0.0 || !self.conhecido {
+            return;
+        }
+        self.espera_informe += tickms;
+        let zona = self.zona();
+        let preciso = match self.informado {
+            None => true,
+            Some((vel, acel, zona_informada)) => {
+                self.espera_informe >= self.periodo_informe
+                    || (self.acel_atual - acel).abs() >= self.limiar_aceleracao
+                    || (self.vel_atual - vel).abs() >= self.limiar_velocidade
+                    || zona != zona_informada
+            }
+        };
+        if !preciso {
+            return;
         }
+        registra!(
+            Categoria::Veiculos,
+            Nivel::Detalhe,
+            placa = &self.placa,
+            "#veiculo @{} informa sua situacao sem ser pedido",
+            self.placa
+        );
+        self.espera_informe = 0.0;
+        self.informado = Some((self.vel_atual, self.acel_atual, zona));
+        comunicacao.send_por_veiculo(self.mensagem_situacao());
+    }
+
+    // Mensagem que conta o estado deste carro aos vizinhos
+    pub fn mensagem_estado(&self) -> MensagemEntreVeiculos {
+        MensagemEntreVeiculos::Estado {
+            placa: self.placa.clone(),
+            via: self.via,
+            pos_atual: self.pos_atual,
+            vel_atual: self.vel_atual,
+            acel_atual: self.acel_atual,
+            acel_min: self.acel_min,
+            comprimento: self.comprimento,
+        }
+    }
+
+    // Maior aceleração que ainda deixa parar atrás do carro da frente, mesmo que ele
+    // comece a frear ao máximo agora e este só perceba depois do tempo de reação
+    // Distância e velocidade do da frente vêm do sensor; as difusões dele, se em dia,
+    // dizem o quanto ele consegue frear e encurtam a reação para o atraso das difusões
+    fn limite_a_bordo(
+        &self,
+        da_frente: Option<&Carro>,
+        tickms: f64,
+        comunicacao: &Comunicacao,
+    ) -> f64 {
+        // Antes do primeiro comando e em falha segura ninguém cuida da distância, o sensor
+        // vale mesmo sem controle a bordo
+        let sem_comando = !self.comandado || self.em_falha_segura;
+        let frente = match (self.controle_a_bordo, da_frente) {
+            (_, None) => return f64::INFINITY,
+            (ControleABordo::Nenhum, _) if !sem_comando => return f64::INFINITY,
+            (_, Some(frente)) => frente,
+        };
+
+        let canal = comunicacao.parametros().entre_veiculos;
+        let difusao = self
+            .vizinhos
+            .get(&frente.placa)
+            .filter(|_| self.controle_a_bordo == ControleABordo::Cacc);
+        let (reacao, freio_frente) = match difusao.map(|p| &p.mensagem) {
+            Some(MensagemEntreVeiculos::Estado { acel_min, .. }) if *acel_min < 0.0 => {
+                (self.periodo_v2v + canal.atraso_maximo() + tickms, -acel_min)
+            }
+            _ => (REACAO_SENSOR, -self.acel_min),
+        };
+        let reacao = reacao / 1000.0;
+        let freio = -self.acel_min;
+
+        // Quanto este pode andar até parar sem chegar perto demais do da frente parado
+        let folga = frente.pos_atual - frente.comprimento - self.pos_atual - self.distancia_minima
+            + frente.vel_atual.powi(2) / (2.0 * freio_frente);
+        let vel_segura = if folga > 0.0 {
+            freio * (-reacao + (reacao.powi(2) + 2.0 * folga / freio).sqrt())
+        } else {
+            0.0
+        };
+        (vel_segura - self.vel_atual) / (tickms / 1000.0)
+    }
+
+    // Aceleração sem o controlador: para antes do cruzamento e espera parado
+    // Quem já não consegue parar antes dele segue em frente, para desocupá-lo
+    fn acel_falha_segura(&self) -> f64 {
+        let ate_o_cruzamento = -self.pos_atual;
+        let parada = self.vel_atual.powi(2) / (-2.0 * self.acel_min);
+        if ate_o_cruzamento <= 0.0 || parada > ate_o_cruzamento {
+            return if self.vel_atual > 0.0 {
+                0.0
+            } else {
+                self.acel_max
+            };
+        }
+        if self.vel_atual <= 0.0 {
+            return 0.0;
+        }
+        let distancia = ate_o_cruzamento - MARGEM_PARADA;
+        if distancia <= 0.0 {
+            return self.acel_min;
+        }
+        (-self.vel_atual.powi(2) / (2.0 * distancia)).max(self.acel_min)
+    }
+
+    // Aplica a aceleração pedida pelo controlador, ou a da falha segura, dentro do
+    // limite do controle a bordo
+    fn aplica_aceleracao(&mut self, limite: f64) {
+        let pedida = if self.em_falha_segura {
+            self.acel_falha_segura()
+        } else {
+            self.acel_pedida
+        };
+        let limitado = limite < pedida;
+        if limitado && !self.limitado {
+            registra!(
+                Categoria::Veiculos,
+                Nivel::Depuracao,
+                placa = &self.placa,
+                "#veiculo @{} limita a bordo a aceleração pedida {:.2} a {:.2}",
+                self.placa,
+                pedida,
+                limite.max(self.acel_min)
+            );
+        }
+        self.limitado = limitado;
+        self.acel_atual = pedida.min(limite).max(self.acel_min);
     }
 
     // Valida formato de uma placa
@@ -87,16 +514,24 @@ impl Carro {
         (true, "")
     }
 
-    // Mostra o estado de um carro na tela
+    // Mostra o estado de um carro no registro
     pub fn mostra(&self) {
-        println!(
+        registra!(
+            Categoria::Transito,
+            Nivel::Detalhe,
+            placa = &self.placa,
             "   @{} na posição {:?} {:.3}, velocidade {:.2}, aceleração {:.2}",
-            self.placa, self.via, self.pos_atual, self.vel_atual, self.acel_atual
+            self.placa,
+            self.via,
+            self.pos_atual,
+            self.vel_atual,
+            self.acel_atual
         );
     }
 
     // Avança o estado de um carro por tickms milissegundos
-    pub fn tick(&mut self, tickms: f64, comunicacao: &mut Comunicacao) {
+    // 'da_frente' é o carro logo à frente na mesma via, como o sensor de distância o vê
+    pub fn tick(&mut self, tickms: f64, da_frente: Option<&Carro>, comunicacao: &mut Comunicacao) {
         //self.mostra();
 
         let pos_anterior = self.pos_atual;
@@ -122,37 +557,278 @@ impl Carro {
             self.vel_atual = self.vel_max; // Trava na velocidade máxima
         }
 
+        // Guarda a última difusão de cada vizinho da mesma via, esquece as que não
+        // estão mais em dia: chegaram há mais tempo que o esperado, mesmo perdendo uma
+        while let Some(pacote) = comunicacao.receive_entre_veiculos(&self.placa) {
+            let MensagemEntreVeiculos::Estado { via, .. } = pacote.mensagem;
+            let placa = pacote.mensagem.placa();
+            let mais_nova = self
+                .vizinhos
+                .get(placa)
+                .is_none_or(|p| p.sequencia < pacote.sequencia);
+            if via == self.via && mais_nova {
+                self.vizinhos.insert(placa.to_string(), pacote);
+            }
+        }
+        let agora = comunicacao.agora();
+        let em_dia = 2.0 * self.periodo_v2v
+            + comunicacao.parametros().entre_veiculos.atraso_maximo()
+            + tickms;
+        self.vizinhos.retain(|_, p| agora - p.enviada_em <= em_dia);
+
+        let limite = self.limite_a_bordo(da_frente, tickms, comunicacao);
+        self.aplica_aceleracao(limite);
+
+        // As mensagens do controlador a cada ciclo servem de batimento: sem elas por
+        // muito tempo, o controlador parou ou a comunicação caiu
+        self.sem_controlador += tickms;
+
         // Processa as mensagens recebidas por este carro
         loop {
             match comunicacao.receive_por_veiculo(&self.placa) {
                 None => break,
-                Some(msg) => {
-                    match msg {
+                Some((pacote, mac)) => {
+                    // Forjada, adulterada, repetida ou mais velha que a última aceita
+                    if !self.aceita(&pacote, &mac, tickms, comunicacao) {
+                        continue;
+                    }
+                    self.ultima_sequencia = pacote.sequencia;
+                    self.conhecido = true;
+                    self.sem_controlador = 0.0;
+                    match pacote.mensagem {
                         MensagemDoControlador::SetAcel { placa, acel } => {
-                            println!("#veiculo @{} recebe acel {:.2}", placa, acel);
+                            registra!(
+                                Categoria::Veiculos,
+                                Nivel::Depuracao,
+                                placa = &placa,
+                                "#veiculo @{} recebe acel {:.2}",
+                                placa,
+                                acel
+                            );
+                            self.comandado = true;
                             // Veículo só aceita aceleração válida !!!
                             if acel > self.acel_max {
-                                self.acel_atual = self.acel_max;
+                                self.acel_pedida = self.acel_max;
                             } else if acel < self.acel_min {
-                                self.acel_atual = self.acel_min;
+                                self.acel_pedida = self.acel_min;
                             } else {
-                                self.acel_atual = acel
+                                self.acel_pedida = acel
                             }
+                            // Só sai da falha segura com um comando novo, a aceleração
+                            // pedida antes da falha não vale mais
+                            if self.em_falha_segura {
+                                self.em_falha_segura = false;
+                                registra!(
+                                    Categoria::Veiculos,
+                                    Nivel::Info,
+                                    placa = &self.placa,
+                                    "#veiculo @{} volta a ouvir o controlador, sai da falha segura",
+                                    self.placa
+                                );
+                            }
+                            self.aplica_aceleracao(limite);
                         }
 
-                        MensagemDoControlador::PedeSituacao { placa } => {
-                            println!("#veiculo @{} informa sua situacao", &self.placa);
-                            let msg = MensagemDeVeiculo::SituacaoAtual {
-                                placa,
-                                pos_atual: self.pos_atual,
-                                vel_atual: self.vel_atual,
-                                acel_atual: self.acel_atual,
-                            };
-                            comunicacao.send_por_veiculo(msg);
+                        MensagemDoControlador::PedeSituacao { .. } => {
+                            registra!(
+                                Categoria::Veiculos,
+                                Nivel::Detalhe,
+                                placa = &self.placa,
+                                "#veiculo @{} informa sua situacao",
+                                &self.placa
+                            );
+                            comunicacao.send_por_veiculo(self.mensagem_situacao());
+                        }
+
+                        MensagemDoControlador::Chave { .. } => {
+                            registra!(
+                                Categoria::Veiculos,
+                                Nivel::Depuracao,
+                                placa = &self.placa,
+                                "#veiculo @{} recebe a chave do controlador",
+                                self.placa
+                            );
                         }
                     }
                 }
             }
         }
+
+        // Sem o controlador, para antes do cruzamento e volta a se apresentar, para o
+        // caso de o controlador ter reiniciado sem saber deste carro
+        if self.tempo_falha_segura > 0.0
+            && !self.em_falha_segura
+            && self.sem_controlador >= self.tempo_falha_segura
+        {
+            self.em_falha_segura = true;
+            self.conhecido = false;
+            self.espera_chegada = REPETE_CHEGADA;
+            registra!(
+                Categoria::Veiculos,
+                Nivel::Aviso,
+                placa = &self.placa,
+                "#veiculo @{} sem mensagens do controlador há {:.0} ms, entra em falha segura",
+                self.placa,
+                self.sem_controlador
+            );
+        }
+        if self.em_falha_segura {
+            self.aplica_aceleracao(limite);
+        }
+
+        self.informa_se_preciso(tickms, comunicacao);
+
+        // Conta o seu estado aos vizinhos, periodicamente
+        if self.periodo_v2v > 0.0 {
+            self.espera_v2v += tickms;
+            if self.espera_v2v >= self.periodo_v2v {
+                self.espera_v2v = 0.0;
+                comunicacao.difunde_por_veiculo(self.mensagem_estado());
+            }
+        }
+
+        // A mensagem de chegada pode ter se perdido, repete até o controlador responder;
+        // em falha segura, até voltar a receber comandos, porque o controlador pode ter
+        // retirado o veículo logo depois de entregar a chave
+        if !self.conhecido || self.em_falha_segura {
+            self.espera_chegada += tickms;
+            if self.espera_chegada >= REPETE_CHEGADA {
+                self.espera_chegada = 0.0;
+                registra!(
+                    Categoria::Veiculos,
+                    Nivel::Depuracao,
+                    placa = &self.placa,
+                    "#veiculo @{} repete chegada",
+                    self.placa
+                );
+                comunicacao.send_por_veiculo(self.mensagem_chegada());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comunicacao::{ParametrosComunicacao, Transporte};
+    use crate::registro::{self, ConfiguracaoLog};
+
+    fn comunicacao() -> Comunicacao {
+        registro::configura(ConfiguracaoLog::interpreta("nenhum").unwrap());
+        Comunicacao::new(ParametrosComunicacao::default(), 1, Transporte::Memoria)
+    }
+
+    // Carro já comandado na via H, 'pos_atual' metros do cruzamento, a 'vel' m/s
+    fn carro(placa: &str, controle_a_bordo: ControleABordo, pos_atual: f64, vel: f64) -> Carro {
+        let parametros = ParametrosVeiculo {
+            controle_a_bordo,
+            ..Default::default()
+        };
+        let mut carro = Carro::new(
+            placa.to_string(),
+            [1; 32],
+            Via::ViaH,
+            vel,
+            0.0,
+            &parametros,
+            150.0,
+            4.0,
+        );
+        carro.pos_atual = pos_atual;
+        carro.comandado = true;
+        carro
+    }
+
+    // A 20 m/s, 25 m atrás de um carro parado: sem controle a bordo nada limita um carro
+    // já comandado, o ACC
+    // manda frear e o CACC, sabendo pela difusão o quanto o da frente freia e reagindo
+    // antes, freia menos; sem ninguém à frente, nada limita
+    #[test]
+    fn limite_a_bordo_atras_de_carro_parado() {
+        let comunicacao = comunicacao();
+        let frente = carro("AAA0001", ControleABordo::Nenhum, -50.0, 0.0);
+        let limite = |controle_a_bordo, com_difusao: bool| {
+            let mut atras = carro("BBB0002", controle_a_bordo, -75.0, 20.0);
+            if com_difusao {
+                let difusao = Pacote {
+                    enviada_em: 0.0,
+                    sequencia: 1,
+                    mensagem: frente.mensagem_estado(),
+                };
+                atras.vizinhos.insert(frente.placa.clone(), difusao);
+            }
+            atras.limite_a_bordo(Some(&frente), 5.0, &comunicacao)
+        };
+
+        assert_eq!(limite(ControleABordo::Nenhum, true), f64::INFINITY);
+        // Sem controle a bordo, o sensor ainda vale antes do primeiro comando
+        let mut novo = carro("BBB0002", ControleABordo::Nenhum, -75.0, 20.0);
+        novo.comandado = false;
+        assert!(novo.limite_a_bordo(Some(&frente), 5.0, &comunicacao) < 0.0);
+        let acc = limite(ControleABordo::Acc, true);
+        let cacc = limite(ControleABordo::Cacc, true);
+        assert!(acc < 0.0, "acc {}", acc);
+        assert!(acc < cacc && cacc < 0.0, "acc {}, cacc {}", acc, cacc);
+        // Sem a difusão do da frente, o CACC age como o ACC
+        assert_eq!(limite(ControleABordo::Cacc, false), acc);
+
+        let sozinho = carro("BBB0002", ControleABordo::Cacc, -75.0, 20.0);
+        assert_eq!(
+            sozinho.limite_a_bordo(None, 5.0, &comunicacao),
+            f64::INFINITY
+        );
+    }
+
+    // Posições em que o carro informou a situação sem ser pedido, andando a 20 m/s de
+    // 120 m antes do cruzamento até depois dele, com o período e os limiares longe
+    fn posicoes_dos_informes(informe: ModoInforme) -> Vec<f64> {
+        let mut comunicacao = comunicacao();
+        let parametros = ParametrosVeiculo {
+            informe,
+            periodo_informe: 60_000.0,
+            ..Default::default()
+        };
+        let mut carro = Carro::new(
+            "AAA0001".to_string(),
+            [1; 32],
+            Via::ViaH,
+            20.0,
+            0.0,
+            &parametros,
+            120.0,
+            4.0,
+        );
+        carro.conhecido = true;
+
+        let mut posicoes = Vec::new();
+        let mut agora = 0.0;
+        while carro.pos_atual < 20.0 {
+            comunicacao.define_tempo(agora);
+            carro.informa_se_preciso(5.0, &mut comunicacao);
+            while let Some(pacote) = comunicacao.receive_por_controlador() {
+                if let MensagemDeVeiculo::SituacaoAtual { pos_atual, .. } = pacote.mensagem {
+                    posicoes.push(pos_atual);
+                }
+            }
+            carro.pos_atual += 0.1;
+            agora += 5.0;
+        }
+        posicoes
+    }
+
+    // Informa ao ser conhecido e ao passar cada zona, a entrada e a saída do cruzamento
+    #[test]
+    fn informes_nas_fronteiras_das_zonas() {
+        let posicoes = posicoes_dos_informes(ModoInforme::Espontaneo);
+        let fronteiras = [-120.0, -100.0, -50.0, -25.0, -10.0, 0.0, 8.0];
+        assert_eq!(posicoes.len(), fronteiras.len(), "{:?}", posicoes);
+        for (pos, fronteira) in posicoes.iter().zip(fronteiras) {
+            assert!(
+                *pos >= fronteira && *pos < fronteira + 0.1 + 1e-9,
+                "{:?}",
+                posicoes
+            );
+        }
+        assert!(posicoes_dos_informes(ModoInforme::Pedido).is_empty());
     }
 }